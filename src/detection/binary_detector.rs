@@ -267,10 +267,10 @@ pub fn detect_binaries(
     let mut tasks: Vec<(String, String)> = Vec::new();
 
     for (category, binaries) in BASE_CANDIDATE_GROUPS {
-        if let Some(ref filter) = filter_set {
-            if !filter.contains(&category.to_lowercase()) {
-                continue;
-            }
+        if let Some(ref filter) = filter_set
+            && !filter.contains(&category.to_lowercase())
+        {
+            continue;
         }
 
         for binary in *binaries {
@@ -381,22 +381,22 @@ pub fn detect_shells() -> Vec<ShellInfo> {
     }
 
     // Check $SHELL environment variable
-    if let Ok(user_shell) = env::var("SHELL") {
-        if !shells.iter().any(|s| s.path == user_shell) {
-            let name = Path::new(&user_shell)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("unknown")
-                .to_string();
-
-            let version = detect_version(&user_shell, 1500).ok();
-
-            shells.push(ShellInfo {
-                name,
-                path: user_shell,
-                version,
-            });
-        }
+    if let Ok(user_shell) = env::var("SHELL")
+        && !shells.iter().any(|s| s.path == user_shell)
+    {
+        let name = Path::new(&user_shell)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let version = detect_version(&user_shell, 1500).ok();
+
+        shells.push(ShellInfo {
+            name,
+            path: user_shell,
+            version,
+        });
     }
 
     shells
@@ -411,17 +411,18 @@ fn which_all(name: &str) -> Vec<String> {
 
     for dir in env::split_paths(&path_var) {
         let candidate = dir.join(name);
-        if candidate.is_file() && is_executable(&candidate) {
-            if let Some(s) = candidate.to_str() {
-                matches.push(s.to_string());
-            }
+        if candidate.is_file()
+            && is_executable(&candidate)
+            && let Some(s) = candidate.to_str()
+        {
+            matches.push(s.to_string());
         }
     }
     matches
 }
 
 #[cfg(unix)]
-fn is_executable(p: &Path) -> bool {
+pub(crate) fn is_executable(p: &Path) -> bool {
     use std::os::unix::fs::PermissionsExt;
     if let Ok(meta) = p.metadata() {
         let mode = meta.permissions().mode();
@@ -432,7 +433,7 @@ fn is_executable(p: &Path) -> bool {
 }
 
 #[cfg(not(unix))]
-fn is_executable(p: &Path) -> bool {
+pub(crate) fn is_executable(p: &Path) -> bool {
     p.is_file()
 }
 
@@ -7,8 +7,15 @@ use rmcp::{ServiceExt, transport::stdio};
 use server::EnhancedTerminalServer;
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
+fn main() -> Result<()> {
+    // Job processes re-exec this binary to apply per-job setup before the shell starts.
+    tools::launcher::run_if_requested();
+    tools::audit_log::run_verify_if_requested();
+    serve()
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn serve() -> Result<()> {
     // Initialize tracing with RUST_LOG env var support
     // Set RUST_LOG=enhanced_terminal_mcp=debug for detailed logs
     tracing_subscriber::registry()
@@ -21,8 +28,13 @@ async fn main() -> Result<()> {
 
     tracing::info!("Enhanced Terminal MCP Server starting");
 
+    // Refuse to start with a policy file that does not load.
+    tools::policy::current()?;
+
     let server = EnhancedTerminalServer::new();
     let service = server.serve(stdio()).await?;
     service.waiting().await?;
+    tools::cgroup::cleanup();
+    tools::output_spool::cleanup();
     Ok(())
 }
@@ -1,6 +1,15 @@
 use crate::detection::{detect_binaries, detect_shells};
-use crate::tools::{JobManager, TerminalExecutionInput, execute_command, preview_output};
-use chrono::{SecondsFormat, Utc};
+use crate::tools::audit_log;
+use crate::tools::landlock::WriteRestriction;
+use crate::tools::output_decoder::OutputEncoding;
+use crate::tools::output_search::{SearchCursor, SearchOptions};
+use crate::tools::sudo;
+use crate::tools::{
+    CaptureMode, CgroupPlacement, CommandCheckInput, EnvChange, ExecutionResult, JobCursor,
+    JobManager, JobQuery, JobRecord, JobStatus, LineSelection, OutputStream, ResourceUsage,
+    SessionCloseInput, SessionExecInput, SessionManager, SessionOpenInput, TerminalExecutionInput,
+    check_command, execute_command, preview_output, sandbox,
+};
 use rmcp::{
     ErrorData as McpError, Peer, handler::server::router::tool::ToolRouter,
     handler::server::wrapper::Parameters, model::*, service::RoleServer, tool, tool_handler,
@@ -8,17 +17,6 @@ use rmcp::{
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{
-    fs::{File, OpenOptions},
-    io::{self, Write},
-    path::PathBuf,
-    sync::Mutex,
-};
-
-#[cfg(unix)]
-use nix::fcntl::FlockArg;
-#[cfg(unix)]
-use std::os::fd::{AsRawFd, RawFd};
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -45,71 +43,125 @@ fn default_version_timeout() -> u64 {
     1500
 }
 
-const ENHANCED_TERMINAL_CALL_LOG_FILE: &str = "enhanced_terminal_calls.jsonl";
-static ENHANCED_TERMINAL_CALL_LOG_MUTEX: Mutex<()> = Mutex::new(());
-
-#[derive(Debug, Serialize)]
-struct EnhancedTerminalCallLogEntry<'a> {
-    datetime: String,
-    tool: &'static str,
-    parameters: &'a TerminalExecutionInput,
+/// Append the output section(s) of an enhanced_terminal result.
+///
+/// Pipes-mode results show stdout and stderr separately; PTY results have one merged stream.
+fn push_command_output(result_text: &mut String, result: &ExecutionResult, label_prefix: &str) {
+    match (result.capture_mode, &result.stdout, &result.stderr) {
+        (CaptureMode::Pipes, Some(stdout), Some(stderr)) => {
+            result_text.push_str(&format!("\n{}Stdout:\n", label_prefix));
+            result_text.push_str(stdout);
+            result_text.push_str(&format!("\n\n{}Stderr:\n", label_prefix));
+            result_text.push_str(stderr);
+        }
+        _ => {
+            result_text.push_str(&format!("\n{}Output:\n", label_prefix));
+            result_text.push_str(&result.output);
+        }
+    }
 }
 
-fn enhanced_terminal_call_log_path() -> PathBuf {
-    std::env::var_os("ENHANCED_TERMINAL_CALL_LOG_PATH")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| {
-            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(ENHANCED_TERMINAL_CALL_LOG_FILE)
-        })
+/// Append the cgroup lines for a job.
+///
+/// `report_unavailable` controls whether a missing cgroup is mentioned at all, so
+/// plain commands on machines without cgroup v2 do not get an extra line.
+/// Final resource usage of a finished job, or a live `/proc` sample of an active
+/// job's process group. The flag is true for live samples.
+fn job_resource_usage(job: &JobRecord) -> Option<(ResourceUsage, bool)> {
+    resource_usage_of(job.resource_usage, &job.status, job.pid)
 }
 
-#[cfg(unix)]
-struct CallLogFileLock(RawFd);
-
-#[cfg(unix)]
-impl Drop for CallLogFileLock {
-    fn drop(&mut self) {
-        #[allow(deprecated)]
-        let _ = nix::fcntl::flock(self.0, FlockArg::Unlock);
+fn resource_usage_of(
+    recorded: Option<ResourceUsage>,
+    status: &JobStatus,
+    pid: Option<u32>,
+) -> Option<(ResourceUsage, bool)> {
+    if let Some(usage) = recorded {
+        return Some((usage, false));
     }
+    if !status.is_active() {
+        return None;
+    }
+    ResourceUsage::sample_process_group(pid?).map(|usage| (usage, true))
 }
 
-#[cfg(unix)]
-fn lock_call_log_file(file: &File) -> io::Result<CallLogFileLock> {
-    let fd = file.as_raw_fd();
-    #[allow(deprecated)]
-    nix::fcntl::flock(fd, FlockArg::LockExclusive).map_err(io::Error::other)?;
-    Ok(CallLogFileLock(fd))
+/// List indirect execution findings (`eval "$X"`, `curl .. | sh`, ..).
+fn push_indirect_execution(result_text: &mut String, findings: &[String]) {
+    if findings.is_empty() {
+        return;
+    }
+    result_text.push_str("Indirect Execution:\n");
+    for finding in findings {
+        result_text.push_str(&format!("  - {}\n", finding));
+    }
 }
 
-fn write_call_log_line(file: &mut File, line: &[u8]) -> io::Result<()> {
-    #[cfg(unix)]
-    let _file_lock = lock_call_log_file(file)?;
+fn push_encoding(result_text: &mut String, encoding: OutputEncoding, invalid_utf8: usize) {
+    if encoding != OutputEncoding::Utf8 {
+        result_text.push_str(&format!("Output Encoding: {}\n", encoding));
+    }
+    match (encoding, invalid_utf8) {
+        (_, 0) => {}
+        (OutputEncoding::Utf8, count) => result_text.push_str(&format!(
+            "Valid UTF-8: no ({} invalid byte sequences shown as U+FFFD; encoding=\"latin-1\" decodes legacy output)\n",
+            count
+        )),
+        (_, count) => result_text.push_str(&format!(
+            "Valid UTF-8: no ({} invalid byte sequences)\n",
+            count
+        )),
+    }
+}
 
-    file.write_all(line)?;
-    file.flush()
+fn push_rewrites(result_text: &mut String, rewrites: &[String]) {
+    if rewrites.is_empty() {
+        return;
+    }
+    result_text.push_str("Rewrites:\n");
+    for rewrite in rewrites {
+        result_text.push_str(&format!("  - {}\n", rewrite));
+    }
 }
 
-fn log_enhanced_terminal_call(input: &TerminalExecutionInput) -> io::Result<()> {
-    let entry = EnhancedTerminalCallLogEntry {
-        datetime: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
-        tool: "enhanced_terminal",
-        parameters: input,
+fn push_write_restriction(result_text: &mut String, restriction: Option<&WriteRestriction>) {
+    let Some(restriction) = restriction else {
+        return;
     };
+    result_text.push_str(&format!("Write Restrictions: {}\n", restriction));
+    if let WriteRestriction::Enforced(rules) = restriction {
+        for dropped in &rules.dropped {
+            result_text.push_str(&format!(
+                "Write Restriction Warning: {} (not writable)\n",
+                dropped
+            ));
+        }
+    }
+}
 
-    let mut line = serde_json::to_vec(&entry).map_err(io::Error::other)?;
-    line.push(0x0A);
-
-    let _process_guard = ENHANCED_TERMINAL_CALL_LOG_MUTEX
-        .lock()
-        .map_err(|e| io::Error::other(format!("call log mutex poisoned: {e}")))?;
-
-    let log_path = enhanced_terminal_call_log_path();
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_path)?;
-    write_call_log_line(&mut file, &line)
+fn push_cgroup_info(
+    result_text: &mut String,
+    placement: Option<&CgroupPlacement>,
+    report_unavailable: bool,
+) {
+    match placement {
+        Some(CgroupPlacement::Placed(cgroup)) => {
+            result_text.push_str(&format!("Cgroup: {}\n", cgroup.path.display()));
+            if !cgroup.limits.is_empty() {
+                result_text.push_str(&format!("Cgroup Limits: {}\n", cgroup.limits));
+            }
+            for warning in &cgroup.warnings {
+                result_text.push_str(&format!("Cgroup Warning: {}\n", warning));
+            }
+            result_text.push_str(&format!("Cgroup Stats: {}\n", cgroup.current_stats()));
+        }
+        Some(CgroupPlacement::Unavailable(reason)) if report_unavailable => {
+            result_text.push_str(&format!(
+                "Cgroup: unavailable - {} (cgroup limits and stats disabled)\n",
+                reason
+            ));
+        }
+        _ => {}
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -126,6 +178,15 @@ pub struct JobStatusInput {
     /// Limit for pagination in bytes (default: 0 = all remaining)
     #[serde(default)]
     pub limit_bytes: usize,
+    /// First line to return, 1-based; negative values count from the end (-1 is the last line)
+    #[serde(default)]
+    pub start_line: Option<i64>,
+    /// Number of lines to return (default: all remaining)
+    #[serde(default)]
+    pub line_count: Option<usize>,
+    /// Return the last N lines
+    #[serde(default)]
+    pub tail_lines: Option<usize>,
     /// Maximum number of GPT-5/o200k_base tokens to return from the selected output chunk.
     /// Defaults to 4096. Set to 0 to disable token truncation.
     #[serde(default = "default_preview_tokens")]
@@ -133,6 +194,72 @@ pub struct JobStatusInput {
     /// If true, include the full command. Defaults to false to keep repeated polling compact.
     #[serde(default)]
     pub full_command: bool,
+    /// Output stream to read: "combined" (default), or for jobs run with capture_mode="pipes"
+    /// also "stdout", "stderr" or "interleaved" (combined output with per-line stream labels).
+    #[serde(default)]
+    pub stream: OutputStream,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JobSearchInput {
+    /// Job ID whose output to search
+    pub job_id: String,
+    /// Regular expression matched against each line (Rust regex syntax)
+    pub pattern: String,
+    /// Match letters regardless of case (default: false)
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Report the lines that do NOT match (default: false)
+    #[serde(default)]
+    pub invert: bool,
+    /// Lines of context before each match (default: 0)
+    #[serde(default)]
+    pub before_context: usize,
+    /// Lines of context after each match (default: 0)
+    #[serde(default)]
+    pub after_context: usize,
+    /// Maximum matches per call (default: 50)
+    #[serde(default = "default_max_matches")]
+    pub max_matches: usize,
+    /// Next Cursor of the previous call, to continue the search after its matches
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Output stream to search: "combined" (default), or for jobs run with capture_mode="pipes"
+    /// also "stdout" or "stderr"
+    #[serde(default)]
+    pub stream: OutputStream,
+}
+
+fn default_max_matches() -> usize {
+    50
+}
+
+/// Line addressing requested by `start_line`, `line_count` and `tail_lines`.
+fn line_selection(input: &JobStatusInput) -> Result<Option<LineSelection>, McpError> {
+    match (input.start_line, input.tail_lines) {
+        (Some(_), Some(_)) => Err(McpError::invalid_params(
+            "Use either start_line or tail_lines, not both",
+            None,
+        )),
+        (None, Some(_)) if input.line_count.is_some() => Err(McpError::invalid_params(
+            "line_count goes with start_line; tail_lines already sets the number of lines",
+            None,
+        )),
+        (Some(0), None) => Err(McpError::invalid_params(
+            "start_line is 1-based; use a negative start_line to count from the end",
+            None,
+        )),
+        (Some(start), None) => Ok(Some(LineSelection::From {
+            start,
+            count: input.line_count,
+        })),
+        (None, Some(count)) => Ok(Some(LineSelection::Tail(count))),
+        (None, None) => Ok(input.line_count.map(|count| LineSelection::From {
+            start: 1,
+            count: Some(count),
+        })),
+    }
 }
 
 fn default_incremental() -> bool {
@@ -146,7 +273,7 @@ fn default_preview_tokens() -> usize {
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct JobListInput {
-    /// Maximum number of jobs to return (default: 50)
+    /// Maximum number of jobs to return per page, at least 1 (default: 50)
     #[serde(default = "default_max_jobs")]
     pub max_jobs: usize,
     /// Filter by job status (e.g., ["Running", "Completed"])
@@ -161,6 +288,12 @@ pub struct JobListInput {
     /// Sort order: "newest" (default) or "oldest"
     #[serde(default = "default_sort_order")]
     pub sort_order: String,
+    /// If true, include CPU time, peak RSS, page faults and context switches per job (default: false)
+    #[serde(default)]
+    pub include_usage: bool,
+    /// Next Cursor of the previous page, to continue the listing after it
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 fn default_max_jobs() -> usize {
@@ -178,6 +311,30 @@ pub struct JobCancelInput {
     pub job_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SudoRevokeInput {
+    /// Also stop the keepalive task for the rest of the session (default: false)
+    #[serde(default)]
+    pub stop_keepalive: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JobSignalInput {
+    /// Job ID to signal
+    pub job_id: String,
+    /// Signal name or number, e.g. "SIGINT", "HUP", "STOP", "CONT" or "2"
+    pub signal: String,
+    /// Signal the job's whole process group (default: true) instead of only the shell
+    #[serde(default = "default_true")]
+    pub process_group: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct JobStdinInput {
@@ -192,6 +349,7 @@ pub struct EnhancedTerminalServer {
     tool_router: ToolRouter<Self>,
     shell_info: String,
     job_manager: JobManager,
+    session_manager: SessionManager,
     detected_shells: Vec<String>,
 }
 
@@ -223,10 +381,45 @@ impl EnhancedTerminalServer {
             tool_router: Self::tool_router(),
             shell_info,
             job_manager: JobManager::new(),
+            session_manager: SessionManager::new(),
             detected_shells,
         }
     }
 
+    /// Reject reading stdout or stderr alone from a job whose streams are merged.
+    fn check_stream(&self, job_id: &str, stream: OutputStream) -> Result<(), McpError> {
+        if stream == OutputStream::Combined {
+            return Ok(());
+        }
+        let job = self
+            .job_manager
+            .get_job(job_id)
+            .ok_or_else(|| McpError::invalid_params("Job not found", None::<serde_json::Value>))?;
+        if !job.separate_streams {
+            return Err(McpError::invalid_params(
+                "Job was captured through a PTY, so stdout and stderr are merged. \
+                 Run it with capture_mode=\"pipes\" to read streams separately.",
+                None,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate a requested shell against the shells detected at startup.
+    fn validate_shell(&self, shell: &str) -> Result<(), McpError> {
+        if !self.detected_shells.is_empty() && !self.detected_shells.iter().any(|s| s == shell) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "Shell '{}' not found. Available shells: {}",
+                    shell,
+                    self.detected_shells.join(", ")
+                ),
+                None,
+            ));
+        }
+        Ok(())
+    }
+
     #[tool(
         name = "enhanced_terminal",
         description = "Execute shell commands in a PTY with smart async switching and security.
@@ -241,6 +434,13 @@ PARAMETERS:
 - force_async (boolean, default: false): Force immediate background execution and return a job_id without waiting for the async threshold
 - custom_denylist (array, default: []): Additional dangerous patterns to block
 - tags (array, default: []): Optional tags for categorizing jobs (e.g., [\"build\", \"ci\"])
+- capture_mode (string, default: 'pty'): 'pty' runs in a terminal with stdout/stderr merged; 'pipes' uses plain pipes and keeps stdout and stderr separate
+- encoding (string, default: 'utf-8'): How to decode the output: 'utf-8' (invalid bytes become U+FFFD) or 'latin-1' for legacy tools that print ISO 8859-1
+- cgroup_limits (object, optional): cgroup v2 limits for the job's own group, covering grandchildren - memory_max_bytes (memory.max), cpu_max_cores (cpu.max, e.g. 0.5), pids_max (pids.max). Needs a delegated cgroup v2 subtree; otherwise the response says the cgroup is unavailable
+- restrict_writes (boolean, default: false): Landlock write restrictions (Linux): writes only beneath the cwd, $TMPDIR, terminal/null devices and server-configured paths; protected paths such as /, /etc and /usr are never writable. Does not cover chmod/chown, and blocks setuid programs like sudo. If the kernel lacks Landlock the command is refused or runs unrestricted, per server policy
+- read_only_paths (array, optional): Extra paths that stay read-only with restrict_writes; a writable directory containing one is not made writable
+- sandbox (string, optional): Linux namespace sandbox profile. Built-in: 'isolated' (read-only root, writable cwd, private /tmp, no network, own PID namespace), 'readonly-root' (same, but with network enabled), 'offline' (no network only). Runs unprivileged; the server may add profiles or set a default, and 'none' opts out only when no default is configured
+- resource_limits (object, optional): rlimits applied before the command starts - cpu_secs (RLIMIT_CPU), memory_bytes (RLIMIT_AS), file_size_bytes (RLIMIT_FSIZE), max_processes (RLIMIT_NPROC, counts all of the user's processes), max_open_files (RLIMIT_NOFILE). Capped by the server's ENHANCED_TERMINAL_MAX_* settings, which also apply when omitted
 
 AVAILABLE SHELLS:
 {shell_list}
@@ -259,6 +459,12 @@ BEHAVIOR:
 SECURITY:
 - 40+ dangerous patterns blocked by default
 - Custom patterns via custom_denylist parameter
+- Server policy file (ENHANCED_TERMINAL_POLICY_FILE) with allow/deny/ask rules that clients cannot override
+- ask rules prompt the user through MCP elicitation before the command runs
+- Optional allowlist-only mode: every program in the command line must be on the policy file's allowlist
+- Rewrite rules ([[rewrite]] in the policy file, e.g. cargo --color=never or npm ci for ci-tagged jobs) change commands before the policy checks them
+- Indirect execution (eval of variables, curl | sh, base64 -d | sh, interpreter one-liners) is reported, and denied or asked about per the policy's [indirect] table
+- Secrets (AWS keys, GitHub tokens, private keys, Authorization headers, secret env values, [redaction] patterns from the policy file) are redacted from output, stream events and the call log
 - No privilege escalation without explicit configuration
 - Token previews and bounded preview buffers prevent oversized MCP responses
 - Optional timeout protection via ENHANCED_TERMINAL_TIMEOUT_SECS environment variable
@@ -267,21 +473,32 @@ RETURNS:
 - job_id: Unique readable adjective-noun-number identifier for this command execution
 - command: The executed command
 - working_directory: Resolved working directory path
+- Sandbox: profile the command ran under, with what it restricts
+- Write Restrictions: Landlock ABI and writable paths, or why writes are not restricted
 - exit_code: Exit code (if completed, null if still running)
 - success: Boolean indicating success (if completed)
-- output: Command output preview (truncated to preview_tokens by default)
+- output: Command output preview (truncated to preview_tokens by default); with capture_mode='pipes', separate Stdout and Stderr sections
 - truncated: Boolean indicating if output was truncated
 - timed_out: Boolean indicating if command was killed by timeout
+- Termination: signal name and number, core dump, and what it means (e.g. SIGSEGV, SIGKILL from the OOM killer, exit codes 126/127/130)
+- Terminated By: SIGINT, SIGTERM or SIGKILL - the stage that stopped the process group after a timeout
+- Resource Limits / Limit Exceeded: effective rlimits, and the limit that ended the command (if any)
+- Cgroup / Cgroup Stats: the job's cgroup v2 group with peak memory, CPU usage and OOM kills
 - switched_to_async: Boolean indicating if command moved to background
 - denied: Boolean indicating if command was blocked
-- denial_reason: Reason for denial (if denied)"
+- denial_reason: Reason for denial (if denied)
+- Approval: how a policy ask decision was approved (if one applied)
+- Rewrites: rewrite rules from the policy file (and sudo -n wrapping) that changed the command before it was checked and run
+- Indirect Execution: constructs that run code the denylist cannot inspect (eval of a variable, curl | sh, base64 -d | sh, python -c with os.system), with the action taken
+- Redactions: number of secrets (AWS keys, GitHub tokens, private keys, Authorization headers, secret env values) replaced with [REDACTED:kind] in the output, if any
+- Output Encoding / Valid UTF-8: shown for latin-1 jobs, and when the output had byte sequences that are not valid UTF-8"
     )]
     async fn enhanced_terminal(
         &self,
         Parameters(input): Parameters<TerminalExecutionInput>,
         peer: Peer<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        log_enhanced_terminal_call(&input).map_err(|e| {
+        audit_log::record_call("enhanced_terminal", &input).map_err(|e| {
             McpError::internal_error(format!("Failed to log enhanced_terminal call: {}", e), None)
         })?;
 
@@ -292,17 +509,9 @@ RETURNS:
             ));
         }
 
-        // Validate shell against detected shells
-        if !self.detected_shells.is_empty() && !self.detected_shells.contains(&input.shell) {
-            return Err(McpError::invalid_params(
-                format!(
-                    "Shell '{}' not found. Available shells: {}",
-                    input.shell,
-                    self.detected_shells.join(", ")
-                ),
-                None,
-            ));
-        }
+        self.validate_shell(&input.shell)?;
+        sandbox::resolve(input.sandbox.as_deref())
+            .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
 
         let result = execute_command(&input, &self.job_manager, Some(peer))
             .await
@@ -311,22 +520,39 @@ RETURNS:
             })?;
 
         if result.denied {
+            let reason = result
+                .denial_reason
+                .unwrap_or_else(|| "Security policy violation".to_string());
+            if let Err(e) = audit_log::record_denied("enhanced_terminal", &result.command, &reason)
+            {
+                tracing::warn!("Failed to write the audit log: {}", e);
+            }
             let mut result_text = format!("Command: {}\n", result.command);
-            result_text.push_str(&format!(
-                "Status: DENIED\n\nReason: {}\n",
-                result
-                    .denial_reason
-                    .unwrap_or_else(|| "Security policy violation".to_string())
-            ));
+            push_rewrites(&mut result_text, &result.rewrites);
+            result_text.push_str(&format!("Status: DENIED\n\nReason: {}\n", reason));
+            push_indirect_execution(&mut result_text, &result.indirect_execution);
             return Ok(CallToolResult::success(vec![Content::text(result_text)]));
         }
 
         let mut result_text = format!("Job ID: {}\n", result.job_id);
         result_text.push_str(&format!("Command: {}\n", result.command));
+        push_rewrites(&mut result_text, &result.rewrites);
         result_text.push_str(&format!(
             "Working Directory: {}\n",
             result.working_directory
         ));
+        if let Some(sandbox) = &result.sandbox {
+            result_text.push_str(&format!("Sandbox: {}\n", sandbox));
+        }
+        push_write_restriction(&mut result_text, result.write_restriction.as_ref());
+        if let Some(approval) = &result.approval {
+            result_text.push_str(&format!("Approval: {}\n", approval));
+        }
+        push_indirect_execution(&mut result_text, &result.indirect_execution);
+        if result.redactions > 0 {
+            result_text.push_str(&format!("Redactions: {}\n", result.redactions));
+        }
+        push_encoding(&mut result_text, result.encoding, result.invalid_utf8);
 
         if result.switched_to_async {
             if let Some(duration) = result.duration_secs {
@@ -338,8 +564,7 @@ RETURNS:
             result_text.push_str("Status: SWITCHED TO BACKGROUND\n");
             result_text
                 .push_str("The command is still running. Use enhanced_terminal_job_status to check progress.\n");
-            result_text.push_str("\nPartial Output:\n");
-            result_text.push_str(&result.output);
+            push_command_output(&mut result_text, &result, "Partial ");
             if result.truncated {
                 result_text
                     .push_str("\n\n[Output preview truncated - use enhanced_terminal_job_status to get full output]");
@@ -363,9 +588,25 @@ RETURNS:
             } else {
                 result_text.push_str("Status: FAILED ❌\n");
             }
+            if let Some(termination) = &result.termination {
+                result_text.push_str(&format!("Termination: {}\n", termination));
+            }
+            if let Some(stage) = result.termination_stage {
+                result_text.push_str(&format!("Terminated By: {} (process group)\n", stage));
+            }
+            if !result.resource_limits.is_empty() {
+                result_text.push_str(&format!("Resource Limits: {}\n", result.resource_limits));
+            }
+            if let Some(hit) = &result.limit_exceeded {
+                result_text.push_str(&format!("Limit Exceeded: {}\n", hit));
+            }
+            push_cgroup_info(
+                &mut result_text,
+                result.cgroup.as_ref(),
+                input.cgroup_limits.is_some(),
+            );
 
-            result_text.push_str("\nOutput:\n");
-            result_text.push_str(&result.output);
+            push_command_output(&mut result_text, &result, "");
 
             if result.truncated {
                 result_text.push_str("\n\n[Output truncated due to preview token limit]");
@@ -375,6 +616,85 @@ RETURNS:
         Ok(CallToolResult::success(vec![Content::text(result_text)]))
     }
 
+    #[tool(
+        name = "enhanced_terminal_check_command",
+        description = "Check what enhanced_terminal would do with a command, without running it.
+
+PARAMETERS:
+- command (string, required): Command to check
+- cwd (string, default: \".\"): Working directory
+- shell (string, default: \"bash\"): Shell the command would run in
+- env_vars (object, optional): Environment variables; PATH here is used to resolve programs
+- custom_denylist (array, optional): Additional denylist patterns
+- tags (array, optional): Job tags, for rewrite rules limited to tagged jobs
+
+BEHAVIOR:
+- Applies the same pipeline as enhanced_terminal: rewrite rules, cwd resolution, shell parsing, denylist, allowlist and policy rules
+- Runs nothing and never prompts for approval
+
+RETURNS:
+- Decision: ALLOW, DENY or ASK, with the reason and the matched denylist pattern, allowlist entry or policy rule
+- Approval: how an ASK decision would be settled
+- Indirect Execution: eval of a variable, piped or decoded shell scripts and interpreter one-liners found in the command
+- Working Directory: the resolved cwd
+- Final Command and Shell: the exact command line and shell that would run, with the rewrite rules that changed it
+- Programs: each program in the command line, resolved from PATH (or builtin, function, unknown until run time)"
+    )]
+    async fn check_command(
+        &self,
+        Parameters(input): Parameters<CommandCheckInput>,
+    ) -> Result<CallToolResult, McpError> {
+        if input.command.trim().is_empty() {
+            return Err(McpError::invalid_params("Command cannot be empty", None));
+        }
+        self.validate_shell(&input.shell)?;
+
+        let check = check_command(&input);
+        let mut result_text = format!("Command: {}\n", check.command);
+        result_text.push_str(&format!(
+            "Decision: {}\n",
+            check.decision.to_string().to_uppercase()
+        ));
+        if let Some(reason) = &check.reason {
+            result_text.push_str(&format!("Reason: {}\n", reason));
+        }
+        result_text.push_str(&format!(
+            "Matched Rule: {}\n",
+            check.matched.as_deref().unwrap_or("none")
+        ));
+        if let Some(approval) = &check.approval {
+            result_text.push_str(&format!("Approval: {}\n", approval));
+        }
+        push_indirect_execution(&mut result_text, &check.indirect_execution);
+        result_text.push_str(&format!(
+            "Working Directory: {}{}\n",
+            check.working_directory.display(),
+            if check.working_directory_exists {
+                ""
+            } else {
+                " (does not exist)"
+            }
+        ));
+        result_text.push_str(&format!("Final Command: {}\n", check.final_command));
+        if check.sudo_rewritten {
+            result_text.push_str("Sudo Rewrite: applied (sudo -n, ENHANCED_TERMINAL_SUDO_WRAP)\n");
+        }
+        push_rewrites(&mut result_text, &check.rewrites);
+        result_text.push_str(&format!(
+            "Shell: {} ({})\n",
+            check.shell.name, check.shell.resolution
+        ));
+        result_text.push_str("Programs:\n");
+        if check.programs.is_empty() {
+            result_text.push_str("  (none)\n");
+        }
+        for program in &check.programs {
+            result_text.push_str(&format!("  {}: {}\n", program.name, program.resolution));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(result_text)]))
+    }
+
     #[tool(
         name = "enhanced_terminal_job_status",
         description = "Get status and output of a background job.
@@ -384,16 +704,20 @@ PARAMETERS:
 - incremental (boolean, default: true): If true, return only new output since last check (RECOMMENDED)
 - offset_bytes (number, default: 0): Starting byte position for pagination
 - limit_bytes (number, default: 0): Maximum bytes to select for pagination (0 = all remaining)
+- start_line (number, optional): First line to return, 1-based; negative counts from the end (-1 = last line)
+- line_count (number, optional): Number of lines to return from start_line (default: all remaining)
+- tail_lines (number, optional): Return the last N lines, e.g. 50
 - preview_tokens (number, default: 4096): Maximum GPT-5/o200k_base tokens to return from the selected output chunk; set 0 to disable token truncation
 - full_command (boolean, default: false): Include the full command; by default job_status returns only the command summary to keep polling compact
+- stream (string, default: 'combined'): For jobs run with capture_mode='pipes', read 'stdout', 'stderr', or 'interleaved' (combined output with [stdout]/[stderr] line labels); pagination offsets and incremental positions are tracked per stream
 
 BEHAVIOR:
-- Returns current status: Running, Completed, Failed, TimedOut, or Canceled
+- Returns current status: Running, Paused, Completed, Failed, TimedOut, or Canceled
 - Full output is available through pagination; preview_tokens can bound the returned text for model context
 - Incremental mode tracks read position per job
 - Duration calculated from start time
 - Exit code available when completed
-- Supports three output modes: incremental, full, and paginated
+- Supports four output modes: incremental, full, paginated (bytes) and lines
 
 INCREMENTAL OUTPUT (DEFAULT):
 When incremental=true (default, recommended):
@@ -414,36 +738,72 @@ When offset_bytes > 0 or limit_bytes > 0:
 - Useful for seeking into very long logs
 - Can re-read specific segments without full retrieval
 
+LINE MODE:
+When start_line, line_count or tail_lines is set:
+- tail_lines=50 returns the last 50 lines; start_line=-50 returns the same lines
+- start_line=100, line_count=20 returns lines 100-119
+- Lines are located through a sparse per-stream line index, so reads stay fast on huge outputs
+- An unfinished last line counts as a line
+- Cannot be combined with offset_bytes/limit_bytes
+
 RETURNS:
 - job_id: Readable adjective-noun-number job identifier
 - command: Full executed command, only present when full_command=true
 - summary: Short command summary returned by default
 - shell: Shell used for execution
 - cwd: Working directory
-- status: Current job status (Running, Completed, Failed, TimedOut, Canceled)
+- status: Current job status (Running, Paused, Completed, Failed, TimedOut, Canceled)
 - exit_code: Exit code (if completed)
 - pid: Process ID (if available)
+- termination: Signal name/number, whether a core was dumped, and an explanation, for jobs killed by a signal or ending with a well-known exit code (126, 127, 128+n)
 - duration: Time elapsed since job start
 - tags: Optional tags assigned to job
+- resource_usage: User/system CPU time, max RSS, major page faults and context switches; final values from wait4 once the job ended, or a live sample of its process group while it runs (Linux)
+- cgroup: The job's cgroup v2 group with live (running) or final stats: peak memory, CPU usage (user/system), OOM kills; or why cgroups are unavailable
 - output: Command output (full, incremental, or paginated based on parameters, optionally token-previewed)
+- Redactions: number of secrets replaced with [REDACTED:kind] in the stored output, if any
+- Output Encoding / Valid UTF-8: the job's encoding if not utf-8, and how many byte sequences were not valid UTF-8
 - truncated: Boolean indicating if output preview was truncated
 - (pagination only) has_more: Boolean indicating if more data available
 - (pagination only) total_length: Total output size in bytes
-- (pagination only) next_offset_bytes: Byte offset to pass to the next call"
+- (pagination only) next_offset_bytes: Byte offset to pass to the next call
+- (line mode only) total_lines, returned line numbers with their byte range, and next_start_line"
     )]
     async fn job_status(
         &self,
         Parameters(input): Parameters<JobStatusInput>,
     ) -> Result<CallToolResult, McpError> {
+        self.check_stream(&input.job_id, input.stream)?;
+
         // Determine if pagination is requested
         let use_pagination = input.offset_bytes > 0 || input.limit_bytes > 0;
+        let line_selection = line_selection(&input)?;
+        if line_selection.is_some() && use_pagination {
+            return Err(McpError::invalid_params(
+                "Address output either by lines (start_line, line_count, tail_lines) \
+                 or by bytes (offset_bytes, limit_bytes), not both",
+                None,
+            ));
+        }
 
+        let mut line_range = None;
         let mut range_start_byte = None;
         let mut range_end_byte = None;
         let mut requested_end_byte = None;
         let mut next_offset_bytes = None;
 
-        let (mut output_to_show, has_more, total_length) = if use_pagination {
+        let (mut output_to_show, has_more, total_length) = if let Some(selection) = line_selection {
+            let mut range = self
+                .job_manager
+                .get_line_range(&input.job_id, input.stream, selection)
+                .ok_or_else(|| {
+                    McpError::invalid_params("Job not found", None::<serde_json::Value>)
+                })?;
+            let output = std::mem::take(&mut range.output);
+            let has_more = range.next_start_line.is_some();
+            line_range = Some(range);
+            (output, Some(has_more), None)
+        } else if use_pagination {
             // Use byte-explicit pagination
             let limit_bytes = if input.limit_bytes == 0 {
                 usize::MAX
@@ -453,7 +813,7 @@ RETURNS:
 
             let range = self
                 .job_manager
-                .get_output_range(&input.job_id, input.offset_bytes, limit_bytes)
+                .get_output_range(&input.job_id, input.stream, input.offset_bytes, limit_bytes)
                 .ok_or_else(|| {
                     McpError::invalid_params("Job not found", None::<serde_json::Value>)
                 })?;
@@ -472,10 +832,10 @@ RETURNS:
             // Get incremental output
             let (new_output, is_running) = self
                 .job_manager
-                .get_incremental_output(&input.job_id)
+                .get_incremental_output(&input.job_id, input.stream)
                 .ok_or_else(|| {
-                McpError::invalid_params("Job not found", None::<serde_json::Value>)
-            })?;
+                    McpError::invalid_params("Job not found", None::<serde_json::Value>)
+                })?;
 
             if new_output.is_empty() && !is_running {
                 return Ok(CallToolResult::success(vec![Content::text(format!(
@@ -490,7 +850,19 @@ RETURNS:
             let job = self.job_manager.get_job(&input.job_id).ok_or_else(|| {
                 McpError::invalid_params("Job not found", None::<serde_json::Value>)
             })?;
-            (job.output.clone(), None, None)
+            // Combined output is cut at the job's output_limit, read from the spool
+            let limit = match input.stream {
+                OutputStream::Combined => job.output_limit,
+                OutputStream::Stdout | OutputStream::Stderr | OutputStream::Interleaved => {
+                    usize::MAX
+                }
+            };
+            let output = self
+                .job_manager
+                .get_output_range(&input.job_id, input.stream, 0, limit)
+                .map(|range| range.output)
+                .unwrap_or_default();
+            (output, None, None)
         };
 
         // Always get current job info for metadata
@@ -506,6 +878,10 @@ RETURNS:
         }
         result_text.push_str(&format!("Shell: {}\n", job.shell));
         result_text.push_str(&format!("Working Directory: {}\n", job.cwd));
+        if let Some(sandbox) = &job.sandbox {
+            result_text.push_str(&format!("Sandbox: {}\n", sandbox));
+        }
+        push_write_restriction(&mut result_text, job.write_restriction.as_ref());
         result_text.push_str(&format!("Status: {:?}\n", job.status));
 
         if !job.tags.is_empty() {
@@ -523,6 +899,38 @@ RETURNS:
             result_text.push_str(&format!("PID: {}\n", pid));
         }
 
+        if let Some(termination) = &job.termination {
+            result_text.push_str(&format!("Termination: {}\n", termination));
+        }
+
+        if let Some(stage) = job.termination_stage {
+            result_text.push_str(&format!("Terminated By: {} (process group)\n", stage));
+        }
+
+        if !job.resource_limits.is_empty() {
+            result_text.push_str(&format!("Resource Limits: {}\n", job.resource_limits));
+        }
+
+        if let Some(hit) = &job.limit_exceeded {
+            result_text.push_str(&format!("Limit Exceeded: {}\n", hit));
+        }
+
+        if let Some((usage, live)) = job_resource_usage(&job) {
+            let label = if live {
+                "Resource Usage (live, process group)"
+            } else {
+                "Resource Usage"
+            };
+            result_text.push_str(&format!("{}: {}\n", label, usage));
+        }
+
+        push_cgroup_info(&mut result_text, job.cgroup.as_ref(), true);
+
+        if job.redactions > 0 {
+            result_text.push_str(&format!("Redactions: {}\n", job.redactions));
+        }
+        push_encoding(&mut result_text, job.encoding, job.invalid_utf8);
+
         let token_preview = if input.preview_tokens > 0 {
             let preview = preview_output(&output_to_show, input.preview_tokens);
             output_to_show = preview.text.clone();
@@ -531,7 +939,36 @@ RETURNS:
             None
         };
 
-        if use_pagination {
+        if let Some(range) = &line_range {
+            match line_selection {
+                Some(LineSelection::Tail(count)) => {
+                    result_text.push_str(&format!("Output Mode: Tail (last {} lines)\n", count));
+                }
+                _ => result_text.push_str(&format!(
+                    "Output Mode: Lines (start_line: {}, line_count: {})\n",
+                    input.start_line.unwrap_or(1),
+                    input
+                        .line_count
+                        .map(|count| count.to_string())
+                        .unwrap_or_else(|| "all".to_string())
+                )),
+            }
+            result_text.push_str(&format!("Total Lines: {}\n", range.total_lines));
+            if range.line_count > 0 {
+                result_text.push_str(&format!(
+                    "Returned Lines: {}-{} (bytes {}..{})\n",
+                    range.first_line,
+                    range.first_line + range.line_count - 1,
+                    range.start_byte,
+                    range.end_byte
+                ));
+            } else {
+                result_text.push_str("Returned Lines: none\n");
+            }
+            if let Some(next) = range.next_start_line {
+                result_text.push_str(&format!("Next Start Line: {}\n", next));
+            }
+        } else if use_pagination {
             result_text.push_str(&format!(
                 "Output Mode: Paginated (offset_bytes: {}, limit_bytes: {})\n",
                 input.offset_bytes,
@@ -559,11 +996,9 @@ RETURNS:
                 result_text.push_str(&format!("Next Offset Bytes: {}\n", next));
             }
         } else if input.incremental {
-            result_text.push_str(&format!(
-                "Output Mode: Incremental (new since last check)\n"
-            ));
+            result_text.push_str("Output Mode: Incremental (new since last check)\n");
         } else {
-            result_text.push_str(&format!("Output Mode: Full\n"));
+            result_text.push_str("Output Mode: Full\n");
         }
 
         if let Some(ref preview) = token_preview {
@@ -578,22 +1013,144 @@ RETURNS:
             }
         }
 
+        if job.separate_streams {
+            result_text.push_str(&format!(
+                "Stream: {}\n",
+                serde_json::to_value(input.stream)
+                    .ok()
+                    .and_then(|v| v.as_str().map(str::to_string))
+                    .unwrap_or_default()
+            ));
+        }
+
         result_text.push_str("\nOutput:\n");
         result_text.push_str(&output_to_show);
 
-        if job.truncated && !input.incremental && !use_pagination {
+        if job.truncated()
+            && input.stream == OutputStream::Combined
+            && !input.incremental
+            && !use_pagination
+            && line_range.is_none()
+        {
             result_text.push_str("\n\n[Output truncated - showing first part only]");
         }
 
-        if use_pagination && has_more == Some(true) {
-            if let Some(next) = next_offset_bytes {
-                result_text.push_str(&format!(
-                    "\n\n[More output available. Next offset_bytes: {}]",
-                    next
-                ));
+        if use_pagination
+            && has_more == Some(true)
+            && let Some(next) = next_offset_bytes
+        {
+            result_text.push_str(&format!(
+                "\n\n[More output available. Next offset_bytes: {}]",
+                next
+            ));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(result_text)]))
+    }
+
+    #[tool(
+        name = "enhanced_terminal_job_search",
+        description = "Search a background job's full output with a regular expression, like grep -n -b.
+
+PARAMETERS:
+- job_id (string, required): The readable adjective-noun-number job identifier
+- pattern (string, required): Regular expression matched against each line, e.g. 'error\\[E\\d+' or 'FAILED|panicked'
+- case_insensitive (boolean, default: false): Ignore case
+- invert (boolean, default: false): Report the lines that do NOT match
+- before_context (number, default: 0): Lines of context before each match
+- after_context (number, default: 0): Lines of context after each match
+- max_matches (number, default: 50): Maximum matches returned per call
+- cursor (string, optional): 'Next Cursor' of the previous call, to get the following matches
+- stream (string, default: 'combined'): For jobs run with capture_mode='pipes', search 'stdout' or 'stderr' alone
+
+BEHAVIOR:
+- Searches everything the job printed so far, including output spooled to disk
+- Streams the output line by line instead of loading it, so huge logs are cheap to search
+- Matches whose context lines touch are shown as one group; groups are separated by '--'
+- Use this instead of re-reading a whole log to find errors or test failures
+
+RETURNS:
+- Matches found and whether more remain
+- Each line as LINE:BYTE:TEXT for matches and LINE-BYTE-TEXT for context lines; LINE is 1-based and BYTE is the offset of the line, usable as offset_bytes for enhanced_terminal_job_status
+- Next Cursor when there are more matches"
+    )]
+    async fn job_search(
+        &self,
+        Parameters(input): Parameters<JobSearchInput>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_stream(&input.job_id, input.stream)?;
+        let options = SearchOptions::new(
+            &input.pattern,
+            input.case_insensitive,
+            input.invert,
+            input.before_context,
+            input.after_context,
+            input.max_matches,
+        )
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+        let cursor = input
+            .cursor
+            .as_deref()
+            .map(|cursor| cursor.parse::<SearchCursor>())
+            .transpose()
+            .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?
+            .unwrap_or_default();
+
+        // Reading a large spool file is blocking I/O
+        let job_manager = self.job_manager.clone();
+        let job_id = input.job_id.clone();
+        let page = tokio::task::spawn_blocking(move || {
+            job_manager.search_output(&job_id, input.stream, cursor, &options)
+        })
+        .await
+        .map_err(|e| McpError::internal_error(format!("Search task failed: {}", e), None))?
+        .map_err(|e| McpError::invalid_params(format!("Failed to search output: {:#}", e), None))?;
+
+        let mut result_text = format!("Job ID: {}\n", input.job_id);
+        result_text.push_str(&format!("Pattern: {}", input.pattern));
+        if input.case_insensitive {
+            result_text.push_str(" (case-insensitive)");
+        }
+        if input.invert {
+            result_text.push_str(" (inverted)");
+        }
+        result_text.push('\n');
+        result_text.push_str(&format!(
+            "Searched Byte Range: {}..{}\n",
+            cursor.byte_offset, page.end_byte
+        ));
+        let more = if page.next_cursor.is_some() {
+            ", more available"
+        } else {
+            ""
+        };
+        result_text.push_str(&format!("Matches: {}{}\n", page.matches, more));
+
+        if page.groups.is_empty() {
+            result_text.push_str("\nNo matching lines.\n");
+        } else {
+            result_text.push_str("\nLines (LINE:BYTE:TEXT match, LINE-BYTE-TEXT context):\n");
+            for (index, group) in page.groups.iter().enumerate() {
+                if index > 0 {
+                    result_text.push_str("--\n");
+                }
+                for line in group {
+                    let separator = if line.is_match { ':' } else { '-' };
+                    result_text.push_str(&format!(
+                        "{}{}{}{}{}\n",
+                        line.line_number, separator, line.byte_offset, separator, line.text
+                    ));
+                }
             }
         }
 
+        if let Some(next) = page.next_cursor {
+            result_text.push_str(&format!(
+                "\nNext Cursor: {} (pass it as cursor for the following matches)\n",
+                next
+            ));
+        }
+
         Ok(CallToolResult::success(vec![Content::text(result_text)]))
     }
 
@@ -602,11 +1159,13 @@ RETURNS:
         description = "List all background jobs with status and output previews.
 
 PARAMETERS:
-- max_jobs (number, default: 50): Maximum number of jobs to return
+- max_jobs (number, default: 50): Maximum number of jobs to return per page (at least 1)
 - status_filter (array, optional): Filter by status (e.g., [\"Running\", \"Completed\"])
 - tag_filter (string, optional): Filter by tag (e.g., \"build\")
 - cwd_filter (string, optional): Filter by working directory
 - sort_order (string, default: \"newest\"): Sort order (\"newest\" or \"oldest\")
+- include_usage (boolean, default: false): Add each job's resource usage (CPU time, max RSS, page faults, context switches)
+- cursor (string, optional): 'Next Cursor' of the previous page, to list the jobs after it with the same filters and sort order
 
 BEHAVIOR:
 - Jobs sorted by start time (newest first by default)
@@ -616,7 +1175,7 @@ BEHAVIOR:
 
 FILTERING:
 - status_filter: Match any of the provided statuses
-  - Valid values: \"Running\", \"Completed\", \"Failed\", \"TimedOut\", \"Canceled\"
+  - Valid values: \"Running\", \"Paused\", \"Completed\", \"Failed\", \"TimedOut\", \"Canceled\"
 - tag_filter: Show only jobs with the specified tag
 - cwd_filter: Show only jobs from a specific directory
 - Filters are combined with AND logic
@@ -625,26 +1184,27 @@ RETURNS: List of jobs with:
 - job_id: Unique readable adjective-noun-number identifier
 - command: Full executed command
 - summary: First 100 characters of command
-- status: Current status (Running, Completed, Failed, TimedOut, Canceled)
+- status: Current status (Running, Paused, Completed, Failed, TimedOut, Canceled)
 - exit_code: Exit code if completed
+- termination: Signal or well-known exit code explanation, when the job did not exit cleanly
 - duration: Time elapsed since start
 - tags: Optional tags assigned to this job
 - cwd: Working directory
 - shell: Shell used
+- resource_usage: Only with include_usage=true
 - output_preview: First 100 characters of output"
     )]
     async fn job_list(
         &self,
         Parameters(input): Parameters<JobListInput>,
     ) -> Result<CallToolResult, McpError> {
-        use crate::tools::JobStatus;
-
         // Parse status filter if provided
         let status_filter: Option<Vec<JobStatus>> = input.status_filter.as_ref().map(|filters| {
             filters
                 .iter()
                 .filter_map(|s| match s.as_str() {
                     "Running" => Some(JobStatus::Running),
+                    "Paused" => Some(JobStatus::Paused),
                     "Completed" => Some(JobStatus::Completed),
                     "Failed" => Some(JobStatus::Failed),
                     "TimedOut" => Some(JobStatus::TimedOut),
@@ -654,40 +1214,69 @@ RETURNS: List of jobs with:
                 .collect()
         });
 
-        // Get filtered jobs
-        let mut jobs = if input.status_filter.is_some()
-            || input.tag_filter.is_some()
-            || input.cwd_filter.is_some()
-        {
-            self.job_manager.list_jobs_filtered(
-                status_filter.as_deref(),
-                input.tag_filter.as_deref(),
-                input.cwd_filter.as_deref(),
-            )
-        } else {
-            self.job_manager.list_jobs()
-        };
+        if input.max_jobs == 0 {
+            return Err(McpError::invalid_params(
+                "max_jobs must be at least 1",
+                None::<serde_json::Value>,
+            ));
+        }
 
-        // Apply sort order
-        if input.sort_order == "oldest" {
-            jobs.reverse();
+        let oldest_first = input.sort_order == "oldest";
+        let cursor = input
+            .cursor
+            .as_deref()
+            .map(|cursor| cursor.parse::<JobCursor>())
+            .transpose()
+            .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+        if let Some(cursor) = &cursor
+            && cursor.oldest_first() != oldest_first
+        {
+            return Err(McpError::invalid_params(
+                format!(
+                    "cursor was made for sort_order \"{}\"",
+                    if cursor.oldest_first() {
+                        "oldest"
+                    } else {
+                        "newest"
+                    }
+                ),
+                None,
+            ));
         }
 
-        let jobs_to_show = jobs.into_iter().take(input.max_jobs).collect::<Vec<_>>();
+        let page = self.job_manager.job_summaries(&JobQuery {
+            statuses: status_filter.as_deref(),
+            tag: input.tag_filter.as_deref(),
+            cwd: input.cwd_filter.as_deref(),
+            oldest_first,
+            cursor: cursor.as_ref(),
+            limit: input.max_jobs,
+        });
 
-        if jobs_to_show.is_empty() {
-            return Ok(CallToolResult::success(vec![Content::text(
-                "No jobs found.".to_string(),
-            )]));
+        if page.jobs.is_empty() {
+            let text = if page.total > 0 {
+                format!("No more jobs ({} in total).", page.total)
+            } else {
+                "No jobs found.".to_string()
+            };
+            return Ok(CallToolResult::success(vec![Content::text(text)]));
         }
 
-        let mut result_text = format!("Found {} job(s):\n\n", jobs_to_show.len());
+        let mut result_text = format!(
+            "Found {} job(s), showing {}-{}:\n\n",
+            page.total,
+            page.skipped + 1,
+            page.skipped + page.jobs.len()
+        );
 
-        for job in jobs_to_show {
+        for job in &page.jobs {
             result_text.push_str(&format!("Job ID: {}\n", job.job_id));
             result_text.push_str(&format!("  Summary: {}\n", job.summary));
             result_text.push_str(&format!("  Status: {:?}\n", job.status));
             result_text.push_str(&format!("  CWD: {}\n", job.cwd));
+            if let Some(sandbox) = &job.sandbox {
+                result_text.push_str(&format!("  Sandbox: {}\n", sandbox));
+            }
             result_text.push_str(&format!("  Shell: {}\n", job.shell));
 
             if !job.tags.is_empty() {
@@ -698,17 +1287,41 @@ RETURNS: List of jobs with:
                 result_text.push_str(&format!("  Exit Code: {}\n", exit_code));
             }
 
+            if let Some(termination) = &job.termination {
+                result_text.push_str(&format!("  Termination: {}\n", termination));
+            }
+
+            if let Some(stage) = job.termination_stage {
+                result_text.push_str(&format!("  Terminated By: {}\n", stage));
+            }
+
+            if let Some(hit) = &job.limit_exceeded {
+                result_text.push_str(&format!("  Limit Exceeded: {}\n", hit.limit.rlimit_name()));
+            }
+
+            if input.include_usage
+                && let Some((usage, _)) =
+                    resource_usage_of(job.resource_usage, &job.status, job.pid)
+            {
+                result_text.push_str(&format!("  Resource Usage: {}\n", usage));
+            }
+
             // Use the duration helper method
             result_text.push_str(&format!("  Duration: {}\n", job.duration_string()));
 
-            // Show output preview (first 100 chars)
-            let preview = if job.output.len() > 100 {
-                format!("{}...", &job.output[..100])
-            } else {
-                job.output.clone()
-            };
-            result_text.push_str(&format!("  Output Preview: {}\n", preview.trim()));
-            result_text.push_str("\n");
+            result_text.push_str(&format!(
+                "  Output Preview: {}\n",
+                job.output_preview.trim()
+            ));
+            result_text.push('\n');
+        }
+
+        if let Some(next) = page.next_cursor {
+            result_text.push_str(&format!(
+                "Next Cursor: {} ({} more job(s); pass it as cursor)\n",
+                next,
+                page.total - page.skipped - page.jobs.len()
+            ));
         }
 
         Ok(CallToolResult::success(vec![Content::text(result_text)]))
@@ -716,20 +1329,20 @@ RETURNS: List of jobs with:
 
     #[tool(
         name = "enhanced_terminal_job_cancel",
-        description = "Cancel a running background job by sending SIGTERM (Unix only).
+        description = "Cancel a running background job and every process it started.
 
 PARAMETERS:
 - job_id (string, required): The readable adjective-noun-number job identifier to cancel
 
 BEHAVIOR:
-- Sends SIGTERM signal to the process (Unix only)
-- Graceful termination attempt
-- Updates job status to Canceled
-- Works with process groups
+- Updates job status to Canceled immediately
+- Signals the job's whole process group, so grandchildren (rustc under cargo, node under npm) stop too
+- Escalates SIGINT, then SIGTERM, then SIGKILL, waiting ENHANCED_TERMINAL_KILL_GRACE_SECS (default: 5) between stages
+- The stage that ended the job is reported as 'Terminated By' in job_status
 
 PLATFORM SUPPORT:
-- Unix/Linux/macOS: Full support with SIGTERM
-- Windows: Limited support (status update only, no signal)
+- Unix/Linux/macOS: Full process-group escalation
+- Windows: The shell process is killed directly
 
 RETURNS:
 - Confirmation message with job_id
@@ -751,6 +1364,154 @@ RETURNS:
         Ok(CallToolResult::success(vec![Content::text(result_text)]))
     }
 
+    #[tool(
+        name = "enhanced_terminal_sudo_status",
+        description = "Show the server's sudo credential cache and keepalive state.
+
+BEHAVIOR:
+- Runs `sudo -n -v` in the server process to see whether credentials are cached (this refreshes a cached timestamp, like any sudo command)
+- Never prompts
+
+RETURNS:
+- Cached Credentials: yes, no, or why sudo could not be run
+- Keepalive: not started, running (interval, refresh count, last refresh) or stopped
+- The ENHANCED_TERMINAL_SUDO_WRAP, _KEEPALIVE and _KEEPALIVE_PRIME settings
+- Sudo Allowlist: the policy file's sudo_allowlist, if any"
+    )]
+    async fn sudo_status(&self) -> Result<CallToolResult, McpError> {
+        let status = sudo::status().await;
+        Ok(CallToolResult::success(vec![Content::text(
+            status.to_string(),
+        )]))
+    }
+
+    #[tool(
+        name = "enhanced_terminal_sudo_revoke",
+        description = "Revoke the server's cached sudo credentials with `sudo -k`.
+
+PARAMETERS:
+- stop_keepalive (boolean, default: false): Also stop the keepalive task for the rest of the session
+
+BEHAVIOR:
+- The next sudo command primes credentials again (askpass) if priming is enabled, or fails with sudo -n
+
+RETURNS:
+- Confirmation, and the keepalive state"
+    )]
+    async fn sudo_revoke(
+        &self,
+        Parameters(input): Parameters<SudoRevokeInput>,
+    ) -> Result<CallToolResult, McpError> {
+        audit_log::record_call("enhanced_terminal_sudo_revoke", &input).map_err(|e| {
+            McpError::internal_error(format!("Failed to log sudo call: {}", e), None)
+        })?;
+        sudo::revoke()
+            .await
+            .map_err(|e| McpError::internal_error(format!("{:#}", e), None))?;
+        let mut result_text = "Sudo credentials revoked (sudo -k).\n".to_string();
+        if input.stop_keepalive {
+            result_text.push_str(if sudo::stop_keepalive() {
+                "Keepalive stopped.\n"
+            } else {
+                "Keepalive was not running; it will not start again.\n"
+            });
+        }
+        Ok(CallToolResult::success(vec![Content::text(result_text)]))
+    }
+
+    #[tool(
+        name = "enhanced_terminal_sudo_keepalive_stop",
+        description = "Stop the sudo keepalive task for the rest of the server session.
+
+BEHAVIOR:
+- Cached credentials stay valid until sudo's own timeout; use enhanced_terminal_sudo_revoke to drop them now
+- Later sudo commands do not restart the keepalive
+
+RETURNS:
+- Whether the keepalive was running"
+    )]
+    async fn sudo_keepalive_stop(&self) -> Result<CallToolResult, McpError> {
+        audit_log::record_call(
+            "enhanced_terminal_sudo_keepalive_stop",
+            &serde_json::json!({}),
+        )
+        .map_err(|e| McpError::internal_error(format!("Failed to log sudo call: {}", e), None))?;
+        let result_text = if sudo::stop_keepalive() {
+            "Sudo keepalive stopped."
+        } else {
+            "Sudo keepalive was not running; it will not start again."
+        };
+        Ok(CallToolResult::success(vec![Content::text(result_text)]))
+    }
+
+    #[tool(
+        name = "enhanced_terminal_job_signal",
+        description = "Send an arbitrary signal to a running or paused background job (Unix only).
+
+PARAMETERS:
+- job_id (string, required): The readable adjective-noun-number job identifier
+- signal (string, required): Signal name or number - 'SIGINT', 'INT', 'hup', 'STOP', 'CONT', '15', ...
+- process_group (boolean, default: true): Signal every process in the job's process group; false signals only the shell (inside the PID namespace for sandboxed jobs)
+
+BEHAVIOR:
+- SIGINT gives Ctrl-C semantics, so many tools print partial results before exiting
+- SIGHUP asks dev servers and daemons to reload
+- SIGSTOP/SIGTSTP pause the job (status Paused); SIGCONT resumes it (status Running)
+- The job's status changes to Completed/Failed once the signal makes it exit
+- Use enhanced_terminal_job_cancel to stop a job with SIGINT → SIGTERM → SIGKILL escalation
+
+RETURNS:
+- Confirmation with the signal sent and the job's status afterwards"
+    )]
+    async fn job_signal(
+        &self,
+        Parameters(input): Parameters<JobSignalInput>,
+    ) -> Result<CallToolResult, McpError> {
+        #[cfg(unix)]
+        {
+            let signal = crate::tools::process_group::parse_signal(&input.signal)
+                .map_err(|e| McpError::invalid_params(e.to_string(), None::<serde_json::Value>))?;
+
+            self.job_manager
+                .signal_job(&input.job_id, signal, input.process_group)
+                .map_err(|e| {
+                    McpError::invalid_params(
+                        format!("Failed to signal job: {}", e),
+                        None::<serde_json::Value>,
+                    )
+                })?;
+
+            let status = self
+                .job_manager
+                .get_job(&input.job_id)
+                .map(|job| format!("{:?}", job.status))
+                .unwrap_or_else(|| "unknown".to_string());
+            let target = if input.process_group {
+                "process group of job"
+            } else {
+                "job"
+            };
+            let result_text = format!(
+                "Sent {} to {} {}.\nStatus: {}\n",
+                signal.as_str(),
+                target,
+                input.job_id,
+                status
+            );
+
+            Ok(CallToolResult::success(vec![Content::text(result_text)]))
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = input;
+            Err(McpError::invalid_params(
+                "Sending signals is only supported on Unix",
+                None::<serde_json::Value>,
+            ))
+        }
+    }
+
     #[tool(
         name = "enhanced_terminal_job_stdin",
         description = "Write input to a running background job's PTY stdin.
@@ -790,6 +1551,202 @@ RETURNS:
         Ok(CallToolResult::success(vec![Content::text(result_text)]))
     }
 
+    #[tool(
+        name = "enhanced_terminal_session_open",
+        description = "Open a persistent shell session that keeps cwd, env and shell state across commands.
+
+PARAMETERS:
+- cwd (string, default: '.'): Initial working directory; '.' resolves to the MCP server process working directory
+- shell (string, default: 'bash'): Shell to run for the lifetime of the session (see enhanced_terminal for available shells)
+- env_vars (object, default: {}): Environment variables to set when the shell starts
+- sandbox (string, optional): Linux namespace sandbox profile for the shell (see enhanced_terminal). The server default applies when omitted; 'none' opts out only when no default is configured
+
+BEHAVIOR:
+- Starts one long-lived shell in a PTY without rc files, prompts or echo
+- The shell and every command run in it stay in the session's sandbox
+- When the server restricts writes for every command (ENHANCED_TERMINAL_LANDLOCK=on), the shell can only write beneath the cwd it was opened in and $TMPDIR; without kernel support the session is refused unless the server fails open
+- cd, export, source (e.g. .venv/bin/activate), aliases and shell functions persist between calls
+- Use enhanced_terminal_session_exec to run commands and enhanced_terminal_session_close when done
+
+RETURNS:
+- session_id: Identifier to pass to enhanced_terminal_session_exec/close
+- shell, pid, cwd, sandbox profile, write restrictions and the number of environment variables in the session"
+    )]
+    async fn session_open(
+        &self,
+        Parameters(input): Parameters<SessionOpenInput>,
+    ) -> Result<CallToolResult, McpError> {
+        self.validate_shell(&input.shell)?;
+
+        let info = self
+            .session_manager
+            .open_session(&input)
+            .await
+            .map_err(|e| {
+                McpError::internal_error(format!("Failed to open session: {}", e), None)
+            })?;
+
+        let mut result_text = format!("Session ID: {}\n", info.session_id);
+        result_text.push_str(&format!("Shell: {}\n", info.shell));
+        if let Some(pid) = info.pid {
+            result_text.push_str(&format!("PID: {}\n", pid));
+        }
+        result_text.push_str(&format!("Working Directory: {}\n", info.cwd));
+        if let Some(sandbox) = &info.sandbox {
+            result_text.push_str(&format!("Sandbox: {}\n", sandbox));
+        }
+        push_write_restriction(&mut result_text, info.write_restriction.as_ref());
+        result_text.push_str(&format!("Environment Variables: {}\n", info.env.len()));
+
+        Ok(CallToolResult::success(vec![Content::text(result_text)]))
+    }
+
+    #[tool(
+        name = "enhanced_terminal_session_exec",
+        description = "Run a command inside a persistent shell session.
+
+PARAMETERS:
+- session_id (string, required): Session ID returned by enhanced_terminal_session_open
+- command (string, required): Command to run in the session shell
+- timeout_secs (number, default: 120): Interrupt the command with Ctrl-C after this many seconds
+- preview_tokens (number, default: 4096): Maximum GPT-5/o200k_base tokens of output to return; set 0 to disable token truncation
+- full_env (boolean, default: false): Return the full session environment instead of only what changed
+- custom_denylist (array, default: []): Additional dangerous patterns to block
+
+BEHAVIOR:
+- Commands run in the session's own shell, so state changes persist for later calls
+- Exit code and output boundaries are delimited with per-command sentinel markers
+- stdin is detached (/dev/null); use enhanced_terminal with force_async for interactive programs
+- The same security denylist and policy file as enhanced_terminal are applied
+- Untagged rewrite rules from the policy file change the command before it is checked; applied rules are listed under Rewrites
+- Running `exit` ends the session
+
+RETURNS:
+- exit_code, output, duration
+- cwd: Working directory of the session after the command
+- env changes since the previous command (or the full environment with full_env=true)"
+    )]
+    async fn session_exec(
+        &self,
+        Parameters(input): Parameters<SessionExecInput>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        audit_log::record_call("enhanced_terminal_session_exec", &input).map_err(|e| {
+            McpError::internal_error(format!("Failed to log session call: {}", e), None)
+        })?;
+
+        let result = self
+            .session_manager
+            .exec(&input, Some(&peer))
+            .await
+            .map_err(|e| {
+                McpError::invalid_params(
+                    format!("Session command failed: {}", e),
+                    None::<serde_json::Value>,
+                )
+            })?;
+
+        if result.denied {
+            let reason = result
+                .denial_reason
+                .unwrap_or_else(|| "Security policy violation".to_string());
+            if let Err(e) =
+                audit_log::record_denied("enhanced_terminal_session_exec", &result.command, &reason)
+            {
+                tracing::warn!("Failed to write the audit log: {}", e);
+            }
+            let mut result_text = format!("Command: {}\n", result.command);
+            result_text.push_str(&format!("Status: DENIED\n\nReason: {}\n", reason));
+            push_rewrites(&mut result_text, &result.rewrites);
+            push_indirect_execution(&mut result_text, &result.indirect_execution);
+            return Ok(CallToolResult::success(vec![Content::text(result_text)]));
+        }
+
+        let mut result_text = format!("Session ID: {}\n", result.session_id);
+        result_text.push_str(&format!("Command: {}\n", result.command));
+        push_rewrites(&mut result_text, &result.rewrites);
+        if let Some(approval) = &result.approval {
+            result_text.push_str(&format!("Approval: {}\n", approval));
+        }
+        push_indirect_execution(&mut result_text, &result.indirect_execution);
+        if result.redactions > 0 {
+            result_text.push_str(&format!("Redactions: {}\n", result.redactions));
+        }
+        result_text.push_str(&format!("Duration: {:.2}s\n", result.duration_secs));
+        match result.exit_code {
+            Some(exit_code) => result_text.push_str(&format!("Exit Code: {}\n", exit_code)),
+            None => result_text.push_str("Exit Code: null\n"),
+        }
+        if result.session_closed {
+            result_text.push_str("Status: SESSION CLOSED (shell exited)\n");
+        } else if result.timed_out {
+            result_text.push_str("Status: TIMED OUT ⏱️ (interrupted with Ctrl-C)\n");
+        } else if result.exit_code == Some(0) {
+            result_text.push_str("Status: COMPLETED ✅\n");
+        } else {
+            result_text.push_str("Status: FAILED ❌\n");
+        }
+        result_text.push_str(&format!("Working Directory: {}\n", result.cwd));
+
+        if input.full_env {
+            result_text.push_str("Environment:\n");
+            for (key, value) in &result.env {
+                result_text.push_str(&format!("  {}={}\n", key, value));
+            }
+        } else if result.env_changes.is_empty() {
+            result_text.push_str("Environment Changes: none\n");
+        } else {
+            result_text.push_str("Environment Changes:\n");
+            for change in &result.env_changes {
+                match change {
+                    EnvChange::Set { key, value } => {
+                        result_text.push_str(&format!("  +{}={}\n", key, value))
+                    }
+                    EnvChange::Removed { key } => result_text.push_str(&format!("  -{}\n", key)),
+                }
+            }
+        }
+
+        let preview = preview_output(&result.output, input.preview_tokens);
+        result_text.push_str("\nOutput:\n");
+        result_text.push_str(&preview.text);
+        if preview.truncated {
+            result_text.push_str("\n\n[Output truncated due to preview token limit]");
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(result_text)]))
+    }
+
+    #[tool(
+        name = "enhanced_terminal_session_close",
+        description = "Close a persistent shell session and terminate its shell.
+
+PARAMETERS:
+- session_id (string, required): Session ID to close
+
+RETURNS:
+- Confirmation message"
+    )]
+    async fn session_close(
+        &self,
+        Parameters(input): Parameters<SessionCloseInput>,
+    ) -> Result<CallToolResult, McpError> {
+        self.session_manager
+            .close_session(&input.session_id)
+            .await
+            .map_err(|e| {
+                McpError::invalid_params(
+                    format!("Failed to close session: {}", e),
+                    None::<serde_json::Value>,
+                )
+            })?;
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Session {} has been closed.",
+            input.session_id
+        ))]))
+    }
+
     #[tool(
         name = "detect_binaries",
         description = "Detect developer tools and their versions with fast parallel scanning.
@@ -877,7 +1834,8 @@ impl rmcp::ServerHandler for EnhancedTerminalServer {
             • Security: 40+ dangerous patterns blocked by default\n\
             • Performance: 16 concurrent binary detection checks\n\
             • Environment: Full env var support, PTY terminal emulation\n\
-            • Audit trail: enhanced_terminal calls are appended to enhanced_terminal_calls.jsonl\n\
+            • Audit trail: calls, job outcomes and denials are appended to the hash-chained enhanced_terminal_calls.jsonl\n\
+            • Secret redaction: AWS keys, GitHub tokens, private keys, Authorization headers and secret env values are replaced with [REDACTED:kind] in output, stream events and the call log\n\
             \n\
             TOOLS:\n\
             \n\
@@ -887,17 +1845,24 @@ impl rmcp::ServerHandler for EnhancedTerminalServer {
                • Smart async: Auto-background after 50s (ENHANCED_TERMINAL_ASYNC_THRESHOLD_SECS env var)\n\
                • Force async: Set force_async=true to immediately get a job_id for stdin-capable interactive jobs\n\
                • No timeout by default (ENHANCED_TERMINAL_TIMEOUT_SECS env var)\n\
+               • Timeouts and cancels stop the whole process group: SIGINT, then SIGTERM, then SIGKILL\n\
                • Environment variables: Set via env_vars parameter\n\
                • Security: Denylist blocks rm -rf /, shutdown, fork bombs, etc.\n\
                • Output: token-bounded previews, captured incrementally\n\
+               • capture_mode='pipes': separate stdout/stderr instead of a PTY\n\
+               • resource_limits: per-job rlimits (CPU, memory, file size, processes, open files)\n\
+               • cgroup_limits: memory.max, cpu.max, pids.max in a per-job cgroup v2 group (when delegated)\n\
+               • restrict_writes: Landlock limits writes to cwd, $TMPDIR and configured paths\n\
+               • sandbox: unprivileged Linux namespace profile (e.g. 'isolated': read-only root, writable cwd, no network)\n\
                • Returns: readable adjective-noun-number job_id for tracking background execution\n\
             \n\
             2. enhanced_terminal_job_status - Monitor background jobs\n\
-               • Get current status: Running, Completed, Failed, TimedOut, Canceled\n\
+               • Get current status: Running, Paused, Completed, Failed, TimedOut, Canceled\n\
                • Output modes: Incremental (default, new since last check) or Full (all output)\n\
                • Incremental mode is default and recommended for efficiency\n\
                • Set incremental=false to get all output from start\n\
-               • Returns: status, exit_code, duration, output, PID\n\
+               • stream: stdout, stderr or interleaved for pipes-mode jobs\n\
+               • Returns: status, exit_code, duration, output, PID, resource usage, cgroup stats\n\
             \n\
             3. enhanced_terminal_job_list - List all jobs\n\
                • Shows recent jobs (newest first)\n\
@@ -905,17 +1870,36 @@ impl rmcp::ServerHandler for EnhancedTerminalServer {
                • Quick overview with output previews\n\
                • Filter by status if needed\n\
             \n\
-            4. enhanced_terminal_job_cancel - Cancel running jobs\n\
-               • Sends SIGTERM (Unix/Linux/macOS only)\n\
-               • Graceful process termination\n\
+            4. enhanced_terminal_job_search - Search a job's output\n\
+               • Regex over the whole output, streamed from disk; context lines, case-insensitive, invert\n\
+               • Returns line numbers and byte offsets usable as offset_bytes in job_status\n\
+               • Prefer it over re-reading huge logs to find errors or failures\n\
+            \n\
+            5. enhanced_terminal_job_cancel / enhanced_terminal_job_signal - Stop or signal jobs\n\
+               • Cancel signals the whole process group: SIGINT, then SIGTERM, then SIGKILL\n\
                • Updates job status to Canceled\n\
+               • job_signal sends any signal (INT, HUP, STOP, CONT, ...) to the job's process group\n\
+               • STOP pauses a job (status Paused), CONT resumes it\n\
             \n\
-            5. enhanced_terminal_job_stdin - Send input to running jobs\n\
+            6. enhanced_terminal_job_stdin - Send input to running jobs\n\
                • Writes exact UTF-8 text to a job's PTY stdin\n\
                • Include \\n in input to submit a line; no newline is appended automatically\n\
                • Useful for prompts after a command switches to background\n\
             \n\
-            6. detect_binaries - Fast tool detection\n\
+            7. enhanced_terminal_session_open / _exec / _close - Persistent shell sessions\n\
+               • One long-lived shell per session keeps cwd, env, venvs and shell functions\n\
+               • Per-command exit codes and output via sentinel markers\n\
+               • Reports cwd and environment changes after every command\n\
+            \n\
+            8. enhanced_terminal_check_command - Dry-run the security checks\n\
+               • Decision (allow/deny/ask), matched rule, final command line and resolved programs\n\
+               • Runs nothing; use it to plan around the denylist and policy\n\
+            \n\
+            9. enhanced_terminal_sudo_status / _sudo_revoke / _sudo_keepalive_stop - Sudo credentials\n\
+               • Whether sudo credentials are cached in the server and the keepalive state\n\
+               • Revoke them with sudo -k, stop the keepalive for the session\n\
+            \n\
+            10. detect_binaries - Fast tool detection\n\
                • Scans 190+ developer tools across 26 categories\n\
                • 16 concurrent checks by default\n\
                • Filter by category for targeted detection\n\
@@ -930,7 +1914,7 @@ impl rmcp::ServerHandler for EnhancedTerminalServer {
             ENVIRONMENT VARIABLES:\n\
             Set environment variables via env_vars parameter:\n\
             {{\"PATH\": \"/custom/path\", \"DEBUG\": \"true\", \"NODE_ENV\": \"production\"}}\n\
-            enhanced_terminal call log path can be overridden with ENHANCED_TERMINAL_CALL_LOG_PATH.\n\
+            enhanced_terminal call log path can be overridden with ENHANCED_TERMINAL_CALL_LOG_PATH; rotation is set with ENHANCED_TERMINAL_CALL_LOG_MAX_BYTES, _MAX_AGE_SECS and _KEEP.\n\
             \n\
             SECURITY DENYLIST:\n\
             Blocks dangerous patterns (40+ default):\n\
@@ -940,6 +1924,8 @@ impl rmcp::ServerHandler for EnhancedTerminalServer {
             • Kernel: rmmod, insmod, modprobe\n\
             • Cron: crontab -r\n\
             • Custom patterns: Add via custom_denylist parameter\n\
+            • Server policy: ENHANCED_TERMINAL_POLICY_FILE adds allow/deny/ask rules; denials cite the file and rule id; ask rules prompt the user via elicitation; an allowlist restricts which programs may run\n\
+            • Indirect execution: eval of a variable, curl | sh and python -c 'os.system(..)' are flagged; base64 -d | sh is denied by default\n\
             \n\
             INCREMENTAL OUTPUT (DEFAULT):\n\
             enhanced_terminal_job_status uses incremental mode by default (recommended):\n\
@@ -0,0 +1,373 @@
+//! Allowlist-only mode.
+//!
+//! When the policy file has an `allowlist`, every program a command line would run
+//! must match one of its entries. That covers each command of a pipeline or list,
+//! the command behind wrappers such as `sudo`, `timeout` or `xargs` (the wrapper must
+//! be listed as well), the commands inside `$(..)`, backticks, `<(..)` and literal
+//! `sh -c` scripts, and function bodies. Program names only known at run time
+//! (`$(printf rm)`, `$CMD`) are never allowed.
+//!
+//! A `sudo_allowlist` uses the same entries for the commands run under `sudo`
+//! only; `sudo` without a command is limited to `-v`, `-k`, `-l` and friends.
+//!
+//! ```toml
+//! [[allowlist]]
+//! program = "git"
+//! subcommands = ["status", "diff", "log"]
+//! deny_args = ["--output", "--ext-diff"]
+//! ```
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::fmt;
+
+use super::denylist::{Rule, glob_matches};
+use super::shell_parser::{self, ParsedCommand, SimpleCommand, Word};
+
+/// One `[[allowlist]]` entry as written in the policy file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntrySpec {
+    /// Program basename, wildcards allowed
+    program: String,
+    /// Allowed values of the first operand, wildcards allowed; empty allows any
+    #[serde(default)]
+    subcommands: Vec<String>,
+    /// Flags and operands that must not appear, written like denylist rules
+    #[serde(default)]
+    deny_args: Vec<String>,
+}
+
+struct Entry {
+    program: String,
+    subcommands: Vec<String>,
+    deny_args: Vec<Rule>,
+}
+
+impl Entry {
+    /// Why this entry does not allow `argv`, or `None` when it does.
+    fn refuse(&self, argv: &[Word]) -> Option<String> {
+        let args = &argv[1..];
+        if !self.subcommands.is_empty() {
+            let subcommand = args
+                .iter()
+                .find(|arg| arg.dynamic || !arg.text.starts_with('-'));
+            if let Some(subcommand) = subcommand {
+                let text = subcommand.text.to_lowercase();
+                if subcommand.dynamic
+                    || !self
+                        .subcommands
+                        .iter()
+                        .any(|allowed| glob_matches(allowed, &text, false))
+                {
+                    return Some(format!(
+                        "`{} {}` is not an allowed subcommand",
+                        argv[0].text, subcommand.text
+                    ));
+                }
+            }
+        }
+        let invocation = SimpleCommand {
+            words: argv.to_vec(),
+            ..Default::default()
+        };
+        self.deny_args
+            .iter()
+            .find(|rule| rule.matches_command(&invocation))
+            .map(|rule| format!("`{}` is not allowed", rule.pattern))
+    }
+}
+
+/// The programs (and argument shapes) allowlist-only mode lets through.
+pub struct Allowlist {
+    entries: Vec<Entry>,
+}
+
+/// A sub-command the allowlist does not allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub reason: String,
+    /// Source text of the sub-command
+    pub command: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (in `{}`)", self.reason, self.command)
+    }
+}
+
+impl Allowlist {
+    pub fn new(specs: Vec<EntrySpec>) -> Result<Self> {
+        let mut entries = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let program = spec.program.trim().to_lowercase();
+            if program.is_empty() || program.contains(char::is_whitespace) {
+                bail!(
+                    "allowlist entry '{}': program must be a single name",
+                    spec.program
+                );
+            }
+            let mut deny_args = Vec::with_capacity(spec.deny_args.len());
+            for arg in &spec.deny_args {
+                match Rule::new(&format!("{} {}", program, arg)).filter(Rule::is_command_pattern) {
+                    Some(rule) => deny_args.push(rule),
+                    None => bail!(
+                        "allowlist entry '{}': deny_args entry '{}' must be plain arguments",
+                        program,
+                        arg
+                    ),
+                }
+            }
+            entries.push(Entry {
+                program,
+                subcommands: spec.subcommands.iter().map(|s| s.to_lowercase()).collect(),
+                deny_args,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check every program a parsed command line would run.
+    pub fn check(&self, parsed: &ParsedCommand) -> Result<(), Rejection> {
+        for command in &parsed.commands {
+            for argv in command.invocations() {
+                if let Some(reason) = self.refuse(argv) {
+                    return Err(Rejection {
+                        reason,
+                        command: command.text.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the commands a parsed command line runs under `sudo` (or `sudoedit`),
+    /// for the policy file's `sudo_allowlist`.
+    pub fn check_sudo(&self, parsed: &ParsedCommand) -> Result<(), Rejection> {
+        for command in &parsed.commands {
+            let invocations = command.invocations();
+            for (index, argv) in invocations.iter().enumerate() {
+                let reason = match shell_parser::basename(&argv[0].text).as_str() {
+                    "sudo" => match invocations.get(index + 1) {
+                        Some(wrapped) => self.refuse(wrapped),
+                        None => sudo_without_command(argv),
+                    },
+                    "sudoedit" => self.refuse(argv),
+                    _ => None,
+                };
+                if let Some(reason) = reason {
+                    return Err(Rejection {
+                        reason,
+                        command: command.text.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn refuse(&self, argv: &[Word]) -> Option<String> {
+        let program = &argv[0];
+        if program.dynamic || program.glob {
+            return Some(format!(
+                "the program `{}` is only known at run time",
+                program.text
+            ));
+        }
+        let name = shell_parser::basename(&program.text);
+        let mut reason = None;
+        for entry in &self.entries {
+            if !glob_matches(&entry.program, &name, false) {
+                continue;
+            }
+            match entry.refuse(argv) {
+                None => return None,
+                Some(refused) => {
+                    reason.get_or_insert(refused);
+                }
+            }
+        }
+        Some(reason.unwrap_or_else(|| format!("`{}` is not on the allowlist", name)))
+    }
+}
+
+/// `sudo` without a command may only validate, list or drop the timestamp;
+/// `-s`, `-i` and `-e` start a root shell or editor.
+fn sudo_without_command(argv: &[Word]) -> Option<String> {
+    const LONG: &[&str] = &[
+        "validate",
+        "reset-timestamp",
+        "remove-timestamp",
+        "list",
+        "non-interactive",
+        "askpass",
+        "version",
+        "help",
+    ];
+    let harmless = argv[1..]
+        .iter()
+        .all(|word| match word.text.strip_prefix("--") {
+            Some(long) => LONG.contains(&long),
+            None => word.text.strip_prefix('-').is_some_and(|short| {
+                !short.is_empty() && short.chars().all(|c| "vkKlnAVh".contains(c))
+            }),
+        });
+    let words: Vec<&str> = argv.iter().map(|word| word.text.as_str()).collect();
+    (!harmless).then(|| {
+        format!(
+            "`{}` runs no command the sudo allowlist allows",
+            words.join(" ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ci_allowlist() -> Allowlist {
+        let specs: Vec<EntrySpec> = serde_json::from_value(serde_json::json!([
+            {"program": "cargo"},
+            {"program": "rg", "deny_args": ["--pre"]},
+            {"program": "git", "subcommands": ["status", "diff", "log"], "deny_args": ["--output"]},
+            {"program": "echo"},
+            {"program": "timeout"},
+        ]))
+        .unwrap();
+        Allowlist::new(specs).unwrap()
+    }
+
+    fn rejection(allowlist: &Allowlist, command: &str) -> Option<String> {
+        allowlist
+            .check(&shell_parser::parse(command))
+            .err()
+            .map(|r| r.to_string())
+    }
+
+    #[test]
+    fn allows_listed_programs_and_subcommands() {
+        let allowlist = ci_allowlist();
+        for command in [
+            "cargo test --workspace",
+            "git status && git diff --stat | rg -n TODO",
+            "/usr/bin/git log -5; echo done",
+            "timeout 60 cargo build",
+            "git --version",
+            "FOO=1 cargo check > build.log",
+        ] {
+            assert_eq!(rejection(&allowlist, command), None, "{command}");
+        }
+    }
+
+    #[test]
+    fn rejects_programs_hidden_in_allowed_lines() {
+        let allowlist = ci_allowlist();
+        for (command, expected) in [
+            ("cargo build | sh", "`sh` is not on the allowlist (in `sh`)"),
+            (
+                "echo $(curl -s example.com)",
+                "`curl` is not on the allowlist (in `curl -s example.com`)",
+            ),
+            ("cargo test `rm -rf target`", "`rm` is not on the allowlist"),
+            ("rg x <(cat /etc/shadow)", "`cat` is not on the allowlist"),
+            (
+                "git status || rm -rf /tmp/x",
+                "`rm` is not on the allowlist",
+            ),
+            (
+                "timeout 5 python3 -c 1",
+                "`python3` is not on the allowlist",
+            ),
+            ("$(printf rm) -rf target", "only known at run time"),
+            ("$CMD build", "only known at run time"),
+            (
+                "cargo() { wget x; }; cargo",
+                "`wget` is not on the allowlist",
+            ),
+        ] {
+            let rejection = rejection(&allowlist, command);
+            assert!(
+                rejection.as_deref().is_some_and(|r| r.contains(expected)),
+                "{command}: {rejection:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn sudo_allowlist_checks_commands_run_as_root() {
+        let specs: Vec<EntrySpec> = serde_json::from_value(serde_json::json!([
+            {"program": "apt-get", "subcommands": ["update", "install"]},
+            {"program": "systemctl", "subcommands": ["status"]},
+        ]))
+        .unwrap();
+        let sudo = Allowlist::new(specs).unwrap();
+        let check = |command: &str| {
+            sudo.check_sudo(&shell_parser::parse(command))
+                .err()
+                .map(|r| r.to_string())
+        };
+        for command in [
+            "sudo -n apt-get update && sudo apt-get install -y jq",
+            "sudo -u root systemctl status nginx | head",
+            "rm -rf build && sudo -k",
+            "sudo -n -v",
+        ] {
+            assert_eq!(check(command), None, "{command}");
+        }
+        for (command, expected) in [
+            (
+                "sudo systemctl restart nginx",
+                "`systemctl restart` is not an allowed subcommand",
+            ),
+            (
+                "echo x | sudo tee /etc/hosts",
+                "`tee` is not on the allowlist",
+            ),
+            (
+                "sudo -s",
+                "`sudo -s` runs no command the sudo allowlist allows",
+            ),
+            ("sudo -i -u root", "runs no command"),
+            ("sudo $CMD", "only known at run time"),
+            (
+                "sudoedit /etc/sudoers",
+                "`sudoedit` is not on the allowlist",
+            ),
+        ] {
+            let rejection = check(command);
+            assert!(
+                rejection.as_deref().is_some_and(|r| r.contains(expected)),
+                "{command}: {rejection:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn applies_argument_constraints() {
+        let allowlist = ci_allowlist();
+        assert_eq!(
+            rejection(&allowlist, "git status; git push origin main").as_deref(),
+            Some("`git push` is not an allowed subcommand (in `git push origin main`)")
+        );
+        assert!(
+            rejection(&allowlist, "git $SUB")
+                .unwrap()
+                .contains("not an allowed subcommand")
+        );
+        assert_eq!(
+            rejection(&allowlist, "git diff --output=/etc/passwd").as_deref(),
+            Some("`git --output` is not allowed (in `git diff --output=/etc/passwd`)")
+        );
+        assert!(
+            rejection(&allowlist, "rg --pre ./run.sh x")
+                .unwrap()
+                .contains("`rg --pre` is not allowed")
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Human approval of policy `ask` decisions.
+//!
+//! When a policy rule with `action = "ask"` decides a command, the server sends the
+//! client an MCP elicitation request showing the command, its parsed sub-commands,
+//! the working directory and every sub-command an `ask` rule matched, and runs the
+//! command only if the user approves. The user can also remember an approval for
+//! the rest of the session (this server process). A remembered approval covers
+//! every sub-command the same rule of the same policy file matches; approvals
+//! given under a file's default or an indirect-execution finding have no pattern
+//! to stand for, so they only cover that exact sub-command. A command line is
+//! only let through without a prompt when each of its `ask` decisions was
+//! remembered. Clients that do not support elicitation get the policy's
+//! `ask_fallback` instead.
+
+use rmcp::model::{CreateElicitationRequestParam, ElicitationAction, ElicitationSchema};
+use rmcp::{Peer, RoleServer, ServiceError};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::policy::{self, Action, AskSettings, PolicyMatch, Verdict};
+use super::shell_parser;
+
+/// Approvals the user asked to remember for this session.
+static REMEMBERED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Resolve a verdict to run or not, asking the user about `ask` decisions.
+///
+/// Returns `Ok(None)` when the command is simply allowed, `Ok(Some(note))` when it
+/// runs because of an approval (or the allow fallback), and `Err(reason)` when it
+/// must not run.
+pub async fn resolve(
+    verdict: Verdict,
+    command: &str,
+    cwd: &Path,
+    peer: Option<&Peer<RoleServer>>,
+) -> Result<Option<String>, String> {
+    let (decisions, settings) = match verdict {
+        Verdict::Allow => return Ok(None),
+        Verdict::Deny(reason) => return Err(reason),
+        Verdict::Ask(decisions, settings) => (decisions, settings),
+    };
+    let decision = policy::list(&decisions);
+
+    if is_remembered(&decisions) {
+        return Ok(Some(format!(
+            "approved earlier in this session (policy {})",
+            decision
+        )));
+    }
+
+    let Some(peer) = peer.filter(|peer| peer.supports_elicitation()) else {
+        return fallback(&decision, settings, "the client cannot prompt for approval");
+    };
+
+    let request = CreateElicitationRequestParam {
+        message: prompt(command, cwd, &decisions),
+        requested_schema: schema(),
+    };
+    let response = match peer
+        .create_elicitation_with_timeout(request, Some(settings.timeout))
+        .await
+    {
+        Ok(response) => response,
+        Err(ServiceError::Timeout { timeout }) => {
+            return Err(format!(
+                "Command denied: no answer to the approval prompt within {}s (policy {})",
+                timeout.as_secs(),
+                decision
+            ));
+        }
+        Err(e) => {
+            tracing::warn!("Approval prompt failed: {}", e);
+            return fallback(&decision, settings, "the approval prompt failed");
+        }
+    };
+
+    let answer = match response.action {
+        ElicitationAction::Accept => response.content.unwrap_or(Value::Null),
+        ElicitationAction::Decline => {
+            return Err(format!(
+                "Command denied: the user declined it (policy {})",
+                decision
+            ));
+        }
+        ElicitationAction::Cancel => {
+            return Err(format!(
+                "Command denied: the user dismissed the approval prompt (policy {})",
+                decision
+            ));
+        }
+    };
+    let flag = |name: &str| answer.get(name).and_then(Value::as_bool).unwrap_or(false);
+    if !flag("approve") {
+        return Err(format!(
+            "Command denied: the user did not approve it (policy {})",
+            decision
+        ));
+    }
+
+    let remember = flag("remember");
+    if remember {
+        remembered().extend(decisions.iter().map(remember_key));
+    }
+    Ok(Some(format!(
+        "approved by the user{} (policy {})",
+        if remember {
+            ", remembered for this session"
+        } else {
+            ""
+        },
+        decision
+    )))
+}
+
+/// Whether the user already approved every one of these decisions for the rest
+/// of the session.
+pub fn is_remembered(decisions: &[PolicyMatch]) -> bool {
+    let remembered = remembered();
+    decisions
+        .iter()
+        .all(|decision| remembered.contains(&remember_key(decision)))
+}
+
+fn remembered() -> std::sync::MutexGuard<'static, BTreeSet<String>> {
+    REMEMBERED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Same file and rule; the sub-command up to whitespace as well when the decision
+/// did not come from a named policy rule.
+fn remember_key(decision: &PolicyMatch) -> String {
+    match decision.rule_id.as_deref() {
+        Some(id) if !id.starts_with("indirect:") => {
+            format!("{}\0{}", decision.file.display(), id)
+        }
+        id => format!(
+            "{}\0{}\0{}",
+            decision.file.display(),
+            id.unwrap_or(""),
+            decision
+                .command
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    }
+}
+
+/// What remembering these decisions would approve, for the prompt.
+fn remember_scope(decisions: &[PolicyMatch]) -> String {
+    let mut scope = Vec::new();
+    for decision in decisions {
+        let line = match decision.rule_id.as_deref() {
+            Some(id) if !id.starts_with("indirect:") => format!(
+                "any command rule '{}' in {} matches",
+                id,
+                decision.file.display()
+            ),
+            _ => format!("exactly `{}`", decision.command),
+        };
+        if !scope.contains(&line) {
+            scope.push(line);
+        }
+    }
+    scope.join("; ")
+}
+
+fn fallback(decision: &str, settings: AskSettings, why: &str) -> Result<Option<String>, String> {
+    match settings.fallback {
+        Action::Allow => Ok(Some(format!(
+            "allowed by ask_fallback because {} (policy {})",
+            why, decision
+        ))),
+        _ => Err(format!(
+            "Command needs approval under policy {}, but {} (ask_fallback = deny)",
+            decision, why
+        )),
+    }
+}
+
+fn prompt(command: &str, cwd: &Path, decisions: &[PolicyMatch]) -> String {
+    let mut message = format!(
+        "Approve running this command?\n\nCommand: {}\nWorking directory: {}\n",
+        command,
+        cwd.display()
+    );
+    for decision in decisions {
+        message.push_str(&format!("Policy: {}\n", decision));
+    }
+    message.push_str(&format!(
+        "Remembering this approval allows {} for the rest of the session.\n",
+        remember_scope(decisions)
+    ));
+    let parsed = shell_parser::parse(command);
+    if !parsed.commands.is_empty() {
+        message.push_str("\nParsed commands:\n");
+        for (i, sub) in parsed.commands.iter().enumerate() {
+            let argv: Vec<&str> = sub.words.iter().map(|w| w.text.as_str()).collect();
+            let mut line = serde_json::to_string(&argv).unwrap_or_default();
+            for redirect in &sub.redirects {
+                line.push_str(&format!(" {} {}", redirect.op, redirect.target.text));
+            }
+            message.push_str(&format!("  {}. {}\n", i + 1, line));
+        }
+    }
+    message
+}
+
+fn schema() -> ElicitationSchema {
+    ElicitationSchema::builder()
+        .required_bool_with("approve", |s| {
+            s.title("Approve").description("Run this command")
+        })
+        .optional_bool_with("remember", |s| {
+            s.title("Remember")
+                .description("Approve what the same policy rules match for the rest of the session")
+                .with_default(false)
+        })
+        .build()
+        .expect("approval schema is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn decision() -> PolicyMatch {
+        PolicyMatch {
+            action: Action::Ask,
+            file: PathBuf::from("/etc/policy.toml"),
+            rule_id: Some("confirm-push".to_string()),
+            reason: None,
+            command: "git  push origin".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_without_a_client_that_can_prompt() {
+        let ask = |fallback| {
+            Verdict::Ask(
+                vec![decision()],
+                AskSettings {
+                    fallback,
+                    timeout: Duration::from_secs(1),
+                },
+            )
+        };
+        let cwd = Path::new("/tmp");
+        let denied = resolve(ask(Action::Deny), "git push origin", cwd, None).await;
+        assert!(denied.unwrap_err().contains("ask_fallback = deny"));
+        let allowed = resolve(ask(Action::Allow), "git push origin", cwd, None).await;
+        assert!(allowed.unwrap().unwrap().contains("ask_fallback"));
+        assert_eq!(resolve(Verdict::Allow, "ls", cwd, None).await, Ok(None));
+    }
+
+    #[test]
+    fn prompt_shows_parsed_commands_and_rule() {
+        let message = prompt(
+            "git push origin > log.txt",
+            Path::new("/srv/app"),
+            &[decision()],
+        );
+        assert!(message.contains("Working directory: /srv/app"));
+        assert!(message.contains("rule 'confirm-push'"));
+        assert!(message.contains(r#"1. ["git","push","origin"] > log.txt"#));
+        assert!(message.contains(
+            "Remembering this approval allows any command rule 'confirm-push' in \
+             /etc/policy.toml matches for the rest of the session."
+        ));
+    }
+
+    #[test]
+    fn remembering_covers_the_rule_but_not_the_default() {
+        let tag = |rule: &str, command: &str| PolicyMatch {
+            rule_id: Some(rule.to_string()),
+            command: command.to_string(),
+            ..decision()
+        };
+        let key = remember_key(&tag("confirm-tag", "git tag a"));
+        assert_eq!(key, remember_key(&tag("confirm-tag", "git  tag b")));
+        assert_ne!(key, remember_key(&tag("confirm-push", "git tag a")));
+
+        // The default and indirect findings only cover the exact sub-command
+        let default = |command: &str| PolicyMatch {
+            rule_id: None,
+            command: command.to_string(),
+            ..decision()
+        };
+        assert_eq!(
+            remember_key(&default("make  deploy")),
+            remember_key(&default("make deploy"))
+        );
+        assert_ne!(
+            remember_key(&default("make deploy")),
+            remember_key(&default("make clean"))
+        );
+        let curl = tag("indirect:pipe-to-shell", "curl x | sh");
+        assert_ne!(
+            remember_key(&curl),
+            remember_key(&tag("indirect:pipe-to-shell", "curl y | sh"))
+        );
+        assert_eq!(
+            remember_scope(&[default("make deploy"), curl]),
+            "exactly `make deploy`; exactly `curl x | sh`"
+        );
+    }
+
+    #[tokio::test]
+    async fn remembering_one_rule_does_not_approve_another() {
+        let a = PolicyMatch {
+            rule_id: Some("confirm-tag".to_string()),
+            command: "git tag a".to_string(),
+            ..decision()
+        };
+        let b = PolicyMatch {
+            rule_id: Some("confirm-release".to_string()),
+            command: "gh release create".to_string(),
+            ..decision()
+        };
+        remembered().insert(remember_key(&a));
+        assert!(is_remembered(std::slice::from_ref(&a)));
+        assert!(!is_remembered(&[a.clone(), b.clone()]));
+
+        // Without a client to prompt, the line falls back instead of running as
+        // approved earlier
+        let ask = Verdict::Ask(
+            vec![a.clone(), b.clone()],
+            AskSettings {
+                fallback: Action::Deny,
+                timeout: Duration::from_secs(1),
+            },
+        );
+        let line = "git tag a && gh release create";
+        let denied = resolve(ask, line, Path::new("/tmp"), None).await;
+        assert!(denied.unwrap_err().contains("ask_fallback = deny"));
+
+        let message = prompt(line, Path::new("/tmp"), &[a, b]);
+        assert!(message.contains("(in `git tag a`)\nPolicy: rule 'confirm-release'"));
+        assert!(message.contains("(in `gh release create`)"));
+    }
+}
@@ -0,0 +1,609 @@
+//! Tamper-evident audit log of tool calls and their outcomes.
+//!
+//! Every `enhanced_terminal` and `enhanced_terminal_session_exec` call is appended
+//! to a JSONL file before it runs, followed by a `job_finished` entry when its job
+//! ends (status, policy decision, exit code or signal, duration, output size) or a
+//! `denied` entry when the command is refused. Secrets are redacted first (see
+//! [`redaction`](super::redaction)). The file is `ENHANCED_TERMINAL_CALL_LOG_PATH`,
+//! or `enhanced_terminal_calls.jsonl` in the crate directory.
+//!
+//! Each entry has a sequence number and the SHA-256 of the entry before it, and
+//! ends with its own hash, so editing, removing, inserting or reordering entries
+//! breaks the chain; `enhanced-terminal-mcp verify [PATH]` checks it. Server
+//! processes sharing the file append under an exclusive `flock` and chain to
+//! whatever entry is last in the file.
+//!
+//! The log rotates to `PATH.1`, `PATH.2`, .. when it would grow past
+//! `ENHANCED_TERMINAL_CALL_LOG_MAX_BYTES` (default 10 MiB) or its first entry is
+//! older than `ENHANCED_TERMINAL_CALL_LOG_MAX_AGE_SECS` (default 7 days); 0 turns
+//! either off. `ENHANCED_TERMINAL_CALL_LOG_KEEP` rotated files are kept (default 5).
+//! The chain continues across files.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(unix)]
+use nix::fcntl::FlockArg;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+
+use super::job_manager::JobRecord;
+use super::redaction::Redactor;
+
+pub const VERIFY_SUBCOMMAND: &str = "verify";
+
+const CALL_LOG_FILE: &str = "enhanced_terminal_calls.jsonl";
+
+/// `prev_hash` of the first entry of a chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_KEEP: u64 = 5;
+
+static WRITE_MUTEX: Mutex<()> = Mutex::new(());
+
+pub fn log_path() -> PathBuf {
+    std::env::var_os("ENHANCED_TERMINAL_CALL_LOG_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(CALL_LOG_FILE))
+}
+
+/// Append a tool call before it runs.
+pub fn record_call<T: Serialize>(tool: &str, parameters: &T) -> io::Result<()> {
+    let mut parameters = serde_json::to_value(parameters).map_err(io::Error::other)?;
+    Redactor::for_env(std::iter::empty()).redact_json(&mut parameters);
+    let mut fields = Map::new();
+    fields.insert("tool".to_string(), tool.into());
+    fields.insert("parameters".to_string(), parameters);
+    append("call", fields)
+}
+
+/// Append a command the server refused to run.
+pub fn record_denied(tool: &str, command: &str, reason: &str) -> io::Result<()> {
+    let redactor = Redactor::for_env(std::iter::empty());
+    let mut fields = Map::new();
+    fields.insert("tool".to_string(), tool.into());
+    fields.insert("command".to_string(), redactor.redact(command).0.into());
+    fields.insert("decision".to_string(), "deny".into());
+    fields.insert("reason".to_string(), redactor.redact(reason).0.into());
+    append("denied", fields)
+}
+
+/// How a job ended, as written to the log.
+#[derive(Debug, Serialize)]
+pub struct JobOutcome {
+    job_id: String,
+    status: String,
+    /// `allow`, or `ask` when the job ran after an approval prompt
+    decision: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approval: Option<String>,
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<String>,
+    duration_secs: Option<f64>,
+    output_bytes: usize,
+    redactions: usize,
+}
+
+impl JobOutcome {
+    pub fn new(job: &JobRecord) -> Self {
+        Self {
+            job_id: job.job_id.clone(),
+            status: format!("{:?}", job.status),
+            decision: if job.approval.is_some() {
+                "ask"
+            } else {
+                "allow"
+            },
+            approval: job.approval.clone(),
+            exit_code: job.exit_code,
+            signal: job
+                .termination
+                .as_ref()
+                .and_then(|termination| termination.signal_name.clone()),
+            duration_secs: job.duration().map(|d| d.as_secs_f64()),
+            output_bytes: job.output_len(),
+            redactions: job.redactions,
+        }
+    }
+}
+
+/// Append the outcome of a finished job.
+pub fn record_job_finished(outcome: &JobOutcome) -> io::Result<()> {
+    match serde_json::to_value(outcome).map_err(io::Error::other)? {
+        Value::Object(fields) => append("job_finished", fields),
+        _ => unreachable!("JobOutcome serializes to an object"),
+    }
+}
+
+/// When the log rotates and how many old files it keeps.
+struct Rotation {
+    max_bytes: u64,
+    max_age: Option<Duration>,
+    keep: u64,
+}
+
+impl Rotation {
+    fn from_env() -> Self {
+        let var = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(default)
+        };
+        let max_age = var(
+            "ENHANCED_TERMINAL_CALL_LOG_MAX_AGE_SECS",
+            DEFAULT_MAX_AGE_SECS,
+        );
+        Self {
+            max_bytes: var("ENHANCED_TERMINAL_CALL_LOG_MAX_BYTES", DEFAULT_MAX_BYTES),
+            max_age: (max_age > 0).then(|| Duration::from_secs(max_age)),
+            keep: var("ENHANCED_TERMINAL_CALL_LOG_KEEP", DEFAULT_KEEP),
+        }
+    }
+
+    fn due(&self, file: &mut File, incoming: u64) -> io::Result<bool> {
+        let len = file.metadata()?.len();
+        if len == 0 {
+            return Ok(false);
+        }
+        if self.max_bytes > 0 && len + incoming > self.max_bytes {
+            return Ok(true);
+        }
+        let Some(max_age) = self.max_age else {
+            return Ok(false);
+        };
+        Ok(first_entry_time(file)?.is_some_and(|started| {
+            (Utc::now() - started)
+                .to_std()
+                .is_ok_and(|age| age > max_age)
+        }))
+    }
+}
+
+/// The log file, exclusively locked against other server processes until dropped.
+struct LockedLog {
+    file: File,
+}
+
+impl Drop for LockedLog {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        #[allow(deprecated)]
+        let _ = nix::fcntl::flock(self.file.as_raw_fd(), FlockArg::Unlock);
+    }
+}
+
+fn open_locked(path: &Path) -> io::Result<LockedLog> {
+    loop {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        #[cfg(unix)]
+        #[allow(deprecated)]
+        nix::fcntl::flock(file.as_raw_fd(), FlockArg::LockExclusive).map_err(io::Error::other)?;
+        let log = LockedLog { file };
+        // Another process may have rotated the file while we waited for the lock
+        if is_current(&log.file, path)? {
+            return Ok(log);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let open = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(open.dev() == current.dev() && open.ino() == current.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+fn append(event: &str, fields: Map<String, Value>) -> io::Result<()> {
+    let _process_guard = WRITE_MUTEX
+        .lock()
+        .map_err(|e| io::Error::other(format!("call log mutex poisoned: {e}")))?;
+
+    let path = log_path();
+    let rotation = Rotation::from_env();
+    let mut log = open_locked(&path)?;
+
+    let previous = match last_line(&mut log.file)? {
+        Some(line) => Some(line),
+        None => last_line_of(&rotated_path(&path, 1))?,
+    };
+    let (seq, prev_hash) = match previous.as_deref().map(parse_entry) {
+        Some(Ok(entry)) => (entry.seq + 1, entry.hash),
+        // An empty log, or one that predates hash chaining, starts a new chain
+        _ => (1, GENESIS_HASH.to_string()),
+    };
+
+    let mut entry = Map::new();
+    entry.insert("seq".to_string(), seq.into());
+    entry.insert(
+        "datetime".to_string(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    entry.insert("event".to_string(), event.into());
+    entry.extend(fields);
+    entry.insert("prev_hash".to_string(), prev_hash.into());
+    let body = serde_json::to_string(&entry).map_err(io::Error::other)?;
+    let line = chain_line(&body);
+
+    if rotation.due(&mut log.file, line.len() as u64)? {
+        rotate(&path, rotation.keep)?;
+        drop(log);
+        log = open_locked(&path)?;
+    }
+    log.file.write_all(line.as_bytes())?;
+    log.file.flush()
+}
+
+fn sha256_hex(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The log line for an entry body: the body with its own hash appended last.
+fn chain_line(body: &str) -> String {
+    let hash = sha256_hex(body);
+    let open = body.strip_suffix('}').expect("entry is a JSON object");
+    format!("{},\"hash\":\"{}\"}}\n", open, hash)
+}
+
+#[derive(Debug)]
+struct ChainedEntry {
+    seq: u64,
+    prev_hash: String,
+    hash: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum EntryError {
+    /// No trailing hash: written before hash chaining, or by something else
+    Unchained,
+    /// The hash does not match the rest of the line
+    Modified,
+    Malformed(String),
+}
+
+fn parse_entry(line: &str) -> Result<ChainedEntry, EntryError> {
+    let (open, hash) = line
+        .rsplit_once(",\"hash\":\"")
+        .and_then(|(open, rest)| Some((open, rest.strip_suffix("\"}")?)))
+        .filter(|(_, hash)| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or(EntryError::Unchained)?;
+    let body = format!("{}}}", open);
+    if sha256_hex(&body) != hash {
+        return Err(EntryError::Modified);
+    }
+    let value: Value =
+        serde_json::from_str(&body).map_err(|e| EntryError::Malformed(e.to_string()))?;
+    let seq = value["seq"]
+        .as_u64()
+        .ok_or_else(|| EntryError::Malformed("missing seq".to_string()))?;
+    let prev_hash = value["prev_hash"]
+        .as_str()
+        .ok_or_else(|| EntryError::Malformed("missing prev_hash".to_string()))?;
+    Ok(ChainedEntry {
+        seq,
+        prev_hash: prev_hash.to_string(),
+        hash: hash.to_string(),
+    })
+}
+
+/// The last line of a file, read backwards from the end.
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    let mut tail: Vec<u8> = Vec::new();
+    while pos > 0 {
+        let step = pos.min(8192);
+        pos -= step;
+        let mut chunk = vec![0; step as usize];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(newline) = body.iter().rposition(|&b| b == b'\n') {
+            return Ok(Some(
+                String::from_utf8_lossy(&body[newline + 1..]).into_owned(),
+            ));
+        }
+    }
+    let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    Ok((!body.is_empty()).then(|| String::from_utf8_lossy(body).into_owned()))
+}
+
+fn last_line_of(path: &Path) -> io::Result<Option<String>> {
+    match File::open(path) {
+        Ok(mut file) => last_line(&mut file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn first_entry_time(file: &mut File) -> io::Result<Option<DateTime<Utc>>> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut line = String::new();
+    BufReader::new(&*file).read_line(&mut line)?;
+    Ok(serde_json::from_str::<Value>(line.trim_end())
+        .ok()
+        .and_then(|entry| {
+            DateTime::parse_from_rfc3339(entry["datetime"].as_str()?)
+                .ok()
+                .map(|time| time.with_timezone(&Utc))
+        }))
+}
+
+fn rotated_path(path: &Path, index: u64) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Shift `PATH.n` to `PATH.n+1`, dropping the oldest beyond `keep`, and move the
+/// log itself to `PATH.1`.
+fn rotate(path: &Path, keep: u64) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    for index in (1..keep).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+/// What `verify` found in an intact log.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Files checked, oldest first
+    pub files: Vec<PathBuf>,
+    pub entries: u64,
+    /// Entries before the chain starts, written before hash chaining
+    pub unchained: u64,
+    pub first_seq: Option<u64>,
+    pub last_seq: Option<u64>,
+    pub last_hash: Option<String>,
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Audit log chain intact: {} entries in {} file(s)",
+            self.entries,
+            self.files.len()
+        )?;
+        if let (Some(first), Some(last)) = (self.first_seq, self.last_seq) {
+            write!(f, " (seq {}..{})", first, last)?;
+        }
+        writeln!(f)?;
+        for file in &self.files {
+            writeln!(f, "  {}", file.display())?;
+        }
+        if let Some(hash) = &self.last_hash {
+            writeln!(f, "Last hash: {}", hash)?;
+        }
+        if self.first_seq.is_some_and(|first| first > 1) {
+            writeln!(
+                f,
+                "Note: the chain starts at seq {}; earlier entries were rotated away",
+                self.first_seq.unwrap_or_default()
+            )?;
+        }
+        if self.unchained > 0 {
+            writeln!(
+                f,
+                "Note: {} entries before the chain have no hash (written before hash chaining)",
+                self.unchained
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the hash chain of a log and its rotated files.
+pub fn verify(path: &Path) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut index = 1;
+    while rotated_path(path, index).exists() {
+        report.files.push(rotated_path(path, index));
+        index += 1;
+    }
+    report.files.reverse();
+    if path.exists() {
+        report.files.push(path.to_path_buf());
+    }
+    if report.files.is_empty() {
+        bail!("{} does not exist", path.display());
+    }
+
+    let mut previous: Option<(u64, String)> = None;
+    for file in &report.files {
+        let reader = BufReader::new(
+            File::open(file).with_context(|| format!("cannot open {}", file.display()))?,
+        );
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.with_context(|| format!("cannot read {}", file.display()))?;
+            let at = format!("{}:{}", file.display(), index + 1);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = match parse_entry(&line) {
+                Ok(entry) => entry,
+                Err(EntryError::Unchained) if previous.is_none() => {
+                    report.unchained += 1;
+                    continue;
+                }
+                Err(EntryError::Unchained) => {
+                    bail!("{}: entry has no hash (not written by the server)", at)
+                }
+                Err(EntryError::Modified) => {
+                    bail!("{}: hash does not match the entry (entry was modified)", at)
+                }
+                Err(EntryError::Malformed(e)) => bail!("{}: malformed entry: {}", at, e),
+            };
+            match &previous {
+                Some((seq, hash)) => {
+                    if entry.prev_hash != *hash {
+                        bail!(
+                            "{}: prev_hash does not match the entry before it (entries removed, inserted or reordered)",
+                            at
+                        );
+                    }
+                    if entry.seq != seq + 1 {
+                        bail!("{}: sequence number {} follows {}", at, entry.seq, seq);
+                    }
+                }
+                None if entry.seq == 1 && entry.prev_hash != GENESIS_HASH => {
+                    bail!("{}: the first entry does not start a chain", at);
+                }
+                None => {}
+            }
+            report.first_seq.get_or_insert(entry.seq);
+            report.last_seq = Some(entry.seq);
+            report.entries += 1;
+            previous = Some((entry.seq, entry.hash));
+        }
+    }
+    report.last_hash = previous.map(|(_, hash)| hash);
+    Ok(report)
+}
+
+/// Run `verify [PATH]` if the binary was started with it. Never returns in that case.
+pub fn run_verify_if_requested() {
+    let mut args = std::env::args_os().skip(1);
+    if args.next().as_deref() != Some(VERIFY_SUBCOMMAND.as_ref()) {
+        return;
+    }
+    let path = args.next().map(PathBuf::from).unwrap_or_else(log_path);
+    match verify(&path) {
+        Ok(report) => {
+            print!("{}", report);
+            std::process::exit(0);
+        }
+        Err(error) => {
+            eprintln!("enhanced-terminal-mcp verify: {:#}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_chain(path: &Path, bodies: &[&str]) {
+        let mut text = String::new();
+        let mut prev = GENESIS_HASH.to_string();
+        for (index, body) in bodies.iter().enumerate() {
+            let body = format!(
+                "{{\"seq\":{},\"event\":\"call\",{},\"prev_hash\":\"{}\"}}",
+                index + 1,
+                body,
+                prev
+            );
+            let line = chain_line(&body);
+            prev = parse_entry(line.trim_end()).unwrap().hash;
+            text.push_str(&line);
+        }
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn verify_detects_modified_removed_and_reordered_entries() {
+        let dir = std::env::temp_dir().join(format!("audit-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("calls.jsonl");
+        let bodies = [r#""tool":"a""#, r#""tool":"b""#, r#""tool":"c""#];
+        write_chain(&path, &bodies);
+
+        let report = verify(&path).unwrap();
+        assert_eq!((report.entries, report.last_seq), (3, Some(3)));
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let check = |lines: Vec<String>| {
+            fs::write(&path, lines.join("\n") + "\n").unwrap();
+            verify(&path).unwrap_err().to_string()
+        };
+        let modified = check(vec![
+            lines[0].to_string(),
+            lines[1].replace("\"b\"", "\"x\""),
+            lines[2].to_string(),
+        ]);
+        assert!(
+            modified.contains("calls.jsonl:2: hash does not match"),
+            "{modified}"
+        );
+        let removed = check(vec![lines[0].to_string(), lines[2].to_string()]);
+        assert!(
+            removed.contains(":2: prev_hash does not match"),
+            "{removed}"
+        );
+        let reordered = check(vec![
+            lines[1].to_string(),
+            lines[0].to_string(),
+            lines[2].to_string(),
+        ]);
+        assert!(
+            reordered.contains(":2: prev_hash does not match"),
+            "{reordered}"
+        );
+        let appended = check(vec![
+            lines[0].to_string(),
+            lines[1].to_string(),
+            r#"{"tool":"forged"}"#.to_string(),
+        ]);
+        assert!(appended.contains(":3: entry has no hash"), "{appended}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reads_the_last_line_across_chunks() {
+        let dir = std::env::temp_dir().join(format!("audit-tail-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tail.jsonl");
+        let long = "y".repeat(20_000);
+        fs::write(&path, format!("first\n{}\n", long)).unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(last_line(&mut file).unwrap(), Some(long));
+        fs::write(&path, "").unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(last_line(&mut file).unwrap(), None);
+
+        rotate(&path, 2).unwrap();
+        assert!(rotated_path(&path, 1).exists() && !path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
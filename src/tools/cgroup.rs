@@ -0,0 +1,442 @@
+//! cgroup v2 placement, enforcement and accounting for jobs.
+//!
+//! When the server can write to a cgroup v2 subtree (its own cgroup by default, or
+//! `ENHANCED_TERMINAL_CGROUP_ROOT`), every job gets a child group
+//! `<root>/enhanced-terminal-<server pid>/<job id>`. The launcher moves the job's
+//! process into that group before exec, so memory.max, cpu.max and pids.max also
+//! cover grandchildren. Set `ENHANCED_TERMINAL_CGROUP=off` to disable placement.
+//!
+//! Everything degrades gracefully: without a usable hierarchy jobs run as before
+//! and the reason is reported; without a controller its limit is reported as not
+//! enforced while the remaining accounting still works.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Controllers the server enables for job groups when they are available.
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/// cpu.max period in microseconds.
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Optional cgroup v2 limits for a single job.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CgroupLimits {
+    /// memory.max in bytes; the kernel OOM-kills inside the group when it is exceeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_max_bytes: Option<u64>,
+    /// cpu.max as a number of CPUs, e.g. 0.5 or 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_max_cores: Option<f64>,
+    /// pids.max: maximum number of processes and threads in the group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids_max: Option<u64>,
+}
+
+impl CgroupLimits {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl fmt::Display for CgroupLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            self.memory_max_bytes
+                .map(|v| format!("memory.max={}", format_bytes(v))),
+            self.cpu_max_cores.map(|v| format!("cpu.max={} CPUs", v)),
+            self.pids_max.map(|v| format!("pids.max={}", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if parts.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// Accounting read from a job's cgroup. Fields are `None` when the kernel or the
+/// enabled controllers do not provide them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CgroupStats {
+    pub memory_current_bytes: Option<u64>,
+    pub memory_peak_bytes: Option<u64>,
+    pub cpu_usage_usec: Option<u64>,
+    pub cpu_user_usec: Option<u64>,
+    pub cpu_system_usec: Option<u64>,
+    /// Times the group hit memory.max and the kernel had to reclaim or OOM
+    pub oom_events: Option<u64>,
+    /// Processes in the group killed by the OOM killer
+    pub oom_kills: Option<u64>,
+    pub pids_peak: Option<u64>,
+}
+
+impl CgroupStats {
+    pub fn read(path: &Path) -> Self {
+        let cpu = read_keyed(&path.join("cpu.stat"));
+        let memory_events = read_keyed(&path.join("memory.events"));
+        let key = |map: &Option<Vec<(String, u64)>>, name: &str| {
+            map.as_ref()
+                .and_then(|entries| entries.iter().find(|(k, _)| k == name).map(|(_, v)| *v))
+        };
+
+        Self {
+            memory_current_bytes: read_u64(&path.join("memory.current")),
+            memory_peak_bytes: read_u64(&path.join("memory.peak")),
+            cpu_usage_usec: key(&cpu, "usage_usec"),
+            cpu_user_usec: key(&cpu, "user_usec"),
+            cpu_system_usec: key(&cpu, "system_usec"),
+            oom_events: key(&memory_events, "oom"),
+            oom_kills: key(&memory_events, "oom_kill"),
+            pids_peak: read_u64(&path.join("pids.peak")),
+        }
+    }
+}
+
+impl fmt::Display for CgroupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = |usec: u64| format!("{:.2}s", usec as f64 / 1_000_000.0);
+        let mut parts = Vec::new();
+
+        if let Some(peak) = self.memory_peak_bytes {
+            parts.push(format!("peak memory {}", format_bytes(peak)));
+        } else if let Some(current) = self.memory_current_bytes {
+            parts.push(format!("memory {}", format_bytes(current)));
+        }
+        if let Some(usage) = self.cpu_usage_usec {
+            match (self.cpu_user_usec, self.cpu_system_usec) {
+                (Some(user), Some(system)) => parts.push(format!(
+                    "CPU {} (user {}, system {})",
+                    secs(usage),
+                    secs(user),
+                    secs(system)
+                )),
+                _ => parts.push(format!("CPU {}", secs(usage))),
+            }
+        }
+        if let Some(kills) = self.oom_kills {
+            parts.push(format!("OOM kills {}", kills));
+        }
+        if let Some(peak) = self.pids_peak {
+            parts.push(format!("peak pids {}", peak));
+        }
+
+        if parts.is_empty() {
+            f.write_str("no statistics available")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// The cgroup a job was placed in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobCgroup {
+    pub path: PathBuf,
+    pub limits: CgroupLimits,
+    /// Limits that could not be enforced, with the reason
+    pub warnings: Vec<String>,
+    /// Final accounting, captured when the job finished
+    pub stats: Option<CgroupStats>,
+}
+
+impl JobCgroup {
+    /// Final stats for finished jobs, live stats otherwise.
+    pub fn current_stats(&self) -> CgroupStats {
+        self.stats
+            .clone()
+            .unwrap_or_else(|| CgroupStats::read(&self.path))
+    }
+}
+
+/// Outcome of trying to place a job in its own cgroup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CgroupPlacement {
+    Placed(JobCgroup),
+    Unavailable(String),
+}
+
+#[derive(Debug)]
+struct CgroupRoot {
+    /// Per-server directory holding one group per job
+    instance: PathBuf,
+    /// Controllers enabled for job groups
+    controllers: Vec<String>,
+}
+
+static CGROUP_ROOT: OnceLock<Result<CgroupRoot, String>> = OnceLock::new();
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn read_keyed(path: &Path) -> Option<Vec<(String, u64)>> {
+    let text = fs::read_to_string(path).ok()?;
+    Some(
+        text.lines()
+            .filter_map(|line| {
+                let (key, value) = line.split_once(' ')?;
+                Some((key.to_string(), value.trim().parse().ok()?))
+            })
+            .collect(),
+    )
+}
+
+pub(super) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// cgroup v2 mount point from /proc/self/mountinfo.
+fn cgroup2_mount() -> Option<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mountinfo.lines().find_map(|line| {
+        let (fields, fs_fields) = line.split_once(" - ")?;
+        (fs_fields.split_whitespace().next() == Some("cgroup2"))
+            .then(|| fields.split_whitespace().nth(4).map(PathBuf::from))?
+    })
+}
+
+/// Path of this process's own cgroup v2 group.
+fn own_cgroup() -> Option<PathBuf> {
+    let membership = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let relative = membership
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))?;
+    Some(cgroup2_mount()?.join(relative.trim_start_matches('/')))
+}
+
+/// Enable `controllers` for the children of `dir`, returning the ones that worked.
+fn enable_controllers(dir: &Path, controllers: &[String]) -> Vec<String> {
+    controllers
+        .iter()
+        .filter(|controller| {
+            fs::write(
+                dir.join("cgroup.subtree_control"),
+                format!("+{}", controller),
+            )
+            .is_ok()
+        })
+        .cloned()
+        .collect()
+}
+
+fn init_root() -> Result<CgroupRoot, String> {
+    if !cfg!(target_os = "linux") {
+        return Err("cgroups are only available on Linux".to_string());
+    }
+    if std::env::var("ENHANCED_TERMINAL_CGROUP").is_ok_and(|v| v.eq_ignore_ascii_case("off")) {
+        return Err("disabled by ENHANCED_TERMINAL_CGROUP=off".to_string());
+    }
+
+    let configured = std::env::var("ENHANCED_TERMINAL_CGROUP_ROOT")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(PathBuf::from);
+    let root = match &configured {
+        Some(root) => root.clone(),
+        None => own_cgroup().ok_or("no cgroup v2 hierarchy is mounted")?,
+    };
+    let available = fs::read_to_string(root.join("cgroup.controllers"))
+        .map_err(|_| format!("{} is not a cgroup v2 group", root.display()))?;
+    let wanted: Vec<String> = CONTROLLERS
+        .iter()
+        .filter(|c| available.split_whitespace().any(|a| a == **c))
+        .map(|c| c.to_string())
+        .collect();
+
+    let mut enabled = enable_controllers(&root, &wanted);
+    if enabled.len() < wanted.len() && configured.is_none() {
+        // cgroup v2 only lets a group hand controllers to its children when it has
+        // no processes of its own, so move the server into a leaf next to the jobs.
+        let leaf = root.join(format!("enhanced-terminal-server-{}", std::process::id()));
+        if fs::create_dir_all(&leaf).is_ok()
+            && fs::write(leaf.join("cgroup.procs"), std::process::id().to_string()).is_ok()
+        {
+            enabled = enable_controllers(&root, &wanted);
+        }
+    }
+
+    let instance = root.join(format!("enhanced-terminal-{}", std::process::id()));
+    fs::create_dir_all(&instance).map_err(|e| {
+        format!(
+            "cannot create a child group in {} ({}); the server needs a delegated cgroup v2 subtree",
+            root.display(),
+            e
+        )
+    })?;
+    let controllers = enable_controllers(&instance, &enabled);
+
+    tracing::info!(
+        "cgroup v2 job groups under {} (controllers: {})",
+        instance.display(),
+        if controllers.is_empty() {
+            "none".to_string()
+        } else {
+            controllers.join(" ")
+        }
+    );
+    Ok(CgroupRoot {
+        instance,
+        controllers,
+    })
+}
+
+/// Create the cgroup for a job and apply its limits.
+pub fn place_job(job_id: &str, limits: &CgroupLimits) -> CgroupPlacement {
+    let root = match CGROUP_ROOT.get_or_init(init_root) {
+        Ok(root) => root,
+        Err(reason) => return CgroupPlacement::Unavailable(reason.clone()),
+    };
+
+    let path = root.instance.join(job_id);
+    if let Err(e) = fs::create_dir(&path) {
+        return CgroupPlacement::Unavailable(format!("cannot create {}: {}", path.display(), e));
+    }
+
+    let mut warnings = Vec::new();
+    let mut set = |controller: &str, file: &str, value: Option<String>| {
+        let Some(value) = value else {
+            return;
+        };
+        if !root.controllers.iter().any(|c| c == controller) {
+            warnings.push(format!(
+                "{} not enforced: {} controller is not available",
+                file, controller
+            ));
+        } else if let Err(e) = fs::write(path.join(file), &value) {
+            warnings.push(format!("{} not enforced: {}", file, e));
+        }
+    };
+
+    set(
+        "memory",
+        "memory.max",
+        limits.memory_max_bytes.map(|v| v.to_string()),
+    );
+    set(
+        "cpu",
+        "cpu.max",
+        limits.cpu_max_cores.map(|cores| {
+            let quota = ((cores * CPU_PERIOD_USEC as f64) as u64).max(1000);
+            format!("{} {}", quota, CPU_PERIOD_USEC)
+        }),
+    );
+    set("pids", "pids.max", limits.pids_max.map(|v| v.to_string()));
+
+    CgroupPlacement::Placed(JobCgroup {
+        path,
+        limits: limits.clone(),
+        warnings,
+        stats: None,
+    })
+}
+
+/// Kill every process still in the group (cgroup.kill, or SIGKILL per pid on
+/// kernels without it). Catches processes that left the job's process group.
+pub fn kill_all(path: &Path) {
+    if fs::write(path.join("cgroup.kill"), "1").is_ok() {
+        return;
+    }
+    #[cfg(unix)]
+    if let Ok(procs) = fs::read_to_string(path.join("cgroup.procs")) {
+        use nix::sys::signal::{Signal, kill};
+        use nix::unistd::Pid;
+
+        for pid in procs
+            .lines()
+            .filter_map(|line| line.trim().parse::<i32>().ok())
+        {
+            let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+        }
+    }
+}
+
+/// Capture final stats and remove the group. A group that still has processes
+/// (e.g. a daemon the job left behind) is kept so its stats stay readable.
+pub fn release(path: &Path) -> CgroupStats {
+    let stats = CgroupStats::read(path);
+    if let Err(e) = fs::remove_dir(path) {
+        tracing::debug!("Keeping cgroup {}: {}", path.display(), e);
+    }
+    stats
+}
+
+/// Remove the server's job groups on shutdown. Groups that still hold processes
+/// are left alone.
+pub fn cleanup() {
+    let Some(Ok(root)) = CGROUP_ROOT.get() else {
+        return;
+    };
+    if let Ok(entries) = fs::read_dir(&root.instance) {
+        for entry in entries.flatten().filter(|e| e.path().is_dir()) {
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+    let _ = fs::remove_dir(&root.instance);
+}
+
+/// Move the calling process into the group at `path` (used by the launcher).
+pub fn join(path: &Path) -> std::io::Result<()> {
+    fs::write(path.join("cgroup.procs"), std::process::id().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_stats_from_group_files() {
+        let dir = std::env::temp_dir().join(format!("et-cgroup-stats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("cpu.stat"),
+            "usage_usec 2500000\nuser_usec 2000000\nsystem_usec 500000\n",
+        )
+        .unwrap();
+        fs::write(dir.join("memory.peak"), "10485760\n").unwrap();
+        fs::write(
+            dir.join("memory.events"),
+            "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n",
+        )
+        .unwrap();
+
+        let stats = CgroupStats::read(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(stats.cpu_usage_usec, Some(2_500_000));
+        assert_eq!(stats.memory_peak_bytes, Some(10 * 1024 * 1024));
+        assert_eq!(stats.oom_kills, Some(1));
+        assert_eq!(stats.pids_peak, None);
+        assert_eq!(
+            stats.to_string(),
+            "peak memory 10.0 MiB, CPU 2.50s (user 2.00s, system 0.50s), OOM kills 1"
+        );
+    }
+
+    #[test]
+    fn missing_files_mean_no_statistics() {
+        let stats = CgroupStats::read(Path::new("/nonexistent/cgroup"));
+        assert_eq!(stats, CgroupStats::default());
+        assert_eq!(stats.to_string(), "no statistics available");
+    }
+}
@@ -0,0 +1,250 @@
+//! Dry run of the checks `execute_command` applies before it starts a command.
+//!
+//! The command goes through the same steps as a real run: rewrite rules, cwd
+//! resolution, shell parsing, the denylist, the allowlist and the policy rules.
+//! Nothing is executed and no approval prompt is shown.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::approval;
+use super::policy::{self, Action, Verdict};
+use super::rewrite::SUDO_NON_INTERACTIVE;
+use super::shell_parser;
+use super::terminal_executor::{
+    apply_default_env, default_cwd, default_shell, resolve_cwd, rewrite_command,
+};
+use crate::detection::binary_detector::is_executable;
+
+/// Commands the shell runs itself rather than from PATH.
+const SHELL_BUILTINS: &[&str] = &[
+    ":", ".", "[", "alias", "bg", "break", "builtin", "cd", "command", "continue", "declare",
+    "echo", "eval", "exec", "exit", "export", "false", "fg", "getopts", "hash", "jobs", "kill",
+    "let", "local", "printf", "pwd", "read", "readonly", "return", "set", "shift", "source",
+    "test", "times", "trap", "true", "type", "typeset", "ulimit", "umask", "unalias", "unset",
+    "wait",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CommandCheckInput {
+    /// Command to check
+    pub command: String,
+    /// Working directory (default: "."), resolved as enhanced_terminal would
+    #[serde(default = "default_cwd")]
+    pub cwd: String,
+    /// Shell the command would run in
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    /// Environment variables the command would get (PATH here is used to resolve programs)
+    #[serde(default)]
+    pub env_vars: HashMap<String, String>,
+    /// Custom denylist patterns (in addition to defaults)
+    #[serde(default)]
+    pub custom_denylist: Vec<String>,
+    /// Job tags, which decide tag-specific rewrite rules
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Where a program name would come from when the shell runs it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Path(PathBuf),
+    Builtin,
+    /// A function defined earlier in the same command line
+    Function,
+    /// The name contains an expansion, so it is only known at run time
+    Dynamic,
+    NotFound,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resolution::Path(path) => write!(f, "{}", path.display()),
+            Resolution::Builtin => f.write_str("shell builtin"),
+            Resolution::Function => f.write_str("function defined in the command"),
+            Resolution::Dynamic => f.write_str("only known at run time"),
+            Resolution::NotFound => f.write_str("not found"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedProgram {
+    pub name: String,
+    pub resolution: Resolution,
+}
+
+/// What `execute_command` would do with a command.
+#[derive(Debug, Clone)]
+pub struct CommandCheck {
+    /// The command as submitted
+    pub command: String,
+    /// The command line the shell would be given, after server-side rewriting
+    pub final_command: String,
+    pub sudo_rewritten: bool,
+    /// Rewrite rules that would change the command
+    pub rewrites: Vec<String>,
+    pub working_directory: PathBuf,
+    pub working_directory_exists: bool,
+    pub shell: ResolvedProgram,
+    pub decision: Action,
+    /// Why the command would be denied
+    pub reason: Option<String>,
+    /// The denylist pattern, allowlist entry or policy rule that decided
+    pub matched: Option<String>,
+    /// How an `ask` decision would be settled
+    pub approval: Option<String>,
+    /// Indirect execution found in the command
+    pub indirect_execution: Vec<String>,
+    /// Programs the command line would run, substitutions after the commands using them
+    pub programs: Vec<ResolvedProgram>,
+}
+
+/// Run the pre-execution checks of `execute_command` without running anything.
+pub fn check_command(input: &CommandCheckInput) -> CommandCheck {
+    let command = input.command.trim();
+    let rewritten = rewrite_command(command, &input.tags);
+    let final_command = rewritten.command.clone();
+    let cwd = resolve_cwd(&input.cwd);
+
+    let env_vars = apply_default_env(input.env_vars.clone());
+    let lookup = |name: &str| {
+        env_vars
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    };
+    let explanation = policy::explain(&final_command, &input.custom_denylist, &cwd, &lookup);
+    let (decision, reason, approval) = match explanation.verdict {
+        Verdict::Allow => (Action::Allow, None, None),
+        Verdict::Deny(reason) => (Action::Deny, Some(reason), None),
+        Verdict::Ask(decisions, settings) => {
+            let approval = if approval::is_remembered(&decisions) {
+                "approved earlier in this session".to_string()
+            } else {
+                format!(
+                    "the user would be asked; clients that cannot prompt get ask_fallback = {}",
+                    settings.fallback
+                )
+            };
+            (Action::Ask, None, Some(approval))
+        }
+    };
+
+    let path = lookup("PATH");
+    CommandCheck {
+        command: command.to_string(),
+        sudo_rewritten: rewritten.applied_rule(SUDO_NON_INTERACTIVE),
+        rewrites: rewritten.applied,
+        working_directory_exists: cwd.is_dir(),
+        shell: ResolvedProgram {
+            name: input.shell.clone(),
+            resolution: find_program(&input.shell, &cwd, path.as_deref()),
+        },
+        programs: resolve_programs(&final_command, &cwd, path.as_deref()),
+        final_command,
+        working_directory: cwd,
+        decision,
+        reason,
+        matched: explanation.matched,
+        approval,
+        indirect_execution: explanation
+            .findings
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+    }
+}
+
+/// Resolve every program the command line would run, including wrapped ones.
+fn resolve_programs(command: &str, cwd: &Path, path: Option<&str>) -> Vec<ResolvedProgram> {
+    let parsed = shell_parser::parse(command);
+    let mut programs: Vec<ResolvedProgram> = Vec::new();
+    for sub in &parsed.commands {
+        for argv in sub.invocations() {
+            let name = &argv[0];
+            if programs.iter().any(|p| p.name == name.text) {
+                continue;
+            }
+            let resolution = if name.dynamic {
+                Resolution::Dynamic
+            } else if parsed.functions.iter().any(|f| f.name == name.text) {
+                Resolution::Function
+            } else if SHELL_BUILTINS.contains(&name.text.as_str()) {
+                Resolution::Builtin
+            } else {
+                find_program(&name.text, cwd, path)
+            };
+            programs.push(ResolvedProgram {
+                name: name.text.clone(),
+                resolution,
+            });
+        }
+    }
+    programs
+}
+
+/// Look a program up the way `execvp` does: names with a `/` relative to `cwd`,
+/// other names in each PATH directory.
+fn find_program(name: &str, cwd: &Path, path: Option<&str>) -> Resolution {
+    let executable = |candidate: &PathBuf| candidate.is_file() && is_executable(candidate);
+    let found = if name.contains('/') {
+        Some(cwd.join(name)).filter(executable)
+    } else {
+        std::env::split_paths(path.unwrap_or_default())
+            .map(|dir| cwd.join(dir).join(name))
+            .find(executable)
+    };
+    found.map_or(Resolution::NotFound, Resolution::Path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_programs_from_path_builtins_and_functions() {
+        let dir = std::env::temp_dir().join(format!("command-check-{}", std::process::id()));
+        let bin = dir.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let tool = bin.join("deploy-tool");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        std::fs::write(bin.join("not-executable"), "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let path = bin.to_string_lossy().into_owned();
+        let programs = resolve_programs(
+            "greet() { echo hi; }; cd /; greet | timeout 5 deploy-tool $(not-executable) && $CMD",
+            &dir,
+            Some(&path),
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let found: Vec<(&str, String)> = programs
+            .iter()
+            .map(|p| (p.name.as_str(), p.resolution.to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("echo", "shell builtin".to_string()),
+                ("cd", "shell builtin".to_string()),
+                ("greet", "function defined in the command".to_string()),
+                ("timeout", "not found".to_string()),
+                ("deploy-tool", tool.display().to_string()),
+                ("$CMD", "only known at run time".to_string()),
+                ("not-executable", "not found".to_string()),
+            ]
+        );
+    }
+}
@@ -1,10 +1,46 @@
+//! Command denylist.
+//!
+//! Rules are written as shell commands and matched against every simple command the
+//! shell would run (see [`shell_parser`](super::shell_parser)), not as substrings of
+//! the command line. A rule matches a command when:
+//!
+//! - the program names match by basename (`/bin/rm` is `rm`), looking through
+//!   wrappers such as `sudo`, `env`, `nice` and `timeout`;
+//! - every short flag in the rule is set, in any combination (`-rf`, `-r -f`,
+//!   `-fr`, `--recursive --force`), and every long flag is present;
+//! - every operand and redirection target in the rule appears in the command,
+//!   after path normalization (`//`, `/./`, `..`, `$HOME` -> `~`).
+//!
+//! Unquoted `*` and `?` in a rule are wildcards; in paths they stop at `/`, as in
+//! shell globbing, so `rm -rf /*` matches `rm -rf /etc` but not `rm -rf /tmp/build`.
+//! A program name only known at run time (`$(printf rm) -rf /`) matches any rule
+//! that also constrains arguments. Matching ignores case. Rules that do not parse
+//! as a single command fall back to a substring match.
+//!
+//! Code the shell only sees at run time cannot be matched at all, so
+//! [`find_indirect`] flags the constructs that run it: `eval` of a non-literal
+//! string, `sh -c "$X"` and `bash <(curl ..)`, shells reading a script from a pipe
+//! (`base64 -d | sh`, `curl .. | bash`), and interpreter one-liners that call out
+//! to the system (`python -c 'os.system(..)'`). Each finding carries the
+//! configured [`IndirectAction`]. Literal `sh -c` and `eval` scripts are parsed and
+//! matched like any other command instead.
+
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::OnceLock;
+
+use super::shell_parser::{
+    self, FunctionDef, ParsedCommand, Redirect, ShellInput, SimpleCommand, Word,
+};
+
 /// Default denylist of dangerous command patterns
 pub const DEFAULT_DENYLIST: &[&str] = &[
     // Destructive file operations
     "rm -rf /",
     "rm -rf /*",
     "rm -rf ~",
-    "rm -rf *",
+    "rm -rf '*'",
     "rm -fr /",
     "rm --no-preserve-root",
     "> /dev/sda",
@@ -12,7 +48,7 @@ pub const DEFAULT_DENYLIST: &[&str] = &[
     "dd if=/dev/zero",
     "dd if=/dev/random",
     "mkfs",
-    "mkfs.ext",
+    "mkfs.*",
     "format c:",
     // System manipulation
     "shutdown",
@@ -31,7 +67,10 @@ pub const DEFAULT_DENYLIST: &[&str] = &[
     // Permission changes
     "chmod 777 /",
     "chmod -R 777 /",
+    "chmod 777 /*",
+    "chmod -R 777 /*",
     "chown -R root",
+    "chown -R root:*",
     "chown root /",
     // Package manager dangers
     "apt-get remove --purge",
@@ -46,8 +85,8 @@ pub const DEFAULT_DENYLIST: &[&str] = &[
     "insmod",
     // Network attacks
     "tcpdump -w /dev/null",
-    "wget http",
-    "curl http",
+    "wget http*",
+    "curl http*",
     // Cron/service manipulation
     "crontab -r",
     // Moving system directories
@@ -59,48 +98,745 @@ pub const DEFAULT_DENYLIST: &[&str] = &[
     "mv /lib",
 ];
 
-/// Check if a command matches any pattern in the denylist
-pub fn is_denied(command: &str, custom_patterns: &[String]) -> bool {
-    let command_lower = command.to_lowercase();
+/// Long options that mean the same as a short flag, per program.
+const LONG_FLAG_ALIASES: &[(&str, &str, char)] = &[
+    ("rm", "--recursive", 'r'),
+    ("rm", "--force", 'f'),
+    ("chmod", "--recursive", 'r'),
+    ("chown", "--recursive", 'r'),
+];
+
+/// A denylist rule that matched, and the sub-command that triggered it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DenylistMatch {
+    /// The rule as written in the denylist
+    pub rule: String,
+    /// Source text of the simple command (or function) that matched
+    pub command: String,
+}
+
+impl fmt::Display for DenylistMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (in `{}`)", self.rule, self.command)
+    }
+}
+
+/// A compiled denylist rule.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub pattern: String,
+    matcher: Matcher,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Command(CommandPattern),
+    /// A function that calls itself in a pipeline or in the background
+    ForkBomb,
+    Substring(String),
+}
+
+/// The argv shape a command must have to match a rule.
+#[derive(Debug, Clone, Default)]
+struct CommandPattern {
+    program: Option<Word>,
+    short_flags: BTreeSet<char>,
+    long_flags: Vec<String>,
+    operands: Vec<Word>,
+    /// Redirections: (writes, target)
+    redirects: Vec<(bool, Word)>,
+}
+
+impl Rule {
+    /// Compile a rule; `None` for an empty pattern.
+    pub fn new(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return None;
+        }
+        let parsed = shell_parser::parse(pattern);
+        let matcher = if parsed.functions.iter().any(is_fork_bomb) {
+            Matcher::ForkBomb
+        } else if let [command] = parsed.commands.as_slice() {
+            Matcher::Command(CommandPattern::from_rule(command))
+        } else {
+            Matcher::Substring(pattern.to_lowercase())
+        };
+        Some(Self {
+            pattern: pattern.to_string(),
+            matcher,
+        })
+    }
+
+    /// True for rules that match the argv of a single command.
+    pub fn is_command_pattern(&self) -> bool {
+        matches!(self.matcher, Matcher::Command(_))
+    }
+
+    /// Whether the rule matches one simple command; only argv rules can.
+    pub fn matches_command(&self, command: &SimpleCommand) -> bool {
+        match &self.matcher {
+            Matcher::Command(pattern) => pattern.matches(command, true),
+            Matcher::ForkBomb | Matcher::Substring(_) => false,
+        }
+    }
+
+    /// Like [`matches_command`](Self::matches_command), but a program name only
+    /// known at run time never matches. For rules that let a command through.
+    pub fn matches_known_command(&self, command: &SimpleCommand) -> bool {
+        match &self.matcher {
+            Matcher::Command(pattern) => pattern.matches(command, false),
+            Matcher::ForkBomb | Matcher::Substring(_) => false,
+        }
+    }
+
+    /// Source text of the first sub-command this rule matches.
+    pub fn find(&self, command: &str, parsed: &ParsedCommand) -> Option<String> {
+        match &self.matcher {
+            Matcher::Command(pattern) => parsed
+                .commands
+                .iter()
+                .find(|c| pattern.matches(c, true))
+                .map(|c| c.text.clone()),
+            Matcher::ForkBomb => parsed
+                .functions
+                .iter()
+                .find(|f| is_fork_bomb(f))
+                .map(|f| f.text.clone()),
+            Matcher::Substring(pattern) => command
+                .to_lowercase()
+                .contains(pattern.as_str())
+                .then(|| command.to_string()),
+        }
+    }
+}
+
+fn is_fork_bomb(function: &FunctionDef) -> bool {
+    function.body.iter().any(|c| {
+        (c.piped || c.background) && c.words.first().is_some_and(|w| w.text == function.name)
+    })
+}
+
+impl CommandPattern {
+    fn from_rule(command: &SimpleCommand) -> Self {
+        let mut pattern = Self {
+            redirects: command
+                .redirects
+                .iter()
+                .map(|r| (r.writes(), normalized(&r.target)))
+                .collect(),
+            ..Default::default()
+        };
+        let Some((program, args)) = command.words.split_first() else {
+            return pattern;
+        };
+        pattern.program = Some(Word {
+            text: shell_parser::basename(&program.text),
+            ..program.clone()
+        });
+        let args = Arguments::collect(&pattern.program.as_ref().unwrap().text, args);
+        pattern.short_flags = args.short_flags;
+        pattern.long_flags = args.long_flags;
+        pattern.operands = args.operands;
+        pattern
+    }
+
+    fn constrains_arguments(&self) -> bool {
+        !self.short_flags.is_empty() || !self.long_flags.is_empty() || !self.operands.is_empty()
+    }
 
-    // Check default denylist
-    for pattern in DEFAULT_DENYLIST {
-        if command_lower.contains(&pattern.to_lowercase()) {
-            return true;
+    /// With `dynamic_program`, a program name only known at run time counts as the
+    /// rule's program when the rule constrains the arguments, which is the safe
+    /// side for denials.
+    fn matches(&self, command: &SimpleCommand, dynamic_program: bool) -> bool {
+        if !self
+            .redirects
+            .iter()
+            .all(|rule| command.redirects.iter().any(|r| redirect_matches(rule, r)))
+        {
+            return false;
         }
+        let Some(program) = &self.program else {
+            return !self.redirects.is_empty();
+        };
+        command.invocations().into_iter().any(|argv| {
+            let Some((name, args)) = argv.split_first() else {
+                return false;
+            };
+            let name = if name.dynamic {
+                if !dynamic_program || !self.constrains_arguments() {
+                    return false;
+                }
+                program.text.clone()
+            } else {
+                shell_parser::basename(&name.text)
+            };
+            if !word_matches(program, &name) {
+                return false;
+            }
+            let args = Arguments::collect(&name, args);
+            self.short_flags.is_subset(&args.short_flags)
+                && self.long_flags.iter().all(|f| args.long_flags.contains(f))
+                && self
+                    .operands
+                    .iter()
+                    .all(|rule| args.operands.iter().any(|o| word_matches(rule, &o.text)))
+        })
     }
+}
+
+fn redirect_matches((writes, target): &(bool, Word), redirect: &Redirect) -> bool {
+    *writes == redirect.writes() && word_matches(target, &normalized(&redirect.target).text)
+}
+
+/// Flags and operands of a command, lowercased and normalized.
+struct Arguments {
+    short_flags: BTreeSet<char>,
+    long_flags: Vec<String>,
+    operands: Vec<Word>,
+}
+
+impl Arguments {
+    fn collect(program: &str, args: &[Word]) -> Self {
+        let mut collected = Self {
+            short_flags: BTreeSet::new(),
+            long_flags: Vec::new(),
+            operands: Vec::new(),
+        };
+        let mut options_done = false;
+        for arg in args {
+            let text = arg.text.to_lowercase();
+            if options_done || arg.dynamic || text.len() < 2 || !text.starts_with('-') {
+                collected.operands.push(normalized(arg));
+            } else if text == "--" {
+                options_done = true;
+            } else if text.starts_with("--") {
+                let name = text.split('=').next().unwrap_or(&text).to_string();
+                if let Some((_, _, flag)) = LONG_FLAG_ALIASES
+                    .iter()
+                    .find(|(p, long, _)| *p == program && *long == name)
+                {
+                    collected.short_flags.insert(*flag);
+                }
+                collected.long_flags.push(name);
+            } else {
+                collected.short_flags.extend(text.chars().skip(1));
+            }
+        }
+        collected
+    }
+}
+
+/// Lowercase a word and normalize it if it is a path (or `key=/path`).
+fn normalized(word: &Word) -> Word {
+    Word {
+        text: normalize_path(&word.text).to_lowercase(),
+        ..word.clone()
+    }
+}
+
+fn normalize_path(text: &str) -> String {
+    for home in ["${HOME}", "$HOME"] {
+        if let Some(rest) = text.strip_prefix(home)
+            && (rest.is_empty() || rest.starts_with('/'))
+        {
+            return normalize_path(&format!("~{}", rest));
+        }
+    }
+    if let Some((key, value)) = text.split_once('=')
+        && !key.contains('/')
+        && (value.starts_with('/') || value.starts_with('~'))
+    {
+        return format!("{}={}", key, normalize_path(value));
+    }
+
+    let (root, rest) = match text.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => ("~", rest),
+        _ if text.starts_with('/') => ("", text),
+        _ => return text.to_string(),
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in rest.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    match (root, parts.is_empty()) {
+        ("", true) => "/".to_string(),
+        (root, true) => root.to_string(),
+        (root, false) => format!("{}/{}", root, parts.join("/")),
+    }
+}
+
+/// Match a command word against a rule word, expanding the rule's wildcards.
+fn word_matches(rule: &Word, text: &str) -> bool {
+    if !rule.glob {
+        return rule.text == text;
+    }
+    let path = rule.text.starts_with('/') || rule.text.starts_with('~');
+    glob_matches(&rule.text, text, path)
+}
 
-    // Check custom patterns
-    for pattern in custom_patterns {
-        if !pattern.is_empty() && command_lower.contains(&pattern.to_lowercase()) {
-            return true;
+/// Shell-style wildcard match. With `path`, `*` and `?` stop at `/` and `**`
+/// matches across directories.
+pub fn glob_matches(pattern: &str, text: &str, path: bool) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match(&pattern, &text, path)
+}
+
+fn glob_match(pattern: &[char], text: &[char], path: bool) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => {
+            // `**` crosses directories in paths
+            let (rest, any) = match rest.split_first() {
+                Some(('*', rest)) if path => (rest, true),
+                _ => (rest, !path),
+            };
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..], path) {
+                    return true;
+                }
+                if !any && text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
         }
+        Some(('?', rest)) => text
+            .split_first()
+            .is_some_and(|(c, tail)| !(path && *c == '/') && glob_match(rest, tail, path)),
+        Some((c, rest)) => text
+            .split_first()
+            .is_some_and(|(t, tail)| t == c && glob_match(rest, tail, path)),
     }
+}
+
+/// Kinds of indirect execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndirectRule {
+    /// `eval` of a string only known at run time
+    Eval,
+    /// A shell running a script only known at run time (`sh -c "$X"`, `bash <(..)`)
+    DynamicShell,
+    /// A shell or interpreter reading a script decoded earlier in the pipeline
+    DecodeToShell,
+    /// A shell or interpreter reading a script from any other pipeline
+    PipeToShell,
+    /// An interpreter one-liner that runs system commands
+    InterpreterExec,
+}
+
+impl IndirectRule {
+    pub fn id(self) -> &'static str {
+        match self {
+            IndirectRule::Eval => "eval",
+            IndirectRule::DynamicShell => "dynamic-shell",
+            IndirectRule::DecodeToShell => "decode-to-shell",
+            IndirectRule::PipeToShell => "pipe-to-shell",
+            IndirectRule::InterpreterExec => "interpreter-exec",
+        }
+    }
+}
+
+/// What to do about an indirect execution finding, from least to most strict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndirectAction {
+    Off,
+    /// Run the command and report the finding
+    Warn,
+    /// Ask the user, like a policy `ask` rule
+    Ask,
+    Deny,
+}
+
+impl fmt::Display for IndirectAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IndirectAction::Off => "off",
+            IndirectAction::Warn => "warn",
+            IndirectAction::Ask => "ask",
+            IndirectAction::Deny => "deny",
+        })
+    }
+}
 
-    false
+fn warn() -> IndirectAction {
+    IndirectAction::Warn
 }
 
-/// Get the matched pattern for reporting
-pub fn find_matched_pattern(command: &str, custom_patterns: &[String]) -> Option<String> {
-    let command_lower = command.to_lowercase();
+fn deny() -> IndirectAction {
+    IndirectAction::Deny
+}
+
+/// Action per indirect execution rule (the policy file's `[indirect]` table).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct IndirectActions {
+    #[serde(default = "warn")]
+    pub eval: IndirectAction,
+    #[serde(default = "warn")]
+    pub dynamic_shell: IndirectAction,
+    #[serde(default = "deny")]
+    pub decode_to_shell: IndirectAction,
+    #[serde(default = "warn")]
+    pub pipe_to_shell: IndirectAction,
+    #[serde(default = "warn")]
+    pub interpreter_exec: IndirectAction,
+}
+
+impl Default for IndirectActions {
+    fn default() -> Self {
+        Self {
+            eval: warn(),
+            dynamic_shell: warn(),
+            decode_to_shell: deny(),
+            pipe_to_shell: warn(),
+            interpreter_exec: warn(),
+        }
+    }
+}
 
-    // Check default denylist
-    for pattern in DEFAULT_DENYLIST {
-        if command_lower.contains(&pattern.to_lowercase()) {
-            return Some(pattern.to_string());
+impl IndirectActions {
+    pub fn action(&self, rule: IndirectRule) -> IndirectAction {
+        match rule {
+            IndirectRule::Eval => self.eval,
+            IndirectRule::DynamicShell => self.dynamic_shell,
+            IndirectRule::DecodeToShell => self.decode_to_shell,
+            IndirectRule::PipeToShell => self.pipe_to_shell,
+            IndirectRule::InterpreterExec => self.interpreter_exec,
         }
     }
+}
+
+/// A construct that runs code the denylist cannot inspect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndirectFinding {
+    pub rule: IndirectRule,
+    pub action: IndirectAction,
+    pub detail: String,
+    /// Source text of the sub-command
+    pub command: String,
+}
+
+impl fmt::Display for IndirectFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}: {}] (in `{}`)",
+            self.detail,
+            self.rule.id(),
+            self.action,
+            self.command
+        )
+    }
+}
+
+/// Interpreters and the options that take inline code.
+const INTERPRETERS: &[(&str, &[&str])] = &[
+    ("python", &["-c"]),
+    ("pypy", &["-c"]),
+    ("perl", &["-e", "-E"]),
+    ("ruby", &["-e"]),
+    ("node", &["-e", "--eval", "-p", "--print"]),
+    ("nodejs", &["-e", "--eval", "-p", "--print"]),
+    ("bun", &["-e", "--eval", "-p", "--print"]),
+    ("deno", &["eval"]),
+    ("php", &["-r"]),
+    ("lua", &["-e"]),
+    ("luajit", &["-e"]),
+    ("rscript", &["-e"]),
+    ("osascript", &["-e"]),
+    ("pwsh", &["-c", "-command", "-encodedcommand", "-e", "-ec"]),
+    (
+        "powershell",
+        &["-c", "-command", "-encodedcommand", "-e", "-ec"],
+    ),
+];
+
+/// Calls that run other programs, in the interpreters above.
+const SYSTEM_CALLS: &[&str] = &[
+    "system",
+    "exec",
+    "popen",
+    "spawn",
+    "subprocess",
+    "child_process",
+    "shell_exec",
+    "passthru",
+    "proc_open",
+    "open3",
+    "qx",
+    "%x",
+    "`",
+    "invoke-expression",
+    "start-process",
+    "do shell script",
+    "__import__",
+    "eval",
+];
+
+const AWKS: &[&str] = &["awk", "gawk", "mawk", "nawk"];
+
+/// The interpreter table entry for a program name (`python3.12` is `python`).
+fn interpreter(name: &str) -> Option<&'static (&'static str, &'static [&'static str])> {
+    let family = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS.iter().find(|(n, _)| *n == family)
+}
+
+/// How an interpreter invocation gets its code.
+enum Code<'a> {
+    Inline(&'a Word),
+    Stdin,
+    File,
+}
 
-    // Check custom patterns
-    for pattern in custom_patterns {
-        if !pattern.is_empty() && command_lower.contains(&pattern.to_lowercase()) {
-            return Some(pattern.clone());
+fn interpreter_code<'a>(inline_flags: &[&str], args: &'a [Word]) -> Code<'a> {
+    let mut words = args.iter();
+    while let Some(word) = words.next() {
+        let text = word.text.to_lowercase();
+        let (flag, attached) = match text.split_once('=') {
+            Some((flag, _)) if flag.starts_with("--") => (flag.to_string(), true),
+            _ => (text.clone(), false),
+        };
+        if inline_flags.contains(&flag.as_str()) {
+            if attached {
+                return Code::Inline(word);
+            }
+            return words.next().map_or(Code::Stdin, Code::Inline);
+        }
+        // A short option cluster ending in the inline flag: `python3 -Ic 'code'`
+        let cluster = text.len() > 2 && text.starts_with('-') && !text.starts_with("--");
+        if cluster
+            && inline_flags
+                .iter()
+                .any(|f| f.len() == 2 && text.ends_with(&f[1..]))
+        {
+            return words.next().map_or(Code::Stdin, Code::Inline);
+        }
+        if text == "-" {
+            return Code::Stdin;
+        }
+        if !text.starts_with('-') {
+            return Code::File;
         }
     }
+    Code::Stdin
+}
 
+/// The awk program text, unless it comes from a file (`-f`).
+fn awk_program(args: &[Word]) -> Option<&Word> {
+    let mut words = args.iter();
+    while let Some(word) = words.next() {
+        match word.text.as_str() {
+            "-f" | "--file" => return None,
+            "-F" | "-v" | "--assign" | "--field-separator" => {
+                words.next();
+            }
+            "--" => return words.next(),
+            text if text.starts_with('-') && text.len() > 1 => {}
+            _ => return Some(word),
+        }
+    }
     None
 }
 
+fn system_call(code: &str) -> Option<&'static str> {
+    let code = code.to_lowercase();
+    SYSTEM_CALLS
+        .iter()
+        .copied()
+        .find(|call| code.contains(call))
+}
+
+/// A pipeline stage that decodes or unpacks its input.
+fn decoder(argv: &[Word]) -> Option<String> {
+    let (program, args) = argv.split_first()?;
+    let name = shell_parser::basename(&program.text);
+    // The flag on its own or in a short option cluster (`xxd -rp`, `gzip -dc`)
+    let has = |flags: &[&str]| {
+        args.iter().any(|arg| {
+            let text = arg.text.as_str();
+            flags.contains(&text)
+                || (text.starts_with('-')
+                    && !text.starts_with("--")
+                    && flags
+                        .iter()
+                        .any(|f| f.len() == 2 && text[1..].contains(&f[1..])))
+        })
+    };
+    let decodes = match name.as_str() {
+        "base64" | "base32" | "basenc" => has(&["-d", "--decode", "-D"]),
+        "xxd" => has(&["-r", "--revert"]),
+        "openssl" => has(&["-d"]),
+        "gzip" | "bzip2" | "xz" | "zstd" | "lz4" | "lzma" => has(&["-d", "--decompress"]),
+        "gunzip" | "zcat" | "bzcat" | "bunzip2" | "xzcat" | "unxz" | "zstdcat" | "uudecode"
+        | "rev" => true,
+        "printf" | "echo" => args.iter().any(|a| a.text.contains("\\x")),
+        _ => false,
+    };
+    decodes.then_some(name)
+}
+
+/// Find constructs in a parsed command line that run code the denylist cannot
+/// inspect. Rules whose action is `off` are skipped.
+pub fn find_indirect(parsed: &ParsedCommand, actions: &IndirectActions) -> Vec<IndirectFinding> {
+    let mut findings = Vec::new();
+    for (index, command) in parsed.commands.iter().enumerate() {
+        let Some(argv) = command.invocations().last().copied() else {
+            continue;
+        };
+        let Some((program, args)) = argv.split_first() else {
+            continue;
+        };
+        let name = shell_parser::basename(&program.text);
+        let reads_stdin = |code_from_stdin: bool| command.piped_in && code_from_stdin;
+
+        let found: Option<(IndirectRule, String)> = if name == "eval" {
+            args.iter().any(|a| a.dynamic).then(|| {
+                (
+                    IndirectRule::Eval,
+                    "`eval` runs a string only known at run time".to_string(),
+                )
+            })
+        } else if matches!(name.as_str(), "source" | ".") {
+            args.first().filter(|a| a.dynamic).map(|_| {
+                (
+                    IndirectRule::DynamicShell,
+                    format!("`{}` runs a script only known at run time", name),
+                )
+            })
+        } else if let Some(input) = command.shell_input() {
+            match input {
+                ShellInput::Inline(word) | ShellInput::File(word) if word.dynamic => Some((
+                    IndirectRule::DynamicShell,
+                    format!("`{}` runs a script only known at run time", name),
+                )),
+                ShellInput::File(word) if word.text == "/dev/stdin" => {
+                    reads_stdin(true).then(|| pipe_finding(parsed, index, &name))
+                }
+                ShellInput::Stdin => reads_stdin(true).then(|| pipe_finding(parsed, index, &name)),
+                _ => None,
+            }
+        } else if let Some((_, inline_flags)) = interpreter(&name) {
+            match interpreter_code(inline_flags, args) {
+                Code::Inline(word) if word.dynamic => Some((
+                    IndirectRule::InterpreterExec,
+                    format!("`{}` runs code only known at run time", name),
+                )),
+                Code::Inline(word) => system_call(&word.text).map(|call| {
+                    (
+                        IndirectRule::InterpreterExec,
+                        format!("`{}` one-liner calls `{}`", name, call),
+                    )
+                }),
+                Code::Stdin => reads_stdin(true).then(|| pipe_finding(parsed, index, &name)),
+                Code::File => None,
+            }
+        } else if AWKS.contains(&name.as_str()) {
+            awk_program(args).and_then(|program| {
+                let code = program.text.replace("||", "");
+                (code.contains("system(") || code.contains('|')).then(|| {
+                    (
+                        IndirectRule::InterpreterExec,
+                        format!("`{}` program runs shell commands", name),
+                    )
+                })
+            })
+        } else {
+            None
+        };
+
+        if let Some((rule, detail)) = found {
+            let action = actions.action(rule);
+            if action != IndirectAction::Off {
+                findings.push(IndirectFinding {
+                    rule,
+                    action,
+                    detail,
+                    command: command.text.clone(),
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// Classify a shell reading its script from the pipeline ending at `index`.
+fn pipe_finding(parsed: &ParsedCommand, index: usize, name: &str) -> (IndirectRule, String) {
+    let mut stage = index;
+    while stage > 0 && parsed.commands[stage].piped_in {
+        stage -= 1;
+        let upstream = &parsed.commands[stage];
+        if let Some(decoder) = upstream.invocations().into_iter().find_map(decoder) {
+            return (
+                IndirectRule::DecodeToShell,
+                format!("`{}` runs a script decoded by `{}`", name, decoder),
+            );
+        }
+    }
+    let source = parsed.commands[stage].text.clone();
+    (
+        IndirectRule::PipeToShell,
+        format!("`{}` runs a script piped from `{}`", name, source),
+    )
+}
+
+/// The built-in rules compiled from [`DEFAULT_DENYLIST`].
+pub fn default_rules() -> &'static [Rule] {
+    static RULES: OnceLock<Vec<Rule>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        DEFAULT_DENYLIST
+            .iter()
+            .filter_map(|p| Rule::new(p))
+            .collect()
+    })
+}
+
+/// Check if a command matches any pattern in the denylist
+#[allow(dead_code)]
+pub fn is_denied(command: &str, custom_patterns: &[String]) -> bool {
+    find_matched_pattern(command, custom_patterns).is_some()
+}
+
+/// Find the first rule the command matches and the sub-command that matched it
+pub fn find_matched_pattern(command: &str, custom_patterns: &[String]) -> Option<DenylistMatch> {
+    find_match(
+        command,
+        &shell_parser::parse(command),
+        custom_patterns,
+        true,
+    )
+}
+
+/// [`find_matched_pattern`] for an already parsed command; `builtin` selects
+/// whether the default rules apply.
+pub fn find_match(
+    command: &str,
+    parsed: &ParsedCommand,
+    custom_patterns: &[String],
+    builtin: bool,
+) -> Option<DenylistMatch> {
+    let builtin_rules = if builtin { default_rules() } else { &[] };
+    let custom: Vec<Rule> = custom_patterns
+        .iter()
+        .filter_map(|p| Rule::new(p))
+        .collect();
+
+    builtin_rules.iter().chain(&custom).find_map(|rule| {
+        rule.find(command, parsed).map(|matched| DenylistMatch {
+            rule: rule.pattern.clone(),
+            command: matched,
+        })
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -138,4 +874,132 @@ mod tests {
         assert!(is_denied("SHUTDOWN", &[]));
         assert!(is_denied("Chmod 777 /", &[]));
     }
+
+    #[test]
+    fn matches_argv_not_substrings() {
+        assert!(!is_denied("rm -rf /tmp/build", &[]));
+        assert!(!is_denied("echo shutdown", &[]));
+        assert!(!is_denied("grep -r 'rm -rf /' docs", &[]));
+        assert!(!is_denied("cat <<EOF\nreboot\nEOF", &[]));
+
+        assert!(is_denied("rm  -rf  /", &[]));
+        assert!(is_denied("rm -r -f /", &[]));
+        assert!(is_denied("rm --recursive --force //", &[]));
+        assert!(is_denied("/bin/rm -fr /usr/..", &[]));
+        assert!(is_denied("$(printf rm) -rf /", &[]));
+        assert!(is_denied("'r'm -rf \"$HOME\"", &[]));
+        assert!(is_denied("rm -rf /etc", &[]));
+        assert!(is_denied("rm -rf '*'", &[]));
+        assert!(!is_denied("rm -rf build", &[]));
+        assert!(is_denied("echo hi > /dev/sda", &[]));
+        assert!(is_denied("mkfs.ext4 /dev/sdb1", &[]));
+        assert!(is_denied("env -S 'rm -rf /'", &[]));
+        assert!(is_denied("env --split-string='rm -rf /'", &[]));
+        assert!(is_denied("sudo env -i -S'rm -rf' /", &[]));
+        assert!(!is_denied("env -S 'rm -rf build'", &[]));
+    }
+
+    #[test]
+    fn reports_rule_and_sub_command() {
+        let matched = find_matched_pattern(
+            "cd /tmp && ls | sudo -u root xargs echo; (true && reboot now)",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(matched.rule, "reboot");
+        assert_eq!(matched.command, "reboot now");
+        assert_eq!(matched.to_string(), "reboot (in `reboot now`)");
+
+        let matched = find_matched_pattern("$(printf rm) -rf /", &[]).unwrap();
+        assert_eq!(matched.command, "$(printf rm) -rf /");
+
+        let matched = find_matched_pattern("echo $(sh -c 'shutdown -h now')", &[]).unwrap();
+        assert_eq!(matched.rule, "shutdown");
+        assert_eq!(matched.command, "shutdown -h now");
+
+        let matched = find_matched_pattern("bomb(){ bomb | bomb & }; bomb", &[]).unwrap();
+        assert_eq!(matched.rule, ":(){:|:&};:");
+        assert_eq!(matched.command, "bomb(){ bomb | bomb & }");
+
+        let custom = vec!["git push --force".to_string()];
+        let matched =
+            find_matched_pattern("git status && git push origin main --force", &custom).unwrap();
+        assert_eq!(matched.rule, "git push --force");
+        assert_eq!(matched.command, "git push origin main --force");
+        assert!(find_matched_pattern("git push origin main", &custom).is_none());
+    }
+
+    fn indirect(command: &str) -> Vec<(IndirectRule, IndirectAction)> {
+        find_indirect(&shell_parser::parse(command), &IndirectActions::default())
+            .into_iter()
+            .map(|f| (f.rule, f.action))
+            .collect()
+    }
+
+    #[test]
+    fn flags_indirect_execution() {
+        use IndirectAction::{Deny, Warn};
+        use IndirectRule::*;
+        for (command, expected) in [
+            (
+                "echo cm0gLXJmIC8= | base64 -d | sh",
+                vec![(DecodeToShell, Deny)],
+            ),
+            ("xxd -rp payload.hex | bash -s", vec![(DecodeToShell, Deny)]),
+            (
+                "curl -fsSL https://example.com/install.sh | sh",
+                vec![(PipeToShell, Warn)],
+            ),
+            ("cat script.py | python3 -", vec![(PipeToShell, Warn)]),
+            ("eval \"$CMD\"", vec![(Eval, Warn)]),
+            (
+                "bash -c \"$(curl -s example.com)\"",
+                vec![(DynamicShell, Warn)],
+            ),
+            ("bash <(curl -s example.com)", vec![(DynamicShell, Warn)]),
+            ("source \"$SCRIPT\"", vec![(DynamicShell, Warn)]),
+            (
+                "python3 -c 'import os; os.system(\"id\")'",
+                vec![(InterpreterExec, Warn)],
+            ),
+            ("perl -e 'exec \"id\"'", vec![(InterpreterExec, Warn)]),
+            (
+                "awk 'BEGIN { system(\"id\") }'",
+                vec![(InterpreterExec, Warn)],
+            ),
+            ("node -e \"$JS\"", vec![(InterpreterExec, Warn)]),
+        ] {
+            assert_eq!(indirect(command), expected, "{command}");
+        }
+    }
+
+    #[test]
+    fn leaves_inspectable_commands_alone() {
+        for command in [
+            "eval 'echo hi'",
+            "sh -c 'ls -la'",
+            "bash script.sh",
+            "python3 -c 'print(1 + 1)'",
+            "python3 manage.py migrate",
+            "awk '{ print $1 }' file.txt",
+            "awk '$1 > 0 || $2 > 0' file.txt",
+            "echo hi | grep h",
+            "base64 -d blob.txt > out.bin",
+        ] {
+            assert_eq!(indirect(command), vec![], "{command}");
+        }
+
+        // Literal eval and sh -c scripts are matched like any other command
+        assert!(is_denied("eval 'rm -rf /'", &[]));
+        assert!(is_denied("bash -c 'rm -rf /'", &[]));
+
+        let actions: IndirectActions =
+            toml::from_str("eval = \"deny\"\ndecode-to-shell = \"off\"").unwrap();
+        let findings = find_indirect(&shell_parser::parse("base64 -d x | sh; eval $X"), &actions);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].to_string(),
+            "`eval` runs a string only known at run time [eval: deny] (in `eval $X`)"
+        );
+    }
 }
@@ -1,20 +1,103 @@
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{self, BufReader, Read, Write};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use super::audit_log::{self, JobOutcome};
+use super::cgroup::{CgroupPlacement, CgroupStats};
+use super::landlock::WriteRestriction;
+use super::output_decoder::OutputEncoding;
+use super::output_search::{self, SearchCursor, SearchOptions, SearchPage};
+use super::output_spool::Spool;
+use super::process_group::TerminationStage;
+use super::resource_limits::{LimitExceeded, ResourceLimits};
+use super::resource_usage::ResourceUsage;
+use super::sandbox::Sandbox;
+use super::termination::{Termination, TerminationContext};
+
 /// Job status for background command execution
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum JobStatus {
     Running,
+    /// Stopped by SIGSTOP/SIGTSTP via `enhanced_terminal_job_signal`; resumes on SIGCONT
+    Paused,
     Completed,
     Failed,
     TimedOut,
     Canceled,
 }
 
+impl JobStatus {
+    /// True while the job's processes exist, whether running or paused
+    pub fn is_active(&self) -> bool {
+        matches!(self, JobStatus::Running | JobStatus::Paused)
+    }
+}
+
+/// Which part of a job's output to read.
+///
+/// PTY jobs only have the `Combined` stream. Jobs run with pipes additionally keep
+/// `Stdout` and `Stderr` separately, and can render `Interleaved`, which is the
+/// combined output in arrival order with each line labeled by its source stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    #[default]
+    Combined,
+    Stdout,
+    Stderr,
+    Interleaved,
+}
+
+/// Contiguous run of combined output that came from a single stream.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamSegment {
+    pub stream: OutputStream,
+    /// Exclusive end offset of the segment in the combined output
+    pub end_byte: usize,
+}
+
+/// Everything a job printed, spooled to disk (see
+/// [`output_spool`](super::output_spool)).
+#[derive(Debug)]
+pub struct JobOutput {
+    pub combined: Spool,
+    /// Only written for jobs that capture the streams separately
+    pub stdout: Spool,
+    pub stderr: Spool,
+    /// Stream boundaries within `combined`, in arrival order
+    pub stream_segments: Vec<StreamSegment>,
+}
+
+impl JobOutput {
+    fn new(job_id: &str) -> Self {
+        Self {
+            combined: Spool::new(format!("{}.out", job_id)),
+            stdout: Spool::new(format!("{}.stdout", job_id)),
+            stderr: Spool::new(format!("{}.stderr", job_id)),
+            stream_segments: Vec::new(),
+        }
+    }
+
+    /// Close the spool files; the job produces no more output.
+    fn finish(&mut self) {
+        self.combined.finish();
+        self.stdout.finish();
+        self.stderr.finish();
+    }
+
+    fn stream(&self, stream: OutputStream) -> &Spool {
+        match stream {
+            OutputStream::Stdout => &self.stdout,
+            OutputStream::Stderr => &self.stderr,
+            OutputStream::Combined | OutputStream::Interleaved => &self.combined,
+        }
+    }
+}
+
 /// Record of a background job
 #[derive(Debug, Clone, Serialize)]
 pub struct JobRecord {
@@ -26,36 +109,299 @@ pub struct JobRecord {
     pub finished_at: Option<SystemTime>,
     pub status: JobStatus,
     pub exit_code: Option<i32>,
-    pub output: String,
-    pub full_output: String,
-    pub truncated: bool,
+    /// All output; clones of the record share it
+    #[serde(skip)]
+    pub spool: Arc<Mutex<JobOutput>>,
+    /// Bytes of output a full `job_status` read shows, read from the spool
+    pub output_limit: usize,
+    /// First characters of output, kept as it arrives so listings never read
+    /// the spool
+    pub output_preview: String,
+    /// True once there is more output than `output_preview`
+    pub output_past_preview: bool,
     pub pid: Option<u32>,
     pub last_read_position: usize,
     /// Optional tags for categorizing jobs (e.g., ["build", "ci"])
     pub tags: Vec<String>,
     /// Command summary (first N characters)
     pub summary: String,
+    /// True when stdout and stderr were captured separately (pipes capture mode)
+    pub separate_streams: bool,
+    pub last_stdout_read_position: usize,
+    pub last_stderr_read_position: usize,
+    /// Escalation stage that ended the job after a timeout or cancel request
+    pub termination_stage: Option<TerminationStage>,
+    /// Effective rlimits the job runs with
+    pub resource_limits: ResourceLimits,
+    /// Resource limit that ended the job, if one did
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// The job's cgroup v2 group, or why it could not get one
+    pub cgroup: Option<CgroupPlacement>,
+    /// CPU time, peak RSS, faults and context switches reported when the job was reaped
+    pub resource_usage: Option<ResourceUsage>,
+    /// Signal, core dump and explanation when the job did not exit cleanly
+    pub termination: Option<Termination>,
+    /// Sandbox profile the job runs under
+    pub sandbox: Option<Sandbox>,
+    /// Landlock write restrictions the job runs under
+    pub write_restriction: Option<WriteRestriction>,
+    /// Secrets replaced in the stored output
+    pub redactions: usize,
+    /// Encoding the output is decoded with
+    pub encoding: OutputEncoding,
+    /// Byte sequences in the output that were not valid UTF-8
+    pub invalid_utf8: usize,
+    /// How a policy ask decision was approved, if the job needed one
+    pub approval: Option<String>,
+}
+
+/// Elapsed time of a job, or its total once it finished.
+fn duration_between(started_at: SystemTime, finished_at: Option<SystemTime>) -> Option<Duration> {
+    let end_time = finished_at.unwrap_or_else(SystemTime::now);
+    end_time.duration_since(started_at).ok()
+}
+
+fn format_duration(started_at: SystemTime, finished_at: Option<SystemTime>) -> String {
+    duration_between(started_at, finished_at)
+        .map(|d| {
+            if finished_at.is_some() {
+                format!("{:.2}s", d.as_secs_f64())
+            } else {
+                format!("{:.2}s (running)", d.as_secs_f64())
+            }
+        })
+        .unwrap_or_else(|| "unknown".to_string())
 }
 
 impl JobRecord {
     /// Get duration of the job (elapsed or total if finished)
     pub fn duration(&self) -> Option<Duration> {
-        let end_time = self.finished_at.unwrap_or_else(SystemTime::now);
-        end_time.duration_since(self.started_at).ok()
+        duration_between(self.started_at, self.finished_at)
     }
 
     /// Get duration as formatted string
     pub fn duration_string(&self) -> String {
-        self.duration()
-            .map(|d| {
-                if self.finished_at.is_some() {
-                    format!("{:.2}s", d.as_secs_f64())
-                } else {
-                    format!("{:.2}s (running)", d.as_secs_f64())
-                }
-            })
-            .unwrap_or_else(|| "unknown".to_string())
+        format_duration(self.started_at, self.finished_at)
+    }
+
+    /// Total bytes of combined output so far
+    pub fn output_len(&self) -> usize {
+        self.spool.lock().unwrap().combined.len()
+    }
+
+    /// True when there is more output than `output_limit`
+    pub fn truncated(&self) -> bool {
+        self.output_len() > self.output_limit
+    }
+}
+
+/// Characters of output shown in a job summary.
+const SUMMARY_PREVIEW_CHARS: usize = 100;
+
+/// What `enhanced_terminal_job_list` shows of a job. Built under the jobs lock
+/// from the record alone, without touching the job's output.
+#[derive(Debug, Clone)]
+pub struct JobSummary {
+    pub job_id: String,
+    pub summary: String,
+    pub shell: String,
+    pub cwd: String,
+    pub started_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub pid: Option<u32>,
+    pub tags: Vec<String>,
+    pub termination: Option<Termination>,
+    pub termination_stage: Option<TerminationStage>,
+    pub limit_exceeded: Option<LimitExceeded>,
+    pub sandbox: Option<String>,
+    pub resource_usage: Option<ResourceUsage>,
+    /// First characters of output, with "..." when there is more
+    pub output_preview: String,
+}
+
+impl JobSummary {
+    fn new(job: &JobRecord) -> Self {
+        let mut output_preview = job.output_preview.clone();
+        if job.output_past_preview {
+            output_preview.push_str("...");
+        }
+        Self {
+            job_id: job.job_id.clone(),
+            summary: job.summary.clone(),
+            shell: job.shell.clone(),
+            cwd: job.cwd.clone(),
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            status: job.status.clone(),
+            exit_code: job.exit_code,
+            pid: job.pid,
+            tags: job.tags.clone(),
+            termination: job.termination.clone(),
+            termination_stage: job.termination_stage,
+            limit_exceeded: job.limit_exceeded.clone(),
+            sandbox: job.sandbox.as_ref().map(|sandbox| sandbox.name.clone()),
+            resource_usage: job.resource_usage,
+            output_preview,
+        }
+    }
+
+    pub fn duration_string(&self) -> String {
+        format_duration(self.started_at, self.finished_at)
+    }
+}
+
+/// Position in a job listing, handed out as `next_cursor` and passed back to get
+/// the following page. Jobs are ordered by start time, then id, so a cursor stays
+/// valid while jobs are added or removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobCursor {
+    oldest_first: bool,
+    started_at_nanos: u128,
+    job_id: String,
+}
+
+impl JobCursor {
+    /// Whether the cursor belongs to an oldest-first listing.
+    pub fn oldest_first(&self) -> bool {
+        self.oldest_first
+    }
+
+    fn after(job: &JobRecord, oldest_first: bool) -> Self {
+        Self {
+            oldest_first,
+            started_at_nanos: start_nanos(job),
+            job_id: job.job_id.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for JobCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let order = if self.oldest_first {
+            "oldest"
+        } else {
+            "newest"
+        };
+        write!(f, "{}:{}:{}", order, self.started_at_nanos, self.job_id)
+    }
+}
+
+impl std::str::FromStr for JobCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("invalid cursor '{}'", text);
+        let mut parts = text.splitn(3, ':');
+        let oldest_first = match parts.next() {
+            Some("oldest") => true,
+            Some("newest") => false,
+            _ => return Err(invalid()),
+        };
+        let started_at_nanos = parts
+            .next()
+            .and_then(|nanos| nanos.parse().ok())
+            .ok_or_else(invalid)?;
+        let job_id = parts
+            .next()
+            .filter(|id| !id.is_empty())
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            oldest_first,
+            started_at_nanos,
+            job_id: job_id.to_string(),
+        })
+    }
+}
+
+fn start_nanos(job: &JobRecord) -> u128 {
+    job.started_at
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or_default()
+}
+
+/// Which jobs to list, and which page of them.
+#[derive(Debug, Clone, Default)]
+pub struct JobQuery<'a> {
+    pub statuses: Option<&'a [JobStatus]>,
+    pub tag: Option<&'a str>,
+    pub cwd: Option<&'a str>,
+    pub oldest_first: bool,
+    /// Continue after this position of an earlier page
+    pub cursor: Option<&'a JobCursor>,
+    pub limit: usize,
+}
+
+impl JobQuery<'_> {
+    fn matches(&self, job: &JobRecord) -> bool {
+        // Filter by status
+        if let Some(statuses) = self.statuses
+            && !statuses
+                .iter()
+                .any(|s| std::mem::discriminant(s) == std::mem::discriminant(&job.status))
+        {
+            return false;
+        }
+
+        // Filter by tag
+        if let Some(tag) = self.tag
+            && !job.tags.iter().any(|t| t == tag)
+        {
+            return false;
+        }
+
+        // Filter by cwd
+        if let Some(cwd) = self.cwd
+            && job.cwd != cwd
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// One page of a job listing.
+#[derive(Debug, Clone)]
+pub struct JobPage {
+    pub jobs: Vec<JobSummary>,
+    /// Jobs matching the filters, on every page
+    pub total: usize,
+    /// Jobs listed before this page
+    pub skipped: usize,
+    pub next_cursor: Option<JobCursor>,
+}
+
+/// Finished jobs kept by default before the oldest are dropped.
+const DEFAULT_JOB_HISTORY: usize = 1000;
+
+/// How many finished jobs to keep, from `ENHANCED_TERMINAL_JOB_HISTORY`.
+fn job_history() -> usize {
+    std::env::var("ENHANCED_TERMINAL_JOB_HISTORY")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_JOB_HISTORY)
+        .max(1)
+}
+
+/// Remove the oldest finished jobs beyond `keep` and return them, so their spool
+/// files can be removed outside the jobs lock.
+fn evict_finished(jobs: &mut HashMap<String, JobRecord>, keep: usize) -> Vec<JobRecord> {
+    let mut finished: Vec<(SystemTime, &String)> = jobs
+        .values()
+        .filter_map(|job| job.finished_at.map(|at| (at, &job.job_id)))
+        .collect();
+    if finished.len() <= keep {
+        return Vec::new();
     }
+    finished.sort_unstable();
+    let oldest: Vec<String> = finished[..finished.len() - keep]
+        .iter()
+        .map(|(_, id)| (*id).clone())
+        .collect();
+    oldest.iter().filter_map(|id| jobs.remove(id)).collect()
 }
 
 /// Global job registry
@@ -67,12 +413,69 @@ pub struct JobManager {
     stdin_writers: Arc<Mutex<HashMap<String, PtyWriter>>>,
 }
 
-fn floor_char_boundary(text: &str, mut index: usize) -> usize {
-    index = index.min(text.len());
-    while index > 0 && !text.is_char_boundary(index) {
-        index -= 1;
+/// Render combined output `start..end` with every line prefixed by its source stream.
+fn label_interleaved(output: &JobOutput, start: usize, end: usize) -> io::Result<String> {
+    let mut rendered = String::new();
+    let mut segment_start = 0;
+    let mut at_line_start = output.combined.at_line_start(start);
+
+    for segment in &output.stream_segments {
+        let from = segment_start.max(start);
+        let to = segment.end_byte.min(end);
+        segment_start = segment.end_byte;
+        if from >= to {
+            continue;
+        }
+
+        let label = match segment.stream {
+            OutputStream::Stderr => "[stderr] ",
+            _ => "[stdout] ",
+        };
+        for piece in output.combined.read(from, to)?.split_inclusive('\n') {
+            if at_line_start {
+                rendered.push_str(label);
+            }
+            rendered.push_str(piece);
+            at_line_start = piece.ends_with('\n');
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Spooled output, or a note in its place when the spool file cannot be read.
+/// Reads a stream of a job's output in chunks. The spool is locked only while a
+/// chunk is copied, so the job keeps writing output during a long read.
+struct SpoolReader {
+    output: Arc<Mutex<JobOutput>>,
+    stream: OutputStream,
+    position: usize,
+    end: usize,
+}
+
+impl Read for SpoolReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = self.end.min(self.position.saturating_add(buf.len()));
+        if self.position >= end {
+            return Ok(0);
+        }
+        let bytes = self
+            .output
+            .lock()
+            .unwrap()
+            .stream(self.stream)
+            .read_bytes(self.position, end)?;
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        self.position += bytes.len();
+        Ok(bytes.len())
     }
-    index
+}
+
+fn text_or_note(job_id: &str, text: io::Result<String>) -> String {
+    text.unwrap_or_else(|e| {
+        tracing::warn!("Failed to read the output of job {}: {}", job_id, e);
+        format!("[output unavailable: {}]", e)
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -86,6 +489,29 @@ pub struct OutputRange {
     pub next_offset_bytes: Option<usize>,
 }
 
+/// Which lines of a stream to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineSelection {
+    /// `count` lines (all remaining if `None`) from the 1-based line `start`.
+    /// A negative `start` counts from the end: -1 is the last line.
+    From { start: i64, count: Option<usize> },
+    /// The last `count` lines
+    Tail(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct LineRange {
+    pub output: String,
+    pub total_lines: usize,
+    /// 1-based number of the first returned line
+    pub first_line: usize,
+    pub line_count: usize,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    /// Set when lines follow the returned ones
+    pub next_start_line: Option<usize>,
+}
+
 const JOB_ID_ADJECTIVES: &[&str] = &[
     "amber", "ancient", "autumn", "bold", "brave", "bright", "calm", "clever", "cosmic", "crimson",
     "curious", "daring", "dusky", "eager", "frosty", "gentle", "golden", "hidden", "honest",
@@ -101,7 +527,7 @@ const JOB_ID_NOUNS: &[&str] = &[
     "saffron", "sparrow", "summit", "thunder", "tiger", "violet", "voyager", "willow", "zephyr",
 ];
 
-fn readable_job_id(sequence: u64) -> String {
+pub(super) fn readable_job_id(sequence: u64) -> String {
     let seed = mix_job_id_seed(sequence);
     let adjective = JOB_ID_ADJECTIVES[seed as usize % JOB_ID_ADJECTIVES.len()];
     let noun = JOB_ID_NOUNS[(seed as usize / JOB_ID_ADJECTIVES.len()) % JOB_ID_NOUNS.len()];
@@ -196,6 +622,7 @@ impl JobManager {
             command.clone()
         };
 
+        let spool = Arc::new(Mutex::new(JobOutput::new(&job_id)));
         let mut jobs = self.jobs.lock().unwrap();
         jobs.insert(
             job_id.clone(),
@@ -208,33 +635,98 @@ impl JobManager {
                 finished_at: None,
                 status: JobStatus::Running,
                 exit_code: None,
-                output: String::new(),
-                full_output: String::new(),
-                truncated: false,
+                spool,
+                output_limit: 0,
+                output_preview: String::new(),
+                output_past_preview: false,
                 pid,
                 last_read_position: 0,
                 tags,
                 summary,
+                separate_streams: false,
+                last_stdout_read_position: 0,
+                last_stderr_read_position: 0,
+                termination_stage: None,
+                resource_limits: ResourceLimits::default(),
+                limit_exceeded: None,
+                cgroup: None,
+                resource_usage: None,
+                termination: None,
+                sandbox: None,
+                write_restriction: None,
+                redactions: 0,
+                encoding: OutputEncoding::default(),
+                invalid_utf8: 0,
+                approval: None,
             },
         );
     }
 
+    /// Note the job's `output_limit`, take what the preview still lacks from
+    /// `output` and return the spool to append it to.
+    fn output_spool(
+        &self,
+        job_id: &str,
+        output: &str,
+        output_limit: usize,
+    ) -> Option<Arc<Mutex<JobOutput>>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id)?;
+        job.output_limit = output_limit;
+        if !job.output_past_preview {
+            let mut chars = output.chars();
+            let missing = SUMMARY_PREVIEW_CHARS - job.output_preview.chars().count();
+            job.output_preview.extend(chars.by_ref().take(missing));
+            job.output_past_preview = chars.next().is_some();
+        }
+        Some(Arc::clone(&job.spool))
+    }
+
     /// Update job with output (incremental)
+    ///
+    /// The spool file is written outside the jobs lock.
     pub fn append_output(&self, job_id: &str, output: &str, output_limit: usize) {
+        if let Some(spool) = self.output_spool(job_id, output, output_limit) {
+            spool.lock().unwrap().combined.append(output);
+        }
+    }
+
+    /// Mark a job as capturing stdout and stderr separately.
+    pub fn mark_separate_streams(&self, job_id: &str) {
         let mut jobs = self.jobs.lock().unwrap();
         if let Some(job) = jobs.get_mut(job_id) {
-            job.full_output.push_str(output);
+            job.separate_streams = true;
+        }
+    }
 
-            if job.output.len() + output.len() <= output_limit {
-                job.output.push_str(output);
-            } else {
-                let remaining = output_limit.saturating_sub(job.output.len());
-                if remaining > 0 {
-                    let end = floor_char_boundary(output, remaining.min(output.len()));
-                    job.output.push_str(&output[..end]);
-                }
-                job.truncated = true;
-            }
+    /// Update job with output from a separately captured stdout or stderr stream.
+    ///
+    /// The chunk is appended to the stream's own buffer and to the combined view,
+    /// which therefore stays ordered by arrival time.
+    pub fn append_stream_output(
+        &self,
+        job_id: &str,
+        stream: OutputStream,
+        output: &str,
+        output_limit: usize,
+    ) {
+        let Some(spool) = self.output_spool(job_id, output, output_limit) else {
+            return;
+        };
+        let mut spool = spool.lock().unwrap();
+        spool.combined.append(output);
+        match stream {
+            OutputStream::Stdout => spool.stdout.append(output),
+            OutputStream::Stderr => spool.stderr.append(output),
+            _ => return,
+        }
+
+        let end_byte = spool.combined.len();
+        match spool.stream_segments.last_mut() {
+            Some(last) if last.stream == stream => last.end_byte = end_byte,
+            _ => spool
+                .stream_segments
+                .push(StreamSegment { stream, end_byte }),
         }
     }
 
@@ -251,7 +743,7 @@ impl JobManager {
             let job = jobs
                 .get(job_id)
                 .ok_or_else(|| anyhow::anyhow!("Job not found"))?;
-            if !matches!(job.status, JobStatus::Running) {
+            if !job.status.is_active() {
                 return Err(anyhow::anyhow!("Job is not running"));
             }
         }
@@ -272,17 +764,211 @@ impl JobManager {
     }
 
     /// Complete a job
+    ///
+    /// A job that was canceled keeps its `Canceled` status; only the exit code and
+    /// finish time are filled in once its process group is gone. The job's spool
+    /// files are closed, and the oldest finished jobs beyond
+    /// `ENHANCED_TERMINAL_JOB_HISTORY` are dropped with their files.
     pub fn complete_job(&self, job_id: &str, exit_code: Option<i32>, status: JobStatus) {
         self.stdin_writers.lock().unwrap().remove(job_id);
 
-        let mut jobs = self.jobs.lock().unwrap();
-        if let Some(job) = jobs.get_mut(job_id) {
+        let (outcome, spool, evicted) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.get_mut(job_id) else {
+                return;
+            };
+            let first_completion = job.finished_at.is_none();
             job.finished_at = Some(SystemTime::now());
             job.exit_code = exit_code;
-            job.status = status;
+            if job.status != JobStatus::Canceled {
+                job.status = status;
+            }
+            let outcome = first_completion.then(|| JobOutcome::new(job));
+            let spool = Arc::clone(&job.spool);
+            (outcome, spool, evict_finished(&mut jobs, job_history()))
+        };
+        // Files are closed, removed and written outside the jobs lock, which file
+        // I/O must not hold up
+        spool.lock().unwrap().finish();
+        if !evicted.is_empty() {
+            tracing::debug!("Dropping {} old finished jobs", evicted.len());
+        }
+        drop(evicted);
+        if let Some(outcome) = outcome
+            && let Err(e) = audit_log::record_job_finished(&outcome)
+        {
+            tracing::warn!("Failed to write the audit log: {}", e);
+        }
+    }
+
+    /// Record how a policy ask decision was approved for a job
+    pub fn set_approval(&self, job_id: &str, approval: Option<String>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.approval = approval;
+        }
+    }
+
+    /// Record which escalation stage ended a job
+    pub fn record_termination_stage(&self, job_id: &str, stage: Option<TerminationStage>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.termination_stage = stage;
+        }
+    }
+
+    /// Record the resource usage reported when the job's process was reaped
+    pub fn record_resource_usage(&self, job_id: &str, usage: Option<ResourceUsage>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.resource_usage = usage;
+        }
+    }
+
+    /// Explain and record how a job's process ended. Uses the termination stage and
+    /// cgroup OOM kills already recorded for the job.
+    pub fn record_termination(
+        &self,
+        job_id: &str,
+        code: Option<i32>,
+        signal: Option<i32>,
+        core_dumped: bool,
+    ) -> Option<Termination> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id)?;
+        let oom_killed = match &job.cgroup {
+            Some(CgroupPlacement::Placed(cgroup)) => cgroup
+                .stats
+                .as_ref()
+                .and_then(|stats| stats.oom_kills)
+                .is_some_and(|kills| kills > 0),
+            _ => false,
+        };
+        let context = TerminationContext {
+            stage: job.termination_stage,
+            oom_killed,
+        };
+        job.termination = Termination::describe(code, signal, core_dumped, context);
+        job.termination.clone()
+    }
+
+    /// Record the effective resource limits of a job
+    pub fn set_resource_limits(&self, job_id: &str, limits: ResourceLimits) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.resource_limits = limits;
+        }
+    }
+
+    /// Record the sandbox profile the job runs under
+    pub fn set_sandbox(&self, job_id: &str, sandbox: Option<Sandbox>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.sandbox = sandbox;
+        }
+    }
+
+    /// Record the Landlock write restrictions of a job
+    pub fn set_write_restriction(&self, job_id: &str, restriction: Option<WriteRestriction>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.write_restriction = restriction;
+        }
+    }
+
+    /// Count secrets redacted from the job's output
+    pub fn add_redactions(&self, job_id: &str, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.redactions += count;
+        }
+    }
+
+    /// Record the encoding the job's output is decoded with
+    pub fn set_encoding(&self, job_id: &str, encoding: OutputEncoding) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.encoding = encoding;
+        }
+    }
+
+    /// Count byte sequences in the job's output that were not valid UTF-8
+    pub fn add_invalid_utf8(&self, job_id: &str, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.invalid_utf8 += count;
+        }
+    }
+
+    /// Record where the job was placed in the cgroup hierarchy
+    pub fn set_cgroup(&self, job_id: &str, placement: CgroupPlacement) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.cgroup = Some(placement);
         }
     }
 
+    /// Path of the job's cgroup, if it has one
+    pub fn cgroup_path(&self, job_id: &str) -> Option<std::path::PathBuf> {
+        let jobs = self.jobs.lock().unwrap();
+        match jobs.get(job_id)?.cgroup.as_ref()? {
+            CgroupPlacement::Placed(cgroup) => Some(cgroup.path.clone()),
+            CgroupPlacement::Unavailable(_) => None,
+        }
+    }
+
+    /// The job's cgroup placement, if one was attempted
+    pub fn cgroup_placement(&self, job_id: &str) -> Option<CgroupPlacement> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(job_id)?.cgroup.clone()
+    }
+
+    /// Store the final accounting of the job's cgroup
+    pub fn record_cgroup_stats(&self, job_id: &str, stats: CgroupStats) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(job_id)
+            && let Some(CgroupPlacement::Placed(cgroup)) = job.cgroup.as_mut()
+        {
+            cgroup.stats = Some(stats);
+        }
+    }
+
+    /// Decide from the exit status and the end of the output whether one of the
+    /// job's resource limits ended it, and record the limit on the job.
+    pub fn detect_limit_exceeded(
+        &self,
+        job_id: &str,
+        signal: Option<i32>,
+        exit_code: Option<i32>,
+    ) -> Option<LimitExceeded> {
+        const OUTPUT_TAIL_BYTES: usize = 4096;
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id)?;
+        if job.resource_limits.is_empty() {
+            return None;
+        }
+        let tail = job.spool.lock().unwrap().combined.tail(OUTPUT_TAIL_BYTES);
+        let hit = job
+            .resource_limits
+            .detect_exceeded(signal, exit_code, &tail)?;
+        job.limit_exceeded = Some(hit.clone());
+        Some(hit)
+    }
+
+    /// True once `cancel_job` has been called for a job that has not finished yet
+    pub fn is_cancel_requested(&self, job_id: &str) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(job_id)
+            .is_some_and(|job| job.status == JobStatus::Canceled && job.finished_at.is_none())
+    }
+
     /// Get a job by ID
     pub fn get_job(&self, job_id: &str) -> Option<JobRecord> {
         let jobs = self.jobs.lock().unwrap();
@@ -290,15 +976,35 @@ impl JobManager {
     }
 
     /// Get incremental output (only new since last read)
-    pub fn get_incremental_output(&self, job_id: &str) -> Option<(String, bool)> {
-        let mut jobs = self.jobs.lock().unwrap();
-        if let Some(job) = jobs.get_mut(job_id) {
-            let new_output = job.full_output[job.last_read_position..].to_string();
-            job.last_read_position = job.full_output.len();
-            Some((new_output, matches!(job.status, JobStatus::Running)))
-        } else {
-            None
-        }
+    ///
+    /// Each stream keeps its own read position; `Interleaved` shares the combined one.
+    pub fn get_incremental_output(
+        &self,
+        job_id: &str,
+        stream: OutputStream,
+    ) -> Option<(String, bool)> {
+        let (spool, start, end, is_running) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let job = jobs.get_mut(job_id)?;
+            let spool = Arc::clone(&job.spool);
+            let end = spool.lock().unwrap().stream(stream).len();
+            let position = match stream {
+                OutputStream::Stdout => &mut job.last_stdout_read_position,
+                OutputStream::Stderr => &mut job.last_stderr_read_position,
+                OutputStream::Combined | OutputStream::Interleaved => &mut job.last_read_position,
+            };
+            let start = std::mem::replace(position, end);
+            (spool, start, end, job.status.is_active())
+        };
+
+        // Older output is read back from the spool file outside the jobs lock
+        let spool = spool.lock().unwrap();
+        let new_output = match stream {
+            OutputStream::Interleaved => label_interleaved(&spool, start, end),
+            _ => spool.stream(stream).read(start, end),
+        };
+
+        Some((text_or_note(job_id, new_output), is_running))
     }
 
     /// Reset read position to get all output again
@@ -307,58 +1013,57 @@ impl JobManager {
         let mut jobs = self.jobs.lock().unwrap();
         if let Some(job) = jobs.get_mut(job_id) {
             job.last_read_position = 0;
+            job.last_stdout_read_position = 0;
+            job.last_stderr_read_position = 0;
         }
     }
 
-    /// List all jobs
-    pub fn list_jobs(&self) -> Vec<JobRecord> {
-        let jobs = self.jobs.lock().unwrap();
-        let mut job_list: Vec<JobRecord> = jobs.values().cloned().collect();
-        job_list.sort_by(|a, b| b.started_at.cmp(&a.started_at));
-        job_list
-    }
-
-    /// List jobs with filtering options
-    pub fn list_jobs_filtered(
-        &self,
-        status_filter: Option<&[JobStatus]>,
-        tag_filter: Option<&str>,
-        cwd_filter: Option<&str>,
-    ) -> Vec<JobRecord> {
+    /// Summaries of the jobs matching a query, one page at a time.
+    ///
+    /// Only the jobs on the page are summarized; the rest are filtered and sorted
+    /// by reference, so the cost does not depend on how much output jobs hold.
+    pub fn job_summaries(&self, query: &JobQuery) -> JobPage {
         let jobs = self.jobs.lock().unwrap();
-        let mut job_list: Vec<JobRecord> =
-            jobs.values()
-                .filter(|job| {
-                    // Filter by status
-                    if let Some(statuses) = status_filter {
-                        if !statuses.iter().any(|s| {
-                            std::mem::discriminant(s) == std::mem::discriminant(&job.status)
-                        }) {
-                            return false;
-                        }
-                    }
-
-                    // Filter by tag
-                    if let Some(tag) = tag_filter {
-                        if !job.tags.iter().any(|t| t == tag) {
-                            return false;
-                        }
-                    }
-
-                    // Filter by cwd
-                    if let Some(cwd) = cwd_filter {
-                        if job.cwd != cwd {
-                            return false;
-                        }
-                    }
+        let mut matching: Vec<(u128, &JobRecord)> = jobs
+            .values()
+            .filter(|job| query.matches(job))
+            .map(|job| (start_nanos(job), job))
+            .collect();
+        matching.sort_unstable_by(|(a_start, a), (b_start, b)| {
+            let order = a_start.cmp(b_start).then_with(|| a.job_id.cmp(&b.job_id));
+            if query.oldest_first {
+                order
+            } else {
+                order.reverse()
+            }
+        });
 
-                    true
-                })
-                .cloned()
-                .collect();
+        let total = matching.len();
+        let skipped = match query.cursor {
+            Some(cursor) => matching.partition_point(|(start, job)| {
+                let order = start
+                    .cmp(&cursor.started_at_nanos)
+                    .then_with(|| job.job_id.as_str().cmp(&cursor.job_id));
+                if query.oldest_first {
+                    order.is_le()
+                } else {
+                    order.is_ge()
+                }
+            }),
+            None => 0,
+        };
+        let page = &matching[skipped..(skipped + query.limit).min(total)];
+        let next_cursor = (skipped + page.len() < total)
+            .then(|| page.last())
+            .flatten()
+            .map(|(_, job)| JobCursor::after(job, query.oldest_first));
 
-        job_list.sort_by(|a, b| b.started_at.cmp(&a.started_at));
-        job_list
+        JobPage {
+            jobs: page.iter().map(|(_, job)| JobSummary::new(job)).collect(),
+            total,
+            skipped,
+            next_cursor,
+        }
     }
 
     /// Add tags to an existing job
@@ -378,16 +1083,23 @@ impl JobManager {
     }
 
     /// Get output with byte-explicit pagination.
+    ///
+    /// Offsets refer to the selected stream; `Interleaved` uses combined offsets.
     pub fn get_output_range(
         &self,
         job_id: &str,
+        stream: OutputStream,
         offset_bytes: usize,
         limit_bytes: usize,
     ) -> Option<OutputRange> {
-        let jobs = self.jobs.lock().unwrap();
-        let job = jobs.get(job_id)?;
+        let spool = {
+            let jobs = self.jobs.lock().unwrap();
+            Arc::clone(&jobs.get(job_id)?.spool)
+        };
+        let spool = spool.lock().unwrap();
+        let text = spool.stream(stream);
 
-        let total_len_bytes = job.full_output.len();
+        let total_len_bytes = text.len();
         let requested_end_byte = if limit_bytes == usize::MAX {
             total_len_bytes
         } else {
@@ -396,10 +1108,15 @@ impl JobManager {
                 .min(total_len_bytes)
         };
 
-        let start_byte = floor_char_boundary(&job.full_output, offset_bytes);
-        let end_byte = floor_char_boundary(&job.full_output, requested_end_byte);
+        let start_byte = text.floor_char_boundary(offset_bytes);
+        let end_byte = text.floor_char_boundary(requested_end_byte);
         let output = if start_byte < total_len_bytes && start_byte <= end_byte {
-            job.full_output[start_byte..end_byte].to_string()
+            let output = if stream == OutputStream::Interleaved {
+                label_interleaved(&spool, start_byte, end_byte)
+            } else {
+                text.read(start_byte, end_byte)
+            };
+            text_or_note(job_id, output)
         } else {
             String::new()
         };
@@ -417,48 +1134,114 @@ impl JobManager {
         })
     }
 
-    /// Cancel a running job (Unix only)
-    #[cfg(unix)]
-    pub fn cancel_job(&self, job_id: &str) -> Result<()> {
-        use nix::sys::signal::{Signal, kill};
-        use nix::unistd::Pid;
-
-        let canceled = {
-            let mut jobs = self.jobs.lock().unwrap();
-            if let Some(job) = jobs.get_mut(job_id) {
-                if matches!(job.status, JobStatus::Running) {
-                    if let Some(pid) = job.pid {
-                        let pid = Pid::from_raw(pid as i32);
-                        kill(pid, Signal::SIGTERM)?;
-                    }
+    /// Get output by line numbers, located through the stream's sparse line index.
+    ///
+    /// Lines are counted in the selected stream; `Interleaved` counts combined lines.
+    pub fn get_line_range(
+        &self,
+        job_id: &str,
+        stream: OutputStream,
+        selection: LineSelection,
+    ) -> Option<LineRange> {
+        let spool = {
+            let jobs = self.jobs.lock().unwrap();
+            Arc::clone(&jobs.get(job_id)?.spool)
+        };
+        let spool = spool.lock().unwrap();
+        let text = spool.stream(stream);
 
-                    job.status = JobStatus::Canceled;
-                    job.finished_at = Some(SystemTime::now());
-                    true
+        let total_lines = text.line_count();
+        let (first, count) = match selection {
+            LineSelection::From { start, count } => {
+                let first = if start < 0 {
+                    total_lines.saturating_sub(start.unsigned_abs() as usize)
                 } else {
-                    false
-                }
-            } else {
-                false
+                    (start.max(1) - 1) as usize
+                };
+                (first, count.unwrap_or(usize::MAX))
             }
+            LineSelection::Tail(count) => (total_lines.saturating_sub(count), count),
         };
+        let first = first.min(total_lines);
+        let line_count = count.min(total_lines - first);
+        let (start_byte, end_byte, output) = match text.line_range(first, line_count) {
+            Ok((start, end)) if stream == OutputStream::Interleaved => {
+                (start, end, label_interleaved(&spool, start, end))
+            }
+            Ok((start, end)) => (start, end, text.read(start, end)),
+            Err(e) => (0, 0, Err(e)),
+        };
+        let next_line = first + line_count;
 
-        if canceled {
-            self.stdin_writers.lock().unwrap().remove(job_id);
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Job not found or not running"))
-        }
+        Some(LineRange {
+            output: text_or_note(job_id, output),
+            total_lines,
+            first_line: first + 1,
+            line_count,
+            start_byte,
+            end_byte,
+            next_start_line: (next_line < total_lines).then_some(next_line + 1),
+        })
+    }
+
+    /// Search a stream of the job's output line by line, starting at `cursor`.
+    ///
+    /// The output is read from the spool in chunks, up to its length when the
+    /// search starts. Offsets refer to the selected stream; `Interleaved` uses
+    /// combined offsets.
+    pub fn search_output(
+        &self,
+        job_id: &str,
+        stream: OutputStream,
+        cursor: SearchCursor,
+        options: &SearchOptions,
+    ) -> Result<SearchPage> {
+        const CHUNK_BYTES: usize = 64 * 1024;
+
+        let output = {
+            let jobs = self.jobs.lock().unwrap();
+            let job = jobs
+                .get(job_id)
+                .ok_or_else(|| anyhow::anyhow!("Job not found"))?;
+            Arc::clone(&job.spool)
+        };
+        let end = {
+            let output = output.lock().unwrap();
+            let text = output.stream(stream);
+            if cursor.byte_offset > text.len() || !text.at_line_start(cursor.byte_offset) {
+                return Err(anyhow::anyhow!(
+                    "cursor {} does not point at the start of a line",
+                    cursor
+                ));
+            }
+            text.len()
+        };
+
+        let reader = SpoolReader {
+            output,
+            stream,
+            position: cursor.byte_offset,
+            end,
+        };
+        let page = output_search::search(
+            BufReader::with_capacity(CHUNK_BYTES, reader),
+            cursor,
+            options,
+        )?;
+        Ok(page)
     }
 
-    #[cfg(not(unix))]
+    /// Request cancellation of a running job
+    ///
+    /// The job is marked `Canceled` right away. The task monitoring the job then
+    /// signals its whole process group (SIGINT, SIGTERM, SIGKILL) and fills in the
+    /// finish time and termination stage once the group is gone.
     pub fn cancel_job(&self, job_id: &str) -> Result<()> {
         let canceled = {
             let mut jobs = self.jobs.lock().unwrap();
             if let Some(job) = jobs.get_mut(job_id) {
-                if matches!(job.status, JobStatus::Running) {
+                if job.status.is_active() {
                     job.status = JobStatus::Canceled;
-                    job.finished_at = Some(SystemTime::now());
                     true
                 } else {
                     false
@@ -476,6 +1259,58 @@ impl JobManager {
         }
     }
 
+    /// Send a signal to a running or paused job
+    ///
+    /// By default the whole process group receives the signal, matching what a
+    /// terminal does for Ctrl-C or Ctrl-Z. Stop signals mark the job `Paused` and
+    /// SIGCONT marks it `Running` again.
+    #[cfg(unix)]
+    pub fn signal_job(
+        &self,
+        job_id: &str,
+        signal: nix::sys::signal::Signal,
+        process_group: bool,
+    ) -> Result<()> {
+        use nix::sys::signal::{Signal, kill, killpg};
+        use nix::unistd::Pid;
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs
+            .get_mut(job_id)
+            .ok_or_else(|| anyhow::anyhow!("Job not found"))?;
+        if !job.status.is_active() {
+            return Err(anyhow::anyhow!("Job is not running"));
+        }
+        let pid = job
+            .pid
+            .ok_or_else(|| anyhow::anyhow!("Job has no process id"))?;
+
+        if process_group {
+            killpg(Pid::from_raw(pid as i32), signal)?;
+        } else {
+            // In its own PID namespace the job's pid is the launcher's relay
+            let pid = match &job.sandbox {
+                Some(sandbox) if sandbox.profile.pid_namespace => {
+                    super::sandbox::namespaced_command_pid(pid)
+                        .ok_or_else(|| anyhow::anyhow!("The sandboxed command is not running"))?
+                }
+                _ => pid,
+            };
+            kill(Pid::from_raw(pid as i32), signal)?;
+        }
+
+        match signal {
+            Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU => {
+                job.status = JobStatus::Paused;
+            }
+            Signal::SIGCONT if job.status == JobStatus::Paused => {
+                job.status = JobStatus::Running;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     /// Delete a job from history
     #[allow(dead_code)]
     pub fn delete_job(&self, job_id: &str) -> Result<()> {
@@ -524,6 +1359,227 @@ mod tests {
         );
     }
 
+    #[test]
+    fn separate_streams_keep_their_own_buffers_and_positions() {
+        let manager = JobManager::new();
+        let id = manager.new_job_id();
+        manager.register_job(
+            id.clone(),
+            "build".to_string(),
+            "bash".to_string(),
+            "/tmp".to_string(),
+            None,
+        );
+        manager.mark_separate_streams(&id);
+
+        manager.append_stream_output(&id, OutputStream::Stdout, "compiling\n", 1024);
+        manager.append_stream_output(&id, OutputStream::Stderr, "warning: x\n", 1024);
+        manager.append_stream_output(&id, OutputStream::Stdout, "done\n", 1024);
+
+        let read_all = |stream| {
+            manager
+                .get_output_range(&id, stream, 0, usize::MAX)
+                .unwrap()
+                .output
+        };
+        assert_eq!(
+            read_all(OutputStream::Combined),
+            "compiling\nwarning: x\ndone\n"
+        );
+        assert_eq!(read_all(OutputStream::Stdout), "compiling\ndone\n");
+        assert_eq!(read_all(OutputStream::Stderr), "warning: x\n");
+
+        let (stderr, _) = manager
+            .get_incremental_output(&id, OutputStream::Stderr)
+            .unwrap();
+        assert_eq!(stderr, "warning: x\n");
+        let (stdout, _) = manager
+            .get_incremental_output(&id, OutputStream::Stdout)
+            .unwrap();
+        assert_eq!(stdout, "compiling\ndone\n");
+
+        let interleaved = manager
+            .get_output_range(&id, OutputStream::Interleaved, 0, usize::MAX)
+            .unwrap();
+        assert_eq!(
+            interleaved.output,
+            "[stdout] compiling\n[stderr] warning: x\n[stdout] done\n"
+        );
+
+        let stderr_page = manager
+            .get_output_range(&id, OutputStream::Stderr, 0, 7)
+            .unwrap();
+        assert_eq!(stderr_page.output, "warning");
+        assert_eq!(stderr_page.total_len_bytes, 11);
+    }
+
+    fn register_jobs(manager: &JobManager, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| {
+                let id = manager.new_job_id();
+                manager.register_job(
+                    id.clone(),
+                    "true".to_string(),
+                    "bash".to_string(),
+                    "/tmp".to_string(),
+                    None,
+                );
+                id
+            })
+            .collect()
+    }
+
+    #[test]
+    fn oldest_finished_jobs_are_evicted_with_their_spool_files() {
+        let manager = JobManager::new();
+        let ids = register_jobs(&manager, 4);
+        let mut paths = Vec::new();
+        for id in &ids[..3] {
+            manager.append_output(id, "done\n", 1024);
+            manager.complete_job(id, Some(0), JobStatus::Completed);
+            let job = manager.get_job(id).unwrap();
+            let output = job.spool.lock().unwrap();
+            paths.push(output.combined.path().unwrap().to_path_buf());
+        }
+
+        let evicted = evict_finished(&mut manager.jobs.lock().unwrap(), 2);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].job_id, ids[0]);
+        drop(evicted);
+        assert!(!paths[0].exists() && paths[1].exists());
+
+        // Running jobs do not count and are never evicted
+        let evicted = evict_finished(&mut manager.jobs.lock().unwrap(), 1);
+        assert_eq!(evicted[0].job_id, ids[1]);
+        assert!(manager.get_job(&ids[2]).is_some() && manager.get_job(&ids[3]).is_some());
+        assert_eq!(
+            manager
+                .get_output_range(&ids[2], OutputStream::Combined, 0, 5)
+                .unwrap()
+                .output,
+            "done\n"
+        );
+    }
+
+    #[test]
+    fn job_summaries_page_through_every_job_once() {
+        let manager = JobManager::new();
+        let ids = register_jobs(&manager, 7);
+
+        for oldest_first in [true, false] {
+            let mut seen = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = manager.job_summaries(&JobQuery {
+                    oldest_first,
+                    cursor: cursor.as_ref(),
+                    limit: 3,
+                    ..Default::default()
+                });
+                assert_eq!(page.total, 7);
+                assert_eq!(page.skipped, seen.len());
+                seen.extend(page.jobs.iter().map(|job| job.job_id.clone()));
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next.to_string().parse().unwrap()),
+                    None => break,
+                }
+            }
+            let mut sorted = seen.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), ids.len());
+        }
+
+        assert!("sideways:1:x".parse::<JobCursor>().is_err());
+        assert!("oldest:1:".parse::<JobCursor>().is_err());
+    }
+
+    #[test]
+    fn job_summary_preview_stops_on_a_char_boundary() {
+        let manager = JobManager::new();
+        let id = register_jobs(&manager, 1).remove(0);
+        // The preview is filled across chunks
+        manager.append_output(&id, &"é".repeat(60), 1 << 20);
+        manager.append_output(&id, &"é".repeat(90), 1 << 20);
+
+        let page = manager.job_summaries(&JobQuery {
+            limit: 10,
+            ..Default::default()
+        });
+        let preview = &page.jobs[0].output_preview;
+        assert_eq!(preview.chars().filter(|&c| c == 'é').count(), 100);
+        assert!(preview.ends_with("..."));
+    }
+
+    #[test]
+    fn head_reads_come_from_the_spool() {
+        let manager = JobManager::new();
+        let id = register_jobs(&manager, 1).remove(0);
+        // More than the in-memory tail, so the head only exists in the spool file
+        let output: String = (0..40_000).map(|line| format!("{:07}\n", line)).collect();
+        manager.append_output(&id, &output, 16);
+
+        let job = manager.get_job(&id).unwrap();
+        assert!(job.truncated());
+        let head = manager
+            .get_output_range(&id, OutputStream::Combined, 0, job.output_limit)
+            .unwrap();
+        assert_eq!(head.output, "0000000\n0000001\n");
+        manager.append_output(&id, "more", 16);
+        let page = manager.job_summaries(&JobQuery {
+            limit: 1,
+            ..Default::default()
+        });
+        assert_eq!(
+            page.jobs[0].output_preview,
+            format!("{}...", &output[..100])
+        );
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture job_list_benchmark`.
+    #[test]
+    #[ignore]
+    fn job_list_benchmark() {
+        const JOBS: usize = 500;
+        const OUTPUT_BYTES: usize = 2 << 20;
+
+        let manager = JobManager::new();
+        let ids = register_jobs(&manager, JOBS);
+        // Every job spools its own output, in chunks like a running job's
+        let chunk = "x".repeat(64 << 10);
+        for id in &ids {
+            for _ in 0..OUTPUT_BYTES / chunk.len() {
+                manager.append_output(id, &chunk, OUTPUT_BYTES);
+            }
+        }
+
+        let started = std::time::Instant::now();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let page = manager.job_summaries(&JobQuery {
+                cursor: cursor.as_ref(),
+                limit: 50,
+                ..Default::default()
+            });
+            pages += 1;
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        let elapsed = started.elapsed();
+        println!(
+            "listed {} jobs of {} MiB output in {} pages: {:?} ({:?} per page)",
+            JOBS,
+            OUTPUT_BYTES >> 20,
+            pages,
+            elapsed,
+            elapsed / pages
+        );
+        assert!(elapsed / pages < Duration::from_millis(50));
+    }
+
     #[test]
     fn readable_job_ids_are_unique_in_registry() {
         let manager = JobManager::new();
@@ -0,0 +1,363 @@
+//! Landlock filesystem write restrictions per job.
+//!
+//! A lighter alternative to the namespace sandbox: the launcher installs a Landlock
+//! ruleset that only lets the job write beneath its cwd, `$TMPDIR`, a few device
+//! files and server-configured extra paths. Reads and execution are not restricted.
+//!
+//! Protected paths (`ENHANCED_TERMINAL_LANDLOCK_READ_ONLY`, default `/`, `/etc`,
+//! `/usr`, ...) are never made writable: Landlock rules only grant access, so a
+//! writable directory that is, or contains, a protected path is dropped instead.
+//! Landlock does not cover metadata changes such as chmod or chown.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Protected paths when `ENHANCED_TERMINAL_LANDLOCK_READ_ONLY` is not set.
+const DEFAULT_READ_ONLY_PATHS: &[&str] = &[
+    "/", "/bin", "/boot", "/etc", "/lib", "/lib64", "/sbin", "/usr", "/var",
+];
+
+/// Device files jobs may always write (terminals, /dev/null and friends).
+pub const WRITABLE_DEVICES: &[&str] = &[
+    "/dev/null",
+    "/dev/zero",
+    "/dev/full",
+    "/dev/random",
+    "/dev/urandom",
+    "/dev/tty",
+    "/dev/ptmx",
+    "/dev/pts",
+];
+
+/// What to do when the kernel cannot enforce write restrictions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailPolicy {
+    /// Run the command without restrictions and say so
+    Open,
+    /// Refuse to run the command
+    Closed,
+}
+
+impl FailPolicy {
+    /// `ENHANCED_TERMINAL_LANDLOCK_FAIL`: "open" or "closed" (default).
+    pub fn from_env() -> Self {
+        match std::env::var("ENHANCED_TERMINAL_LANDLOCK_FAIL") {
+            Ok(value) if value.trim().eq_ignore_ascii_case("open") => FailPolicy::Open,
+            _ => FailPolicy::Closed,
+        }
+    }
+}
+
+/// True when the server applies write restrictions to every command
+/// (`ENHANCED_TERMINAL_LANDLOCK=on`).
+pub fn enabled_by_server() -> bool {
+    matches!(
+        std::env::var("ENHANCED_TERMINAL_LANDLOCK").as_deref(),
+        Ok("1" | "on" | "true")
+    )
+}
+
+fn env_paths(name: &str) -> Option<Vec<PathBuf>> {
+    let value = std::env::var(name).ok()?;
+    Some(
+        std::env::split_paths(&value)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect(),
+    )
+}
+
+/// Writable paths the launcher grants, decided by the server before spawn.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteRules {
+    pub writable: Vec<PathBuf>,
+}
+
+/// Effective write restrictions of a job, as reported to clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteRestrictions {
+    /// Landlock ABI version of the running kernel
+    pub abi: u32,
+    /// Directories the job may write beneath (device files are not listed)
+    pub writable: Vec<PathBuf>,
+    /// Requested writable paths that were dropped, with the reason
+    pub dropped: Vec<String>,
+}
+
+/// Outcome of asking for write restrictions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteRestriction {
+    Enforced(WriteRestrictions),
+    /// Landlock is unavailable and the fail-open policy let the job run unrestricted
+    Unavailable(String),
+}
+
+impl fmt::Display for WriteRestriction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteRestriction::Enforced(rules) => {
+                let writable: Vec<String> = rules
+                    .writable
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect();
+                write!(
+                    f,
+                    "Landlock ABI {} - writable: {}",
+                    rules.abi,
+                    if writable.is_empty() {
+                        "nothing but devices".to_string()
+                    } else {
+                        writable.join(", ")
+                    }
+                )
+            }
+            WriteRestriction::Unavailable(reason) => {
+                write!(f, "unavailable - {} (writes not restricted)", reason)
+            }
+        }
+    }
+}
+
+/// Work out the writable directories for a job.
+///
+/// `cwd`, `tmpdir` and the server's `ENHANCED_TERMINAL_LANDLOCK_WRITABLE` paths are
+/// candidates; a candidate is dropped when it is, or contains, a protected path
+/// (the server's list plus any `extra_read_only` from the client).
+pub fn plan(cwd: &Path, tmpdir: &Path, extra_read_only: &[PathBuf]) -> (Vec<PathBuf>, Vec<String>) {
+    let mut read_only: Vec<PathBuf> = env_paths("ENHANCED_TERMINAL_LANDLOCK_READ_ONLY")
+        .unwrap_or_else(|| DEFAULT_READ_ONLY_PATHS.iter().map(PathBuf::from).collect());
+    read_only.extend(extra_read_only.iter().cloned());
+    let read_only: Vec<PathBuf> = read_only.iter().map(|p| canonical(p)).collect();
+
+    let mut candidates = vec![cwd.to_path_buf(), tmpdir.to_path_buf()];
+    candidates.extend(env_paths("ENHANCED_TERMINAL_LANDLOCK_WRITABLE").unwrap_or_default());
+
+    let mut writable: Vec<PathBuf> = Vec::new();
+    let mut dropped = Vec::new();
+    for candidate in candidates {
+        let candidate = canonical(&candidate);
+        if writable.contains(&candidate) {
+            continue;
+        }
+        match read_only
+            .iter()
+            .find(|protected| protected.starts_with(&candidate))
+        {
+            Some(protected) if protected == &candidate => {
+                dropped.push(format!("{}: protected path", candidate.display()));
+            }
+            Some(protected) => dropped.push(format!(
+                "{}: contains protected path {}",
+                candidate.display(),
+                protected.display()
+            )),
+            None => writable.push(candidate),
+        }
+    }
+    (writable, dropped)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Landlock ABI: include/uapi/linux/landlock.h
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+const RULE_PATH_BENEATH: u32 = 1;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+/// Every write-type access right the given ABI version knows about.
+fn write_access(abi: u32) -> u64 {
+    let mut access = ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_REMOVE_DIR
+        | ACCESS_FS_REMOVE_FILE
+        | ACCESS_FS_MAKE_CHAR
+        | ACCESS_FS_MAKE_DIR
+        | ACCESS_FS_MAKE_REG
+        | ACCESS_FS_MAKE_SOCK
+        | ACCESS_FS_MAKE_FIFO
+        | ACCESS_FS_MAKE_BLOCK
+        | ACCESS_FS_MAKE_SYM;
+    if abi >= 2 {
+        access |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_FS_TRUNCATE;
+    }
+    access
+}
+
+/// Rights that make sense on a non-directory.
+const FILE_ACCESS: u64 = ACCESS_FS_WRITE_FILE | ACCESS_FS_TRUNCATE;
+
+/// Landlock ABI version supported by the running kernel.
+#[cfg(target_os = "linux")]
+pub fn abi_version() -> Result<u32, String> {
+    // SAFETY: querying the version takes no pointers.
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<u8>(),
+            0usize,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    if version > 0 {
+        return Ok(version as u32);
+    }
+    let error = std::io::Error::last_os_error();
+    Err(match error.raw_os_error() {
+        Some(libc::ENOSYS) => "kernel built without Landlock".to_string(),
+        Some(libc::EOPNOTSUPP) => "Landlock is disabled (not in the lsm= boot list)".to_string(),
+        _ => format!("Landlock unavailable: {}", error),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn abi_version() -> Result<u32, String> {
+    Err("Landlock requires Linux".to_string())
+}
+
+/// Restrict writes of the current process (and everything it execs) to `rules`.
+/// Called by the launcher right before exec. Sets `no_new_privs`, so setuid
+/// programs such as sudo cannot gain privileges afterwards.
+#[cfg(target_os = "linux")]
+pub fn restrict_self(rules: &WriteRules) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    let abi = abi_version().map_err(|e| anyhow::anyhow!(e))?;
+    let handled = write_access(abi);
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    // SAFETY: `attr` outlives the call and the size matches the struct.
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0u32,
+        )
+    };
+    if ruleset < 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to create Landlock ruleset");
+    }
+    // SAFETY: the syscall returned a new descriptor that nothing else owns.
+    let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as i32) };
+
+    let paths = rules
+        .writable
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(WRITABLE_DEVICES.iter().map(Path::new));
+    for path in paths {
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+            continue;
+        };
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            // Missing paths (e.g. no /dev/tty) simply get no rule.
+            continue;
+        }
+        // SAFETY: `open` returned a new descriptor that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let allowed = if path.is_dir() {
+            handled
+        } else {
+            handled & FILE_ACCESS
+        };
+        let rule = PathBeneathAttr {
+            allowed_access: allowed,
+            parent_fd: fd.as_raw_fd(),
+        };
+        // SAFETY: `rule` outlives the call; both descriptors are open.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &rule as *const PathBeneathAttr,
+                0u32,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to allow writes beneath {}", path.display()));
+        }
+    }
+
+    // SAFETY: prctl and landlock_restrict_self take no pointers here.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(std::io::Error::last_os_error()).context("failed to set no_new_privs");
+        }
+        if libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("failed to enforce Landlock ruleset");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restrict_self(_rules: &WriteRules) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("Landlock requires Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_paths_are_never_writable() {
+        let dir = std::env::temp_dir().join(format!("landlock-plan-{}", std::process::id()));
+        let project = dir.join("project");
+        std::fs::create_dir_all(project.join(".git")).unwrap();
+        let project = project.canonicalize().unwrap();
+
+        let (writable, dropped) = plan(&project, &dir, &[]);
+        assert!(writable.contains(&project));
+        assert!(dropped.is_empty(), "{dropped:?}");
+
+        // cwd "/" would make everything writable.
+        let (writable, dropped) = plan(Path::new("/"), &dir, &[]);
+        assert!(!writable.contains(&PathBuf::from("/")));
+        assert_eq!(dropped, vec!["/: protected path".to_string()]);
+
+        // A client can only add protected paths, which removes the enclosing cwd.
+        let (writable, dropped) = plan(&project, &dir, &[project.join(".git")]);
+        assert!(!writable.contains(&project));
+        assert!(
+            dropped[0].contains("contains protected path"),
+            "{dropped:?}"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
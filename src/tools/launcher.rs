@@ -0,0 +1,116 @@
+//! Re-exec launcher that prepares a job's process before the shell starts.
+//!
+//! portable-pty has no `pre_exec` hook, so jobs that need per-process setup run as
+//! `<server binary> __enhanced_terminal_launch <spec-json> <program> <args...>`.
+//! The launcher applies the spec to itself and then execs the program, keeping the
+//! pid, process group, controlling terminal, cwd and environment it was given.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use super::landlock::WriteRules;
+use super::resource_limits::ResourceLimits;
+use super::sandbox::SandboxProfile;
+
+pub const LAUNCH_SUBCOMMAND: &str = "__enhanced_terminal_launch";
+
+/// Exit code when the launcher cannot prepare or exec the command
+/// (same meaning as a shell's "found but cannot execute").
+pub(super) const LAUNCH_FAILURE_EXIT_CODE: i32 = 126;
+
+/// Everything the launcher sets up before exec.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaunchSpec {
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// cgroup v2 group to join before exec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<PathBuf>,
+    /// Namespace sandbox to enter before exec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxProfile>,
+    /// Landlock write rules to enforce before exec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_rules: Option<WriteRules>,
+}
+
+impl LaunchSpec {
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+            && self.cgroup.is_none()
+            && self.sandbox.is_none()
+            && self.write_rules.is_none()
+    }
+
+    /// Program and arguments that run `program args` under this spec.
+    ///
+    /// Without anything to set up the command is returned unchanged, so the
+    /// common case does not pay for an extra exec.
+    pub fn wrap(&self, program: &str, args: Vec<String>) -> Result<(String, Vec<String>)> {
+        if self.is_empty() {
+            return Ok((program.to_string(), args));
+        }
+
+        let launcher = std::env::current_exe().context("cannot locate server binary")?;
+        let mut launch_args = vec![
+            LAUNCH_SUBCOMMAND.to_string(),
+            serde_json::to_string(self)?,
+            program.to_string(),
+        ];
+        launch_args.extend(args);
+        Ok((launcher.to_string_lossy().into_owned(), launch_args))
+    }
+}
+
+/// Run the launcher if this process was started as one. Never returns in that case.
+///
+/// Must be called at the very start of `main`, before the async runtime starts.
+pub fn run_if_requested() {
+    let mut args = std::env::args_os().skip(1);
+    if args.next().as_deref() != Some(LAUNCH_SUBCOMMAND.as_ref()) {
+        return;
+    }
+
+    let error = match launch(args.collect()) {
+        Ok(never) => match never {},
+        Err(error) => error,
+    };
+    eprintln!("enhanced-terminal-mcp launcher: {:#}", error);
+    std::process::exit(LAUNCH_FAILURE_EXIT_CODE);
+}
+
+#[cfg(unix)]
+fn launch(args: Vec<OsString>) -> Result<std::convert::Infallible> {
+    use std::os::unix::process::CommandExt;
+
+    let mut args = args.into_iter();
+    let spec = args
+        .next()
+        .context("missing launch spec")?
+        .into_string()
+        .map_err(|_| anyhow::anyhow!("launch spec is not valid UTF-8"))?;
+    let spec: LaunchSpec = serde_json::from_str(&spec).context("invalid launch spec")?;
+    let program = args.next().context("missing program to launch")?;
+
+    if let Some(cgroup) = &spec.cgroup {
+        super::cgroup::join(cgroup)
+            .with_context(|| format!("failed to join cgroup {}", cgroup.display()))?;
+    }
+    if let Some(sandbox) = &spec.sandbox {
+        super::sandbox::enter(sandbox).context("failed to enter sandbox")?;
+    }
+    if let Some(rules) = &spec.write_rules {
+        super::landlock::restrict_self(rules).context("failed to restrict writes")?;
+    }
+    spec.limits.apply()?;
+
+    let error = std::process::Command::new(&program).args(args).exec();
+    Err(error).with_context(|| format!("failed to exec {}", program.to_string_lossy()))
+}
+
+#[cfg(not(unix))]
+fn launch(_args: Vec<OsString>) -> Result<std::convert::Infallible> {
+    Err(anyhow::anyhow!("the launcher is only supported on Unix"))
+}
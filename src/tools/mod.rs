@@ -1,6 +1,37 @@
+pub mod allowlist;
+pub mod approval;
+pub mod audit_log;
+pub mod cgroup;
+pub mod command_check;
 pub mod denylist;
 pub mod job_manager;
+pub mod landlock;
+pub mod launcher;
+pub mod output_decoder;
+pub mod output_search;
+pub mod output_spool;
+pub mod policy;
+pub mod process_group;
+pub mod redaction;
+pub mod resource_limits;
+pub mod resource_usage;
+pub mod rewrite;
+pub mod sandbox;
+pub mod session_manager;
+pub mod shell_parser;
+pub mod sudo;
 pub mod terminal_executor;
+pub mod termination;
 
-pub use job_manager::{JobManager, JobStatus};
-pub use terminal_executor::{TerminalExecutionInput, execute_command, preview_output};
+pub use cgroup::CgroupPlacement;
+pub use command_check::{CommandCheckInput, check_command};
+pub use job_manager::{
+    JobCursor, JobManager, JobQuery, JobRecord, JobStatus, LineSelection, OutputStream,
+};
+pub use resource_usage::ResourceUsage;
+pub use session_manager::{
+    EnvChange, SessionCloseInput, SessionExecInput, SessionManager, SessionOpenInput,
+};
+pub use terminal_executor::{
+    CaptureMode, ExecutionResult, TerminalExecutionInput, execute_command, preview_output,
+};
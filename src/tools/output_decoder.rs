@@ -0,0 +1,190 @@
+//! Incremental decoding of job output.
+//!
+//! Readers hand over output in chunks of up to 4096 bytes, so a multibyte UTF-8
+//! character can arrive split across two chunks. [`OutputDecoder`] keeps the bytes
+//! of an unfinished character until the rest arrives, and counts byte sequences that
+//! are not valid UTF-8 at all. A job can pick `latin-1` to decode legacy output
+//! byte for byte instead; the UTF-8 check still runs so the job reports whether its
+//! output would have been valid UTF-8.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::job_manager::OutputStream;
+
+/// Character encoding used to decode a job's output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum OutputEncoding {
+    /// Invalid byte sequences are shown as U+FFFD
+    #[default]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    /// ISO 8859-1: every byte is the character with the same code point
+    #[serde(rename = "latin-1", alias = "latin1", alias = "iso-8859-1")]
+    Latin1,
+}
+
+impl fmt::Display for OutputEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputEncoding::Utf8 => "utf-8",
+            OutputEncoding::Latin1 => "latin-1",
+        })
+    }
+}
+
+/// Decodes the output streams of one job chunk by chunk.
+pub struct OutputDecoder {
+    encoding: OutputEncoding,
+    /// Bytes of a character that started at the end of the last chunk, per stream
+    pending: Vec<(OutputStream, Vec<u8>)>,
+    invalid: usize,
+    unreported: usize,
+}
+
+impl OutputDecoder {
+    pub fn new(encoding: OutputEncoding) -> Self {
+        Self {
+            encoding,
+            pending: Vec::new(),
+            invalid: 0,
+            unreported: 0,
+        }
+    }
+
+    fn pending(&mut self, stream: OutputStream) -> &mut Vec<u8> {
+        let index = match self.pending.iter().position(|(s, _)| *s == stream) {
+            Some(index) => index,
+            None => {
+                self.pending.push((stream, Vec::new()));
+                self.pending.len() - 1
+            }
+        };
+        &mut self.pending[index].1
+    }
+
+    fn record_invalid(&mut self, count: usize) {
+        self.invalid += count;
+        self.unreported += count;
+    }
+
+    /// Decode a chunk. Bytes of a character that continues in the next chunk are
+    /// kept until then.
+    pub fn push(&mut self, stream: OutputStream, chunk: &[u8]) -> String {
+        let mut bytes = std::mem::take(self.pending(stream));
+        bytes.extend_from_slice(chunk);
+
+        let mut text = String::with_capacity(bytes.len());
+        let mut invalid = 0;
+        let mut rest = bytes.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            invalid += 1;
+                            rest = &after[len..];
+                        }
+                        // Cut off at the end of the chunk
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.record_invalid(invalid);
+        *self.pending(stream) = rest.to_vec();
+
+        match self.encoding {
+            OutputEncoding::Utf8 => text,
+            // The held-back bytes only matter for the UTF-8 check here
+            OutputEncoding::Latin1 => chunk.iter().map(|&byte| char::from(byte)).collect(),
+        }
+    }
+
+    /// Decode the bytes still held back at the end of the output; an unfinished
+    /// character is invalid.
+    pub fn finish(&mut self) -> Vec<(OutputStream, String)> {
+        let mut finished = Vec::new();
+        let mut invalid = 0;
+        for (stream, pending) in &mut self.pending {
+            if pending.is_empty() {
+                continue;
+            }
+            pending.clear();
+            invalid += 1;
+            if self.encoding == OutputEncoding::Utf8 {
+                finished.push((*stream, char::REPLACEMENT_CHARACTER.to_string()));
+            }
+        }
+        self.record_invalid(invalid);
+        finished
+    }
+
+    /// Invalid byte sequences so far.
+    pub fn invalid(&self) -> usize {
+        self.invalid
+    }
+
+    /// Invalid byte sequences found since the last call.
+    pub fn take_new(&mut self) -> usize {
+        std::mem::take(&mut self.unreported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters_split_across_chunks_survive() {
+        let mut decoder = OutputDecoder::new(OutputEncoding::Utf8);
+        let bytes = "größe: 10 €\n".as_bytes();
+        // Split inside "ö" and inside "€"
+        let mut text = decoder.push(OutputStream::Combined, &bytes[..3]);
+        text += &decoder.push(OutputStream::Combined, &bytes[3..13]);
+        text += &decoder.push(OutputStream::Combined, &bytes[13..]);
+        assert_eq!(text, "größe: 10 €\n");
+        assert_eq!(decoder.invalid(), 0);
+
+        // Streams keep their own carry-over
+        let euro = "€".as_bytes();
+        assert_eq!(decoder.push(OutputStream::Stdout, &euro[..1]), "");
+        assert_eq!(decoder.push(OutputStream::Stderr, b"ok"), "ok");
+        assert_eq!(decoder.push(OutputStream::Stdout, &euro[1..]), "€");
+
+        assert_eq!(
+            decoder.push(OutputStream::Combined, b"a\xffb\xe2\x82"),
+            "a\u{FFFD}b"
+        );
+        assert_eq!(
+            decoder.finish(),
+            vec![(OutputStream::Combined, "\u{FFFD}".to_string())]
+        );
+        assert_eq!(decoder.invalid(), 2);
+        assert_eq!(decoder.take_new(), 2);
+        assert_eq!(decoder.take_new(), 0);
+    }
+
+    #[test]
+    fn latin1_decodes_every_byte() {
+        let mut decoder = OutputDecoder::new(OutputEncoding::Latin1);
+        assert_eq!(
+            decoder.push(OutputStream::Combined, b"caf\xe9 \xa310"),
+            "café £10"
+        );
+        assert_eq!(decoder.invalid(), 2);
+        assert!(decoder.finish().is_empty());
+    }
+}
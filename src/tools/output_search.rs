@@ -0,0 +1,257 @@
+//! Regex search over a job's output, line by line.
+//!
+//! The output is streamed from the spool (see
+//! [`output_spool`](super::output_spool)), so searching a log of any size only
+//! keeps the current line and the requested context lines in memory. Like grep,
+//! matches whose context lines touch are reported as one group. Every line comes
+//! with its 1-based line number and the byte offset where it starts, which can be
+//! passed as `offset_bytes` to `enhanced_terminal_job_status`.
+//!
+//! A page ends after `max_matches` matches. Its cursor points at the first line
+//! that was not reported yet, or at the start of the before-context of the next
+//! match, so the next page neither repeats nor loses a line.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+
+/// Characters of a line shown in a search result.
+const MAX_LINE_CHARS: usize = 1000;
+
+/// What to search for and how much of it to report.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    regex: Regex,
+    invert: bool,
+    before: usize,
+    after: usize,
+    max_matches: usize,
+}
+
+impl SearchOptions {
+    pub fn new(
+        pattern: &str,
+        case_insensitive: bool,
+        invert: bool,
+        before: usize,
+        after: usize,
+        max_matches: usize,
+    ) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| anyhow::anyhow!("invalid pattern: {}", e))?;
+        Ok(Self {
+            regex,
+            invert,
+            before,
+            after,
+            max_matches: max_matches.max(1),
+        })
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        self.regex.is_match(line) != self.invert
+    }
+}
+
+/// Position to continue a search from: the start of a line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchCursor {
+    /// 1-based number of the line, 0 for the start of the output
+    pub line_number: usize,
+    pub byte_offset: usize,
+}
+
+impl SearchCursor {
+    fn line_number(&self) -> usize {
+        self.line_number.max(1)
+    }
+}
+
+impl fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line_number, self.byte_offset)
+    }
+}
+
+impl std::str::FromStr for SearchCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (line_number, byte_offset) = text
+            .split_once(':')
+            .and_then(|(line, byte)| Some((line.parse().ok()?, byte.parse().ok()?)))
+            .ok_or_else(|| anyhow::anyhow!("invalid cursor '{}'", text))?;
+        Ok(Self {
+            line_number,
+            byte_offset,
+        })
+    }
+}
+
+/// One line of a search result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchLine {
+    /// 1-based line number
+    pub line_number: usize,
+    /// Offset of the start of the line in the searched stream
+    pub byte_offset: usize,
+    /// The line without its line ending, cut at `MAX_LINE_CHARS` characters
+    pub text: String,
+    /// False for context lines
+    pub is_match: bool,
+}
+
+/// Matches and their context lines, without gaps between them.
+pub type SearchGroup = Vec<SearchLine>;
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchPage {
+    pub groups: Vec<SearchGroup>,
+    pub matches: usize,
+    /// Set when there are more matches after this page
+    pub next_cursor: Option<SearchCursor>,
+    /// End of the searched output
+    pub end_byte: usize,
+}
+
+fn display_text(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// Search `reader`, which yields the output from `start` on.
+pub fn search(
+    mut reader: impl BufRead,
+    start: SearchCursor,
+    options: &SearchOptions,
+) -> io::Result<SearchPage> {
+    let mut page = SearchPage::default();
+    // Unreported lines just before the current one, for before-context
+    let mut before: VecDeque<SearchLine> = VecDeque::new();
+    let mut after_left = 0;
+    let mut last_reported = None;
+
+    let mut line_number = start.line_number();
+    let mut byte_offset = start.byte_offset;
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let content = String::from_utf8_lossy(&buffer);
+        let content = content.strip_suffix('\n').unwrap_or(&content);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        // Match the whole line, not only the part that is shown
+        let line = SearchLine {
+            line_number,
+            byte_offset,
+            is_match: options.is_match(content),
+            text: display_text(content),
+        };
+        line_number += 1;
+        byte_offset += read;
+
+        if line.is_match {
+            if page.matches == options.max_matches {
+                let next = before.front().unwrap_or(&line);
+                page.next_cursor = Some(SearchCursor {
+                    line_number: next.line_number,
+                    byte_offset: next.byte_offset,
+                });
+                return Ok(page);
+            }
+            let first = before.front().map_or(line.line_number, |l| l.line_number);
+            if last_reported.is_none_or(|last| last + 1 < first) {
+                page.groups.push(Vec::new());
+            }
+            let group = page.groups.last_mut().expect("group was just added");
+            group.extend(before.drain(..));
+            last_reported = Some(line.line_number);
+            group.push(line);
+            page.matches += 1;
+            after_left = options.after;
+        } else if after_left > 0 {
+            after_left -= 1;
+            last_reported = Some(line.line_number);
+            if let Some(group) = page.groups.last_mut() {
+                group.push(line);
+            }
+        } else if options.before > 0 {
+            if before.len() == options.before {
+                before.pop_front();
+            }
+            before.push_back(line);
+        }
+    }
+    page.end_byte = byte_offset;
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(page: &SearchPage) -> Vec<Vec<usize>> {
+        page.groups
+            .iter()
+            .map(|group| group.iter().map(|line| line.line_number).collect())
+            .collect()
+    }
+
+    #[test]
+    fn merges_touching_context_and_pages_without_repeating_lines() {
+        let log = "ok\nerror[E0308]: a\nok\nok\nok\nFAILED b\nok\nok\nok\nok\nerror: c\r\n";
+        let options = SearchOptions::new("error|failed", true, false, 1, 1, 2).unwrap();
+
+        let first = search(log.as_bytes(), SearchCursor::default(), &options).unwrap();
+        assert_eq!(numbers(&first), vec![vec![1, 2, 3], vec![5, 6, 7]]);
+        assert_eq!(first.matches, 2);
+        assert_eq!(first.groups[0][1].text, "error[E0308]: a");
+        assert_eq!(first.groups[0][1].byte_offset, 3);
+        assert!(first.groups[0][1].is_match && !first.groups[0][0].is_match);
+
+        // The cursor starts at the before-context of the third match
+        let cursor = first.next_cursor.expect("more matches");
+        assert_eq!(
+            cursor,
+            SearchCursor {
+                line_number: 10,
+                byte_offset: 46
+            }
+        );
+        assert_eq!(&log[cursor.byte_offset..cursor.byte_offset + 3], "ok\n");
+        let second = search(
+            &log.as_bytes()[cursor.byte_offset..],
+            cursor.to_string().parse().unwrap(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(numbers(&second), vec![vec![10, 11]]);
+        assert_eq!(second.groups[0][1].text, "error: c");
+        assert!(second.next_cursor.is_none());
+        assert_eq!(second.end_byte, log.len());
+    }
+
+    #[test]
+    fn invert_reports_the_lines_that_do_not_match() {
+        let options = SearchOptions::new("^ok$", false, true, 0, 0, 10).unwrap();
+        let page = search(
+            "ok\nbad\nok\nworse".as_bytes(),
+            SearchCursor::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(numbers(&page), vec![vec![2], vec![4]]);
+        assert!(SearchOptions::new("(", false, false, 0, 0, 1).is_err());
+        assert!("12".parse::<SearchCursor>().is_err());
+    }
+}
@@ -0,0 +1,565 @@
+//! Job output spooled to disk, with only the recent part kept in memory.
+//!
+//! Each output stream of a job (the combined output, and stdout and stderr for jobs
+//! run with pipes) is appended to its own file under `<state dir>/spool/<server
+//! pid>/`. The state directory is `ENHANCED_TERMINAL_STATE_DIR`, or
+//! `$XDG_STATE_HOME/enhanced-terminal-mcp` (`~/.local/state/enhanced-terminal-mcp`).
+//! Only the last `ENHANCED_TERMINAL_OUTPUT_MEMORY_BYTES` of each stream (default
+//! 256 KiB) stay in a ring buffer; older byte ranges are read back from the file at
+//! the same offsets.
+//!
+//! Every stream also keeps a sparse line index: the start offset of every Nth
+//! line, with N doubling whenever the index reaches `MAX_LINE_CHECKPOINTS`
+//! entries. A line is found by scanning forward from the checkpoint before it, so
+//! the index stays under 32 KiB per stream however many lines the output has.
+//! The ends of the last `RECENT_LINE_ENDS` lines are kept as well, so the lines
+//! `tail_lines` usually asks for are found without scanning. Only asking for more
+//! lines than that from the end scans up to one stride of lines.
+//!
+//! A stream's file is only held open for writing until the job finishes; reads
+//! open it read-only for the duration of the read, so finished jobs hold no file
+//! descriptors. A job's files are removed when the job is dropped from the
+//! registry and the server's directory on shutdown. Directories of servers that are no longer
+//! running are removed when the next server starts spooling. When no spool file can
+//! be written, the stream is kept in memory in full and a warning is logged.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileExt, OpenOptionsExt};
+
+const DEFAULT_MEMORY_BYTES: usize = 256 * 1024;
+
+/// Enough to look at the end of the output for resource limit messages.
+const MIN_MEMORY_BYTES: usize = 4096;
+
+/// Checkpoints kept per stream before their spacing doubles.
+const MAX_LINE_CHECKPOINTS: usize = 4096;
+
+/// Lines between checkpoints of a new stream.
+const INITIAL_LINE_STRIDE: usize = 64;
+
+/// Line ends kept for the most recent lines.
+const RECENT_LINE_ENDS: usize = 4096;
+
+/// Bytes read at a time when scanning for a line.
+const LINE_SCAN_CHUNK: usize = 64 * 1024;
+
+static SPOOL_DIR: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+
+fn state_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("ENHANCED_TERMINAL_STATE_DIR").filter(|v| !v.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("enhanced-terminal-mcp")
+}
+
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(path)
+}
+
+#[cfg(unix)]
+fn server_running(pid: i32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH))
+}
+
+#[cfg(not(unix))]
+fn server_running(_pid: i32) -> bool {
+    true
+}
+
+/// Remove the directories of servers that exited without cleaning up.
+fn remove_stale(root: &Path) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        if pid as u32 != std::process::id() && !server_running(pid) {
+            tracing::debug!("Removing stale output spool {}", entry.path().display());
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+fn init_dir() -> Result<PathBuf, String> {
+    let root = state_dir().join("spool");
+    create_private_dir(&root).map_err(|e| format!("cannot create {}: {}", root.display(), e))?;
+    remove_stale(&root);
+    let dir = root.join(std::process::id().to_string());
+    create_private_dir(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    tracing::info!("Spooling job output to {}", dir.display());
+    Ok(dir)
+}
+
+/// This server's spool directory, created on first use.
+pub fn spool_dir() -> Result<&'static Path, &'static str> {
+    match SPOOL_DIR.get_or_init(init_dir) {
+        Ok(dir) => Ok(dir),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove this server's spool directory on shutdown.
+pub fn cleanup() {
+    if let Some(Ok(dir)) = SPOOL_DIR.get() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+fn memory_bytes() -> usize {
+    std::env::var("ENHANCED_TERMINAL_OUTPUT_MEMORY_BYTES")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_MEMORY_BYTES)
+        .max(MIN_MEMORY_BYTES)
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// One output stream of a job: a spool file and the last bytes in memory.
+pub struct Spool {
+    name: String,
+    path: Option<PathBuf>,
+    /// Open for appending until the stream is finished
+    writer: Option<File>,
+    finished: bool,
+    /// Why the stream is not (or no longer) spooled to disk
+    unspooled: Option<String>,
+    len: usize,
+    /// The last bytes of the stream, starting at `tail_start`
+    tail: VecDeque<u8>,
+    tail_start: usize,
+    capacity: usize,
+    /// Number of `\n` in the stream
+    lines: usize,
+    /// Offset just past the last `\n`
+    last_line_end: usize,
+    /// Start offset of every `line_stride`th line: entry `i` is line
+    /// `(i + 1) * line_stride`, 0-based
+    line_checkpoints: Vec<usize>,
+    line_stride: usize,
+    /// Offsets just past each of the last `RECENT_LINE_ENDS` `\n`
+    recent_line_ends: VecDeque<usize>,
+}
+
+impl fmt::Debug for Spool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spool")
+            .field("path", &self.path)
+            .field("len", &self.len)
+            .field("tail_start", &self.tail_start)
+            .finish()
+    }
+}
+
+impl Spool {
+    /// A stream spooled to `<spool dir>/<name>` once it gets output.
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            path: None,
+            writer: None,
+            finished: false,
+            unspooled: None,
+            len: 0,
+            tail: VecDeque::new(),
+            tail_start: 0,
+            capacity: memory_bytes(),
+            lines: 0,
+            last_line_end: 0,
+            line_checkpoints: Vec::new(),
+            line_stride: INITIAL_LINE_STRIDE,
+            recent_line_ends: VecDeque::new(),
+        }
+    }
+
+    /// Total bytes appended so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Path of the spool file, if the stream is spooled.
+    #[allow(dead_code)]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Create the spool file, or reopen it for appending after [`finish`](Self::finish).
+    fn open(&mut self) {
+        let opened = match &self.path {
+            Some(path) => OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map(|file| (path.clone(), file))
+                .map_err(|e| format!("reopening the spool file failed: {}", e)),
+            None => spool_dir().map_err(str::to_string).and_then(|dir| {
+                let path = dir.join(&self.name);
+                let mut options = OpenOptions::new();
+                options.create(true).truncate(true).write(true);
+                #[cfg(unix)]
+                options.mode(0o600);
+                options
+                    .open(&path)
+                    .map(|file| (path, file))
+                    .map_err(|e| e.to_string())
+            }),
+        };
+        match opened {
+            Ok((path, file)) => {
+                self.path = Some(path);
+                self.writer = Some(file);
+            }
+            Err(e) => self.keep_in_memory(e),
+        }
+    }
+
+    /// Close the spool file once no more output is expected. Output appended
+    /// later still goes to the file, which is closed again right after.
+    pub fn finish(&mut self) {
+        self.finished = true;
+        self.writer = None;
+    }
+
+    /// Stop spooling and keep everything from here on in memory.
+    fn keep_in_memory(&mut self, reason: String) {
+        tracing::warn!("Job output {} stays in memory: {}", self.name, reason);
+        self.writer = None;
+        if let Some(path) = self.path.take() {
+            let _ = fs::remove_file(path);
+        }
+        self.unspooled = Some(reason);
+        self.capacity = usize::MAX;
+    }
+
+    pub fn append(&mut self, data: &str) {
+        if data.is_empty() {
+            return;
+        }
+        if self.writer.is_none() && self.unspooled.is_none() {
+            self.open();
+        }
+        if let Some(file) = &mut self.writer
+            && let Err(e) = file.write_all(data.as_bytes())
+        {
+            self.keep_in_memory(format!("writing the spool file failed: {}", e));
+        }
+        if self.finished {
+            self.writer = None;
+        }
+
+        let start = self.len;
+        for (index, _) in data.bytes().enumerate().filter(|(_, byte)| *byte == b'\n') {
+            self.end_line(start + index + 1);
+        }
+        self.len += data.len();
+        self.tail.extend(data.as_bytes());
+        if self.tail.len() > self.capacity {
+            self.tail.drain(..self.tail.len() - self.capacity);
+            // Start the buffer on a character boundary
+            while self.tail.front().copied().is_some_and(is_continuation) {
+                self.tail.pop_front();
+            }
+        }
+        self.tail_start = self.len - self.tail.len();
+    }
+
+    /// The bytes in `start..end`, which need not be character boundaries.
+    pub fn read_bytes(&self, start: usize, end: usize) -> io::Result<Vec<u8>> {
+        let end = end.min(self.len);
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        if start >= self.tail_start {
+            return Ok(self
+                .tail
+                .range(start - self.tail_start..end - self.tail_start)
+                .copied()
+                .collect());
+        }
+        let Some(path) = &self.path else {
+            return Err(io::Error::other(format!(
+                "output before byte {} is gone: {}",
+                self.tail_start,
+                self.unspooled.as_deref().unwrap_or("it was not spooled")
+            )));
+        };
+        let file = File::open(path)?;
+        let mut bytes = vec![0; end - start];
+        #[cfg(unix)]
+        file.read_exact_at(&mut bytes, start as u64)?;
+        #[cfg(not(unix))]
+        {
+            use std::io::{Read, Seek, SeekFrom};
+            let mut file = &file;
+            file.seek(SeekFrom::Start(start as u64))?;
+            file.read_exact(&mut bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    /// The text in `start..end`, which should be character boundaries.
+    pub fn read(&self, start: usize, end: usize) -> io::Result<String> {
+        self.read_bytes(start, end)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn byte_at(&self, index: usize) -> Option<u8> {
+        self.read_bytes(index, index + 1)
+            .ok()
+            .and_then(|bytes| bytes.first().copied())
+    }
+
+    /// The largest character boundary at or before `index`.
+    pub fn floor_char_boundary(&self, index: usize) -> usize {
+        let mut index = index.min(self.len);
+        while index > 0 && self.byte_at(index).is_some_and(is_continuation) {
+            index -= 1;
+        }
+        index
+    }
+
+    /// True when `index` is the start of the stream or of a line.
+    pub fn at_line_start(&self, index: usize) -> bool {
+        index == 0 || self.byte_at(index - 1) == Some(b'\n')
+    }
+
+    /// Record a line ending just before `end`.
+    fn end_line(&mut self, end: usize) {
+        self.lines += 1;
+        self.last_line_end = end;
+        if self.recent_line_ends.len() == RECENT_LINE_ENDS {
+            self.recent_line_ends.pop_front();
+        }
+        self.recent_line_ends.push_back(end);
+        if !self.lines.is_multiple_of(self.line_stride) {
+            return;
+        }
+        if self.line_checkpoints.len() == MAX_LINE_CHECKPOINTS {
+            // Keep the checkpoints at multiples of the doubled stride
+            let mut index = 0;
+            self.line_checkpoints.retain(|_| {
+                index += 1;
+                index % 2 == 0
+            });
+            self.line_stride *= 2;
+            if !self.lines.is_multiple_of(self.line_stride) {
+                return;
+            }
+        }
+        self.line_checkpoints.push(end);
+    }
+
+    /// Number of lines, counting an unfinished last line.
+    pub fn line_count(&self) -> usize {
+        if self.last_line_end < self.len {
+            self.lines + 1
+        } else {
+            self.lines
+        }
+    }
+
+    /// Start offset of the 0-based line `line`, or the end of the stream past the
+    /// last line.
+    fn line_start(&self, line: usize) -> io::Result<usize> {
+        if line > self.lines {
+            return Ok(self.len);
+        }
+        // Line `line` starts where line `line - 1` ends
+        let first_recent = self.lines - self.recent_line_ends.len();
+        if line > first_recent {
+            return Ok(self.recent_line_ends[line - 1 - first_recent]);
+        }
+        let checkpoint = line / self.line_stride;
+        let mut offset = match checkpoint {
+            0 => 0,
+            checkpoint => self.line_checkpoints[checkpoint - 1],
+        };
+        let mut left = line - checkpoint * self.line_stride;
+        while left > 0 {
+            let chunk = self.read_bytes(offset, offset + LINE_SCAN_CHUNK)?;
+            if chunk.is_empty() {
+                break;
+            }
+            for (index, byte) in chunk.iter().enumerate() {
+                if *byte == b'\n' {
+                    left -= 1;
+                    if left == 0 {
+                        return Ok(offset + index + 1);
+                    }
+                }
+            }
+            offset += chunk.len();
+        }
+        Ok(offset)
+    }
+
+    /// Byte range of `count` lines starting at the 0-based line `first`, clamped
+    /// to the lines there are. Includes the line end of the last line.
+    pub fn line_range(&self, first: usize, count: usize) -> io::Result<(usize, usize)> {
+        let first = first.min(self.line_count());
+        let end = first.saturating_add(count).min(self.line_count());
+        Ok((self.line_start(first)?, self.line_start(end)?))
+    }
+
+    /// Up to the last `bytes` bytes of the stream, from memory.
+    pub fn tail(&self, bytes: usize) -> String {
+        let skip = self.tail.len().saturating_sub(bytes);
+        let mut tail: Vec<u8> = self.tail.range(skip..).copied().collect();
+        let boundary = tail
+            .iter()
+            .take_while(|byte| is_continuation(**byte))
+            .count();
+        tail.drain(..boundary);
+        String::from_utf8_lossy(&tail).into_owned()
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        self.writer = None;
+        if let Some(path) = self.path.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_old_ranges_from_the_file_and_recent_ones_from_memory() {
+        let mut spool = Spool::new(format!("test-{}.out", std::process::id()));
+        spool.capacity = 16;
+        for line in 0..100 {
+            spool.append(&format!("line {:03} é\n", line));
+        }
+        let full: String = (0..100)
+            .map(|line| format!("line {:03} é\n", line))
+            .collect();
+        assert_eq!(spool.len(), full.len());
+        assert!(spool.tail.len() <= 16 && spool.tail_start > 0);
+
+        let path = spool.path().expect("spool file").to_path_buf();
+        assert_eq!(fs::read_to_string(&path).unwrap(), full);
+        assert_eq!(spool.read(0, full.len()).unwrap(), full);
+        assert_eq!(spool.read(13, 26).unwrap(), &full[13..26]);
+        assert_eq!(spool.tail(5), "9 é\n");
+
+        // "é" is two bytes; offsets inside it move back to its start
+        let inside = full.find('é').unwrap() + 1;
+        assert_eq!(spool.floor_char_boundary(inside), inside - 1);
+        assert!(spool.at_line_start(12) && !spool.at_line_start(13));
+
+        assert_eq!(spool.line_count(), 100);
+        assert_eq!(spool.line_range(0, 1).unwrap(), (0, 12));
+        assert_eq!(spool.line_range(98, 5).unwrap(), (98 * 12, full.len()));
+        assert_eq!(spool.line_range(150, 5).unwrap(), (full.len(), full.len()));
+        spool.append("partial");
+        assert_eq!(spool.line_count(), 101);
+        assert_eq!(
+            spool.line_range(100, 1).unwrap(),
+            (full.len(), full.len() + 7)
+        );
+
+        // Finished streams hold no open file; reads and late output reopen it
+        spool.finish();
+        assert!(spool.writer.is_none());
+        spool.append("!\n");
+        assert!(spool.writer.is_none());
+        assert_eq!(spool.read(0, 12).unwrap(), &full[..12]);
+        assert!(fs::read_to_string(&path).unwrap().ends_with("partial!\n"));
+
+        drop(spool);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn line_index_stays_bounded_for_many_lines() {
+        let mut spool = Spool::new(format!("test-lines-{}.out", std::process::id()));
+        spool.capacity = 4096;
+        let lines = 2 * MAX_LINE_CHECKPOINTS * INITIAL_LINE_STRIDE + 123;
+        let chunk: String = (0..1000).map(|line| format!("{:07}\n", line)).collect();
+        for _ in 0..lines / 1000 {
+            spool.append(&chunk);
+        }
+        for line in 0..lines % 1000 {
+            spool.append(&format!("{:07}\n", line));
+        }
+
+        assert_eq!(spool.line_count(), lines);
+        assert!(spool.line_checkpoints.len() <= MAX_LINE_CHECKPOINTS);
+        assert!(spool.line_checkpoints.capacity() <= MAX_LINE_CHECKPOINTS);
+        assert_eq!(spool.line_stride, 2 * INITIAL_LINE_STRIDE);
+
+        // Every line is 8 bytes, so the index must agree with plain arithmetic
+        for first in [0, 1, 255, 256, 257, 300_001, lines - 1, lines] {
+            assert_eq!(
+                spool.line_range(first, 2).unwrap(),
+                (8 * first, 8 * (first + 2).min(lines)),
+                "line {first}"
+            );
+        }
+        assert_eq!(spool.read(8 * 300_001, 8 * 300_002).unwrap(), "0000001\n");
+    }
+
+    #[test]
+    fn recent_lines_are_found_without_scanning() {
+        let mut spool = Spool::new(format!("test-recent-{}.out", std::process::id()));
+        // Fewer lines in memory than a stride, so scanning from a checkpoint would
+        // have to read the file
+        spool.capacity = 256;
+        let lines = RECENT_LINE_ENDS + 1000;
+        for line in 0..lines {
+            spool.append(&format!("{:07}\n", line));
+        }
+        spool.keep_in_memory("test".to_string());
+
+        let first = lines - 10;
+        let (start, end) = spool.line_range(first, 10).unwrap();
+        assert_eq!((start, end), (8 * first, 8 * lines));
+        assert_eq!(
+            &spool.read(start, end).unwrap()[..8],
+            format!("{:07}\n", first)
+        );
+        let oldest = lines - RECENT_LINE_ENDS + 1;
+        assert_eq!(spool.line_range(oldest, 1).unwrap().0, 8 * oldest);
+
+        // Further back, lines are found from a checkpoint, which needs the file
+        assert!(spool.line_range(oldest - 1, 1).is_err());
+    }
+}
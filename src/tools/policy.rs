@@ -0,0 +1,793 @@
+//! Server-side command policy file.
+//!
+//! `ENHANCED_TERMINAL_POLICY_FILE` names a TOML (or `.json`) file with ordered
+//! allow/deny/ask rules. Each simple command in a command line is checked on its
+//! own: the first rule whose program, argument, cwd and env conditions all match
+//! decides, and the file's `default` applies when none does. The strictest decision
+//! across the sub-commands wins, so allowing `ls` never lets `ls && rm -rf build`
+//! through, and an `ask` line needs every sub-command an `ask` rule matched
+//! approved. An `allowlist` switches the server to allowlist-only mode (see
+//! [`allowlist`](super::allowlist)), a `sudo_allowlist` limits what may run under
+//! `sudo`, the `[indirect]` table sets the action for
+//! each kind of indirect execution (see [`find_indirect`](denylist::find_indirect)),
+//! the `[redaction]` table adds secret patterns (see [`redaction`](super::redaction))
+//! and `[[rewrite]]` entries change commands before they are checked (see
+//! [`rewrite`](super::rewrite)). The file is reloaded when it changes. Clients
+//! cannot weaken it: their `custom_denylist` can only add denials.
+//!
+//! ```toml
+//! default = "allow"
+//!
+//! [[rules]]
+//! id = "no-force-push"
+//! action = "deny"
+//! program = "git"
+//! args = ["push", "--force"]
+//! cwd = ["/srv/**"]
+//! env = { DEPLOY_ENV = "prod*" }
+//! reason = "force pushes need review"
+//! ```
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use super::allowlist::{self, Allowlist};
+use super::denylist::{self, IndirectAction, IndirectActions, IndirectFinding, Rule, glob_matches};
+use super::redaction::{Detector, RedactionSpec};
+use super::rewrite::{RewriteRule, RewriteSpec};
+use super::shell_parser::{self, ParsedCommand, SimpleCommand};
+
+/// Environment variable naming the policy file.
+pub const POLICY_FILE_ENV: &str = "ENHANCED_TERMINAL_POLICY_FILE";
+
+/// What a policy rule decides, from least to most strict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    Ask,
+    Deny,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Allow => "allow",
+            Action::Ask => "ask",
+            Action::Deny => "deny",
+        })
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_ask_fallback() -> Action {
+    Action::Deny
+}
+
+fn default_ask_timeout_secs() -> u64 {
+    120
+}
+
+/// How `ask` decisions are put to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AskSettings {
+    /// `allow` or `deny` when the client does not support elicitation
+    pub fallback: Action,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    /// Decision for commands no rule matches
+    #[serde(default)]
+    default: Action,
+    /// Check the built-in denylist before the rules
+    #[serde(default = "default_true")]
+    builtin_denylist: bool,
+    /// Decision for `ask` when the client cannot prompt the user
+    #[serde(default = "default_ask_fallback")]
+    ask_fallback: Action,
+    /// How long to wait for the user to answer an approval prompt
+    #[serde(default = "default_ask_timeout_secs")]
+    ask_timeout_secs: u64,
+    #[serde(default)]
+    rules: Vec<RuleSpec>,
+    /// Allowlist-only mode: the programs commands may run
+    #[serde(default)]
+    allowlist: Option<Vec<allowlist::EntrySpec>>,
+    /// The commands that may run under sudo
+    #[serde(default)]
+    sudo_allowlist: Option<Vec<allowlist::EntrySpec>>,
+    /// Action for each kind of indirect execution
+    #[serde(default)]
+    indirect: IndirectActions,
+    /// Extra secret patterns to redact from output and the call log
+    #[serde(default)]
+    redaction: RedactionSpec,
+    /// Rewrite rules applied before commands are checked
+    #[serde(default)]
+    rewrite: Vec<RewriteSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    id: String,
+    action: Action,
+    /// Program basename, wildcards allowed
+    #[serde(default)]
+    program: Option<String>,
+    /// Flags and operands that must all appear, written like denylist rules
+    #[serde(default)]
+    args: Vec<String>,
+    /// Working directory globs; any may match
+    #[serde(default)]
+    cwd: Vec<String>,
+    /// Variables that must be set and match the glob
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+struct PolicyRule {
+    id: String,
+    action: Action,
+    command: Option<Rule>,
+    cwd: Vec<String>,
+    env: BTreeMap<String, String>,
+    reason: Option<String>,
+}
+
+impl PolicyRule {
+    fn matches(
+        &self,
+        command: &SimpleCommand,
+        cwd: &Path,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> bool {
+        let cwd = cwd.to_string_lossy();
+        // Only denials may assume a dynamic program name is theirs; an allow or ask
+        // rule must not let `$CMD status` through as `git status`
+        self.command.as_ref().is_none_or(|rule| match self.action {
+            Action::Deny => rule.matches_command(command),
+            Action::Allow | Action::Ask => rule.matches_known_command(command),
+        }) && (self.cwd.is_empty() || self.cwd.iter().any(|glob| glob_matches(glob, &cwd, true)))
+            && self.env.iter().all(|(name, glob)| {
+                env(name).is_some_and(|value| glob_matches(glob, &value, false))
+            })
+    }
+}
+
+/// A loaded policy file.
+pub struct Policy {
+    pub path: PathBuf,
+    default: Action,
+    builtin_denylist: bool,
+    pub ask: AskSettings,
+    rules: Vec<PolicyRule>,
+    allowlist: Option<Allowlist>,
+    sudo_allowlist: Option<Allowlist>,
+    indirect: IndirectActions,
+    redaction: Vec<Detector>,
+    rewrites: Vec<RewriteRule>,
+}
+
+/// The rule (or default) that decided a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyMatch {
+    pub action: Action,
+    pub file: PathBuf,
+    /// Rule id, or `None` when the file's default applied
+    pub rule_id: Option<String>,
+    pub reason: Option<String>,
+    /// Source text of the sub-command that decided
+    pub command: String,
+}
+
+impl fmt::Display for PolicyMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rule_id {
+            Some(id) => write!(f, "rule '{}' in {}", id, self.file.display())?,
+            None => write!(f, "default in {}", self.file.display())?,
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        write!(f, " (in `{}`)", self.command)
+    }
+}
+
+impl Policy {
+    /// Read and validate a policy file; `.json` files are JSON, anything else TOML.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read policy file {}", path.display()))?;
+        let file: PolicyFile = if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+        {
+            serde_json::from_str(&text)
+                .with_context(|| format!("invalid policy file {}", path.display()))?
+        } else {
+            toml::from_str(&text)
+                .with_context(|| format!("invalid policy file {}", path.display()))?
+        };
+
+        if file.ask_fallback == Action::Ask {
+            bail!(
+                "{}: ask_fallback must be \"allow\" or \"deny\"",
+                path.display()
+            );
+        }
+
+        let mut ids = BTreeSet::new();
+        let mut rules = Vec::with_capacity(file.rules.len());
+        for spec in file.rules {
+            if spec.id.trim().is_empty() {
+                bail!("{}: every rule needs an id", path.display());
+            }
+            if !ids.insert(spec.id.clone()) {
+                bail!("{}: duplicate rule id '{}'", path.display(), spec.id);
+            }
+            let command = if spec.program.is_some() || !spec.args.is_empty() {
+                let pattern = format!(
+                    "{} {}",
+                    spec.program.as_deref().unwrap_or("*"),
+                    spec.args.join(" ")
+                );
+                let rule = Rule::new(&pattern)
+                    .filter(Rule::is_command_pattern)
+                    .with_context(|| {
+                        format!(
+                            "{}: rule '{}': program and args must form a single command",
+                            path.display(),
+                            spec.id
+                        )
+                    })?;
+                Some(rule)
+            } else {
+                None
+            };
+            rules.push(PolicyRule {
+                id: spec.id,
+                action: spec.action,
+                command,
+                cwd: spec.cwd,
+                env: spec.env,
+                reason: spec.reason,
+            });
+        }
+
+        let allowlist = file
+            .allowlist
+            .map(Allowlist::new)
+            .transpose()
+            .with_context(|| format!("invalid policy file {}", path.display()))?;
+        let sudo_allowlist = file
+            .sudo_allowlist
+            .map(Allowlist::new)
+            .transpose()
+            .with_context(|| format!("invalid policy file {}", path.display()))?;
+        let redaction = file
+            .redaction
+            .compile()
+            .with_context(|| format!("invalid policy file {}", path.display()))?;
+        let mut rewrites: Vec<RewriteRule> = Vec::with_capacity(file.rewrite.len());
+        for spec in file.rewrite {
+            let rule = spec
+                .compile()
+                .with_context(|| format!("invalid policy file {}", path.display()))?;
+            if rewrites.iter().any(|other| other.id == rule.id) {
+                bail!("{}: duplicate rewrite id '{}'", path.display(), rule.id);
+            }
+            rewrites.push(rule);
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            default: file.default,
+            builtin_denylist: file.builtin_denylist,
+            ask: AskSettings {
+                fallback: file.ask_fallback,
+                timeout: Duration::from_secs(file.ask_timeout_secs),
+            },
+            rules,
+            allowlist,
+            sudo_allowlist,
+            indirect: file.indirect,
+            redaction,
+            rewrites,
+        })
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Secret patterns from the `[redaction]` table.
+    pub fn redaction_patterns(&self) -> &[Detector] {
+        &self.redaction
+    }
+
+    /// The `sudo_allowlist`, if the file has one.
+    pub fn sudo_allowlist(&self) -> Option<&Allowlist> {
+        self.sudo_allowlist.as_ref()
+    }
+
+    /// Rewrite rules from the `[[rewrite]]` entries, in file order.
+    pub fn rewrite_rules(&self) -> &[RewriteRule] {
+        &self.rewrites
+    }
+
+    /// Decide a parsed command line: the decisions of the sub-commands that share
+    /// the strictest action, in command order. Never empty.
+    pub fn evaluate(
+        &self,
+        command: &str,
+        commands: &[SimpleCommand],
+        cwd: &Path,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Vec<PolicyMatch> {
+        let whole = [SimpleCommand {
+            text: command.to_string(),
+            ..Default::default()
+        }];
+        let commands = if commands.is_empty() {
+            &whole[..]
+        } else {
+            commands
+        };
+
+        let mut decided: Vec<PolicyMatch> = Vec::new();
+        for command in commands {
+            let decision = match self.rules.iter().find(|r| r.matches(command, cwd, env)) {
+                Some(rule) => PolicyMatch {
+                    action: rule.action,
+                    file: self.path.clone(),
+                    rule_id: Some(rule.id.clone()),
+                    reason: rule.reason.clone(),
+                    command: command.text.clone(),
+                },
+                None => PolicyMatch {
+                    action: self.default,
+                    file: self.path.clone(),
+                    rule_id: None,
+                    reason: None,
+                    command: command.text.clone(),
+                },
+            };
+            match decided
+                .first()
+                .map(|current| decision.action.cmp(&current.action))
+            {
+                Some(std::cmp::Ordering::Less) => {}
+                Some(std::cmp::Ordering::Equal) => decided.push(decision),
+                _ => decided = vec![decision],
+            }
+        }
+        decided
+    }
+}
+
+struct Loaded {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    policy: Arc<Policy>,
+}
+
+static LOADED: Mutex<Option<Loaded>> = Mutex::new(None);
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The policy named by `ENHANCED_TERMINAL_POLICY_FILE`, reloaded when the file
+/// changes. A file that stops loading keeps the last good policy in force.
+pub fn current() -> Result<Option<Arc<Policy>>> {
+    let Some(path) = std::env::var_os(POLICY_FILE_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+    else {
+        return Ok(None);
+    };
+    let stamp = file_stamp(&path);
+
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(current) = loaded.as_ref().filter(|l| l.path == path)
+        && current.stamp == stamp
+    {
+        return Ok(Some(current.policy.clone()));
+    }
+
+    match Policy::load(&path) {
+        Ok(policy) => {
+            tracing::info!(
+                "Loaded command policy {} ({} rules{})",
+                path.display(),
+                policy.rule_count(),
+                policy
+                    .allowlist
+                    .as_ref()
+                    .map(|a| format!(", allowlist-only with {} entries", a.entry_count()))
+                    .unwrap_or_default()
+            );
+            let policy = Arc::new(policy);
+            *loaded = Some(Loaded {
+                path,
+                stamp,
+                policy: policy.clone(),
+            });
+            Ok(Some(policy))
+        }
+        Err(e) => match loaded.as_mut().filter(|l| l.path == path) {
+            Some(previous) => {
+                tracing::warn!("Keeping the previous command policy: {:#}", e);
+                previous.stamp = stamp;
+                Ok(Some(previous.policy.clone()))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Outcome of checking a command line before it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Denied, with the reason to report
+    Deny(String),
+    /// Policy `ask` rules matched; the user decides on all of them at once
+    Ask(Vec<PolicyMatch>, AskSettings),
+}
+
+/// A verdict together with what decided it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub verdict: Verdict,
+    /// The denylist pattern, allowlist entry or policy rule that decided, if any
+    pub matched: Option<String>,
+    /// Indirect execution found in the command, whatever the verdict
+    pub findings: Vec<IndirectFinding>,
+}
+
+/// Check a command line against the built-in denylist, the client's custom
+/// denylist and the policy file, and report the rule that decided. `env` looks up
+/// the command's environment.
+pub fn explain(
+    command: &str,
+    custom_denylist: &[String],
+    cwd: &Path,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Explanation {
+    let policy = match current() {
+        Ok(policy) => policy,
+        Err(e) => {
+            return Explanation {
+                verdict: Verdict::Deny(format!(
+                    "Command denied: the command policy could not be loaded: {:#}",
+                    e
+                )),
+                matched: None,
+                findings: Vec::new(),
+            };
+        }
+    };
+
+    let parsed = shell_parser::parse(command);
+    let actions = policy
+        .as_ref()
+        .map_or_else(IndirectActions::default, |p| p.indirect);
+    let findings = denylist::find_indirect(&parsed, &actions);
+    let (verdict, matched) = decide(
+        command,
+        &parsed,
+        &findings,
+        policy.as_deref(),
+        custom_denylist,
+        cwd,
+        env,
+    );
+    Explanation {
+        verdict,
+        matched,
+        findings,
+    }
+}
+
+fn decide(
+    command: &str,
+    parsed: &ParsedCommand,
+    findings: &[IndirectFinding],
+    policy: Option<&Policy>,
+    custom_denylist: &[String],
+    cwd: &Path,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> (Verdict, Option<String>) {
+    let builtin = policy.is_none_or(|p| p.builtin_denylist);
+    if let Some(matched) = denylist::find_match(command, parsed, custom_denylist, builtin) {
+        return (
+            Verdict::Deny(format!(
+                "Command denied by security policy. Matched pattern: {}",
+                matched
+            )),
+            Some(format!("denylist pattern {}", matched)),
+        );
+    }
+    if let Some(finding) = findings.iter().find(|f| f.action == IndirectAction::Deny) {
+        return (
+            Verdict::Deny(format!(
+                "Command denied: indirect execution the denylist cannot check: {}",
+                finding
+            )),
+            Some(format!("indirect execution rule '{}'", finding.rule.id())),
+        );
+    }
+
+    let Some(policy) = policy else {
+        return (Verdict::Allow, None);
+    };
+    if let Some(allowlist) = &policy.allowlist
+        && let Err(rejection) = allowlist.check(parsed)
+    {
+        return (
+            Verdict::Deny(format!(
+                "Command denied by the allowlist in {}: {}",
+                policy.path.display(),
+                rejection
+            )),
+            Some(format!(
+                "allowlist in {}: {}",
+                policy.path.display(),
+                rejection
+            )),
+        );
+    }
+
+    if let Some(sudo_allowlist) = &policy.sudo_allowlist
+        && let Err(rejection) = sudo_allowlist.check_sudo(parsed)
+    {
+        return (
+            Verdict::Deny(format!(
+                "Command denied by the sudo allowlist in {}: {}",
+                policy.path.display(),
+                rejection
+            )),
+            Some(format!(
+                "sudo allowlist in {}: {}",
+                policy.path.display(),
+                rejection
+            )),
+        );
+    }
+
+    let mut decisions = policy.evaluate(command, &parsed.commands, cwd, env);
+    let indirect = findings
+        .iter()
+        .filter(|f| f.action == IndirectAction::Ask)
+        .map(|finding| PolicyMatch {
+            action: Action::Ask,
+            file: policy.path.clone(),
+            rule_id: Some(format!("indirect:{}", finding.rule.id())),
+            reason: Some(finding.detail.clone()),
+            command: finding.command.clone(),
+        });
+    match decisions[0].action {
+        Action::Allow => {
+            let asks: Vec<PolicyMatch> = indirect.collect();
+            if !asks.is_empty() {
+                decisions = asks;
+            }
+        }
+        Action::Ask => decisions.extend(indirect),
+        Action::Deny => {}
+    }
+    match decisions[0].action {
+        Action::Allow => (Verdict::Allow, Some(format!("policy {}", decisions[0]))),
+        Action::Deny => (
+            Verdict::Deny(format!("Command denied by policy {}", decisions[0])),
+            Some(format!("policy {}", decisions[0])),
+        ),
+        Action::Ask => {
+            let matched = Some(format!("policy {}", list(&decisions)));
+            (Verdict::Ask(decisions, policy.ask), matched)
+        }
+    }
+}
+
+/// Several decisions for one message, separated by semicolons.
+pub fn list(decisions: &[PolicyMatch]) -> String {
+    decisions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide(policy: &Policy, command: &str, cwd: &str, env: &[(&str, &str)]) -> PolicyMatch {
+        decide_all(policy, command, cwd, env).remove(0)
+    }
+
+    fn decide_all(
+        policy: &Policy,
+        command: &str,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Vec<PolicyMatch> {
+        let lookup = |name: &str| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        let parsed = shell_parser::parse(command);
+        policy.evaluate(command, &parsed.commands, Path::new(cwd), &lookup)
+    }
+
+    #[test]
+    fn first_matching_rule_decides_and_strictest_sub_command_wins() {
+        let dir = std::env::temp_dir().join(format!("policy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("policy.toml");
+        std::fs::write(
+            &path,
+            r#"
+default = "deny"
+
+[[rules]]
+id = "prod-push"
+action = "ask"
+program = "git"
+args = ["push"]
+env = { DEPLOY_ENV = "prod*" }
+
+[[rules]]
+id = "force-push"
+action = "deny"
+program = "git"
+args = ["push", "--force"]
+reason = "force pushes need review"
+
+[[rules]]
+id = "git"
+action = "allow"
+program = "git"
+
+[[rules]]
+id = "builds"
+action = "allow"
+program = "make"
+cwd = ["/srv/**"]
+"#,
+        )
+        .unwrap();
+        let policy = Policy::load(&path).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let force = decide(&policy, "git push -f origin --force", "/", &[]);
+        assert_eq!(force.action, Action::Deny);
+        assert_eq!(force.rule_id.as_deref(), Some("force-push"));
+        assert_eq!(
+            force.to_string(),
+            format!(
+                "rule 'force-push' in {}: force pushes need review (in `git push -f origin --force`)",
+                path.display()
+            )
+        );
+
+        let prod = decide(
+            &policy,
+            "git push --force",
+            "/",
+            &[("DEPLOY_ENV", "prod-eu")],
+        );
+        assert_eq!(prod.rule_id.as_deref(), Some("prod-push"));
+        assert_eq!(prod.action, Action::Ask);
+        // Every sub-command an `ask` rule matched is reported, not only the first
+        let asks = decide_all(
+            &policy,
+            "git push a && git status && git push b",
+            "/",
+            &[("DEPLOY_ENV", "prod")],
+        );
+        let commands: Vec<&str> = asks.iter().map(|m| m.command.as_str()).collect();
+        assert_eq!(commands, ["git push a", "git push b"]);
+        assert_eq!(
+            decide(&policy, "git status", "/", &[]).action,
+            Action::Allow
+        );
+
+        assert_eq!(
+            decide(&policy, "make -j4", "/srv/app/src", &[]).action,
+            Action::Allow
+        );
+        let elsewhere = decide(&policy, "make", "/home/me", &[]);
+        assert_eq!((elsewhere.action, elsewhere.rule_id), (Action::Deny, None));
+
+        // `git log` is allowed, but `less` falls through to the default
+        let mixed = decide(&policy, "git log | less", "/", &[]);
+        assert_eq!((mixed.action, mixed.rule_id), (Action::Deny, None));
+        assert_eq!(mixed.command, "less");
+    }
+
+    #[test]
+    fn dynamic_programs_only_match_deny_rules() {
+        let dir = std::env::temp_dir().join(format!("policy-dynamic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("policy.toml");
+        std::fs::write(
+            &path,
+            r#"
+default = "deny"
+
+[[rules]]
+id = "status"
+action = "allow"
+program = "git"
+args = ["status"]
+
+[[rules]]
+id = "confirm-apply"
+action = "ask"
+program = "kubectl"
+args = ["apply"]
+
+[[rules]]
+id = "no-rm"
+action = "deny"
+program = "rm"
+args = ["-r"]
+"#,
+        )
+        .unwrap();
+        let policy = Policy::load(&path).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // `$CMD` could be `rm -rf ~`; the allow rule must not vouch for it
+        let env = [("CMD", "rm -rf ~")];
+        let allowed = decide(&policy, "$CMD status", "/", &env);
+        assert_eq!((allowed.action, allowed.rule_id), (Action::Deny, None));
+        assert_eq!(
+            decide(&policy, "git status", "/", &[]).action,
+            Action::Allow
+        );
+        let asked = decide(&policy, "$KUBECTL apply -f x.yaml", "/", &[]);
+        assert_eq!((asked.action, asked.rule_id), (Action::Deny, None));
+        let denied = decide(&policy, "$CMD -r build", "/", &env);
+        assert_eq!(denied.rule_id.as_deref(), Some("no-rm"));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let dir = std::env::temp_dir().join(format!("policy-invalid-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let duplicate = dir.join("duplicate.json");
+        std::fs::write(
+            &duplicate,
+            r#"{"rules": [{"id": "a", "action": "deny"}, {"id": "a", "action": "allow"}]}"#,
+        )
+        .unwrap();
+        let unknown = dir.join("unknown.toml");
+        std::fs::write(&unknown, "[[rules]]\nid = \"a\"\naction = \"block\"\n").unwrap();
+
+        let duplicate = Policy::load(&duplicate).err().unwrap();
+        let unknown = Policy::load(&unknown).err().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(format!("{:#}", duplicate).contains("duplicate rule id 'a'"));
+        assert!(format!("{:#}", unknown).contains("invalid policy file"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Process-group termination with signal escalation.
+//!
+//! Every job is the leader of its own process group: PTY jobs get a fresh session
+//! from portable-pty, and piped jobs are spawned with `process_group(0)`. Signalling
+//! the group instead of the shell pid also reaches grandchildren such as `rustc`
+//! under `cargo` or a `node` server started by `npm`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Stage of the SIGINT → SIGTERM → SIGKILL escalation that ended a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TerminationStage {
+    Interrupt,
+    Terminate,
+    Kill,
+}
+
+impl TerminationStage {
+    /// Stages in the order they are tried.
+    pub const ESCALATION: [TerminationStage; 3] = [
+        TerminationStage::Interrupt,
+        TerminationStage::Terminate,
+        TerminationStage::Kill,
+    ];
+
+    pub fn signal_name(self) -> &'static str {
+        match self {
+            TerminationStage::Interrupt => "SIGINT",
+            TerminationStage::Terminate => "SIGTERM",
+            TerminationStage::Kill => "SIGKILL",
+        }
+    }
+
+    #[cfg(unix)]
+    fn signal(self) -> nix::sys::signal::Signal {
+        use nix::sys::signal::Signal;
+
+        match self {
+            TerminationStage::Interrupt => Signal::SIGINT,
+            TerminationStage::Terminate => Signal::SIGTERM,
+            TerminationStage::Kill => Signal::SIGKILL,
+        }
+    }
+}
+
+impl fmt::Display for TerminationStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.signal_name())
+    }
+}
+
+/// Parse a signal given as a name (`SIGINT`, `INT`, `int`) or a number (`2`).
+#[cfg(unix)]
+pub fn parse_signal(value: &str) -> anyhow::Result<nix::sys::signal::Signal> {
+    use nix::sys::signal::Signal;
+    use std::str::FromStr;
+
+    let value = value.trim();
+    if let Ok(number) = value.parse::<i32>() {
+        return Signal::try_from(number)
+            .map_err(|_| anyhow::anyhow!("Unknown signal number: {}", number));
+    }
+
+    let upper = value.to_ascii_uppercase();
+    let name = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{}", upper)
+    };
+    Signal::from_str(&name).map_err(|_| anyhow::anyhow!("Unknown signal: {}", value))
+}
+
+/// Returns true while any process (other than an already reaped leader) is in the group.
+#[cfg(unix)]
+fn group_alive(pgid: u32) -> bool {
+    use nix::sys::signal::killpg;
+    use nix::unistd::Pid;
+
+    if killpg(Pid::from_raw(pgid as i32), None).is_err() {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        has_live_member(pgid)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        true
+    }
+}
+
+/// Scan `/proc` for a non-zombie process in the group.
+///
+/// `kill(2)` still succeeds for zombies, and orphaned grandchildren can stay zombies
+/// for a long time when the container's init does not reap them.
+#[cfg(target_os = "linux")]
+fn has_live_member(pgid: u32) -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return true;
+    };
+    let pgid = pgid.to_string();
+
+    entries.flatten().any(|entry| {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            return false;
+        }
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            return false;
+        };
+        // Fields after the parenthesised command name: state ppid pgrp ...
+        let Some((_, rest)) = stat.rsplit_once(") ") else {
+            return false;
+        };
+        let mut fields = rest.split_whitespace();
+        let state = fields.next();
+        let pgrp = fields.nth(1);
+        state != Some("Z") && pgrp == Some(pgid.as_str())
+    })
+}
+
+/// Terminate the process group led by `pgid`, escalating SIGINT → SIGTERM → SIGKILL.
+///
+/// Each stage waits up to `grace` for the group to disappear. `leader_exited` must
+/// poll (and thereby reap) the group leader, otherwise its zombie keeps the group
+/// alive. Returns the stage that ended the group, or `None` if it was already gone.
+#[cfg(unix)]
+pub(super) async fn terminate_group(
+    pgid: u32,
+    grace: Duration,
+    mut leader_exited: impl FnMut() -> bool,
+) -> Option<TerminationStage> {
+    use nix::sys::signal::{Signal, killpg};
+    use nix::unistd::Pid;
+    use tokio::time::Instant;
+
+    let poll_interval = Duration::from_millis(50);
+    let mut finished = || leader_exited() && !group_alive(pgid);
+
+    if finished() {
+        return None;
+    }
+
+    for stage in TerminationStage::ESCALATION {
+        tracing::debug!("Sending {} to process group {}", stage, pgid);
+        let _ = killpg(Pid::from_raw(pgid as i32), stage.signal());
+        // A paused job only sees SIGINT/SIGTERM once it runs again.
+        let _ = killpg(Pid::from_raw(pgid as i32), Signal::SIGCONT);
+
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if finished() {
+                return Some(stage);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+        if finished() {
+            return Some(stage);
+        }
+    }
+
+    tracing::warn!("Process group {} survived SIGKILL grace period", pgid);
+    Some(TerminationStage::Kill)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn parses_signal_names_and_numbers() {
+        use nix::sys::signal::Signal;
+
+        assert_eq!(parse_signal("SIGINT").unwrap(), Signal::SIGINT);
+        assert_eq!(parse_signal("hup").unwrap(), Signal::SIGHUP);
+        assert_eq!(parse_signal(" Stop ").unwrap(), Signal::SIGSTOP);
+        assert_eq!(parse_signal("9").unwrap(), Signal::SIGKILL);
+        assert!(parse_signal("SIGNOPE").is_err());
+        assert!(parse_signal("999").is_err());
+    }
+
+    #[tokio::test]
+    async fn escalates_until_the_whole_group_is_gone() {
+        // The shell ignores SIGINT; its background sleep inherits that and also
+        // ignores SIGTERM, so only SIGKILL can clear the group.
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("trap '' INT TERM; sleep 30 & wait")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let pgid = child.id();
+        let stage = terminate_group(pgid, Duration::from_millis(300), || {
+            matches!(child.try_wait(), Ok(Some(_)))
+        })
+        .await;
+
+        assert_eq!(stage, Some(TerminationStage::Kill));
+        assert!(!group_alive(pgid));
+    }
+
+    #[tokio::test]
+    async fn first_stage_ends_a_cooperative_group() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+
+        let stage = terminate_group(child.id(), Duration::from_secs(5), || {
+            matches!(child.try_wait(), Ok(Some(_)))
+        })
+        .await;
+
+        assert_eq!(stage, Some(TerminationStage::Interrupt));
+    }
+}
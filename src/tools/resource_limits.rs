@@ -0,0 +1,313 @@
+//! Per-job resource limits (rlimits).
+//!
+//! Limits requested by the client are capped by server-side maximums from
+//! `ENHANCED_TERMINAL_MAX_*` environment variables; a configured maximum also
+//! applies to commands that do not request a limit. The launcher applies the
+//! effective limits to the job's process right before it execs the shell.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Optional rlimits for a single command. Unset fields leave the limit unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// RLIMIT_CPU: CPU time in seconds. The process gets SIGXCPU when it is reached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+    /// RLIMIT_AS: maximum virtual memory (address space) in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<u64>,
+    /// RLIMIT_FSIZE: largest file the process may write, in bytes. Exceeding it raises SIGXFSZ.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size_bytes: Option<u64>,
+    /// RLIMIT_NPROC: maximum processes for the user. Counts every process of the user, not just this job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_processes: Option<u64>,
+    /// RLIMIT_NOFILE: maximum number of open file descriptors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<u64>,
+}
+
+/// A single rlimit, used to report which limit ended a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResourceLimit {
+    Cpu,
+    AddressSpace,
+    FileSize,
+    Processes,
+    OpenFiles,
+}
+
+impl ResourceLimit {
+    pub fn rlimit_name(self) -> &'static str {
+        match self {
+            ResourceLimit::Cpu => "RLIMIT_CPU",
+            ResourceLimit::AddressSpace => "RLIMIT_AS",
+            ResourceLimit::FileSize => "RLIMIT_FSIZE",
+            ResourceLimit::Processes => "RLIMIT_NPROC",
+            ResourceLimit::OpenFiles => "RLIMIT_NOFILE",
+        }
+    }
+}
+
+/// A limit that (most likely) ended a job, with the evidence it was derived from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitExceeded {
+    pub limit: ResourceLimit,
+    pub detail: String,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.limit.rlimit_name(), self.detail)
+    }
+}
+
+fn env_limit(name: &str) -> Option<u64> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+}
+
+fn cap(requested: Option<u64>, maximum: Option<u64>) -> Option<u64> {
+    match (requested, maximum) {
+        (Some(requested), Some(maximum)) => Some(requested.min(maximum)),
+        (requested, maximum) => requested.or(maximum),
+    }
+}
+
+impl ResourceLimits {
+    /// Server-side maximums from `ENHANCED_TERMINAL_MAX_*` environment variables.
+    pub fn server_maximums() -> Self {
+        Self {
+            cpu_secs: env_limit("ENHANCED_TERMINAL_MAX_CPU_SECS"),
+            memory_bytes: env_limit("ENHANCED_TERMINAL_MAX_MEMORY_BYTES"),
+            file_size_bytes: env_limit("ENHANCED_TERMINAL_MAX_FILE_SIZE_BYTES"),
+            max_processes: env_limit("ENHANCED_TERMINAL_MAX_PROCESSES"),
+            max_open_files: env_limit("ENHANCED_TERMINAL_MAX_OPEN_FILES"),
+        }
+    }
+
+    /// Combine a client request with server maximums: the smaller value wins, and a
+    /// maximum applies even when the client did not ask for that limit.
+    pub fn capped_by(requested: Option<&ResourceLimits>, maximums: &ResourceLimits) -> Self {
+        let requested = requested.cloned().unwrap_or_default();
+        Self {
+            cpu_secs: cap(requested.cpu_secs, maximums.cpu_secs),
+            memory_bytes: cap(requested.memory_bytes, maximums.memory_bytes),
+            file_size_bytes: cap(requested.file_size_bytes, maximums.file_size_bytes),
+            max_processes: cap(requested.max_processes, maximums.max_processes),
+            max_open_files: cap(requested.max_open_files, maximums.max_open_files),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Apply the limits to the current process. Values above the current hard limit
+    /// are lowered to it, since an unprivileged process cannot raise hard limits.
+    #[cfg(unix)]
+    pub fn apply(&self) -> anyhow::Result<()> {
+        use nix::sys::resource::{Resource, getrlimit, setrlimit};
+
+        let set = |resource: Resource, value: Option<u64>, name: &str| -> anyhow::Result<()> {
+            let Some(value) = value else {
+                return Ok(());
+            };
+            let (_, hard) = getrlimit(resource)?;
+            let value = (value as nix::sys::resource::rlim_t).min(hard);
+            // Leave one second between SIGXCPU (soft) and SIGKILL (hard) for CPU time.
+            let hard = if resource == Resource::RLIMIT_CPU {
+                value.saturating_add(1).min(hard)
+            } else {
+                value
+            };
+            setrlimit(resource, value, hard)
+                .map_err(|e| anyhow::anyhow!("failed to set {}: {}", name, e))
+        };
+
+        set(Resource::RLIMIT_CPU, self.cpu_secs, "RLIMIT_CPU")?;
+        set(Resource::RLIMIT_AS, self.memory_bytes, "RLIMIT_AS")?;
+        set(Resource::RLIMIT_FSIZE, self.file_size_bytes, "RLIMIT_FSIZE")?;
+        set(Resource::RLIMIT_NPROC, self.max_processes, "RLIMIT_NPROC")?;
+        set(
+            Resource::RLIMIT_NOFILE,
+            self.max_open_files,
+            "RLIMIT_NOFILE",
+        )?;
+        Ok(())
+    }
+
+    /// Work out whether one of these limits ended a job.
+    ///
+    /// CPU and file size limits are certain when the job died from SIGXCPU/SIGXFSZ
+    /// (or the shell exited with 128 + that signal). The other limits only make
+    /// system calls fail, so they are recognised from the error message the
+    /// command printed last.
+    pub fn detect_exceeded(
+        &self,
+        signal: Option<i32>,
+        exit_code: Option<i32>,
+        output_tail: &str,
+    ) -> Option<LimitExceeded> {
+        let signal = signal.or_else(|| exit_code.filter(|code| *code > 128).map(|c| c - 128));
+
+        #[cfg(unix)]
+        {
+            use nix::sys::signal::Signal;
+
+            if let Some(cpu_secs) = self.cpu_secs
+                && signal == Some(Signal::SIGXCPU as i32)
+            {
+                return Some(LimitExceeded {
+                    limit: ResourceLimit::Cpu,
+                    detail: format!("killed by SIGXCPU after {}s of CPU time", cpu_secs),
+                });
+            }
+            if let Some(file_size) = self.file_size_bytes
+                && signal == Some(Signal::SIGXFSZ as i32)
+            {
+                return Some(LimitExceeded {
+                    limit: ResourceLimit::FileSize,
+                    detail: format!("killed by SIGXFSZ writing past {} bytes", file_size),
+                });
+            }
+        }
+
+        if exit_code == Some(0) {
+            return None;
+        }
+
+        let output = output_tail.to_ascii_lowercase();
+        let checks: [(Option<u64>, ResourceLimit, &[&str]); 4] = [
+            (
+                self.max_open_files,
+                ResourceLimit::OpenFiles,
+                &["too many open files"],
+            ),
+            (
+                self.max_processes,
+                ResourceLimit::Processes,
+                &[
+                    "fork: resource temporarily unavailable",
+                    "fork: retry: resource temporarily unavailable",
+                    "cannot fork",
+                ],
+            ),
+            (
+                self.memory_bytes,
+                ResourceLimit::AddressSpace,
+                &[
+                    "cannot allocate memory",
+                    "memory allocation of",
+                    "out of memory",
+                    "memoryerror",
+                    "std::bad_alloc",
+                ],
+            ),
+            (
+                self.file_size_bytes,
+                ResourceLimit::FileSize,
+                &["file too large"],
+            ),
+        ];
+
+        checks.into_iter().find_map(|(value, limit, needles)| {
+            let value = value?;
+            let needle = needles.iter().find(|needle| output.contains(**needle))?;
+            Some(LimitExceeded {
+                limit,
+                detail: format!("limit {} reached; output reports \"{}\"", value, needle),
+            })
+        })
+    }
+}
+
+impl fmt::Display for ResourceLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            self.cpu_secs.map(|v| format!("cpu={}s", v)),
+            self.memory_bytes.map(|v| format!("memory={}B", v)),
+            self.file_size_bytes.map(|v| format!("file_size={}B", v)),
+            self.max_processes.map(|v| format!("processes={}", v)),
+            self.max_open_files.map(|v| format!("open_files={}", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if parts.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_maximums_cap_and_default_requests() {
+        let maximums = ResourceLimits {
+            cpu_secs: Some(60),
+            max_open_files: Some(256),
+            ..Default::default()
+        };
+        let requested = ResourceLimits {
+            cpu_secs: Some(600),
+            memory_bytes: Some(1 << 30),
+            max_open_files: Some(64),
+            ..Default::default()
+        };
+
+        let effective = ResourceLimits::capped_by(Some(&requested), &maximums);
+        assert_eq!(effective.cpu_secs, Some(60));
+        assert_eq!(effective.memory_bytes, Some(1 << 30));
+        assert_eq!(effective.max_open_files, Some(64));
+        assert_eq!(effective.max_processes, None);
+
+        let defaulted = ResourceLimits::capped_by(None, &maximums);
+        assert_eq!(defaulted, maximums);
+        assert!(ResourceLimits::capped_by(None, &ResourceLimits::default()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detects_which_limit_ended_a_job() {
+        let limits = ResourceLimits {
+            cpu_secs: Some(1),
+            max_open_files: Some(16),
+            ..Default::default()
+        };
+
+        let cpu = limits.detect_exceeded(Some(nix::sys::signal::Signal::SIGXCPU as i32), None, "");
+        assert_eq!(cpu.map(|hit| hit.limit), Some(ResourceLimit::Cpu));
+
+        let shell_reported = limits.detect_exceeded(None, Some(128 + 24), "");
+        assert_eq!(
+            shell_reported.map(|hit| hit.limit),
+            Some(ResourceLimit::Cpu)
+        );
+
+        let files =
+            limits.detect_exceeded(None, Some(1), "open: Too many open files (os error 24)");
+        assert_eq!(files.map(|hit| hit.limit), Some(ResourceLimit::OpenFiles));
+
+        // Limits that were not set, and jobs that succeeded, are never blamed.
+        assert!(
+            limits
+                .detect_exceeded(None, Some(1), "Cannot allocate memory")
+                .is_none()
+        );
+        assert!(
+            limits
+                .detect_exceeded(None, Some(0), "Too many open files")
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Per-job resource usage: CPU time, peak RSS, page faults and context switches.
+//!
+//! Finished jobs report the `rusage` returned by `wait4` for the job's process,
+//! which includes every descendant it waited for. While a job runs, Linux builds
+//! sample `/proc` for all processes in the job's process group instead.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::cgroup::format_bytes;
+
+/// Resource usage of a job's processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub user_cpu_secs: f64,
+    pub system_cpu_secs: f64,
+    /// Largest resident set size of any single process
+    pub max_rss_bytes: u64,
+    pub major_faults: u64,
+    pub minor_faults: u64,
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+}
+
+impl ResourceUsage {
+    #[cfg(unix)]
+    pub fn from_rusage(usage: &libc::rusage) -> Self {
+        let secs = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1_000_000.0;
+        // ru_maxrss is in kilobytes on Linux and in bytes on macOS.
+        let max_rss_bytes = if cfg!(target_os = "macos") {
+            usage.ru_maxrss as u64
+        } else {
+            usage.ru_maxrss as u64 * 1024
+        };
+
+        Self {
+            user_cpu_secs: secs(usage.ru_utime),
+            system_cpu_secs: secs(usage.ru_stime),
+            max_rss_bytes,
+            major_faults: usage.ru_majflt as u64,
+            minor_faults: usage.ru_minflt as u64,
+            voluntary_context_switches: usage.ru_nvcsw as u64,
+            involuntary_context_switches: usage.ru_nivcsw as u64,
+        }
+    }
+
+    /// Sample the live usage of every process in a process group from `/proc`.
+    ///
+    /// CPU time and faults include the children each process already waited for,
+    /// mirroring what `wait4` reports once the job ends.
+    #[cfg(target_os = "linux")]
+    pub fn sample_process_group(pgid: u32) -> Option<Self> {
+        // SAFETY: sysconf has no preconditions.
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        let ticks = if ticks > 0 { ticks as f64 } else { 100.0 };
+        let pgid = pgid.to_string();
+        let mut total = Self::default();
+        let mut found = false;
+
+        for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+            if !entry
+                .file_name()
+                .to_string_lossy()
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+            {
+                continue;
+            }
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            let Some((_, rest)) = stat.rsplit_once(") ") else {
+                continue;
+            };
+            // Fields from "state" (field 3) on; see proc(5).
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            let field = |n: usize| {
+                fields
+                    .get(n - 3)
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            if fields.get(2) != Some(&pgid.as_str()) {
+                continue;
+            }
+            found = true;
+
+            total.minor_faults += field(10) + field(11);
+            total.major_faults += field(12) + field(13);
+            total.user_cpu_secs += (field(14) + field(16)) as f64 / ticks;
+            total.system_cpu_secs += (field(15) + field(17)) as f64 / ticks;
+
+            let status = std::fs::read_to_string(entry.path().join("status")).unwrap_or_default();
+            for line in status.lines() {
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let value = value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(0);
+                match key {
+                    "VmHWM" => total.max_rss_bytes = total.max_rss_bytes.max(value * 1024),
+                    "voluntary_ctxt_switches" => total.voluntary_context_switches += value,
+                    "nonvoluntary_ctxt_switches" => total.involuntary_context_switches += value,
+                    _ => {}
+                }
+            }
+        }
+
+        found.then_some(total)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn sample_process_group(_pgid: u32) -> Option<Self> {
+        None
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "user {:.2}s, system {:.2}s, max RSS {}, major faults {}, context switches {} voluntary / {} involuntary",
+            self.user_cpu_secs,
+            self.system_cpu_secs,
+            format_bytes(self.max_rss_bytes),
+            self.major_faults,
+            self.voluntary_context_switches,
+            self.involuntary_context_switches
+        )
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn samples_a_running_process_group() {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done; exec sleep 30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(300));
+
+        let usage = ResourceUsage::sample_process_group(child.id());
+        let _ = child.kill();
+        let _ = child.wait();
+
+        let usage = usage.expect("process group not found in /proc");
+        assert!(usage.max_rss_bytes > 0);
+        assert!(usage.user_cpu_secs + usage.system_cpu_secs > 0.0);
+        assert!(ResourceUsage::sample_process_group(child.id()).is_none());
+    }
+}
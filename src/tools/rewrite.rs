@@ -0,0 +1,341 @@
+//! Command rewrite rules applied before a command is checked and run.
+//!
+//! Each `[[rewrite]]` entry of the policy file matches simple commands by their
+//! leading words (the program by basename, then exact arguments) and either
+//! inserts arguments after them or replaces them. Commands behind wrappers such as
+//! `sudo` or `env` match too, so `sudo apt-get install` gets the `-y` of an
+//! `apt-get install` rule. `tags` limits a rule to jobs with one of those tags.
+//! Only commands written directly in the command line are rewritten, not scripts
+//! inside substitutions or `sh -c`. The rewritten command line is what the
+//! denylist and policy see.
+//!
+//! ```toml
+//! [[rewrite]]
+//! id = "cargo-no-color"
+//! match = "cargo"
+//! insert = ["--color=never"]
+//!
+//! [[rewrite]]
+//! id = "npm-ci"
+//! match = "npm install"
+//! exact = true
+//! replace = "npm ci"
+//! tags = ["ci"]
+//! ```
+//!
+//! Server-side sudo wrapping (`ENHANCED_TERMINAL_SUDO_WRAP`) is the built-in rule
+//! [`SUDO_NON_INTERACTIVE`], which inserts `-n` after `sudo`.
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use super::shell_parser::{self, SimpleCommand, Word};
+
+/// Id of the built-in rule that runs sudo non-interactively.
+pub const SUDO_NON_INTERACTIVE: &str = "sudo-non-interactive";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewriteSpec {
+    id: String,
+    /// Program and leading arguments the command must start with
+    #[serde(rename = "match")]
+    pattern: String,
+    /// The command must be exactly the matched words
+    #[serde(default)]
+    exact: bool,
+    /// Arguments to add after the matched words
+    #[serde(default)]
+    insert: Vec<String>,
+    /// Shell text that replaces the matched words
+    #[serde(default)]
+    replace: Option<String>,
+    /// Only rewrite jobs carrying one of these tags
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A validated rewrite rule.
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub id: String,
+    words: Vec<String>,
+    exact: bool,
+    insert: Vec<String>,
+    replace: Option<String>,
+    tags: Vec<String>,
+}
+
+/// The words of a single command without expansions, or `None`.
+fn plain_words(text: &str) -> Option<Vec<String>> {
+    let parsed = shell_parser::parse(text);
+    match &parsed.commands[..] {
+        [command]
+            if command.assignments.is_empty()
+                && command.redirects.is_empty()
+                && command.words.iter().all(|word| !word.dynamic && !word.glob) =>
+        {
+            Some(command.words.iter().map(|word| word.text.clone()).collect())
+        }
+        _ => None,
+    }
+}
+
+impl RewriteSpec {
+    pub fn compile(self) -> Result<RewriteRule> {
+        if self.id.trim().is_empty() {
+            bail!("every rewrite rule needs an id");
+        }
+        let words = plain_words(&self.pattern).filter(|words| !words.is_empty());
+        let Some(words) = words else {
+            bail!(
+                "rewrite '{}': match must be a program and literal arguments",
+                self.id
+            );
+        };
+        if self.insert.is_empty() == self.replace.is_none() {
+            bail!(
+                "rewrite '{}': set exactly one of insert and replace",
+                self.id
+            );
+        }
+        if let Some(replace) = &self.replace
+            && plain_words(replace).is_none_or(|words| words.is_empty())
+        {
+            bail!(
+                "rewrite '{}': replace must be a single command without expansions",
+                self.id
+            );
+        }
+        Ok(RewriteRule {
+            id: self.id,
+            words,
+            exact: self.exact,
+            insert: self.insert,
+            replace: self.replace,
+            tags: self.tags,
+        })
+    }
+}
+
+impl RewriteRule {
+    /// The built-in rule behind `ENHANCED_TERMINAL_SUDO_WRAP`.
+    pub fn sudo_non_interactive() -> Self {
+        Self {
+            id: SUDO_NON_INTERACTIVE.to_string(),
+            words: vec!["sudo".to_string()],
+            exact: false,
+            insert: vec!["-n".to_string()],
+            replace: None,
+            tags: Vec::new(),
+        }
+    }
+
+    fn applies_to(&self, tags: &[String]) -> bool {
+        self.tags.is_empty() || self.tags.iter().any(|tag| tags.contains(tag))
+    }
+
+    fn matches(&self, argv: &[Word]) -> bool {
+        argv.len() >= self.words.len()
+            && (!self.exact || argv.len() == self.words.len())
+            && argv
+                .iter()
+                .zip(&self.words)
+                .enumerate()
+                .all(|(i, (word, expected))| {
+                    !word.dynamic
+                        && if i == 0 {
+                            shell_parser::basename(&word.text) == *expected
+                        } else {
+                            word.text == *expected
+                        }
+                })
+    }
+
+    /// True when every inserted argument is already given, e.g. `--color=always`
+    /// for an inserted `--color=never`.
+    fn already_applied(&self, argv: &[Word]) -> bool {
+        !self.insert.is_empty()
+            && self.insert.iter().all(|arg| {
+                let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+                argv.iter().any(|word| {
+                    word.text == name
+                        || word.text == *arg
+                        || (name.starts_with("--") && word.text.starts_with(&format!("{}=", name)))
+                })
+            })
+    }
+
+    /// The edit this rule makes to a command, as a char range and its new text.
+    fn edit(&self, command: &SimpleCommand) -> Option<(usize, usize, String)> {
+        if command.word_spans.len() != command.words.len() {
+            return None;
+        }
+        let invocations = command.invocations();
+        let index = invocations.iter().position(|argv| self.matches(argv))?;
+        let argv = invocations[index];
+        // A wrapper's own options end where the command it runs starts
+        let own = &argv[..argv.len() - invocations.get(index + 1).map_or(0, |next| next.len())];
+        if self.already_applied(own) {
+            return None;
+        }
+        let first = command.words.len() - argv.len();
+        let last = first + self.words.len() - 1;
+        let (start, end) = (command.word_spans[first].0, command.word_spans[last].1);
+        match &self.replace {
+            Some(replace) => Some((start, end, replace.clone())),
+            None => {
+                let args: Vec<String> = self.insert.iter().map(|arg| quote(arg)).collect();
+                Some((end, end, format!(" {}", args.join(" "))))
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.replace {
+            Some(replace) => format!("{}: `{}` -> `{}`", self.id, self.words.join(" "), replace),
+            None => format!(
+                "{}: added `{}` after `{}`",
+                self.id,
+                self.insert.join(" "),
+                self.words.join(" ")
+            ),
+        }
+    }
+}
+
+/// Quote an argument for the shell unless it only has safe characters.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=.,/:@%+".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// A command line after rewriting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewritten {
+    pub command: String,
+    /// One line per rule that changed the command, in the order applied
+    pub applied: Vec<String>,
+}
+
+impl Rewritten {
+    pub fn applied_rule(&self, id: &str) -> bool {
+        self.applied.iter().any(|line| {
+            line.strip_prefix(id)
+                .is_some_and(|rest| rest.starts_with(':'))
+        })
+    }
+}
+
+/// Apply each rule in order to every simple command it matches.
+pub fn apply(command: &str, rules: &[&RewriteRule], tags: &[String]) -> Rewritten {
+    let mut chars: Vec<char> = command.chars().collect();
+    let mut applied = Vec::new();
+    for rule in rules.iter().filter(|rule| rule.applies_to(tags)) {
+        let text: String = chars.iter().collect();
+        let mut edits: Vec<(usize, usize, String)> = shell_parser::parse(&text)
+            .commands
+            .iter()
+            .filter_map(|command| rule.edit(command))
+            .collect();
+        if edits.is_empty() {
+            continue;
+        }
+        edits.sort_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+        for (start, end, replacement) in edits {
+            chars.splice(start..end, replacement.chars());
+        }
+        applied.push(rule.describe());
+    }
+    Rewritten {
+        command: chars.into_iter().collect(),
+        applied,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(toml_text: &str) -> RewriteRule {
+        toml::from_str::<RewriteSpec>(toml_text)
+            .unwrap()
+            .compile()
+            .unwrap()
+    }
+
+    #[test]
+    fn inserts_and_replaces_matching_commands() {
+        let color = rule(
+            r#"id = "cargo-color"
+match = "cargo"
+insert = ["--color=never"]"#,
+        );
+        let npm = rule(
+            r#"id = "npm-ci"
+match = "npm install"
+exact = true
+replace = "npm ci"
+tags = ["ci"]"#,
+        );
+        let yes = rule(
+            r#"id = "apt-yes"
+match = "apt-get install"
+insert = ["-y"]"#,
+        );
+        let rules = [&color, &npm, &yes];
+        let ci = vec!["ci".to_string()];
+
+        let out = apply(
+            "cargo build && npm install; sudo apt-get install jq | tee log",
+            &rules,
+            &ci,
+        );
+        assert_eq!(
+            out.command,
+            "cargo --color=never build && npm ci; sudo apt-get install -y jq | tee log"
+        );
+        assert_eq!(
+            out.applied,
+            vec![
+                "cargo-color: added `--color=never` after `cargo`",
+                "npm-ci: `npm install` -> `npm ci`",
+                "apt-yes: added `-y` after `apt-get install`",
+            ]
+        );
+
+        // Tags, exact matches and flags already given
+        let out = apply("npm install && cargo --color=always test", &rules, &[]);
+        assert_eq!(out.command, "npm install && cargo --color=always test");
+        assert!(out.applied.is_empty());
+        let out = apply("npm install left-pad", &rules, &ci);
+        assert_eq!(out.command, "npm install left-pad");
+        // Quoted text and nested scripts are left alone
+        let out = apply("echo 'cargo build' $(cargo metadata)", &rules, &[]);
+        assert!(out.applied.is_empty(), "{out:?}");
+    }
+
+    #[test]
+    fn sudo_rule_matches_parsed_commands() {
+        let sudo = RewriteRule::sudo_non_interactive();
+        let out = apply("echo hi && sudo ls | sudo tee x; sudo -n id", &[&sudo], &[]);
+        assert_eq!(
+            out.command,
+            "echo hi && sudo -n ls | sudo -n tee x; sudo -n id"
+        );
+        assert!(out.applied_rule(SUDO_NON_INTERACTIVE));
+        assert!(
+            toml::from_str::<RewriteSpec>("id = \"x\"\nmatch = \"$X\"\ninsert = [\"-y\"]")
+                .unwrap()
+                .compile()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,626 @@
+//! Linux namespace sandbox profiles for untrusted commands.
+//!
+//! A sandboxed job runs in fresh user and mount namespaces, and optionally fresh
+//! PID and network namespaces, created by the launcher with `unshare(2)`. No
+//! privileges are needed: the user namespace maps the server's own uid and gid,
+//! which is allowed on stock kernels with unprivileged user namespaces enabled.
+//!
+//! Profiles are named. The built-in ones can be extended or replaced with a JSON
+//! file (`ENHANCED_TERMINAL_SANDBOX_PROFILES`) mapping profile names to settings,
+//! and `ENHANCED_TERMINAL_SANDBOX_DEFAULT` applies a profile to every command that
+//! does not request one.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Profile name that runs the command without a sandbox.
+pub const NO_SANDBOX: &str = "none";
+
+/// Namespaces and filesystem view for a sandboxed job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxProfile {
+    /// Keep network access. Otherwise the job gets its own network namespace with
+    /// only a loopback interface.
+    #[serde(default)]
+    pub network: bool,
+    /// Remount every filesystem read-only
+    #[serde(default = "default_true")]
+    pub read_only_root: bool,
+    /// Keep the job's working directory writable (bind-mounted over the read-only root)
+    #[serde(default = "default_true")]
+    pub writable_cwd: bool,
+    /// Additional directories that stay writable; missing ones are skipped
+    #[serde(default)]
+    pub writable_paths: Vec<PathBuf>,
+    /// Mount an empty tmpfs on /tmp and /dev/shm
+    #[serde(default = "default_true")]
+    pub private_tmp: bool,
+    /// Run the job in its own PID namespace with a fresh /proc
+    #[serde(default = "default_true")]
+    pub pid_namespace: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for SandboxProfile {
+    fn default() -> Self {
+        Self {
+            network: false,
+            read_only_root: true,
+            writable_cwd: true,
+            writable_paths: Vec::new(),
+            private_tmp: true,
+            pid_namespace: true,
+        }
+    }
+}
+
+impl fmt::Display for SandboxProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.read_only_root {
+            parts.push("read-only root".to_string());
+            let mut writable: Vec<String> = Vec::new();
+            if self.writable_cwd {
+                writable.push("cwd".to_string());
+            }
+            writable.extend(self.writable_paths.iter().map(|p| p.display().to_string()));
+            if !writable.is_empty() {
+                parts.push(format!("writable: {}", writable.join(", ")));
+            }
+        }
+        if self.private_tmp {
+            parts.push("private /tmp".to_string());
+        }
+        parts.push(
+            if self.network {
+                "network"
+            } else {
+                "no network"
+            }
+            .to_string(),
+        );
+        if self.pid_namespace {
+            parts.push("own PID namespace".to_string());
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// The profile a job runs under.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sandbox {
+    pub name: String,
+    pub profile: SandboxProfile,
+}
+
+impl fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.profile)
+    }
+}
+
+/// Built-in profiles, before the server's profile file is applied.
+fn builtin_profiles() -> BTreeMap<String, SandboxProfile> {
+    BTreeMap::from([
+        ("isolated".to_string(), SandboxProfile::default()),
+        (
+            "readonly-root".to_string(),
+            SandboxProfile {
+                network: true,
+                ..SandboxProfile::default()
+            },
+        ),
+        (
+            "offline".to_string(),
+            SandboxProfile {
+                network: false,
+                read_only_root: false,
+                private_tmp: false,
+                pid_namespace: false,
+                ..SandboxProfile::default()
+            },
+        ),
+    ])
+}
+
+/// Built-in profiles merged with `ENHANCED_TERMINAL_SANDBOX_PROFILES`.
+///
+/// The file is read on every call, so edits apply to the next command.
+pub fn profiles() -> Result<BTreeMap<String, SandboxProfile>> {
+    let mut profiles = builtin_profiles();
+    if let Ok(path) = std::env::var("ENHANCED_TERMINAL_SANDBOX_PROFILES") {
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read sandbox profiles from {}", path))?;
+        let configured: BTreeMap<String, SandboxProfile> = serde_json::from_str(&text)
+            .with_context(|| format!("invalid sandbox profiles in {}", path))?;
+        profiles.extend(configured);
+    }
+    profiles.remove(NO_SANDBOX);
+    Ok(profiles)
+}
+
+/// Resolve the profile a command runs under: the requested one, or the server
+/// default. `"none"` opts out, unless the server configured a default.
+pub fn resolve(requested: Option<&str>) -> Result<Option<Sandbox>> {
+    let server_default = std::env::var("ENHANCED_TERMINAL_SANDBOX_DEFAULT")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && name != NO_SANDBOX);
+
+    let name = match (requested, &server_default) {
+        (Some(NO_SANDBOX), Some(default)) => {
+            return Err(anyhow::anyhow!(
+                "this server runs every command in the '{}' sandbox profile; 'none' is not allowed",
+                default
+            ));
+        }
+        (Some(NO_SANDBOX), None) | (None, None) => return Ok(None),
+        (Some(name), _) => name.to_string(),
+        (None, Some(default)) => default.clone(),
+    };
+
+    let mut profiles = profiles()?;
+    let profile = profiles.remove(&name).ok_or_else(|| {
+        anyhow::anyhow!(
+            "unknown sandbox profile '{}' (available: {})",
+            name,
+            profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+        )
+    })?;
+
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow::anyhow!("sandbox profiles require Linux"));
+    }
+    Ok(Some(Sandbox { name, profile }))
+}
+
+/// Enter the sandbox described by `profile`. Called by the launcher, which must
+/// still be single-threaded, right before it execs the command.
+///
+/// With a PID namespace this forks twice: the original process stays outside
+/// and relays the command's exit status, the first child becomes the namespace's
+/// init, and only the second child returns to exec the command.
+#[cfg(target_os = "linux")]
+pub fn enter(profile: &SandboxProfile) -> Result<()> {
+    use nix::fcntl::{OFlag, open};
+    use nix::sched::{CloneFlags, unshare};
+    use nix::sys::stat::Mode;
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let cwd = std::env::current_dir().context("cannot resolve working directory")?;
+    let mut writable = Vec::new();
+    if profile.writable_cwd {
+        writable.push(cwd.clone());
+    }
+    writable.extend(
+        profile
+            .writable_paths
+            .iter()
+            .filter(|p| p.is_dir())
+            .cloned(),
+    );
+
+    // SAFETY: getuid and getgid cannot fail.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let mut flags = CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS;
+    if !profile.network {
+        flags |= CloneFlags::CLONE_NEWNET;
+    }
+    if profile.pid_namespace {
+        flags |= CloneFlags::CLONE_NEWPID;
+    }
+    unshare(flags)
+        .context("failed to create namespaces (are unprivileged user namespaces enabled?)")?;
+    map_ids(uid, gid)?;
+
+    linux::make_mounts_private()?;
+    // Hold on to the writable directories before the mounts change: a private /tmp
+    // would hide them otherwise. Bind sources must belong to the new mount namespace.
+    let handles = writable
+        .into_iter()
+        .map(|path| {
+            let fd = open(
+                &path,
+                OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )
+            .with_context(|| format!("cannot open {}", path.display()))?;
+            // SAFETY: `open` returned a new descriptor that nothing else owns.
+            Ok((path, unsafe { OwnedFd::from_raw_fd(fd) }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if profile.read_only_root {
+        linux::remount_all_read_only()?;
+    }
+    if profile.private_tmp {
+        linux::mount_private_tmp()?;
+    }
+    for (path, handle) in &handles {
+        linux::bind_writable(path, handle)?;
+    }
+    drop(handles);
+    if !profile.network {
+        linux::loopback_up().context("failed to bring up loopback")?;
+    }
+    if profile.pid_namespace {
+        linux::become_pid_namespace_init()?;
+    }
+
+    // A nested user namespace locks the mounts above, so even a command running as
+    // root inside the sandbox cannot remount them writable.
+    unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS)
+        .context("failed to lock sandbox mounts")?;
+    map_ids(uid, gid)?;
+
+    // Re-resolve the working directory so it refers to the writable bind mount.
+    std::env::set_current_dir(&cwd).with_context(|| format!("cannot enter {}", cwd.display()))?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_profile: &SandboxProfile) -> Result<()> {
+    Err(anyhow::anyhow!("sandbox profiles require Linux"))
+}
+
+/// Map our own uid and gid into a freshly created user namespace.
+#[cfg(target_os = "linux")]
+fn map_ids(uid: u32, gid: u32) -> Result<()> {
+    std::fs::write("/proc/self/setgroups", "deny").context("failed to write setgroups")?;
+    std::fs::write("/proc/self/uid_map", format!("{uid} {uid} 1\n"))
+        .context("failed to write uid_map")?;
+    std::fs::write("/proc/self/gid_map", format!("{gid} {gid} 1\n"))
+        .context("failed to write gid_map")?;
+    Ok(())
+}
+
+/// Host pid of the command of a job that runs in its own PID namespace.
+///
+/// The job's pid is the launcher's relay, which ignores the terminal's signals,
+/// and its only child is the namespace's init, which only handles the signals it
+/// relays. The command is the init's child with PID 2 inside the namespace, so
+/// signals meant for the command alone are sent there instead.
+#[cfg(target_os = "linux")]
+pub fn namespaced_command_pid(relay: u32) -> Option<u32> {
+    let init = child_pids(relay).into_iter().next()?;
+    child_pids(init)
+        .into_iter()
+        .find(|&pid| innermost_pid(pid) == Some(2))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn namespaced_command_pid(_relay: u32) -> Option<u32> {
+    None
+}
+
+/// Processes whose parent is `parent`, from `/proc/<pid>/stat`.
+#[cfg(target_os = "linux")]
+fn child_pids(parent: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let parent = parent.to_string();
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            // Fields after the parenthesised command name: state ppid ...
+            let (_, rest) = stat.rsplit_once(") ")?;
+            (rest.split_whitespace().nth(1) == Some(parent.as_str())).then_some(pid)
+        })
+        .collect()
+}
+
+/// The pid of `pid` in the innermost PID namespace it belongs to.
+#[cfg(target_os = "linux")]
+fn innermost_pid(pid: u32) -> Option<u32> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("NSpid:"))?
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use anyhow::{Context, Result};
+    use nix::errno::Errno;
+    use nix::mount::{MsFlags, mount};
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::path::{Path, PathBuf};
+
+    const NONE: Option<&str> = None;
+
+    pub(super) fn make_mounts_private() -> Result<()> {
+        mount(NONE, "/", NONE, MsFlags::MS_REC | MsFlags::MS_PRIVATE, NONE)
+            .context("failed to make mounts private")
+    }
+
+    /// Mount points with their per-mount options, in /proc/self/mountinfo order.
+    fn mount_points() -> Result<Vec<(PathBuf, String)>> {
+        let mountinfo =
+            std::fs::read_to_string("/proc/self/mountinfo").context("cannot read mountinfo")?;
+        Ok(mountinfo
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ').skip(4);
+                let mount_point = fields.next()?;
+                let options = fields.next()?;
+                Some((PathBuf::from(unescape(mount_point)), options.to_string()))
+            })
+            .collect())
+    }
+
+    /// Undo the octal escapes mountinfo uses for spaces, tabs, newlines and backslashes.
+    fn unescape(field: &str) -> String {
+        let mut out = String::with_capacity(field.len());
+        let mut chars = field.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                let digits: String = chars.clone().take(3).collect();
+                if let Ok(value) = u8::from_str_radix(&digits, 8) {
+                    out.push(value as char);
+                    chars.nth(2);
+                    continue;
+                }
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    /// Flags that must be repeated on a bind remount, since the kernel refuses to
+    /// clear them for mounts inherited from a more privileged namespace.
+    fn locked_flags(options: &str) -> MsFlags {
+        options
+            .split(',')
+            .fold(MsFlags::empty(), |flags, option| match option {
+                "nosuid" => flags | MsFlags::MS_NOSUID,
+                "nodev" => flags | MsFlags::MS_NODEV,
+                "noexec" => flags | MsFlags::MS_NOEXEC,
+                "noatime" => flags | MsFlags::MS_NOATIME,
+                "nodiratime" => flags | MsFlags::MS_NODIRATIME,
+                "relatime" => flags | MsFlags::MS_RELATIME,
+                _ => flags,
+            })
+    }
+
+    fn remount(path: &Path, options: &str, read_only: bool) -> nix::Result<()> {
+        let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | locked_flags(options);
+        if read_only {
+            flags |= MsFlags::MS_RDONLY;
+        }
+        mount(NONE, path, NONE, flags, NONE)
+    }
+
+    pub(super) fn remount_all_read_only() -> Result<()> {
+        for (path, options) in mount_points()? {
+            match remount(&path, &options, true) {
+                Ok(()) => {}
+                // Mounts hidden under other mounts cannot be reached by path.
+                Err(Errno::ENOENT | Errno::EACCES) if path != Path::new("/") => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to remount {} read-only", path.display())
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn mount_private_tmp() -> Result<()> {
+        let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        for dir in ["/tmp", "/dev/shm"] {
+            if !Path::new(dir).is_dir() {
+                continue;
+            }
+            mount(Some("tmpfs"), dir, Some("tmpfs"), flags, Some("mode=1777"))
+                .with_context(|| format!("failed to mount a private {}", dir))?;
+        }
+        Ok(())
+    }
+
+    /// Bind `handle` (a directory opened before the mounts changed) over `path`
+    /// and make that bind mount writable.
+    pub(super) fn bind_writable(path: &Path, handle: &OwnedFd) -> Result<()> {
+        // Inside a private /tmp the directory has to be recreated first.
+        let _ = std::fs::create_dir_all(path);
+        let source = format!("/proc/self/fd/{}", handle.as_raw_fd());
+        mount(
+            Some(source.as_str()),
+            path,
+            NONE,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            NONE,
+        )
+        .with_context(|| format!("failed to bind {}", path.display()))?;
+
+        let options = mount_points()?
+            .into_iter()
+            .rev()
+            .find(|(mount_point, _)| mount_point == path)
+            .map(|(_, options)| options)
+            .unwrap_or_default();
+        remount(path, &options, false)
+            .with_context(|| format!("failed to make {} writable", path.display()))
+    }
+
+    /// Bring up `lo` in a new network namespace so localhost still works.
+    pub(super) fn loopback_up() -> Result<()> {
+        // SAFETY: plain socket/ioctl calls on a zeroed ifreq naming "lo"; the socket
+        // is closed on every path.
+        unsafe {
+            let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            if socket < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let mut request: libc::ifreq = std::mem::zeroed();
+            for (dst, src) in request.ifr_name.iter_mut().zip(b"lo\0") {
+                *dst = *src as libc::c_char;
+            }
+            let mut result = libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request);
+            if result == 0 {
+                request.ifr_ifru.ifru_flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+                result = libc::ioctl(socket, libc::SIOCSIFFLAGS, &request);
+            }
+            let error = std::io::Error::last_os_error();
+            libc::close(socket);
+            if result != 0 {
+                return Err(error.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fork into the new PID namespace. Returns only in the process that goes on to
+    /// exec the command; the other two never return.
+    ///
+    /// The original process ignores the terminal's interrupt signals and relays the
+    /// command's exit status; signals for the command alone are sent to it
+    /// directly (see [`namespaced_command_pid`](super::namespaced_command_pid)). Its child is the namespace's init: the kernel shields
+    /// it from signals it has no handler for, it mounts a fresh /proc, reaps orphans
+    /// and reports the command's wait status through a pipe. When it exits, the
+    /// kernel kills whatever is left in the namespace.
+    pub(super) fn become_pid_namespace_init() -> Result<()> {
+        use nix::fcntl::OFlag;
+        use nix::sys::signal::{SigHandler, Signal, signal};
+        use nix::unistd::{ForkResult, fork, pipe2};
+        use std::io::{Read, Write};
+
+        let (status_read, status_write) = pipe2(OFlag::O_CLOEXEC)?;
+
+        // SAFETY: the launcher is single-threaded, so forking is sound.
+        match unsafe { fork() }.context("failed to fork sandbox init")? {
+            ForkResult::Parent { child } => {
+                drop(status_write);
+                for sig in [
+                    Signal::SIGINT,
+                    Signal::SIGTERM,
+                    Signal::SIGHUP,
+                    Signal::SIGQUIT,
+                ] {
+                    // SAFETY: installing SIG_IGN has no handler-safety requirements.
+                    let _ = unsafe { signal(sig, SigHandler::SigIgn) };
+                }
+                let init_status = wait_raw(child.as_raw());
+                let mut bytes = [0u8; 4];
+                let status = match std::fs::File::from(status_read).read_exact(&mut bytes) {
+                    Ok(()) => i32::from_ne_bytes(bytes),
+                    Err(_) => init_status,
+                };
+                relay_exit(status);
+            }
+            ForkResult::Child => {
+                drop(status_read);
+                mount(
+                    Some("proc"),
+                    "/proc",
+                    Some("proc"),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                    NONE,
+                )
+                .context("failed to mount /proc for the PID namespace")?;
+
+                // SAFETY: still single-threaded.
+                match unsafe { fork() }.context("failed to fork sandboxed command")? {
+                    ForkResult::Child => {
+                        drop(status_write);
+                        Ok(())
+                    }
+                    ForkResult::Parent { child } => {
+                        let command = child.as_raw();
+                        loop {
+                            let mut status = 0;
+                            // SAFETY: `status` is a live, writable local.
+                            let pid = unsafe { libc::waitpid(-1, &mut status, 0) };
+                            if pid == command {
+                                let mut pipe = std::fs::File::from(status_write);
+                                let _ = pipe.write_all(&status.to_ne_bytes());
+                                std::process::exit(0);
+                            }
+                            if pid < 0 && Errno::last() != Errno::EINTR {
+                                std::process::exit(
+                                    super::super::launcher::LAUNCH_FAILURE_EXIT_CODE,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn wait_raw(pid: libc::pid_t) -> i32 {
+        loop {
+            let mut status = 0;
+            // SAFETY: `status` is a live, writable local.
+            let result = unsafe { libc::waitpid(pid, &mut status, 0) };
+            if result == pid {
+                return status;
+            }
+            if result < 0 && Errno::last() != Errno::EINTR {
+                return 0;
+            }
+        }
+    }
+
+    /// Exit the way the sandboxed command did: with its code, or by its signal.
+    fn relay_exit(status: i32) -> ! {
+        if libc::WIFSIGNALED(status) {
+            let sig = libc::WTERMSIG(status);
+            // SAFETY: resetting the disposition and raising a signal on ourselves.
+            unsafe {
+                let limit = libc::rlimit {
+                    rlim_cur: 0,
+                    rlim_max: libc::RLIM_INFINITY,
+                };
+                // No core file for the relay; the command already dumped one if enabled.
+                libc::setrlimit(libc::RLIMIT_CORE, &limit);
+                libc::signal(sig, libc::SIG_DFL);
+                libc::raise(sig);
+            }
+            std::process::exit(128 + sig);
+        }
+        std::process::exit(libc::WEXITSTATUS(status));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_describe_themselves_and_parse_with_defaults() {
+        let isolated = &builtin_profiles()["isolated"];
+        assert_eq!(
+            isolated.to_string(),
+            "read-only root, writable: cwd, private /tmp, no network, own PID namespace"
+        );
+
+        let configured: SandboxProfile =
+            serde_json::from_str(r#"{"network": true, "writable_paths": ["/var/cache"]}"#).unwrap();
+        assert!(configured.read_only_root && configured.pid_namespace);
+        assert_eq!(
+            configured.to_string(),
+            "read-only root, writable: cwd, /var/cache, private /tmp, network, own PID namespace"
+        );
+        assert!(serde_json::from_str::<SandboxProfile>(r#"{"netwrok": true}"#).is_err());
+
+        assert_eq!(
+            builtin_profiles()["readonly-root"].to_string(),
+            "read-only root, writable: cwd, private /tmp, network, own PID namespace"
+        );
+    }
+}
@@ -0,0 +1,797 @@
+use anyhow::Result;
+use portable_pty::{Child, CommandBuilder, MasterPty, PtySize, native_pty_system};
+use rmcp::{Peer, RoleServer};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex as TokioMutex, mpsc};
+
+use super::approval;
+use super::job_manager::readable_job_id;
+use super::landlock::{self, WriteRestriction, WriteRules};
+use super::launcher::LaunchSpec;
+use super::policy;
+use super::redaction::{self, Redactor};
+use super::sandbox::{self, Sandbox};
+use super::terminal_executor::{apply_default_env, plan_write_restriction, rewrite_command};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionOpenInput {
+    /// Initial working directory (default: "."). When omitted, "." is resolved from the
+    /// MCP server process working directory supplied by the caller/client.
+    #[serde(default = "default_cwd")]
+    pub cwd: String,
+    /// Shell to run for the lifetime of the session (default: "bash")
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    /// Environment variables to set when the session shell starts
+    #[serde(default)]
+    pub env_vars: HashMap<String, String>,
+    /// Optional Linux namespace sandbox profile to run the session shell under (e.g.
+    /// "isolated"). The server default applies when omitted; "none" opts out of it only
+    /// when the server allows it.
+    #[serde(default)]
+    pub sandbox: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionExecInput {
+    /// Session ID returned by enhanced_terminal_session_open
+    pub session_id: String,
+    /// Command to run inside the session shell
+    pub command: String,
+    /// Seconds to wait before interrupting the command with Ctrl-C (default: 120)
+    #[serde(default = "default_exec_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Maximum number of GPT-5/o200k_base tokens to return from the command output.
+    /// Set to 0 to disable token truncation.
+    #[serde(default = "default_preview_tokens")]
+    pub preview_tokens: usize,
+    /// If true, include the full session environment instead of only the changes
+    #[serde(default)]
+    pub full_env: bool,
+    /// Custom denylist patterns (in addition to defaults)
+    #[serde(default)]
+    pub custom_denylist: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionCloseInput {
+    /// Session ID to close
+    pub session_id: String,
+}
+
+fn default_cwd() -> String {
+    ".".to_string()
+}
+
+fn default_shell() -> String {
+    "bash".to_string()
+}
+
+fn default_preview_tokens() -> usize {
+    4096
+}
+
+fn default_exec_timeout_secs() -> u64 {
+    120
+}
+
+/// How long to wait for the shell to report back after an interrupted command.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(5);
+
+/// Snapshot of a session after it has been opened.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub shell: String,
+    pub pid: Option<u32>,
+    pub cwd: String,
+    pub env: BTreeMap<String, String>,
+    /// Sandbox profile the session shell runs under
+    pub sandbox: Option<Sandbox>,
+    /// Landlock write restrictions the session shell runs under
+    pub write_restriction: Option<WriteRestriction>,
+}
+
+/// Result of running one command inside a session.
+#[derive(Debug, Clone)]
+pub struct SessionExecResult {
+    pub session_id: String,
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub output: String,
+    pub timed_out: bool,
+    pub denied: bool,
+    pub denial_reason: Option<String>,
+    /// How a policy `ask` decision was resolved when the command ran anyway
+    pub approval: Option<String>,
+    /// Rewrite rules that changed the command, one line each
+    pub rewrites: Vec<String>,
+    /// Indirect execution found in the command
+    pub indirect_execution: Vec<String>,
+    /// Secrets replaced in the output
+    pub redactions: usize,
+    pub duration_secs: f64,
+    pub cwd: String,
+    pub env: BTreeMap<String, String>,
+    pub env_changes: Vec<EnvChange>,
+    /// True when the shell exited (e.g. the command ran `exit`) and the session is gone
+    pub session_closed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvChange {
+    Set { key: String, value: String },
+    Removed { key: String },
+}
+
+enum SessionRead {
+    Data(Vec<u8>),
+    Eof,
+}
+
+struct ShellSession {
+    session_id: String,
+    shell: String,
+    pid: Option<u32>,
+    cwd: String,
+    env: BTreeMap<String, String>,
+    writer: Box<dyn Write + Send>,
+    rx: mpsc::UnboundedReceiver<SessionRead>,
+    pending: Vec<u8>,
+    child: Box<dyn Child + Send + Sync>,
+    // Keep the PTY master alive for as long as the session exists.
+    _master: Box<dyn MasterPty + Send>,
+    marker_counter: u64,
+    closed: bool,
+}
+
+/// Parsed trailer printed by the session shell after each command.
+#[derive(Debug, PartialEq)]
+struct CommandTrailer {
+    output: Vec<u8>,
+    exit_code: Option<i32>,
+    cwd: String,
+    env: BTreeMap<String, String>,
+}
+
+/// Registry of long-lived shell sessions.
+pub struct SessionManager {
+    sessions: Arc<Mutex<HashMap<String, Arc<TokioMutex<ShellSession>>>>>,
+    session_counter: Arc<Mutex<u64>>,
+}
+
+fn shell_session_args(shell: &str) -> &'static [&'static str] {
+    // Skip user rc files and line editing so prompts and readline escape
+    // sequences never end up in command output.
+    let name = shell.rsplit('/').next().unwrap_or(shell);
+    match name {
+        "bash" => &["--noprofile", "--norc", "--noediting"],
+        "zsh" => &["-f"],
+        _ => &[],
+    }
+}
+
+fn marker_token(counter: u64) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default();
+    format!("{:x}{:x}", nanos, counter)
+}
+
+/// Shell snippet that reports exit code, cwd and environment for the previous command.
+///
+/// The marker words are assembled by `printf` so that the literal marker never
+/// appears in the script text itself (which the PTY may echo back).
+fn trailer_script(token: &str) -> String {
+    format!(
+        "__et_rc=$?; printf '\\n__ET_%s_{token}__ %s\\n' DONE \"$__et_rc\"; pwd; \
+         printf '__ET_%s_{token}__\\n' ENV; env -0 2>/dev/null || env; \
+         printf '\\n__ET_%s_{token}__\\n' END\n"
+    )
+}
+
+/// Wrap a command so it runs in the current shell with stdin detached,
+/// followed by the trailer that delimits its output.
+fn wrap_session_command(command: &str, token: &str) -> String {
+    format!("{{ {command}\n}} </dev/null\n{}", trailer_script(token))
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_env_block(block: &[u8]) -> BTreeMap<String, String> {
+    let text = String::from_utf8_lossy(block);
+    let entries: Vec<&str> = if text.contains('\0') {
+        text.split('\0').collect()
+    } else {
+        text.lines().collect()
+    };
+
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let entry = entry.trim_start_matches('\n');
+            let (key, value) = entry.split_once('=')?;
+            (!key.is_empty()).then(|| (key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Try to split a complete trailer off the front of `pending`.
+///
+/// Returns the parsed trailer and leaves any bytes after the end marker in `pending`.
+fn take_trailer(pending: &mut Vec<u8>, token: &str) -> Option<CommandTrailer> {
+    let done_marker = format!("\n__ET_DONE_{token}__ ");
+    let env_marker = format!("__ET_ENV_{token}__\n");
+    let end_marker = format!("\n__ET_END_{token}__\n");
+
+    let done_pos = find_subslice(pending, done_marker.as_bytes())?;
+    let end_pos = find_subslice(pending, end_marker.as_bytes())?;
+    if end_pos < done_pos {
+        return None;
+    }
+
+    let after_done = &pending[done_pos + done_marker.len()..end_pos];
+    let env_pos = find_subslice(after_done, env_marker.as_bytes())?;
+    let header = String::from_utf8_lossy(&after_done[..env_pos]).to_string();
+    let mut header_lines = header.lines();
+    let exit_code = header_lines
+        .next()
+        .and_then(|code| code.trim().parse::<i32>().ok());
+    let cwd = header_lines.next().unwrap_or_default().trim().to_string();
+    let env = parse_env_block(&after_done[env_pos + env_marker.len()..]);
+
+    let output = pending[..done_pos].to_vec();
+    pending.drain(..end_pos + end_marker.len());
+
+    Some(CommandTrailer {
+        output,
+        exit_code,
+        cwd,
+        env,
+    })
+}
+
+const SECRET_VALUE: &str = "[REDACTED:secret-env]";
+
+fn diff_env(before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> Vec<EnvChange> {
+    let mut changes = Vec::new();
+    for (key, value) in after {
+        if before.get(key) != Some(value) {
+            changes.push(EnvChange::Set {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    for key in before.keys() {
+        if !after.contains_key(key) {
+            changes.push(EnvChange::Removed { key: key.clone() });
+        }
+    }
+    changes
+}
+
+/// The environment as shown to the client, with the values of secret variables
+/// (`*TOKEN*`, `*PASSWORD*`, ..) replaced.
+fn redacted_env(env: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    env.iter()
+        .map(|(key, value)| {
+            let value = if redaction::is_secret_name(key) {
+                SECRET_VALUE.to_string()
+            } else {
+                value.clone()
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+fn redact_env_change(change: EnvChange) -> EnvChange {
+    match change {
+        EnvChange::Set { key, .. } if redaction::is_secret_name(&key) => EnvChange::Set {
+            key,
+            value: SECRET_VALUE.to_string(),
+        },
+        change => change,
+    }
+}
+
+impl ShellSession {
+    fn next_token(&mut self) -> String {
+        self.marker_counter += 1;
+        marker_token(self.marker_counter)
+    }
+
+    fn write_script(&mut self, script: &str) -> Result<()> {
+        self.writer.write_all(script.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Read PTY output until the trailer for `token` arrives, the shell exits, or `deadline` passes.
+    async fn read_trailer(&mut self, token: &str, deadline: Instant) -> Option<CommandTrailer> {
+        loop {
+            if let Some(trailer) = take_trailer(&mut self.pending, token) {
+                return Some(trailer);
+            }
+            if self.closed {
+                return None;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+
+            match tokio::time::timeout(remaining, self.rx.recv()).await {
+                Ok(Some(SessionRead::Data(data))) => self.pending.extend_from_slice(&data),
+                Ok(Some(SessionRead::Eof)) | Ok(None) => {
+                    tracing::debug!("Session shell exited: session_id={}", self.session_id);
+                    self.closed = true;
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn terminate(&mut self) {
+        let _ = self.write_script("exit\n");
+        let _ = self.child.kill();
+        self.closed = true;
+    }
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            session_counter: Arc::new(Mutex::new(1)),
+        }
+    }
+
+    fn new_session_id(&self) -> String {
+        let mut counter = self.session_counter.lock().unwrap();
+        let sessions = self.sessions.lock().unwrap();
+
+        loop {
+            let sequence = *counter;
+            *counter += 1;
+            let candidate = format!("session-{}", readable_job_id(sequence));
+            if !sessions.contains_key(&candidate) {
+                return candidate;
+            }
+        }
+    }
+
+    fn get_session(&self, session_id: &str) -> Result<Arc<TokioMutex<ShellSession>>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))
+    }
+
+    /// Start a new long-lived shell and wait until it reports its initial state.
+    ///
+    /// The shell runs under the requested sandbox profile or the server default,
+    /// and under Landlock write restrictions when the server enables them for
+    /// every command, both applied by the launcher like a job's. Writes stay
+    /// limited to the cwd the session was opened in, whatever it `cd`s to.
+    pub async fn open_session(&self, input: &SessionOpenInput) -> Result<SessionInfo> {
+        let cwd = if input.cwd == "." || input.cwd.is_empty() {
+            std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+        } else {
+            PathBuf::from(&input.cwd)
+        };
+        let cwd = cwd.canonicalize().unwrap_or(cwd);
+
+        let mut env_vars = apply_default_env(input.env_vars.clone());
+        let sandbox = sandbox::resolve(input.sandbox.as_deref())?;
+        let write_restriction = if landlock::enabled_by_server() {
+            Some(
+                plan_write_restriction(&cwd, &env_vars, &[])
+                    .map_err(|reason| anyhow::anyhow!(reason))?,
+            )
+        } else {
+            None
+        };
+        let launch = LaunchSpec {
+            sandbox: sandbox.as_ref().map(|sandbox| sandbox.profile.clone()),
+            write_rules: match &write_restriction {
+                Some(WriteRestriction::Enforced(rules)) => Some(WriteRules {
+                    writable: rules.writable.clone(),
+                }),
+                _ => None,
+            },
+            ..Default::default()
+        };
+        let args = shell_session_args(&input.shell)
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let (program, args) = launch.wrap(&input.shell, args)?;
+
+        let pty_system = native_pty_system();
+        let pair = pty_system
+            .openpty(PtySize {
+                rows: 24,
+                cols: 80,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to open PTY: {}", e))?;
+
+        let mut cmd = CommandBuilder::new(program);
+        cmd.args(args);
+        cmd.cwd(&cwd);
+
+        for key in ["PS1", "PS2", "PS3", "PS4", "PROMPT_COMMAND", "HISTFILE"] {
+            env_vars.entry(key.to_string()).or_default();
+        }
+        for (key, value) in env_vars {
+            cmd.env(key, value);
+        }
+
+        let child = pair
+            .slave
+            .spawn_command(cmd)
+            .map_err(|e| anyhow::anyhow!("Failed to spawn: {}", e))?;
+        let pid = child.process_id();
+        drop(pair.slave);
+
+        let mut reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| anyhow::anyhow!("Failed to clone reader: {}", e))?;
+        let writer = pair
+            .master
+            .take_writer()
+            .map_err(|e| anyhow::anyhow!("Failed to open PTY stdin writer: {}", e))?;
+
+        let session_id = self.new_session_id();
+        let (tx, rx) = mpsc::unbounded_channel::<SessionRead>();
+        let reader_session_id = session_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => {
+                        let _ = tx.send(SessionRead::Eof);
+                        break;
+                    }
+                    Ok(n) => {
+                        if tx.send(SessionRead::Data(buffer[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(e) => {
+                        tracing::debug!(
+                            "Session reader error: {:?}, session_id={}",
+                            e,
+                            reader_session_id
+                        );
+                        let _ = tx.send(SessionRead::Eof);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut session = ShellSession {
+            session_id: session_id.clone(),
+            shell: input.shell.clone(),
+            pid,
+            cwd: cwd.display().to_string(),
+            env: BTreeMap::new(),
+            writer,
+            rx,
+            pending: Vec::new(),
+            child,
+            _master: pair.master,
+            marker_counter: 0,
+            closed: false,
+        };
+
+        // Turn off echo and newline translation so command output comes back verbatim.
+        let token = session.next_token();
+        session.write_script(&format!(
+            "stty -echo -onlcr 2>/dev/null; PS1=''; PS2=''; unset PROMPT_COMMAND\n{}",
+            trailer_script(&token)
+        ))?;
+        let Some(trailer) = session
+            .read_trailer(&token, Instant::now() + Duration::from_secs(10))
+            .await
+        else {
+            session.terminate();
+            // Whatever the shell (or the launcher) printed explains why
+            let printed = String::from_utf8_lossy(&session.pending).trim().to_string();
+            return Err(anyhow::anyhow!(
+                "Shell '{}' did not become ready for session commands{}",
+                input.shell,
+                if printed.is_empty() {
+                    String::new()
+                } else {
+                    format!(": {}", printed)
+                }
+            ));
+        };
+        if !trailer.cwd.is_empty() {
+            session.cwd = trailer.cwd;
+        }
+        session.env = trailer.env;
+
+        let info = SessionInfo {
+            session_id: session_id.clone(),
+            shell: session.shell.clone(),
+            pid: session.pid,
+            cwd: session.cwd.clone(),
+            env: session.env.clone(),
+            sandbox,
+            write_restriction,
+        };
+
+        tracing::info!(
+            "Session opened: session_id={}, shell={}, pid={:?}, sandbox={:?}",
+            session_id,
+            input.shell,
+            pid,
+            info.sandbox.as_ref().map(|sandbox| sandbox.name.as_str())
+        );
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_id, Arc::new(TokioMutex::new(session)));
+        Ok(info)
+    }
+
+    /// Run a command inside an existing session and report its exit code, cwd and env.
+    ///
+    /// `peer` is used to ask the user about commands a policy `ask` rule matches.
+    pub async fn exec(
+        &self,
+        input: &SessionExecInput,
+        peer: Option<&Peer<RoleServer>>,
+    ) -> Result<SessionExecResult> {
+        if input.command.trim().is_empty() {
+            return Err(anyhow::anyhow!("Command cannot be empty"));
+        }
+        // Sessions have no tags, so only untagged rewrite rules apply
+        let rewritten = rewrite_command(input.command.trim(), &[]);
+        let command = rewritten.command.as_str();
+
+        let session = self.get_session(&input.session_id)?;
+        let mut session = session.lock().await;
+
+        let explanation = policy::explain(
+            command,
+            &input.custom_denylist,
+            Path::new(&session.cwd),
+            &|name| session.env.get(name).cloned(),
+        );
+        let indirect_execution: Vec<String> = explanation
+            .findings
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let approval =
+            match approval::resolve(explanation.verdict, command, Path::new(&session.cwd), peer)
+                .await
+            {
+                Ok(approval) => approval,
+                Err(reason) => {
+                    return Ok(SessionExecResult {
+                        session_id: input.session_id.clone(),
+                        command: command.to_string(),
+                        exit_code: None,
+                        output: String::new(),
+                        timed_out: false,
+                        denied: true,
+                        denial_reason: Some(reason),
+                        approval: None,
+                        rewrites: rewritten.applied,
+                        indirect_execution,
+                        redactions: 0,
+                        duration_secs: 0.0,
+                        cwd: session.cwd.clone(),
+                        env: redacted_env(&session.env),
+                        env_changes: Vec::new(),
+                        session_closed: false,
+                    });
+                }
+            };
+
+        if session.closed {
+            drop(session);
+            self.sessions.lock().unwrap().remove(&input.session_id);
+            return Err(anyhow::anyhow!("Session shell has exited"));
+        }
+
+        // Drop anything left over from earlier commands (e.g. late background output).
+        session.pending.clear();
+
+        let start_time = Instant::now();
+        let token = session.next_token();
+        session.write_script(&wrap_session_command(command, &token))?;
+
+        let deadline = start_time + Duration::from_secs(input.timeout_secs);
+        let mut timed_out = false;
+        let mut trailer = session.read_trailer(&token, deadline).await;
+
+        if trailer.is_none() && !session.closed {
+            // Interrupt the foreground command. SIGINT flushes the terminal input
+            // queue, so the trailer has to be sent again afterwards.
+            tracing::debug!(
+                "Session command timed out, interrupting: session_id={}",
+                input.session_id
+            );
+            timed_out = true;
+            let retry_token = session.next_token();
+            session.write_script("\x03")?;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            session.write_script(&format!("\n{}", trailer_script(&retry_token)))?;
+            trailer = session
+                .read_trailer(&retry_token, Instant::now() + INTERRUPT_GRACE)
+                .await;
+            if trailer.is_none() {
+                session.terminate();
+            }
+        }
+
+        let duration_secs = start_time.elapsed().as_secs_f64();
+
+        let Some(trailer) = trailer else {
+            let pending = std::mem::take(&mut session.pending);
+            let (output, redactions) =
+                Redactor::for_env(&session.env).redact(&String::from_utf8_lossy(&pending));
+            let result = SessionExecResult {
+                session_id: input.session_id.clone(),
+                command: command.to_string(),
+                exit_code: None,
+                output,
+                timed_out,
+                denied: false,
+                denial_reason: None,
+                approval,
+                rewrites: rewritten.applied.clone(),
+                indirect_execution,
+                redactions,
+                duration_secs,
+                cwd: session.cwd.clone(),
+                env: redacted_env(&session.env),
+                env_changes: Vec::new(),
+                session_closed: true,
+            };
+            drop(session);
+            self.sessions.lock().unwrap().remove(&input.session_id);
+            return Ok(result);
+        };
+
+        let env_changes = diff_env(&session.env, &trailer.env);
+        if !trailer.cwd.is_empty() {
+            session.cwd = trailer.cwd;
+        }
+        session.env = trailer.env;
+        let (output, redactions) =
+            Redactor::for_env(&session.env).redact(&String::from_utf8_lossy(&trailer.output));
+
+        Ok(SessionExecResult {
+            session_id: input.session_id.clone(),
+            command: command.to_string(),
+            exit_code: trailer.exit_code,
+            output,
+            timed_out,
+            denied: false,
+            denial_reason: None,
+            approval,
+            rewrites: rewritten.applied,
+            indirect_execution,
+            redactions,
+            duration_secs,
+            cwd: session.cwd.clone(),
+            env: redacted_env(&session.env),
+            env_changes: env_changes.into_iter().map(redact_env_change).collect(),
+            session_closed: false,
+        })
+    }
+
+    /// Terminate a session's shell and forget it.
+    pub async fn close_session(&self, session_id: &str) -> Result<()> {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.lock().await.terminate();
+        tracing::info!("Session closed: session_id={}", session_id);
+        Ok(())
+    }
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for SessionManager {
+    fn clone(&self) -> Self {
+        Self {
+            sessions: Arc::clone(&self.sessions),
+            session_counter: Arc::clone(&self.session_counter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailer_is_parsed_from_pty_output() {
+        let token = "abc1";
+        let mut pending = b"hello\nworld\n__ET_DONE_abc1__ 3\n/tmp/work\n__ET_ENV_abc1__\nFOO=bar\0MULTI=a\nb\0\n__ET_END_abc1__\nleftover".to_vec();
+
+        let trailer = take_trailer(&mut pending, token).expect("trailer should parse");
+
+        assert_eq!(trailer.output, b"hello\nworld");
+        assert_eq!(trailer.exit_code, Some(3));
+        assert_eq!(trailer.cwd, "/tmp/work");
+        assert_eq!(trailer.env.get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(trailer.env.get("MULTI").map(String::as_str), Some("a\nb"));
+        assert_eq!(pending, b"leftover");
+    }
+
+    #[test]
+    fn incomplete_trailer_waits_for_more_output() {
+        let mut pending = b"partial\n__ET_DONE_abc1__ 0\n/tmp\n".to_vec();
+        assert!(take_trailer(&mut pending, "abc1").is_none());
+        assert_eq!(pending, b"partial\n__ET_DONE_abc1__ 0\n/tmp\n");
+    }
+
+    #[test]
+    fn env_diff_reports_set_and_removed_keys() {
+        let before = BTreeMap::from([
+            ("KEEP".to_string(), "1".to_string()),
+            ("GONE".to_string(), "x".to_string()),
+        ]);
+        let after = BTreeMap::from([
+            ("KEEP".to_string(), "1".to_string()),
+            ("NEW".to_string(), "y".to_string()),
+        ]);
+
+        assert_eq!(
+            diff_env(&before, &after),
+            vec![
+                EnvChange::Set {
+                    key: "NEW".to_string(),
+                    value: "y".to_string()
+                },
+                EnvChange::Removed {
+                    key: "GONE".to_string()
+                },
+            ]
+        );
+    }
+}
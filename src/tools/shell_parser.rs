@@ -0,0 +1,991 @@
+//! POSIX shell tokenizer used by the command policy.
+//!
+//! Splits a command line into the simple commands the shell would run: pipeline
+//! stages, `&&`/`||`/`;` lists, subshells, brace groups and function bodies, plus the
+//! scripts inside command substitutions, process substitutions and `sh -c '...'`.
+//! Quoting follows POSIX rules (and bash's `$'...'`), so `'r'm` and `r\m` both name
+//! `rm`. Nothing is expanded: words containing `$VAR` or `$(...)` keep their source
+//! text and are flagged as dynamic.
+
+/// How deep substitutions and `sh -c` scripts are followed.
+const MAX_DEPTH: usize = 16;
+
+/// Programs that run another command given as their arguments, with the options
+/// that take a separate value.
+const WRAPPERS: &[(&str, &[&str])] = &[
+    (
+        "sudo",
+        &[
+            "-u",
+            "-g",
+            "-h",
+            "-p",
+            "-C",
+            "-D",
+            "-r",
+            "-t",
+            "-U",
+            "-T",
+            "--user",
+            "--group",
+            "--host",
+            "--prompt",
+            "--close-from",
+            "--chdir",
+            "--role",
+            "--type",
+            "--other-user",
+            "--command-timeout",
+        ],
+    ),
+    ("doas", &["-u", "-C"]),
+    (
+        "env",
+        &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"],
+    ),
+    ("nice", &["-n", "--adjustment"]),
+    ("ionice", &["-c", "-n", "-p", "--class", "--classdata"]),
+    ("timeout", &["-s", "-k", "--signal", "--kill-after"]),
+    ("stdbuf", &["-i", "-o", "-e"]),
+    ("xargs", &["-I", "-L", "-n", "-P", "-s", "-d", "-E", "-a"]),
+    ("time", &["-f", "-o"]),
+    ("exec", &["-a"]),
+    ("nohup", &[]),
+    ("setsid", &[]),
+    ("command", &[]),
+    ("builtin", &[]),
+    ("busybox", &[]),
+];
+
+/// Shells whose `-c` script is parsed as well.
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh", "ash", "mksh"];
+
+/// Reserved words that only structure the command (dropped in command position).
+const RESERVED: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "esac", "!", "time",
+];
+
+/// Operators, longest first.
+const OPERATORS: &[&str] = &[
+    "&>>", "<<<", "<<-", "&&", "||", ";;", "|&", "<<", ">>", "<>", "<&", ">&", ">|", "&>", "|",
+    "&", ";", "(", ")", "<", ">",
+];
+
+/// One shell word after quote removal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    /// Text with quoting removed; expansions are kept as written
+    pub text: String,
+    /// Contains a parameter expansion or command substitution, so the value is
+    /// only known when the shell runs it
+    pub dynamic: bool,
+    /// Contains an unquoted `*` or `?`
+    pub glob: bool,
+}
+
+/// A redirection such as `> /dev/sda` (file descriptor numbers are dropped).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub op: String,
+    pub target: Word,
+}
+
+impl Redirect {
+    /// True for redirections that open the target for writing.
+    pub fn writes(&self) -> bool {
+        self.op.contains('>')
+    }
+}
+
+/// A single command: assignments, argv and redirections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// Leading `NAME=value` assignments
+    pub assignments: Vec<String>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    /// Source text of the command, for reporting
+    pub text: String,
+    /// Part of a pipeline with other commands
+    pub piped: bool,
+    /// Reads its stdin from the previous command of the pipeline
+    pub piped_in: bool,
+    /// Run in the background with `&`
+    pub background: bool,
+    /// Char ranges of `words` in the command line; empty for commands from nested
+    /// scripts (substitutions and `sh -c` bodies)
+    pub word_spans: Vec<(usize, usize)>,
+}
+
+impl SimpleCommand {
+    /// The argv as written, then once more for each wrapper (`sudo`, `env`,
+    /// `nice`, `timeout`, ...) peeled off the front.
+    pub fn invocations(&self) -> Vec<&[Word]> {
+        let mut words = &self.words[..];
+        let mut invocations = Vec::new();
+        while !words.is_empty() {
+            invocations.push(words);
+            let name = basename(&words[0].text);
+            let Some((wrapper, value_options)) = WRAPPERS.iter().find(|(w, _)| *w == name) else {
+                break;
+            };
+            let mut i = 1;
+            while let Some(word) = words.get(i) {
+                let text = word.text.as_str();
+                if text == "--" {
+                    i += 1;
+                    break;
+                }
+                if text.len() > 1 && text.starts_with('-') {
+                    i += if value_options.contains(&text) { 2 } else { 1 };
+                } else if *wrapper == "env" && is_assignment(text) {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            if *wrapper == "timeout" {
+                // Skip the duration
+                i += 1;
+            }
+            words = &words[i.min(words.len())..];
+        }
+        invocations
+    }
+
+    /// Where the script comes from when the (innermost) program is a shell.
+    pub fn shell_input(&self) -> Option<ShellInput<'_>> {
+        let argv = *self.invocations().last()?;
+        if !SHELLS.contains(&basename(&argv.first()?.text).as_str()) {
+            return None;
+        }
+        let (mut saw_c, mut saw_s) = (false, false);
+        let mut words = argv[1..].iter();
+        while let Some(word) = words.next() {
+            let text = word.text.as_str();
+            if text == "-o" || text == "+o" {
+                words.next();
+            } else if text.starts_with('-') || text.starts_with('+') {
+                if !text.starts_with("--") {
+                    saw_c |= text.contains('c');
+                    saw_s |= text.contains('s');
+                }
+            } else if saw_c {
+                return Some(ShellInput::Inline(word));
+            } else if saw_s {
+                return Some(ShellInput::Stdin);
+            } else {
+                return Some(ShellInput::File(word));
+            }
+        }
+        (!saw_c).then_some(ShellInput::Stdin)
+    }
+
+    /// The script passed to `sh -c` (or another shell) or to `eval`, or the
+    /// command line of `env -S`, when it is a literal.
+    fn shell_script(&self) -> Option<String> {
+        if let Some(script) = self.invocations().into_iter().find_map(env_split_string) {
+            return Some(script);
+        }
+        let argv = *self.invocations().last()?;
+        if basename(&argv.first()?.text) == "eval" {
+            let args = &argv[1..];
+            return (!args.is_empty() && args.iter().all(|w| !w.dynamic)).then(|| {
+                args.iter()
+                    .map(|w| w.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            });
+        }
+        match self.shell_input()? {
+            ShellInput::Inline(word) if !word.dynamic => Some(word.text.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The command `env -S` (`--split-string`) runs: the split string followed by the
+/// remaining arguments. `None` for other invocations and dynamic strings.
+fn env_split_string(argv: &[Word]) -> Option<String> {
+    if basename(&argv.first()?.text) != "env" {
+        return None;
+    }
+    let value_options = ["-u", "-C", "--unset", "--chdir"];
+    let mut i = 1;
+    while let Some(word) = argv.get(i) {
+        let text = word.text.as_str();
+        let inline = text
+            .strip_prefix("--split-string=")
+            .or_else(|| text.strip_prefix("-S").filter(|rest| !rest.is_empty()));
+        let (split, rest) = if let Some(split) = inline {
+            (split.to_string(), i + 1)
+        } else if text == "-S" || text == "--split-string" {
+            (argv.get(i + 1)?.text.clone(), i + 2)
+        } else if text == "--" || !(text.starts_with('-') || is_assignment(text)) {
+            return None;
+        } else {
+            i += if value_options.contains(&text) { 2 } else { 1 };
+            continue;
+        };
+        if word.dynamic || argv.get(rest - 1).is_some_and(|w| w.dynamic) {
+            return None;
+        }
+        let mut script = split;
+        for word in argv.get(rest..).unwrap_or_default() {
+            script.push(' ');
+            script.push_str(&word.text);
+        }
+        return Some(script);
+    }
+    None
+}
+
+/// How a shell invocation gets the script it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellInput<'a> {
+    /// The argument of `-c`
+    Inline(&'a Word),
+    /// A script file operand
+    File(&'a Word),
+    /// Standard input (no operand, or `-s`)
+    Stdin,
+}
+
+/// A shell function definition and the commands in its body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub body: Vec<SimpleCommand>,
+    /// Source text of the definition
+    pub text: String,
+}
+
+/// Everything a command line would run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedCommand {
+    pub commands: Vec<SimpleCommand>,
+    pub functions: Vec<FunctionDef>,
+}
+
+/// Split a command line into the simple commands and functions it contains.
+pub fn parse(source: &str) -> ParsedCommand {
+    let mut parsed = ParsedCommand::default();
+    parse_into(source, 0, &mut parsed);
+    parsed
+}
+
+/// Last path component, lowercased: `/usr/bin/RM` -> `rm`.
+pub fn basename(program: &str) -> String {
+    program.rsplit('/').next().unwrap_or(program).to_lowercase()
+}
+
+fn is_assignment(text: &str) -> bool {
+    let Some((name, _)) = text.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug)]
+enum Token {
+    Word {
+        word: Word,
+        start: usize,
+        end: usize,
+    },
+    Op {
+        op: &'static str,
+        start: usize,
+        end: usize,
+    },
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    /// Scripts found in command and process substitutions
+    nested: Vec<String>,
+    /// Here-document delimiters waiting for the next newline
+    heredocs: Vec<(String, bool)>,
+    /// The next word is a here-document delimiter (`<<-` strips tabs)
+    heredoc_next: Option<bool>,
+}
+
+impl Lexer {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+            nested: Vec::new(),
+            heredocs: Vec::new(),
+            heredoc_next: None,
+        }
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        self.chars[start..end].iter().collect()
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        loop {
+            match self.peek_at(0)? {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '\\' if self.peek_at(1) == Some('\n') => self.pos += 2,
+                '#' => {
+                    while self.peek_at(0).is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let start = self.pos;
+        let c = self.peek_at(0)?;
+        if c == '\n' {
+            self.pos += 1;
+            self.skip_heredoc_bodies();
+            return Some(Token::Op {
+                op: ";",
+                start,
+                end: self.pos,
+            });
+        }
+        if matches!(c, '<' | '>') && self.peek_at(1) == Some('(') {
+            // Process substitution
+            self.pos += 1;
+            let inner = self.read_balanced('(', ')');
+            let text = format!("{}({})", c, inner);
+            self.nested.push(inner);
+            return Some(Token::Word {
+                word: Word {
+                    text,
+                    dynamic: true,
+                    glob: false,
+                },
+                start,
+                end: self.pos,
+            });
+        }
+        if let Some(op) = self.read_operator() {
+            if op == "<<" || op == "<<-" {
+                self.heredoc_next = Some(op == "<<-");
+            }
+            return Some(Token::Op {
+                op,
+                start,
+                end: self.pos,
+            });
+        }
+
+        let (word, quoted) = self.read_word();
+        // An unquoted number right before a redirection is its file descriptor
+        if !quoted
+            && !word.text.is_empty()
+            && word.text.bytes().all(|b| b.is_ascii_digit())
+            && matches!(self.peek_at(0), Some('<' | '>'))
+            && let Some(op) = self.read_operator()
+        {
+            return Some(Token::Op {
+                op,
+                start,
+                end: self.pos,
+            });
+        }
+        if let Some(strip_tabs) = self.heredoc_next.take() {
+            self.heredocs.push((word.text.clone(), strip_tabs));
+        }
+        Some(Token::Word {
+            word,
+            start,
+            end: self.pos,
+        })
+    }
+
+    fn read_operator(&mut self) -> Option<&'static str> {
+        let op = OPERATORS.iter().find(|op| {
+            op.chars()
+                .enumerate()
+                .all(|(i, c)| self.peek_at(i) == Some(c))
+        })?;
+        self.pos += op.chars().count();
+        Some(op)
+    }
+
+    fn skip_heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.chars.len() {
+                let start = self.pos;
+                while self.peek_at(0).is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+                let line = self.text(start, self.pos);
+                self.pos = (self.pos + 1).min(self.chars.len());
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Read one word; also reports whether any part of it was quoted.
+    fn read_word(&mut self) -> (Word, bool) {
+        let mut word = Word::default();
+        let mut quoted = false;
+        while let Some(c) = self.peek_at(0) {
+            match c {
+                ' ' | '\t' | '\r' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')' => break,
+                '\\' => {
+                    quoted = true;
+                    match self.peek_at(1) {
+                        Some('\n') => {}
+                        Some(next) => word.text.push(next),
+                        None => {}
+                    }
+                    self.pos = (self.pos + 2).min(self.chars.len());
+                }
+                '\'' => {
+                    quoted = true;
+                    self.pos += 1;
+                    while let Some(c) = self.peek_at(0) {
+                        self.pos += 1;
+                        if c == '\'' {
+                            break;
+                        }
+                        word.text.push(c);
+                    }
+                }
+                '"' => {
+                    quoted = true;
+                    self.pos += 1;
+                    self.read_double_quoted(&mut word);
+                }
+                '$' => self.read_dollar(&mut word, &mut quoted),
+                '`' => self.read_backticks(&mut word),
+                '*' | '?' => {
+                    word.glob = true;
+                    word.text.push(c);
+                    self.pos += 1;
+                }
+                _ => {
+                    word.text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        (word, quoted)
+    }
+
+    fn read_double_quoted(&mut self, word: &mut Word) {
+        while let Some(c) = self.peek_at(0) {
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return;
+                }
+                '\\' => {
+                    match self.peek_at(1) {
+                        Some('\n') => {}
+                        Some(next @ ('$' | '`' | '"' | '\\')) => word.text.push(next),
+                        _ => {
+                            word.text.push('\\');
+                            self.pos += 1;
+                            continue;
+                        }
+                    }
+                    self.pos += 2;
+                }
+                '$' => {
+                    let mut quoted = true;
+                    self.read_dollar(word, &mut quoted);
+                }
+                '`' => self.read_backticks(word),
+                _ => {
+                    word.text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn read_dollar(&mut self, word: &mut Word, quoted: &mut bool) {
+        match self.peek_at(1) {
+            Some('(') if self.peek_at(2) == Some('(') => {
+                // Arithmetic expansion runs no command
+                self.pos += 1;
+                let inner = self.read_balanced('(', ')');
+                word.text.push_str(&format!("$({})", inner));
+                word.dynamic = true;
+            }
+            Some('(') => {
+                self.pos += 1;
+                let inner = self.read_balanced('(', ')');
+                word.text.push_str(&format!("$({})", inner));
+                word.dynamic = true;
+                self.nested.push(inner);
+            }
+            Some('{') => {
+                self.pos += 1;
+                let inner = self.read_balanced('{', '}');
+                word.text.push_str(&format!("${{{}}}", inner));
+                word.dynamic = true;
+            }
+            Some('\'') => {
+                *quoted = true;
+                self.pos += 2;
+                self.read_ansi_c_quoted(word);
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.pos;
+                self.pos += 1;
+                while self
+                    .peek_at(0)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    self.pos += 1;
+                }
+                word.text.push_str(&self.text(start, self.pos));
+                word.dynamic = true;
+            }
+            Some(c) if c.is_ascii_digit() || "?@*#$!-".contains(c) => {
+                word.text.push('$');
+                word.text.push(c);
+                word.dynamic = true;
+                self.pos += 2;
+            }
+            _ => {
+                word.text.push('$');
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Bash `$'...'` strings, with the common escapes decoded.
+    fn read_ansi_c_quoted(&mut self, word: &mut Word) {
+        while let Some(c) = self.peek_at(0) {
+            self.pos += 1;
+            match c {
+                '\'' => return,
+                '\\' => {
+                    let Some(escape) = self.peek_at(0) else {
+                        return;
+                    };
+                    self.pos += 1;
+                    match escape {
+                        'n' => word.text.push('\n'),
+                        't' => word.text.push('\t'),
+                        'r' => word.text.push('\r'),
+                        'a' => word.text.push('\x07'),
+                        'e' | 'E' => word.text.push('\x1b'),
+                        'x' => {
+                            let code = self.read_digits(16, 2);
+                            word.text.extend(code.and_then(char::from_u32));
+                        }
+                        '0'..='7' => {
+                            self.pos -= 1;
+                            let code = self.read_digits(8, 3);
+                            word.text.extend(code.and_then(char::from_u32));
+                        }
+                        other => word.text.push(other),
+                    }
+                }
+                other => word.text.push(other),
+            }
+        }
+    }
+
+    fn read_digits(&mut self, radix: u32, max: usize) -> Option<u32> {
+        let mut value = None;
+        for _ in 0..max {
+            let Some(digit) = self.peek_at(0).and_then(|c| c.to_digit(radix)) else {
+                break;
+            };
+            value = Some(value.unwrap_or(0) * radix + digit);
+            self.pos += 1;
+        }
+        value
+    }
+
+    fn read_backticks(&mut self, word: &mut Word) {
+        self.pos += 1;
+        let mut inner = String::new();
+        while let Some(c) = self.peek_at(0) {
+            self.pos += 1;
+            match c {
+                '`' => break,
+                '\\' if matches!(self.peek_at(0), Some('`' | '\\' | '$')) => {
+                    inner.push(self.peek_at(0).unwrap_or('\\'));
+                    self.pos += 1;
+                }
+                _ => inner.push(c),
+            }
+        }
+        word.text.push_str(&format!("`{}`", inner));
+        word.dynamic = true;
+        self.nested.push(inner);
+    }
+
+    /// Read from an opening bracket to its match and return what is between them.
+    fn read_balanced(&mut self, open: char, close: char) -> String {
+        let start = self.pos + 1;
+        let mut depth = 0usize;
+        while let Some(c) = self.peek_at(0) {
+            self.pos += 1;
+            match c {
+                '\\' => self.pos = (self.pos + 1).min(self.chars.len()),
+                '\'' => {
+                    while self.peek_at(0).is_some_and(|c| c != '\'') {
+                        self.pos += 1;
+                    }
+                    self.pos = (self.pos + 1).min(self.chars.len());
+                }
+                '"' => {
+                    while let Some(c) = self.peek_at(0) {
+                        self.pos += 1;
+                        match c {
+                            '\\' => self.pos = (self.pos + 1).min(self.chars.len()),
+                            '"' => break,
+                            _ => {}
+                        }
+                    }
+                }
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.text(start, self.pos - 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.text(start.min(self.chars.len()), self.chars.len())
+    }
+}
+
+/// A simple command being assembled from tokens.
+#[derive(Default)]
+struct PendingCommand {
+    command: SimpleCommand,
+    span: Option<(usize, usize)>,
+    word_spans: Vec<(usize, usize)>,
+    redirect: Option<&'static str>,
+    /// Inside a `for`/`case`/`select` header, which runs nothing itself
+    header: bool,
+    /// Saw the `function` keyword; the next word names a function
+    function_keyword: bool,
+}
+
+impl PendingCommand {
+    fn extend(&mut self, start: usize, end: usize) {
+        self.span = Some(match self.span {
+            Some((first, _)) => (first, end),
+            None => (start, end),
+        });
+    }
+
+    fn is_empty(&self) -> bool {
+        self.command.words.is_empty()
+            && self.command.assignments.is_empty()
+            && self.command.redirects.is_empty()
+    }
+}
+
+struct OpenFunction {
+    name: String,
+    start: usize,
+    first_command: usize,
+    brace_depth: usize,
+}
+
+fn parse_into(source: &str, depth: usize, out: &mut ParsedCommand) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+
+    let first_command = out.commands.len();
+    let mut current = PendingCommand::default();
+    let mut piped_in = false;
+    let mut brace_depth = 0usize;
+    let mut open_functions: Vec<OpenFunction> = Vec::new();
+    // A function header was seen; its `{` body comes next
+    let mut expect_body: Option<(String, usize)> = None;
+
+    let finish = |current: &mut PendingCommand,
+                  out: &mut ParsedCommand,
+                  piped_in: bool,
+                  piped_out: bool,
+                  background: bool| {
+        let pending = std::mem::take(current);
+        if pending.header || pending.is_empty() {
+            return;
+        }
+        let mut command = pending.command;
+        if let Some((start, end)) = pending.span {
+            command.text = lexer_text(source, start, end);
+        }
+        if depth == 0 {
+            command.word_spans = pending.word_spans;
+        }
+        command.piped = piped_in || piped_out;
+        command.piped_in = piped_in;
+        command.background = background;
+        out.commands.push(command);
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Op { op, start, end } if is_redirect(op) => {
+                current.redirect = Some(op);
+                current.extend(*start, *end);
+            }
+            Token::Op { op: "(", start, .. }
+                if current.command.words.len() == 1
+                    && current.command.assignments.is_empty()
+                    && matches!(tokens.get(i + 1), Some(Token::Op { op: ")", .. })) =>
+            {
+                // NAME ( ) starts a function definition
+                let name = current.command.words.remove(0).text;
+                let start = current.span.map_or(*start, |(first, _)| first);
+                current = PendingCommand::default();
+                expect_body = Some((name, start));
+                i += 2;
+                continue;
+            }
+            Token::Op { op, .. } => {
+                let piped_out = matches!(*op, "|" | "|&");
+                finish(&mut current, out, piped_in, piped_out, *op == "&");
+                piped_in = piped_out;
+            }
+            Token::Word { word, start, end } => {
+                let (start, end) = (*start, *end);
+                if let Some(op) = current.redirect.take() {
+                    current.command.redirects.push(Redirect {
+                        op: op.trim_start_matches('&').to_string(),
+                        target: word.clone(),
+                    });
+                    current.extend(start, end);
+                } else if current.header {
+                } else if current.function_keyword {
+                    current.function_keyword = false;
+                    expect_body = Some((word.text.clone(), start));
+                    if matches!(tokens.get(i + 1), Some(Token::Op { op: "(", .. }))
+                        && matches!(tokens.get(i + 2), Some(Token::Op { op: ")", .. }))
+                    {
+                        i += 2;
+                    }
+                } else if current.command.words.is_empty() {
+                    let mut word = word.clone();
+                    let mut start = start;
+                    if let Some((name, def_start)) = expect_body.take()
+                        && word.text.starts_with('{')
+                    {
+                        open_functions.push(OpenFunction {
+                            name,
+                            start: def_start,
+                            first_command: out.commands.len(),
+                            brace_depth,
+                        });
+                        brace_depth += 1;
+                        // `{:|:&}` written without a space after the brace
+                        word.text.remove(0);
+                        start += 1;
+                        if word.text.is_empty() {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    match word.text.as_str() {
+                        _ if word.dynamic => {
+                            current.command.words.push(word);
+                            current.word_spans.push((start, end));
+                            current.extend(start, end);
+                        }
+                        "{" => brace_depth += 1,
+                        "}" => {
+                            brace_depth = brace_depth.saturating_sub(1);
+                            if open_functions
+                                .last()
+                                .is_some_and(|f| f.brace_depth == brace_depth)
+                            {
+                                let function = open_functions.pop().expect("checked above");
+                                out.functions.push(FunctionDef {
+                                    name: function.name,
+                                    body: out.commands[function.first_command..].to_vec(),
+                                    text: lexer_text(source, function.start, end),
+                                });
+                            }
+                        }
+                        "for" | "select" | "case" => current.header = true,
+                        "function" => current.function_keyword = true,
+                        text if RESERVED.contains(&text) => {}
+                        text if is_assignment(text) => {
+                            current.command.assignments.push(word.text.clone());
+                            current.extend(start, end);
+                        }
+                        _ => {
+                            current.command.words.push(word);
+                            current.word_spans.push((start, end));
+                            current.extend(start, end);
+                        }
+                    }
+                } else {
+                    current.command.words.push(word.clone());
+                    current.word_spans.push((start, end));
+                    current.extend(start, end);
+                }
+            }
+        }
+        i += 1;
+    }
+    finish(&mut current, out, piped_in, false, false);
+
+    for inner in std::mem::take(&mut lexer.nested) {
+        parse_into(&inner, depth + 1, out);
+    }
+    let scripts: Vec<String> = out.commands[first_command..]
+        .iter()
+        .filter_map(SimpleCommand::shell_script)
+        .collect();
+    for script in scripts {
+        parse_into(&script, depth + 1, out);
+    }
+}
+
+fn is_redirect(op: &str) -> bool {
+    op.contains('<') || op.contains('>')
+}
+
+fn lexer_text(source: &str, start: usize, end: usize) -> String {
+    source.chars().skip(start).take(end - start).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argvs(command: &str) -> Vec<Vec<String>> {
+        parse(command)
+            .commands
+            .iter()
+            .map(|c| c.words.iter().map(|w| w.text.clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn splits_lists_pipelines_subshells_and_substitutions() {
+        assert_eq!(
+            argvs("cd /tmp && (make | tee log; echo 'a b' \"c$HOME\") || x=1 r\\m -r -f  /"),
+            vec![
+                vec!["cd", "/tmp"],
+                vec!["make"],
+                vec!["tee", "log"],
+                vec!["echo", "a b", "c$HOME"],
+                vec!["rm", "-r", "-f", "/"],
+            ]
+        );
+
+        let parsed = parse("echo $(printf rm) `id -u` > /dev/null; cat <(ls /)");
+        let texts: Vec<&str> = parsed.commands.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "echo $(printf rm) `id -u` > /dev/null",
+                "cat <(ls /)",
+                "printf rm",
+                "id -u",
+                "ls /"
+            ]
+        );
+        assert!(parsed.commands[0].words[1].dynamic);
+        assert_eq!(parsed.commands[0].redirects[0].target.text, "/dev/null");
+
+        assert_eq!(
+            argvs("sudo -u root bash -lc 'rm -rf \"/\"'"),
+            vec![
+                vec!["sudo", "-u", "root", "bash", "-lc", "rm -rf \"/\""],
+                vec!["rm", "-rf", "/"],
+            ]
+        );
+        assert_eq!(argvs("$'\\x72\\x6d' -rf /")[0][0], "rm");
+        assert_eq!(
+            argvs("eval 'rm -rf /'; eval \"$X\""),
+            vec![
+                vec!["eval", "rm -rf /"],
+                vec!["eval", "$X"],
+                vec!["rm", "-rf", "/"]
+            ]
+        );
+
+        let parsed = parse("curl -s x | base64 -d | bash -s -- arg; bash -lc \"$(curl y)\" x");
+        let piped_in: Vec<bool> = parsed.commands.iter().map(|c| c.piped_in).collect();
+        assert_eq!(piped_in, vec![false, true, true, false, false]);
+        assert_eq!(parsed.commands[2].shell_input(), Some(ShellInput::Stdin));
+        assert!(matches!(
+            parsed.commands[3].shell_input(),
+            Some(ShellInput::Inline(word)) if word.dynamic
+        ));
+    }
+
+    #[test]
+    fn ignores_reserved_words_comments_and_heredoc_bodies() {
+        assert_eq!(
+            argvs("if true; then reboot; fi # shutdown\nfor f in a b; do rm $f; done"),
+            vec![vec!["true"], vec!["reboot"], vec!["rm", "$f"]]
+        );
+        assert_eq!(
+            argvs("cat <<EOF > out\nshutdown now\nEOF\necho done"),
+            vec![vec!["cat"], vec!["echo", "done"]]
+        );
+    }
+
+    #[test]
+    fn finds_function_bodies_and_wrapped_commands() {
+        let parsed = parse(":(){ :|:& };:");
+        assert_eq!(parsed.functions.len(), 1);
+        let function = &parsed.functions[0];
+        assert_eq!(function.name, ":");
+        assert_eq!(function.text, ":(){ :|:& }");
+        assert!(function.body.iter().all(|c| c.piped));
+        assert!(function.body[1].background);
+        assert_eq!(parse(":(){:|:&};:").functions[0].body.len(), 2);
+
+        let parsed = parse("sudo -u admin env FOO=1 nice -n 5 timeout 10 rm -rf /");
+        let invocations = parsed.commands[0].invocations();
+        let programs: Vec<&str> = invocations
+            .iter()
+            .map(|argv| argv[0].text.as_str())
+            .collect();
+        assert_eq!(programs, vec!["sudo", "env", "nice", "timeout", "rm"]);
+
+        // `env -S` splits its string into the argv it runs
+        assert_eq!(
+            argvs("env -i -S 'rm -rf' / && env --split-string='git  push' -f"),
+            vec![
+                vec!["env", "-i", "-S", "rm -rf", "/"],
+                vec!["env", "--split-string=git  push", "-f"],
+                vec!["rm", "-rf", "/"],
+                vec!["git", "push", "-f"],
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Server-side sudo credential cache and keepalive.
+//!
+//! The executor primes sudo in the server process (askpass) and a keepalive task
+//! refreshes the timestamp with `sudo -n -v` (see `maybe_start_sudo_keepalive`).
+//! This module owns the keepalive task so clients can see it, and implements the
+//! status, revoke (`sudo -k`) and stop tools. A stopped keepalive stays stopped for
+//! the rest of the server process.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::task::AbortHandle;
+
+use super::policy;
+use super::terminal_executor::env_bool;
+
+struct Keepalive {
+    refresh: Duration,
+    started_at: SystemTime,
+    /// When the last refresh ran and whether `sudo -n -v` succeeded
+    last_refresh: Option<(SystemTime, bool)>,
+    refreshes: u64,
+    task: AbortHandle,
+}
+
+enum KeepaliveState {
+    NotStarted,
+    Running(Keepalive),
+    Stopped(SystemTime),
+}
+
+static KEEPALIVE: Mutex<KeepaliveState> = Mutex::new(KeepaliveState::NotStarted);
+
+/// Run `sudo` with the given arguments, without a terminal or input.
+async fn run_sudo(args: &[&str]) -> Result<std::process::Output> {
+    tokio::process::Command::new("sudo")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .with_context(|| format!("failed to run sudo {}", args.join(" ")))
+}
+
+/// Start the keepalive task unless it is running or was stopped. Returns whether
+/// it started.
+pub fn start_keepalive(refresh: Duration) -> bool {
+    let mut state = KEEPALIVE.lock().unwrap_or_else(|e| e.into_inner());
+    if !matches!(*state, KeepaliveState::NotStarted) {
+        return false;
+    }
+    tracing::info!(
+        "Starting sudo keepalive task (refresh_secs={})",
+        refresh.as_secs()
+    );
+    let task = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(refresh).await;
+
+            // `sudo -n -v` refreshes timestamp if already authenticated; fails if it would prompt.
+            let refreshed = match run_sudo(&["-n", "-v"]).await {
+                Ok(output) if output.status.success() => {
+                    tracing::debug!("sudo keepalive refreshed timestamp");
+                    true
+                }
+                Ok(_) => {
+                    tracing::debug!("sudo keepalive: no cached credentials (sudo -n -v failed)");
+                    false
+                }
+                Err(e) => {
+                    tracing::warn!("sudo keepalive: {:#}", e);
+                    false
+                }
+            };
+            let mut state = KEEPALIVE.lock().unwrap_or_else(|e| e.into_inner());
+            if let KeepaliveState::Running(keepalive) = &mut *state {
+                keepalive.last_refresh = Some((SystemTime::now(), refreshed));
+                keepalive.refreshes += 1;
+            }
+        }
+    });
+    *state = KeepaliveState::Running(Keepalive {
+        refresh,
+        started_at: SystemTime::now(),
+        last_refresh: None,
+        refreshes: 0,
+        task: task.abort_handle(),
+    });
+    true
+}
+
+/// Stop the keepalive task for good. Returns whether it was running.
+pub fn stop_keepalive() -> bool {
+    let mut state = KEEPALIVE.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = std::mem::replace(&mut *state, KeepaliveState::Stopped(SystemTime::now()));
+    match previous {
+        KeepaliveState::Running(keepalive) => {
+            keepalive.task.abort();
+            tracing::info!("sudo keepalive stopped");
+            true
+        }
+        // Keep the original stop time
+        KeepaliveState::Stopped(at) => {
+            *state = KeepaliveState::Stopped(at);
+            false
+        }
+        KeepaliveState::NotStarted => false,
+    }
+}
+
+fn ago(time: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or_default()
+        .as_secs();
+    format!("{}s ago", secs)
+}
+
+fn keepalive_description() -> String {
+    let state = KEEPALIVE.lock().unwrap_or_else(|e| e.into_inner());
+    match &*state {
+        KeepaliveState::NotStarted => "not started".to_string(),
+        KeepaliveState::Stopped(at) => format!("stopped {}", ago(*at)),
+        KeepaliveState::Running(keepalive) => {
+            let mut text = format!(
+                "running, every {}s since {}, {} refreshes",
+                keepalive.refresh.as_secs(),
+                ago(keepalive.started_at),
+                keepalive.refreshes
+            );
+            if let Some((at, ok)) = keepalive.last_refresh {
+                text.push_str(&format!(
+                    ", last {} ({})",
+                    ago(at),
+                    if ok { "ok" } else { "no cached credentials" }
+                ));
+            }
+            text
+        }
+    }
+}
+
+/// What `enhanced_terminal_sudo_status` reports.
+#[derive(Debug, Clone)]
+pub struct SudoStatus {
+    /// Whether `sudo -n -v` succeeds, or why it could not be run
+    pub cached: Result<bool, String>,
+    pub keepalive: String,
+    /// Environment switches for wrapping, keepalive and priming
+    pub settings: Vec<(&'static str, bool)>,
+    /// The policy file's sudo allowlist, if it has one
+    pub sudo_allowlist: Option<String>,
+}
+
+impl fmt::Display for SudoStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cached {
+            Ok(true) => writeln!(f, "Cached Credentials: yes")?,
+            Ok(false) => writeln!(f, "Cached Credentials: no (sudo -n -v would prompt)")?,
+            Err(e) => writeln!(f, "Cached Credentials: unknown ({})", e)?,
+        }
+        writeln!(f, "Keepalive: {}", self.keepalive)?;
+        for (name, enabled) in &self.settings {
+            writeln!(f, "{}: {}", name, if *enabled { "on" } else { "off" })?;
+        }
+        match &self.sudo_allowlist {
+            Some(allowlist) => writeln!(f, "Sudo Allowlist: {}", allowlist),
+            None => writeln!(f, "Sudo Allowlist: none (any command may run under sudo)"),
+        }
+    }
+}
+
+/// Check the credential cache and describe the keepalive and sudo settings.
+pub async fn status() -> SudoStatus {
+    let settings = [
+        "ENHANCED_TERMINAL_SUDO_WRAP",
+        "ENHANCED_TERMINAL_SUDO_KEEPALIVE",
+        "ENHANCED_TERMINAL_SUDO_KEEPALIVE_PRIME",
+    ]
+    .into_iter()
+    .map(|name| (name, env_bool(name)))
+    .collect();
+    // Checking refreshes a cached timestamp, like any sudo command
+    let cached = run_sudo(&["-n", "-v"])
+        .await
+        .map(|output| output.status.success())
+        .map_err(|e| format!("{:#}", e));
+    let sudo_allowlist = match policy::current() {
+        Ok(Some(policy)) => policy.sudo_allowlist().map(|allowlist| {
+            format!(
+                "{} entries in {}",
+                allowlist.entry_count(),
+                policy.path.display()
+            )
+        }),
+        Ok(None) => None,
+        Err(e) => Some(format!("policy file does not load: {:#}", e)),
+    };
+    SudoStatus {
+        cached,
+        keepalive: keepalive_description(),
+        settings,
+        sudo_allowlist,
+    }
+}
+
+/// Drop the cached credentials with `sudo -k`.
+pub async fn revoke() -> Result<()> {
+    let output = run_sudo(&["-k"]).await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "sudo -k failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    tracing::info!("sudo credentials revoked (sudo -k)");
+    Ok(())
+}
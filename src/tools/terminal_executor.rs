@@ -9,14 +9,28 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 use tiktoken_rs::o200k_base_singleton;
 use tokio::sync::{Mutex as TokioMutex, mpsc};
 
-use super::denylist::{find_matched_pattern, is_denied};
-use super::job_manager::{JobManager, JobStatus};
+use super::approval;
+use super::cgroup::{self, CgroupLimits, CgroupPlacement};
+use super::job_manager::{JobManager, JobStatus, OutputStream};
+use super::landlock::{self, FailPolicy, WriteRestriction, WriteRestrictions, WriteRules};
+use super::launcher::LaunchSpec;
+use super::output_decoder::{OutputDecoder, OutputEncoding};
+use super::policy;
+use super::process_group::TerminationStage;
+use super::redaction::{Redactor, StreamRedactor};
+use super::resource_limits::{LimitExceeded, ResourceLimits};
+use super::resource_usage::ResourceUsage;
+use super::rewrite::{self, RewriteRule, Rewritten};
+use super::sandbox::{self, Sandbox};
+use super::sudo;
+use super::termination::Termination;
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -49,13 +63,54 @@ pub struct TerminalExecutionInput {
     /// Optional tags for categorizing jobs (e.g., ["build", "ci"])
     #[serde(default)]
     pub tags: Vec<String>,
+    /// How to capture output: "pty" (default, merged terminal stream) or "pipes"
+    /// (plain pipes with stdout and stderr stored separately).
+    #[serde(default)]
+    pub capture_mode: CaptureMode,
+    /// Optional rlimits applied to the command's process before it starts.
+    /// Values are capped by the server's ENHANCED_TERMINAL_MAX_* configuration.
+    #[serde(default)]
+    pub resource_limits: Option<ResourceLimits>,
+    /// Optional cgroup v2 limits (memory.max, cpu.max, pids.max) for the job's own
+    /// cgroup. They cover grandchildren too; only enforced when the server has a
+    /// delegated cgroup v2 subtree.
+    #[serde(default)]
+    pub cgroup_limits: Option<CgroupLimits>,
+    /// Optional Linux namespace sandbox profile to run the command under (e.g. "isolated").
+    /// The server may configure more profiles and a default; "none" opts out of the default
+    /// only when the server allows it.
+    #[serde(default)]
+    pub sandbox: Option<String>,
+    /// Restrict filesystem writes with Landlock to the cwd, $TMPDIR and server-configured
+    /// paths (Linux). The server may also enable this for every command.
+    #[serde(default)]
+    pub restrict_writes: bool,
+    /// Extra paths that must stay read-only when writes are restricted. A writable
+    /// directory that contains one of them is not made writable.
+    #[serde(default)]
+    pub read_only_paths: Vec<String>,
+    /// Character encoding of the output: "utf-8" (default) or "latin-1" for legacy
+    /// tools. Byte sequences that are not valid UTF-8 are counted either way.
+    #[serde(default)]
+    pub encoding: OutputEncoding,
+}
+
+/// Output capture strategy for a command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// Run the command in a PTY; stdout and stderr arrive as one terminal stream.
+    #[default]
+    Pty,
+    /// Run the command with plain pipes; stdout and stderr are captured separately.
+    Pipes,
 }
 
-fn default_cwd() -> String {
+pub(super) fn default_cwd() -> String {
     ".".to_string()
 }
 
-fn default_shell() -> String {
+pub(super) fn default_shell() -> String {
     "bash".to_string()
 }
 
@@ -65,7 +120,7 @@ fn default_preview_tokens() -> usize {
 
 const MIN_PREVIEW_CAPTURE_BYTES: usize = 64 * 1024;
 
-fn apply_default_env(mut env_vars: HashMap<String, String>) -> HashMap<String, String> {
+pub(super) fn apply_default_env(mut env_vars: HashMap<String, String>) -> HashMap<String, String> {
     env_vars
         .entry("TERM".to_string())
         .or_insert_with(|| "dumb".to_string());
@@ -94,6 +149,11 @@ fn get_async_threshold_secs() -> u64 {
         .unwrap_or(50)
 }
 
+/// Grace period between the SIGINT, SIGTERM and SIGKILL stages when stopping a job.
+fn get_kill_grace() -> Duration {
+    Duration::from_secs(env_u64("ENHANCED_TERMINAL_KILL_GRACE_SECS", 5))
+}
+
 fn get_timeout_secs() -> Option<u64> {
     std::env::var("ENHANCED_TERMINAL_TIMEOUT_SECS")
         .ok()
@@ -170,7 +230,12 @@ fn append_to_preview_buffer(buffer: &mut Vec<u8>, data: &[u8], limit: usize) ->
         buffer.extend_from_slice(data);
         false
     } else {
-        buffer.extend_from_slice(&data[..remaining]);
+        // Cut before a character the limit splits
+        let mut end = remaining;
+        while end > 0 && data[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+        buffer.extend_from_slice(&data[..end]);
         true
     }
 }
@@ -188,76 +253,282 @@ pub struct ExecutionResult {
     pub switched_to_async: bool,
     pub denied: bool,
     pub denial_reason: Option<String>,
+    /// How a policy `ask` decision was resolved when the command ran anyway
+    pub approval: Option<String>,
+    /// Indirect execution found in the command (`eval "$X"`, `curl .. | sh`, ..)
+    pub indirect_execution: Vec<String>,
+    /// Secrets replaced in the output so far
+    pub redactions: usize,
+    /// Encoding the output was decoded with
+    pub encoding: OutputEncoding,
+    /// Byte sequences in the output so far that were not valid UTF-8
+    pub invalid_utf8: usize,
     pub duration_secs: Option<f64>,
     /// What was added/prepended to sudo commands (e.g., "-n" for non-interactive)
     pub sudo_wrapper_applied: Option<String>,
+    /// Rewrite rules that changed the command before it was checked and run
+    pub rewrites: Vec<String>,
+    /// Capture mode the command ran with
+    pub capture_mode: CaptureMode,
+    /// Separate stdout preview (pipes capture mode only)
+    pub stdout: Option<String>,
+    /// Separate stderr preview (pipes capture mode only)
+    pub stderr: Option<String>,
+    /// Escalation stage that stopped the command after a timeout or cancel
+    pub termination_stage: Option<TerminationStage>,
+    /// Effective rlimits the command ran with (after server maximums)
+    pub resource_limits: ResourceLimits,
+    /// Resource limit that ended the command, if any
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// cgroup v2 placement of the command (with final stats once it finished)
+    pub cgroup: Option<CgroupPlacement>,
+    /// Signal, core dump and explanation when the command did not exit cleanly
+    pub termination: Option<Termination>,
+    /// Sandbox profile the command ran under
+    pub sandbox: Option<Sandbox>,
+    /// Landlock write restrictions the command ran under
+    pub write_restriction: Option<WriteRestriction>,
 }
 
-pub async fn execute_command(
-    input: &TerminalExecutionInput,
-    job_manager: &JobManager,
-    peer: Option<Peer<RoleServer>>,
-) -> Result<ExecutionResult> {
-    let command = input.command.trim();
+/// Messages from the blocking reader threads to the monitoring loop.
+#[derive(Debug)]
+enum ReadMsg {
+    Data(OutputStream, Vec<u8>),
+    Eof,
+    Error,
+}
 
-    tracing::debug!(
-        "execute_command called: command={}, force_sync={}, force_async={}",
-        command,
-        input.force_sync,
-        input.force_async
-    );
+/// How a command's process ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ExitInfo {
+    /// Exit code when the process exited normally
+    code: Option<i32>,
+    /// Signal number when the process was killed by a signal
+    signal: Option<i32>,
+    /// The signal produced a core dump
+    core_dumped: bool,
+    /// Resource usage reported by `wait4`
+    usage: Option<ResourceUsage>,
+}
 
-    // Optional: server-side sudo wrapping (opt-in).
-    // When enabled, any command containing `sudo` is executed such that:
-    // - sudo authentication is primed in the server process context (askpass)
-    // - the actual privileged command runs via `sudo -n` so it never prompts
-    //
-    // This allows sudo timestamp reuse across multiple tool calls.
-    if env_bool("ENHANCED_TERMINAL_SUDO_WRAP") && sudo_looks_used(command) {
-        let report = ensure_sudo_primed_for_wrap(command, &input.env_vars).await;
-        let wrapped = wrap_sudo_command_for_server(command);
-        return execute_command_inner(&wrapped, input, job_manager, report, peer).await;
+impl ExitInfo {
+    /// Exit code in shell convention: the real code, or 128 + signal number.
+    fn exit_code(&self) -> Option<i32> {
+        self.code.or_else(|| self.signal.map(|signal| 128 + signal))
     }
+}
 
-    let sudo_prime_report = maybe_start_sudo_keepalive(command, &input.env_vars).await;
+/// Handle to a spawned command, independent of how its output is captured.
+enum ChildHandle {
+    Pty(Box<dyn portable_pty::Child + Send + Sync>),
+    Pipes(std::process::Child),
+}
 
-    if command.is_empty() {
-        return Err(anyhow::anyhow!("Command cannot be empty"));
+/// A spawned command. On Unix the process is reaped with `wait4` directly, so
+/// the exit status keeps the signal that killed it along with its resource usage.
+struct ChildProcess {
+    handle: ChildHandle,
+    exit: Option<ExitInfo>,
+}
+
+impl ChildProcess {
+    fn new(handle: ChildHandle) -> Self {
+        Self { handle, exit: None }
     }
 
-    // Check denylist
-    if is_denied(command, &input.custom_denylist) {
-        let matched_pattern = find_matched_pattern(command, &input.custom_denylist);
-        return Ok(ExecutionResult {
-            job_id: String::new(),
-            command: command.to_string(),
-            working_directory: String::new(),
-            exit_code: None,
-            success: false,
-            output: String::new(),
-            truncated: false,
-            timed_out: false,
-            switched_to_async: false,
-            denied: true,
-            denial_reason: Some(format!(
-                "Command denied by security policy. Matched pattern: {}",
-                matched_pattern.unwrap_or_else(|| "unknown".to_string())
-            )),
-            duration_secs: None,
-            sudo_wrapper_applied: None,
-        });
+    fn process_id(&self) -> Option<u32> {
+        match &self.handle {
+            ChildHandle::Pty(child) => child.process_id(),
+            ChildHandle::Pipes(child) => Some(child.id()),
+        }
     }
 
-    // Resolve working directory and canonicalize (resolve symlinks)
-    let cwd = if input.cwd == "." || input.cwd.is_empty() {
-        std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-    } else {
-        PathBuf::from(&input.cwd)
-    };
+    #[cfg(not(unix))]
+    fn kill(&mut self) {
+        match &mut self.handle {
+            ChildHandle::Pty(child) => {
+                let _ = child.kill();
+            }
+            ChildHandle::Pipes(child) => {
+                let _ = child.kill();
+            }
+        }
+    }
 
-    // Canonicalize to resolve symlinks
-    let cwd = cwd.canonicalize().unwrap_or(cwd);
+    /// Reap the process; `block` waits for it, otherwise only checks.
+    ///
+    /// Uses `wait4` so the resource usage of the process (and every descendant it
+    /// waited for) is captured together with the exit status.
+    #[cfg(unix)]
+    fn reap(&mut self, block: bool) -> Option<ExitInfo> {
+        if self.exit.is_some() {
+            return self.exit;
+        }
+        let pid = self.process_id()? as libc::pid_t;
+        let flags = if block { 0 } else { libc::WNOHANG };
 
+        loop {
+            let mut status: libc::c_int = 0;
+            // SAFETY: zeroed rusage is a valid value for wait4 to fill in.
+            let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+            // SAFETY: both pointers refer to live, writable locals.
+            let result = unsafe { libc::wait4(pid, &mut status, flags, &mut usage) };
+            if result == 0 {
+                return None;
+            }
+            if result < 0 {
+                if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+                    continue;
+                }
+                // Already reaped elsewhere; the status is lost.
+                self.exit = Some(ExitInfo::default());
+                return self.exit;
+            }
+
+            let usage = Some(ResourceUsage::from_rusage(&usage));
+            if libc::WIFEXITED(status) {
+                self.exit = Some(ExitInfo {
+                    code: Some(libc::WEXITSTATUS(status)),
+                    signal: None,
+                    core_dumped: false,
+                    usage,
+                });
+            } else if libc::WIFSIGNALED(status) {
+                self.exit = Some(ExitInfo {
+                    code: None,
+                    signal: Some(libc::WTERMSIG(status)),
+                    core_dumped: libc::WCOREDUMP(status),
+                    usage,
+                });
+            } else {
+                // Stopped or continued; keep waiting for the real exit.
+                continue;
+            }
+            return self.exit;
+        }
+    }
+
+    /// Non-blocking check whether the process has exited (reaping it if so).
+    #[cfg(unix)]
+    fn has_exited(&mut self) -> bool {
+        self.reap(false).is_some()
+    }
+
+    /// Block until the process exits.
+    #[cfg(unix)]
+    fn wait(&mut self) -> ExitInfo {
+        self.reap(true).unwrap_or_default()
+    }
+
+    #[cfg(not(unix))]
+    fn wait(&mut self) -> ExitInfo {
+        let code = match &mut self.handle {
+            ChildHandle::Pty(child) => child.wait().ok().map(|s| s.exit_code() as i32),
+            ChildHandle::Pipes(child) => child.wait().ok().map(|s| s.code().unwrap_or(1)),
+        };
+        ExitInfo {
+            code,
+            signal: None,
+            core_dumped: false,
+            usage: None,
+        }
+    }
+
+    /// Stop the command and everything it started, escalating from SIGINT to SIGKILL.
+    #[cfg(unix)]
+    async fn terminate(&mut self, grace: Duration) -> Option<TerminationStage> {
+        match self.process_id() {
+            Some(pgid) => {
+                super::process_group::terminate_group(pgid, grace, || self.has_exited()).await
+            }
+            None => None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn terminate(&mut self, _grace: Duration) -> Option<TerminationStage> {
+        self.kill();
+        Some(TerminationStage::Kill)
+    }
+}
+
+/// A started command together with the channel its output arrives on.
+struct SpawnedCommand {
+    child: ChildProcess,
+    rx: mpsc::UnboundedReceiver<ReadMsg>,
+    /// Number of reader threads that each send one `Eof` (or `Error`) when done.
+    readers: usize,
+}
+
+/// Preview buffers collected while the command runs in the foreground.
+#[derive(Default)]
+struct PreviewBuffers {
+    combined: Vec<u8>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
+
+impl PreviewBuffers {
+    fn append(&mut self, stream: OutputStream, data: &[u8], limit: usize) {
+        self.truncated |= append_to_preview_buffer(&mut self.combined, data, limit);
+        match stream {
+            OutputStream::Stdout => {
+                append_to_preview_buffer(&mut self.stdout, data, limit);
+            }
+            OutputStream::Stderr => {
+                append_to_preview_buffer(&mut self.stderr, data, limit);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn spawn_reader<R: Read + Send + 'static>(
+    mut reader: R,
+    stream: OutputStream,
+    tx: mpsc::UnboundedSender<ReadMsg>,
+    job_id: String,
+) {
+    // Spawn reader task using tokio::task::spawn_blocking (PTY/pipe read is blocking I/O)
+    tokio::task::spawn_blocking(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => {
+                    tracing::debug!("Reader task: EOF, job_id={}, stream={:?}", job_id, stream);
+                    let _ = tx.send(ReadMsg::Eof);
+                    break;
+                }
+                Ok(n) => {
+                    let data = buffer[..n].to_vec();
+                    if tx.send(ReadMsg::Data(stream, data)).is_err() {
+                        break; // Main task dropped receiver
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("Reader task error: {:?}, job_id={}", e, job_id);
+                    let _ = tx.send(ReadMsg::Error);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn spawn_pty_command(
+    command: &str,
+    input: &TerminalExecutionInput,
+    launch: &LaunchSpec,
+    cwd: &Path,
+    env_vars: HashMap<String, String>,
+    job_manager: &JobManager,
+    job_id: &str,
+) -> Result<SpawnedCommand> {
     // Create PTY system
     let pty_system = native_pty_system();
     let pair = pty_system
@@ -270,39 +541,25 @@ pub async fn execute_command(
         .map_err(|e| anyhow::anyhow!("Failed to open PTY: {}", e))?;
 
     // Build command
-    let mut cmd = CommandBuilder::new(&input.shell);
-    cmd.arg("-c");
-    cmd.arg(command);
-    cmd.cwd(&cwd);
+    let (program, args) = launch.wrap(&input.shell, vec!["-c".to_string(), command.to_string()])?;
+    let mut cmd = CommandBuilder::new(program);
+    cmd.args(args);
+    cmd.cwd(cwd);
 
     // Set environment variables
-    let env_vars = apply_default_env(input.env_vars.clone());
     for (key, value) in env_vars {
         cmd.env(key, value);
     }
 
     // Start the process
-    let mut child = pair
+    let child = pair
         .slave
         .spawn_command(cmd)
         .map_err(|e| anyhow::anyhow!("Failed to spawn: {}", e))?;
-
-    let pid = child.process_id();
     drop(pair.slave);
 
-    // Register job
-    let job_id = job_manager.new_job_id();
-    job_manager.register_job_with_tags(
-        job_id.clone(),
-        command.to_string(),
-        input.shell.clone(),
-        cwd.display().to_string(),
-        pid,
-        input.tags.clone(),
-    );
-
-    // Read output with smart async switching and keep a writer for future stdin calls.
-    let mut reader = pair
+    // Read output and keep a writer for future stdin calls.
+    let reader = pair
         .master
         .try_clone_reader()
         .map_err(|e| anyhow::anyhow!("Failed to clone reader: {}", e))?;
@@ -310,331 +567,394 @@ pub async fn execute_command(
         .master
         .take_writer()
         .map_err(|e| anyhow::anyhow!("Failed to open PTY stdin writer: {}", e))?;
-    job_manager.attach_stdin_writer(&job_id, stdin_writer);
+    job_manager.attach_stdin_writer(job_id, stdin_writer);
 
-    let preview_byte_limit = preview_buffer_limit(input);
-    let timeout = get_timeout_secs().map(Duration::from_secs);
-    let async_threshold = Duration::from_secs(get_async_threshold_secs());
-    let start_time = Instant::now();
+    let (tx, rx) = mpsc::unbounded_channel::<ReadMsg>();
+    spawn_reader(reader, OutputStream::Combined, tx, job_id.to_string());
 
-    let mut output = Vec::new();
-    let mut truncated = false;
-    let mut timed_out = false;
-    let mut switched_to_async = false;
+    Ok(SpawnedCommand {
+        child: ChildProcess::new(ChildHandle::Pty(child)),
+        rx,
+        readers: 1,
+    })
+}
 
-    // Channel for receiving output from reader task
-    #[derive(Debug)]
-    enum ReadMsg {
-        Data(Vec<u8>),
-        Eof,
-        Error,
+fn spawn_piped_command(
+    command: &str,
+    input: &TerminalExecutionInput,
+    launch: &LaunchSpec,
+    cwd: &Path,
+    env_vars: HashMap<String, String>,
+    job_manager: &JobManager,
+    job_id: &str,
+) -> Result<SpawnedCommand> {
+    let (program, args) = launch.wrap(&input.shell, vec!["-c".to_string(), command.to_string()])?;
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(args)
+        .current_dir(cwd)
+        .envs(env_vars)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Own process group so timeouts and cancels reach every descendant.
+    // (PTY commands already lead a new session.)
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
     }
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<ReadMsg>();
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to spawn: {}", e))?;
 
-    // Spawn reader task using tokio::task::spawn_blocking (PTY read is blocking I/O)
-    let reader_job_id = job_id.clone();
-    tokio::task::spawn_blocking(move || {
-        let mut buffer = [0u8; 4096];
-        loop {
-            match reader.read(&mut buffer) {
-                Ok(0) => {
-                    tracing::debug!("Reader task: EOF, job_id={}", reader_job_id);
-                    let _ = tx.send(ReadMsg::Eof);
-                    break;
-                }
-                Ok(n) => {
-                    let data = buffer[..n].to_vec();
-                    if tx.send(ReadMsg::Data(data)).is_err() {
-                        break; // Main task dropped receiver
-                    }
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    std::thread::sleep(Duration::from_millis(10));
-                    continue;
-                }
-                Err(e) => {
-                    tracing::warn!("Reader task error: {:?}, job_id={}", e, reader_job_id);
-                    let _ = tx.send(ReadMsg::Error);
-                    break;
-                }
-            }
-        }
-    });
+    if let Some(stdin) = child.stdin.take() {
+        job_manager.attach_stdin_writer(job_id, Box::new(stdin));
+    }
 
-    // Main loop: check elapsed time independently and receive output
-    let check_interval = Duration::from_millis(100);
-    loop {
-        let elapsed = start_time.elapsed();
+    let (tx, rx) = mpsc::unbounded_channel::<ReadMsg>();
+    let mut readers = 0;
+    if let Some(stdout) = child.stdout.take() {
+        spawn_reader(stdout, OutputStream::Stdout, tx.clone(), job_id.to_string());
+        readers += 1;
+    }
+    if let Some(stderr) = child.stderr.take() {
+        spawn_reader(stderr, OutputStream::Stderr, tx, job_id.to_string());
+        readers += 1;
+    }
 
-        // Check if we should switch to async (independent of I/O)
-        if input.force_async || (!input.force_sync && elapsed > async_threshold) {
-            if input.force_async {
-                tracing::debug!("Main task: force_async requested, job_id={}", job_id);
-            } else {
-                tracing::debug!(
-                    "Main task: async threshold reached at {:.2}s, job_id={}",
-                    elapsed.as_secs_f64(),
-                    job_id
-                );
-            }
-            switched_to_async = true;
-            break;
-        }
+    Ok(SpawnedCommand {
+        child: ChildProcess::new(ChildHandle::Pipes(child)),
+        rx,
+        readers,
+    })
+}
 
-        // Check for overall timeout (if set)
-        if let Some(timeout_duration) = timeout {
-            if elapsed > timeout_duration {
-                tracing::debug!("Main task: timeout reached, job_id={}", job_id);
-                let _ = child.kill();
-                timed_out = true;
-                break;
-            }
+/// Decide the job's (or session shell's) Landlock write rules, or why it must not
+/// run. When the kernel cannot enforce them, the fail-open policy runs it
+/// unrestricted and reports it.
+pub(super) fn plan_write_restriction(
+    cwd: &Path,
+    env_vars: &HashMap<String, String>,
+    read_only_paths: &[String],
+) -> std::result::Result<WriteRestriction, String> {
+    match landlock::abi_version() {
+        Ok(abi) => {
+            let tmpdir = env_vars
+                .get("TMPDIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir);
+            let read_only: Vec<PathBuf> = read_only_paths.iter().map(PathBuf::from).collect();
+            let (writable, dropped) = landlock::plan(cwd, &tmpdir, &read_only);
+            Ok(WriteRestriction::Enforced(WriteRestrictions {
+                abi,
+                writable,
+                dropped,
+            }))
         }
+        Err(reason) => match FailPolicy::from_env() {
+            FailPolicy::Open => Ok(WriteRestriction::Unavailable(reason)),
+            FailPolicy::Closed => Err(format!(
+                "Write restrictions requested but cannot be enforced: {}. \
+                 The server is configured to fail closed (ENHANCED_TERMINAL_LANDLOCK_FAIL=closed).",
+                reason
+            )),
+        },
+    }
+}
 
-        // Try to receive output from reader task with timeout
-        match tokio::time::timeout(check_interval, rx.recv()).await {
-            Ok(Some(ReadMsg::Data(data))) => {
-                truncated |= append_to_preview_buffer(&mut output, &data, preview_byte_limit);
-
-                // Update job with incremental output
-                let output_str = String::from_utf8_lossy(&data).to_string();
-                job_manager.append_output(&job_id, &output_str, preview_byte_limit);
-
-                // Send streaming notification if peer is available
-                if let Some(ref peer) = peer {
-                    let _ = peer
-                        .notify_logging_message(LoggingMessageNotificationParam {
-                            level: LoggingLevel::Info,
-                            logger: Some("enhanced_terminal".to_string()),
-                            data: serde_json::json!({
-                                "job_id": &job_id,
-                                "output": &output_str,
-                                "type": "stream"
-                            }),
-                        })
-                        .await;
-                }
-            }
-            Ok(Some(ReadMsg::Eof)) => {
-                tracing::debug!("Main task: EOF received, job_id={}", job_id);
-                break;
-            }
-            Ok(Some(ReadMsg::Error)) => {
-                tracing::warn!("Main task: read error received, job_id={}", job_id);
-                break;
-            }
-            Ok(None) => {
-                tracing::warn!("Main task: reader task disconnected, job_id={}", job_id);
-                break;
-            }
-            Err(_) => {
-                // Timeout waiting for data - this is normal, continue loop to check elapsed time
-                continue;
-            }
-        }
+/// Capture the final stats of the job's cgroup and remove it. After a timeout or
+/// cancel, processes that escaped the job's process group are killed first.
+fn release_job_cgroup(job_manager: &JobManager, job_id: &str, kill_remaining: bool) {
+    let Some(path) = job_manager.cgroup_path(job_id) else {
+        return;
+    };
+    if kill_remaining {
+        cgroup::kill_all(&path);
     }
+    job_manager.record_cgroup_stats(job_id, cgroup::release(&path));
+}
 
-    if switched_to_async {
-        tracing::info!(
-            "Command switched to async mode: job_id={}, elapsed={:.2}s, output_so_far={} bytes",
-            job_id,
-            start_time.elapsed().as_secs_f64(),
-            output.len()
-        );
+/// Stop a background job's process group and record how it ended: termination
+/// stage, resource usage, cgroup stats and termination. A job that was already
+/// gone is only reaped.
+async fn stop_background_job(
+    job_manager: &JobManager,
+    job_id: &str,
+    child: &tokio::sync::Mutex<ChildProcess>,
+    status: JobStatus,
+) {
+    let mut child = child.lock().await;
+    let stage = child.terminate(get_kill_grace()).await;
+    let exit = child.wait();
+    drop(child);
+    tracing::debug!(
+        "Background job stopped: job_id={}, stage={:?}, status={:?}",
+        job_id,
+        stage,
+        status
+    );
+    job_manager.record_termination_stage(job_id, stage);
+    job_manager.record_resource_usage(job_id, exit.usage);
+    release_job_cgroup(job_manager, job_id, true);
+    record_termination(job_manager, job_id, exit);
+    job_manager.complete_job(job_id, exit.exit_code(), status);
+}
 
-        // Spawn background task to continue monitoring
-        let job_manager_clone = job_manager.clone();
-        let job_id_clone = job_id.clone();
-        let timeout_remaining = timeout.map(|t| t.saturating_sub(start_time.elapsed()));
-        let child_arc = Arc::new(tokio::sync::Mutex::new(child));
+/// Check whether the job's resource limits ended it and record the result.
+fn record_limit_exceeded(
+    job_manager: &JobManager,
+    job_id: &str,
+    exit: ExitInfo,
+) -> Option<LimitExceeded> {
+    let hit = job_manager.detect_limit_exceeded(job_id, exit.signal, exit.exit_code())?;
+    tracing::info!("Job {} hit resource limit: {}", job_id, hit);
+    Some(hit)
+}
 
-        tokio::spawn(async move {
-            tracing::debug!("Background task started for job_id={}", job_id_clone);
-            let start_bg = Instant::now();
+/// Explain how the job ended (signal, core dump, well-known exit codes) and record it.
+/// Call after the termination stage and cgroup stats are recorded, which it uses.
+fn record_termination(
+    job_manager: &JobManager,
+    job_id: &str,
+    exit: ExitInfo,
+) -> Option<Termination> {
+    job_manager.record_termination(job_id, exit.code, exit.signal, exit.core_dumped)
+}
 
-            // Continue receiving from the reader task channel
-            loop {
-                // Check for timeout (if set)
-                if let Some(timeout_dur) = timeout_remaining {
-                    if start_bg.elapsed() > timeout_dur {
-                        let mut child_guard = child_arc.lock().await;
-                        let _ = child_guard.kill();
-                        drop(child_guard);
-                        job_manager_clone.complete_job(&job_id_clone, None, JobStatus::TimedOut);
-                        break;
-                    }
-                }
+fn append_job_output(
+    job_manager: &JobManager,
+    job_id: &str,
+    stream: OutputStream,
+    output: &str,
+    output_limit: usize,
+) {
+    match stream {
+        OutputStream::Stdout | OutputStream::Stderr => {
+            job_manager.append_stream_output(job_id, stream, output, output_limit)
+        }
+        _ => job_manager.append_output(job_id, output, output_limit),
+    }
+}
 
-                match tokio::time::timeout(Duration::from_millis(100), rx.recv()).await {
-                    Ok(Some(ReadMsg::Data(data))) => {
-                        let output_str = String::from_utf8_lossy(&data).to_string();
-                        job_manager_clone.append_output(
-                            &job_id_clone,
-                            &output_str,
-                            preview_byte_limit,
-                        );
-                    }
-                    Ok(Some(ReadMsg::Eof)) => {
-                        // Process finished
-                        let mut child_guard = child_arc.lock().await;
-                        let exit_status = child_guard.wait().ok();
-                        let exit_code = exit_status.map(|s| s.exit_code() as i32);
-                        let status = if exit_code == Some(0) {
-                            JobStatus::Completed
-                        } else {
-                            JobStatus::Failed
-                        };
-                        tracing::debug!(
-                            "Background job completed: job_id={}, exit_code={:?}, status={:?}",
-                            job_id_clone,
-                            exit_code,
-                            status
-                        );
-                        job_manager_clone.complete_job(&job_id_clone, exit_code, status);
-                        break;
-                    }
-                    Ok(Some(ReadMsg::Error)) => {
-                        job_manager_clone.complete_job(&job_id_clone, None, JobStatus::Failed);
-                        break;
-                    }
-                    Ok(None) => {
-                        // Reader task died unexpectedly
-                        job_manager_clone.complete_job(&job_id_clone, None, JobStatus::Failed);
-                        break;
-                    }
-                    Err(_) => {
-                        // Timeout waiting for data, continue loop to check timeout
-                        continue;
-                    }
-                }
-            }
-        });
+/// Decode and redact a chunk of background output (or, without one, flush the
+/// held-back partial lines) and append the result to the job.
+fn store_redacted(
+    job_manager: &JobManager,
+    job_id: &str,
+    decoder: &mut OutputDecoder,
+    redactor: &mut StreamRedactor,
+    chunk: Option<(OutputStream, &[u8])>,
+    output_limit: usize,
+) {
+    let redacted = match chunk {
+        Some((stream, data)) => redactor
+            .push(stream, &decoder.push(stream, data))
+            .into_iter()
+            .collect(),
+        None => redactor.flush(),
+    };
+    for (stream, output) in redacted {
+        append_job_output(job_manager, job_id, stream, &output, output_limit);
+    }
+    job_manager.add_redactions(job_id, redactor.take_new());
+    job_manager.add_invalid_utf8(job_id, decoder.take_new());
+}
 
-        // Return immediately with a bounded preview and duration so far.
-        let raw_output_str = String::from_utf8_lossy(&output).to_string();
-        let preview = preview_output(&raw_output_str, input.preview_tokens);
-        let output_str = preview.text;
-        truncated |= preview.truncated;
-        let duration_secs = start_time.elapsed().as_secs_f64();
-        tracing::info!(
-            "Returning async result: job_id={}, duration={:.2}s",
-            job_id,
-            duration_secs
-        );
-        return Ok(ExecutionResult {
-            job_id,
-            command: command.to_string(),
-            working_directory: cwd.display().to_string(),
-            exit_code: None,
-            success: false,
-            output: output_str,
-            truncated,
-            timed_out: false,
-            switched_to_async: true,
-            denied: false,
-            denial_reason: None,
-            duration_secs: Some(duration_secs),
-            sudo_wrapper_applied: if sudo_looks_used(command) {
-                Some("-n".to_string())
-            } else {
-                None
-            },
-        });
+/// Store the end of background output: the bytes of an unfinished character and
+/// the held-back partial lines.
+fn store_rest(
+    job_manager: &JobManager,
+    job_id: &str,
+    decoder: &mut OutputDecoder,
+    redactor: &mut StreamRedactor,
+    output_limit: usize,
+) {
+    for (stream, output) in finish_decoding(decoder, redactor) {
+        append_job_output(job_manager, job_id, stream, &output, output_limit);
     }
+    store_redacted(job_manager, job_id, decoder, redactor, None, output_limit);
+}
 
-    // Synchronous completion
-    let exit_status = child.wait().ok();
-    let exit_code = exit_status.map(|s| s.exit_code() as i32);
-    let success = exit_code.map(|c| c == 0).unwrap_or(false);
+/// Decode the bytes of an unfinished character left at the end of the output.
+fn finish_decoding(
+    decoder: &mut OutputDecoder,
+    redactor: &mut StreamRedactor,
+) -> Vec<(OutputStream, String)> {
+    decoder
+        .finish()
+        .into_iter()
+        .filter_map(|(stream, text)| redactor.push(stream, &text))
+        .collect()
+}
 
-    let raw_output_str = String::from_utf8_lossy(&output).to_string();
-    let preview = preview_output(&raw_output_str, input.preview_tokens);
-    let mut output_str = preview.text;
-    truncated |= preview.truncated;
+fn stream_previews(
+    capture_mode: CaptureMode,
+    buffers: &PreviewBuffers,
+    preview_tokens: usize,
+) -> (Option<String>, Option<String>, bool) {
+    if capture_mode != CaptureMode::Pipes {
+        return (None, None, false);
+    }
+    let stdout = preview_output(&String::from_utf8_lossy(&buffers.stdout), preview_tokens);
+    let stderr = preview_output(&String::from_utf8_lossy(&buffers.stderr), preview_tokens);
+    let truncated = stdout.truncated || stderr.truncated;
+    (Some(stdout.text), Some(stderr.text), truncated)
+}
 
-    // Complete job
-    let status = if timed_out {
-        JobStatus::TimedOut
-    } else if success {
-        JobStatus::Completed
-    } else {
-        JobStatus::Failed
-    };
-    job_manager.complete_job(&job_id, exit_code, status);
+fn append_sudo_prime_diagnostics(output: &mut String, report: SudoPrimeReport) {
+    let mut diag = String::new();
+    diag.push_str("\n\n[SUDO_PRIME]\n");
+    diag.push_str(&format!("success: {}\n", report.success));
+    if let Some(code) = report.exit_code {
+        diag.push_str(&format!("exit_code: {}\n", code));
+    }
+    if let Some(askpass) = report.askpass {
+        diag.push_str(&format!("askpass: {}\n", askpass));
+    }
+    if let Some(v) = report.display {
+        diag.push_str(&format!("DISPLAY: {}\n", v));
+    }
+    if let Some(v) = report.wayland_display {
+        diag.push_str(&format!("WAYLAND_DISPLAY: {}\n", v));
+    }
+    if let Some(v) = report.xdg_runtime_dir {
+        diag.push_str(&format!("XDG_RUNTIME_DIR: {}\n", v));
+    }
+    if let Some(v) = report.dbus_session_bus_address {
+        diag.push_str(&format!("DBUS_SESSION_BUS_ADDRESS: {}\n", v));
+    }
+    if !report.stderr.trim().is_empty() {
+        diag.push_str("stderr:\n");
+        diag.push_str(report.stderr.trim());
+        diag.push('\n');
+    }
+    output.push_str(&diag);
+}
 
-    let duration_secs = start_time.elapsed().as_secs_f64();
+pub async fn execute_command(
+    input: &TerminalExecutionInput,
+    job_manager: &JobManager,
+    peer: Option<Peer<RoleServer>>,
+) -> Result<ExecutionResult> {
+    let command = input.command.trim();
 
     tracing::debug!(
-        "Synchronous command completed: job_id={}, exit_code={:?}, duration={:.2}s",
-        job_id,
-        exit_code,
-        duration_secs
+        "execute_command called: command={}, force_sync={}, force_async={}",
+        command,
+        input.force_sync,
+        input.force_async
     );
 
-    // If this command used sudo, surface any priming attempt diagnostics in the output.
-    // This makes failures visible even when MCP server logs aren't easily accessible.
+    let rewritten = rewrite_command(command, &input.tags);
+    execute_command_inner(
+        &rewritten.command,
+        input,
+        job_manager,
+        rewritten.applied,
+        peer,
+    )
+    .await
+}
+
+/// Prime sudo credentials in the server context and start the keepalive, for a
+/// command that uses sudo and is about to run.
+async fn prepare_sudo(
+    command: &str,
+    env_vars: &std::collections::HashMap<String, String>,
+) -> Option<SudoPrimeReport> {
+    // Optional: server-side sudo wrapping (opt-in).
+    // When enabled, any command containing `sudo` is executed such that:
+    // - sudo authentication is primed in the server process context (askpass)
+    // - the actual privileged command runs via `sudo -n` so it never prompts
     //
-    // Note: we only surface the report produced by the keepalive/priming attempt for *this*
-    // command invocation to avoid introducing extra async plumbing or dependencies.
-    if sudo_looks_used(command) {
-        if let Some(report) = sudo_prime_report {
-            let mut diag = String::new();
-            diag.push_str("\n\n[SUDO_PRIME]\n");
-            diag.push_str(&format!("success: {}\n", report.success));
-            if let Some(code) = report.exit_code {
-                diag.push_str(&format!("exit_code: {}\n", code));
-            }
-            if let Some(askpass) = report.askpass {
-                diag.push_str(&format!("askpass: {}\n", askpass));
-            }
-            if let Some(v) = report.display {
-                diag.push_str(&format!("DISPLAY: {}\n", v));
-            }
-            if let Some(v) = report.wayland_display {
-                diag.push_str(&format!("WAYLAND_DISPLAY: {}\n", v));
-            }
-            if let Some(v) = report.xdg_runtime_dir {
-                diag.push_str(&format!("XDG_RUNTIME_DIR: {}\n", v));
-            }
-            if let Some(v) = report.dbus_session_bus_address {
-                diag.push_str(&format!("DBUS_SESSION_BUS_ADDRESS: {}\n", v));
-            }
-            if !report.stderr.trim().is_empty() {
-                diag.push_str("stderr:\n");
-                diag.push_str(report.stderr.trim());
-                diag.push('\n');
-            }
-            output_str.push_str(&diag);
-        }
+    // This allows sudo timestamp reuse across multiple tool calls.
+    let wrap_report = if sudo_wrap_enabled(command) {
+        ensure_sudo_primed_for_wrap(command, env_vars).await
+    } else {
+        None
+    };
+    let keepalive_report = maybe_start_sudo_keepalive(command, env_vars).await;
+    wrap_report.or(keepalive_report)
+}
+
+fn sudo_wrap_enabled(command: &str) -> bool {
+    env_bool("ENHANCED_TERMINAL_SUDO_WRAP") && sudo_looks_used(command)
+}
+
+/// Apply the built-in sudo rule (`ENHANCED_TERMINAL_SUDO_WRAP`) and the policy
+/// file's rewrite rules to a command line.
+pub(super) fn rewrite_command(command: &str, tags: &[String]) -> Rewritten {
+    let sudo = RewriteRule::sudo_non_interactive();
+    // A policy file that does not load denies the command later on
+    let policy = policy::current().ok().flatten();
+    let mut rules: Vec<&RewriteRule> = Vec::new();
+    if sudo_wrap_enabled(command) {
+        rules.push(&sudo);
     }
+    if let Some(policy) = &policy {
+        rules.extend(policy.rewrite_rules());
+    }
+    rewrite::apply(command, &rules, tags)
+}
 
-    Ok(ExecutionResult {
-        job_id,
+/// Resolve a requested working directory: "." or empty is the server's cwd, and
+/// symlinks are resolved when the directory exists.
+pub(super) fn resolve_cwd(cwd: &str) -> PathBuf {
+    let cwd = if cwd == "." || cwd.is_empty() {
+        std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+    } else {
+        PathBuf::from(cwd)
+    };
+    cwd.canonicalize().unwrap_or(cwd)
+}
+
+/// Result for a command that was refused before it started.
+fn denied_result(
+    command: &str,
+    input: &TerminalExecutionInput,
+    reason: String,
+    indirect_execution: Vec<String>,
+    rewrites: Vec<String>,
+) -> ExecutionResult {
+    ExecutionResult {
+        job_id: String::new(),
         command: command.to_string(),
-        working_directory: cwd.display().to_string(),
-        exit_code,
-        success,
-        output: output_str,
-        truncated,
-        timed_out,
+        working_directory: String::new(),
+        exit_code: None,
+        success: false,
+        output: String::new(),
+        truncated: false,
+        timed_out: false,
         switched_to_async: false,
-        denied: false,
-        denial_reason: None,
-        duration_secs: Some(duration_secs),
-        sudo_wrapper_applied: if sudo_looks_used(command) {
-            Some("-n".to_string())
-        } else {
-            None
-        },
-    })
+        denied: true,
+        denial_reason: Some(reason),
+        approval: None,
+        indirect_execution,
+        redactions: 0,
+        encoding: input.encoding,
+        invalid_utf8: 0,
+        duration_secs: None,
+        sudo_wrapper_applied: None,
+        rewrites,
+        capture_mode: input.capture_mode,
+        stdout: None,
+        stderr: None,
+        termination_stage: None,
+        resource_limits: ResourceLimits::default(),
+        limit_exceeded: None,
+        cgroup: None,
+        termination: None,
+        sandbox: None,
+        write_restriction: None,
+    }
 }
 
 async fn execute_command_inner(
     command: &str,
     input: &TerminalExecutionInput,
     job_manager: &JobManager,
-    sudo_prime_report: Option<SudoPrimeReport>,
+    rewrites: Vec<String>,
     peer: Option<Peer<RoleServer>>,
 ) -> Result<ExecutionResult> {
     let command = command.trim();
@@ -643,73 +963,117 @@ async fn execute_command_inner(
         return Err(anyhow::anyhow!("Command cannot be empty"));
     }
 
-    // Check denylist
-    if is_denied(command, &input.custom_denylist) {
-        let matched_pattern = find_matched_pattern(command, &input.custom_denylist);
-        return Ok(ExecutionResult {
-            job_id: String::new(),
-            command: command.to_string(),
-            working_directory: String::new(),
-            exit_code: None,
-            success: false,
-            output: String::new(),
-            truncated: false,
-            timed_out: false,
-            switched_to_async: false,
-            denied: true,
-            denial_reason: Some(format!(
-                "Command denied by security policy. Matched pattern: {}",
-                matched_pattern.unwrap_or_else(|| "unknown".to_string())
-            )),
-            duration_secs: None,
-            sudo_wrapper_applied: None,
-        });
-    }
+    let cwd = resolve_cwd(&input.cwd);
 
-    // Resolve working directory and canonicalize (resolve symlinks)
-    let cwd = if input.cwd == "." || input.cwd.is_empty() {
-        std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-    } else {
-        PathBuf::from(&input.cwd)
+    let env_vars = apply_default_env(input.env_vars.clone());
+    let explanation = policy::explain(command, &input.custom_denylist, &cwd, &|name| {
+        env_vars
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    });
+    let indirect_execution: Vec<String> = explanation
+        .findings
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let approval = match approval::resolve(explanation.verdict, command, &cwd, peer.as_ref()).await
+    {
+        Ok(approval) => approval,
+        Err(reason) => {
+            return Ok(denied_result(
+                command,
+                input,
+                reason,
+                indirect_execution,
+                rewrites,
+            ));
+        }
     };
 
-    // Canonicalize to resolve symlinks
-    let cwd = cwd.canonicalize().unwrap_or(cwd);
-
-    // Create PTY system
-    let pty_system = native_pty_system();
-    let pair = pty_system
-        .openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| anyhow::anyhow!("Failed to open PTY: {}", e))?;
+    let sandbox = sandbox::resolve(input.sandbox.as_deref())?;
+    let write_restriction = if input.restrict_writes || landlock::enabled_by_server() {
+        match plan_write_restriction(&cwd, &env_vars, &input.read_only_paths) {
+            Ok(restriction) => Some(restriction),
+            Err(reason) => {
+                return Ok(denied_result(
+                    command,
+                    input,
+                    reason,
+                    indirect_execution,
+                    rewrites,
+                ));
+            }
+        }
+    } else {
+        None
+    };
 
-    // Build command
-    let mut cmd = CommandBuilder::new(&input.shell);
-    cmd.arg("-c");
-    cmd.arg(command);
-    cmd.cwd(&cwd);
+    // Only ask for sudo credentials once the command is known to run
+    let sudo_prime_report = prepare_sudo(command, &input.env_vars).await;
 
-    // Set environment variables
-    let env_vars = apply_default_env(input.env_vars.clone());
-    for (key, value) in env_vars {
-        cmd.env(key, value);
-    }
+    let mut redactor = StreamRedactor::new(Redactor::for_env(&env_vars));
+    let mut decoder = OutputDecoder::new(input.encoding);
 
-    // Start the process
-    let mut child = pair
-        .slave
-        .spawn_command(cmd)
-        .map_err(|e| anyhow::anyhow!("Failed to spawn: {}", e))?;
+    // Reserve the job id up front so the stdin writer and cgroup can be set up at spawn time.
+    let job_id = job_manager.new_job_id();
 
-    let pid = child.process_id();
-    drop(pair.slave);
+    let cgroup_limits = input.cgroup_limits.clone().unwrap_or_default();
+    let cgroup = cgroup::place_job(&job_id, &cgroup_limits);
+    let launch = LaunchSpec {
+        limits: ResourceLimits::capped_by(
+            input.resource_limits.as_ref(),
+            &ResourceLimits::server_maximums(),
+        ),
+        cgroup: match &cgroup {
+            CgroupPlacement::Placed(group) => Some(group.path.clone()),
+            CgroupPlacement::Unavailable(_) => None,
+        },
+        sandbox: sandbox.as_ref().map(|sandbox| sandbox.profile.clone()),
+        write_rules: match &write_restriction {
+            Some(WriteRestriction::Enforced(rules)) => Some(WriteRules {
+                writable: rules.writable.clone(),
+            }),
+            _ => None,
+        },
+    };
+    let spawned = match input.capture_mode {
+        CaptureMode::Pty => spawn_pty_command(
+            command,
+            input,
+            &launch,
+            &cwd,
+            env_vars,
+            job_manager,
+            &job_id,
+        ),
+        CaptureMode::Pipes => spawn_piped_command(
+            command,
+            input,
+            &launch,
+            &cwd,
+            env_vars,
+            job_manager,
+            &job_id,
+        ),
+    };
+    let spawned = match spawned {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            if let Some(path) = &launch.cgroup {
+                cgroup::release(path);
+            }
+            return Err(e);
+        }
+    };
+    let SpawnedCommand {
+        mut child,
+        mut rx,
+        mut readers,
+    } = spawned;
 
     // Register job
-    let job_id = job_manager.new_job_id();
+    let pid = child.process_id();
     job_manager.register_job_with_tags(
         job_id.clone(),
         command.to_string(),
@@ -718,67 +1082,25 @@ async fn execute_command_inner(
         pid,
         input.tags.clone(),
     );
-
-    // Read output with smart async switching and keep a writer for future stdin calls.
-    let mut reader = pair
-        .master
-        .try_clone_reader()
-        .map_err(|e| anyhow::anyhow!("Failed to clone reader: {}", e))?;
-    let stdin_writer = pair
-        .master
-        .take_writer()
-        .map_err(|e| anyhow::anyhow!("Failed to open PTY stdin writer: {}", e))?;
-    job_manager.attach_stdin_writer(&job_id, stdin_writer);
+    if input.capture_mode == CaptureMode::Pipes {
+        job_manager.mark_separate_streams(&job_id);
+    }
+    job_manager.set_resource_limits(&job_id, launch.limits.clone());
+    job_manager.set_cgroup(&job_id, cgroup);
+    job_manager.set_sandbox(&job_id, sandbox.clone());
+    job_manager.set_write_restriction(&job_id, write_restriction.clone());
+    job_manager.set_approval(&job_id, approval.clone());
+    job_manager.set_encoding(&job_id, input.encoding);
 
     let preview_byte_limit = preview_buffer_limit(input);
     let timeout = get_timeout_secs().map(Duration::from_secs);
     let async_threshold = Duration::from_secs(get_async_threshold_secs());
     let start_time = Instant::now();
 
-    let mut output = Vec::new();
-    let mut truncated = false;
+    let mut previews = PreviewBuffers::default();
     let mut timed_out = false;
     let mut switched_to_async = false;
-
-    // Channel for receiving output from reader task
-    #[derive(Debug)]
-    enum ReadMsg {
-        Data(Vec<u8>),
-        Eof,
-        Error,
-    }
-
-    let (tx, mut rx) = mpsc::unbounded_channel::<ReadMsg>();
-
-    // Spawn reader task using tokio::task::spawn_blocking (PTY read is blocking I/O)
-    let reader_job_id = job_id.clone();
-    tokio::task::spawn_blocking(move || {
-        let mut buffer = [0u8; 4096];
-        loop {
-            match reader.read(&mut buffer) {
-                Ok(0) => {
-                    tracing::debug!("Reader task: EOF, job_id={}", reader_job_id);
-                    let _ = tx.send(ReadMsg::Eof);
-                    break;
-                }
-                Ok(n) => {
-                    let data = buffer[..n].to_vec();
-                    if tx.send(ReadMsg::Data(data)).is_err() {
-                        break; // Main task dropped receiver
-                    }
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    std::thread::sleep(Duration::from_millis(10));
-                    continue;
-                }
-                Err(e) => {
-                    tracing::warn!("Reader task error: {:?}, job_id={}", e, reader_job_id);
-                    let _ = tx.send(ReadMsg::Error);
-                    break;
-                }
-            }
-        }
-    });
+    let mut termination_stage = None;
 
     // Main loop: check elapsed time independently and receive output
     let check_interval = Duration::from_millis(100);
@@ -801,42 +1123,34 @@ async fn execute_command_inner(
         }
 
         // Check for overall timeout (if set)
-        if let Some(timeout_duration) = timeout {
-            if elapsed > timeout_duration {
-                tracing::debug!("Main task: timeout reached, job_id={}", job_id);
-                let _ = child.kill();
-                timed_out = true;
-                break;
-            }
+        if let Some(timeout_duration) = timeout
+            && elapsed > timeout_duration
+        {
+            tracing::debug!("Main task: timeout reached, job_id={}", job_id);
+            termination_stage = child.terminate(get_kill_grace()).await;
+            timed_out = true;
+            break;
+        }
+
+        if job_manager.is_cancel_requested(&job_id) {
+            tracing::debug!("Main task: cancel requested, job_id={}", job_id);
+            termination_stage = child.terminate(get_kill_grace()).await;
+            break;
         }
 
         // Try to receive output from reader task with timeout
-        match tokio::time::timeout(check_interval, rx.recv()).await {
-            Ok(Some(ReadMsg::Data(data))) => {
-                truncated |= append_to_preview_buffer(&mut output, &data, preview_byte_limit);
-
-                // Update job with incremental output
-                let output_str = String::from_utf8_lossy(&data).to_string();
-                job_manager.append_output(&job_id, &output_str, preview_byte_limit);
-
-                // Send streaming notification if peer is available
-                if let Some(ref peer) = peer {
-                    let _ = peer
-                        .notify_logging_message(LoggingMessageNotificationParam {
-                            level: LoggingLevel::Info,
-                            logger: Some("enhanced_terminal".to_string()),
-                            data: serde_json::json!({
-                                "job_id": &job_id,
-                                "output": &output_str,
-                                "type": "stream"
-                            }),
-                        })
-                        .await;
-                }
-            }
+        let chunks = match tokio::time::timeout(check_interval, rx.recv()).await {
+            Ok(Some(ReadMsg::Data(stream, data))) => redactor
+                .push(stream, &decoder.push(stream, &data))
+                .into_iter()
+                .collect(),
             Ok(Some(ReadMsg::Eof)) => {
-                tracing::debug!("Main task: EOF received, job_id={}", job_id);
-                break;
+                readers = readers.saturating_sub(1);
+                if readers == 0 {
+                    tracing::debug!("Main task: EOF received, job_id={}", job_id);
+                    break;
+                }
+                continue;
             }
             Ok(Some(ReadMsg::Error)) => {
                 tracing::warn!("Main task: read error received, job_id={}", job_id);
@@ -847,18 +1161,72 @@ async fn execute_command_inner(
                 break;
             }
             Err(_) => {
-                // Timeout waiting for data - this is normal, continue loop to check elapsed time
-                continue;
+                // Timeout waiting for data - this is normal. Release a held-back partial
+                // line (e.g. a prompt) and continue loop to check elapsed time
+                redactor.flush()
+            }
+        };
+
+        for (stream, output_str) in chunks {
+            previews.append(stream, output_str.as_bytes(), preview_byte_limit);
+
+            // Update job with incremental output
+            append_job_output(
+                job_manager,
+                &job_id,
+                stream,
+                &output_str,
+                preview_byte_limit,
+            );
+
+            // Send streaming notification if peer is available
+            if let Some(ref peer) = peer {
+                let mut data = serde_json::json!({
+                    "job_id": &job_id,
+                    "output": &output_str,
+                    "type": "stream"
+                });
+                if input.capture_mode == CaptureMode::Pipes {
+                    data["stream"] = serde_json::json!(stream);
+                }
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("enhanced_terminal".to_string()),
+                        data,
+                    })
+                    .await;
             }
         }
+        job_manager.add_redactions(&job_id, redactor.take_new());
+        job_manager.add_invalid_utf8(&job_id, decoder.take_new());
+    }
+
+    if !switched_to_async {
+        let mut rest = finish_decoding(&mut decoder, &mut redactor);
+        rest.extend(redactor.flush());
+        for (stream, output_str) in rest {
+            previews.append(stream, output_str.as_bytes(), preview_byte_limit);
+            append_job_output(
+                job_manager,
+                &job_id,
+                stream,
+                &output_str,
+                preview_byte_limit,
+            );
+        }
+        job_manager.add_redactions(&job_id, redactor.take_new());
+        job_manager.add_invalid_utf8(&job_id, decoder.take_new());
     }
+    let redactions = redactor.total();
+    let invalid_utf8 = decoder.invalid();
 
     if switched_to_async {
         tracing::info!(
             "Command switched to async mode: job_id={}, elapsed={:.2}s, output_so_far={} bytes",
             job_id,
             start_time.elapsed().as_secs_f64(),
-            output.len()
+            previews.combined.len()
         );
 
         // Spawn background task to continue monitoring
@@ -873,31 +1241,58 @@ async fn execute_command_inner(
 
             // Continue receiving from the reader task channel
             loop {
-                // Check for timeout (if set)
-                if let Some(timeout_dur) = timeout_remaining {
-                    if start_bg.elapsed() > timeout_dur {
-                        let mut child_guard = child_arc.lock().await;
-                        let _ = child_guard.kill();
-                        drop(child_guard);
-                        job_manager_clone.complete_job(&job_id_clone, None, JobStatus::TimedOut);
-                        break;
-                    }
+                // Check for timeout (if set) or a cancel request
+                let timed_out = timeout_remaining.is_some_and(|t| start_bg.elapsed() > t);
+                if timed_out || job_manager_clone.is_cancel_requested(&job_id_clone) {
+                    store_rest(
+                        &job_manager_clone,
+                        &job_id_clone,
+                        &mut decoder,
+                        &mut redactor,
+                        preview_byte_limit,
+                    );
+                    stop_background_job(
+                        &job_manager_clone,
+                        &job_id_clone,
+                        &child_arc,
+                        JobStatus::TimedOut,
+                    )
+                    .await;
+                    break;
                 }
 
                 match tokio::time::timeout(Duration::from_millis(100), rx.recv()).await {
-                    Ok(Some(ReadMsg::Data(data))) => {
-                        let output_str = String::from_utf8_lossy(&data).to_string();
-                        job_manager_clone.append_output(
+                    Ok(Some(ReadMsg::Data(stream, data))) => {
+                        store_redacted(
+                            &job_manager_clone,
                             &job_id_clone,
-                            &output_str,
+                            &mut decoder,
+                            &mut redactor,
+                            Some((stream, &data)),
                             preview_byte_limit,
                         );
                     }
                     Ok(Some(ReadMsg::Eof)) => {
+                        readers = readers.saturating_sub(1);
+                        if readers > 0 {
+                            continue;
+                        }
+                        store_rest(
+                            &job_manager_clone,
+                            &job_id_clone,
+                            &mut decoder,
+                            &mut redactor,
+                            preview_byte_limit,
+                        );
+
                         // Process finished
                         let mut child_guard = child_arc.lock().await;
-                        let exit_status = child_guard.wait().ok();
-                        let exit_code = exit_status.map(|s| s.exit_code() as i32);
+                        let exit = child_guard.wait();
+                        let exit_code = exit.exit_code();
+                        job_manager_clone.record_resource_usage(&job_id_clone, exit.usage);
+                        record_limit_exceeded(&job_manager_clone, &job_id_clone, exit);
+                        release_job_cgroup(&job_manager_clone, &job_id_clone, false);
+                        record_termination(&job_manager_clone, &job_id_clone, exit);
                         let status = if exit_code == Some(0) {
                             JobStatus::Completed
                         } else {
@@ -912,17 +1307,40 @@ async fn execute_command_inner(
                         job_manager_clone.complete_job(&job_id_clone, exit_code, status);
                         break;
                     }
-                    Ok(Some(ReadMsg::Error)) => {
-                        job_manager_clone.complete_job(&job_id_clone, None, JobStatus::Failed);
-                        break;
-                    }
-                    Ok(None) => {
-                        // Reader task died unexpectedly
-                        job_manager_clone.complete_job(&job_id_clone, None, JobStatus::Failed);
+                    Ok(Some(ReadMsg::Error)) | Ok(None) => {
+                        // A reader failed or died: output is lost from here on, so stop
+                        // the job rather than leave it running unobserved
+                        tracing::warn!(
+                            "Background task: reading output failed, job_id={}",
+                            job_id_clone
+                        );
+                        store_rest(
+                            &job_manager_clone,
+                            &job_id_clone,
+                            &mut decoder,
+                            &mut redactor,
+                            preview_byte_limit,
+                        );
+                        stop_background_job(
+                            &job_manager_clone,
+                            &job_id_clone,
+                            &child_arc,
+                            JobStatus::Failed,
+                        )
+                        .await;
                         break;
                     }
                     Err(_) => {
-                        // Timeout waiting for data, continue loop to check timeout
+                        // Timeout waiting for data: release a held-back partial line and
+                        // continue loop to check timeout
+                        store_redacted(
+                            &job_manager_clone,
+                            &job_id_clone,
+                            &mut decoder,
+                            &mut redactor,
+                            None,
+                            preview_byte_limit,
+                        );
                         continue;
                     }
                 }
@@ -930,44 +1348,21 @@ async fn execute_command_inner(
         });
 
         // Return immediately with a bounded preview and duration so far.
-        let raw_output_str = String::from_utf8_lossy(&output).to_string();
+        let raw_output_str = String::from_utf8_lossy(&previews.combined).to_string();
         let preview = preview_output(&raw_output_str, input.preview_tokens);
         let mut output_str = preview.text;
-        truncated |= preview.truncated;
+        let (stdout, stderr, streams_truncated) =
+            stream_previews(input.capture_mode, &previews, input.preview_tokens);
+        let truncated = previews.truncated || preview.truncated || streams_truncated;
         let duration_secs = start_time.elapsed().as_secs_f64();
 
-        if sudo_looks_used(command) {
-            if let Some(report) = sudo_prime_report {
-                let mut diag = String::new();
-                diag.push_str("\n\n[SUDO_PRIME]\n");
-                diag.push_str(&format!("success: {}\n", report.success));
-                if let Some(code) = report.exit_code {
-                    diag.push_str(&format!("exit_code: {}\n", code));
-                }
-                if let Some(askpass) = report.askpass {
-                    diag.push_str(&format!("askpass: {}\n", askpass));
-                }
-                if let Some(v) = report.display {
-                    diag.push_str(&format!("DISPLAY: {}\n", v));
-                }
-                if let Some(v) = report.wayland_display {
-                    diag.push_str(&format!("WAYLAND_DISPLAY: {}\n", v));
-                }
-                if let Some(v) = report.xdg_runtime_dir {
-                    diag.push_str(&format!("XDG_RUNTIME_DIR: {}\n", v));
-                }
-                if let Some(v) = report.dbus_session_bus_address {
-                    diag.push_str(&format!("DBUS_SESSION_BUS_ADDRESS: {}\n", v));
-                }
-                if !report.stderr.trim().is_empty() {
-                    diag.push_str("stderr:\n");
-                    diag.push_str(report.stderr.trim());
-                    diag.push('\n');
-                }
-                output_str.push_str(&diag);
-            }
+        if sudo_looks_used(command)
+            && let Some(report) = sudo_prime_report
+        {
+            append_sudo_prime_diagnostics(&mut output_str, report);
         }
 
+        let cgroup = job_manager.cgroup_placement(&job_id);
         tracing::info!(
             "Returning async result: job_id={}, duration={:.2}s",
             job_id,
@@ -985,26 +1380,49 @@ async fn execute_command_inner(
             switched_to_async: true,
             denied: false,
             denial_reason: None,
+            approval,
+            indirect_execution,
+            redactions,
+            encoding: input.encoding,
+            invalid_utf8,
             duration_secs: Some(duration_secs),
             sudo_wrapper_applied: if sudo_looks_used(command) {
                 Some("-n".to_string())
             } else {
                 None
             },
+            rewrites,
+            capture_mode: input.capture_mode,
+            stdout,
+            stderr,
+            termination_stage: None,
+            resource_limits: launch.limits,
+            limit_exceeded: None,
+            cgroup,
+            termination: None,
+            sandbox,
+            write_restriction,
         });
     }
 
     // Synchronous completion
-    let exit_status = child.wait().ok();
-    let exit_code = exit_status.map(|s| s.exit_code() as i32);
+    let exit = child.wait();
+    let exit_code = exit.exit_code();
     let success = exit_code.map(|c| c == 0).unwrap_or(false);
+    job_manager.record_resource_usage(&job_id, exit.usage);
+    let limit_exceeded = record_limit_exceeded(job_manager, &job_id, exit);
 
-    let raw_output_str = String::from_utf8_lossy(&output).to_string();
+    let raw_output_str = String::from_utf8_lossy(&previews.combined).to_string();
     let preview = preview_output(&raw_output_str, input.preview_tokens);
     let mut output_str = preview.text;
-    truncated |= preview.truncated;
+    let (stdout, stderr, streams_truncated) =
+        stream_previews(input.capture_mode, &previews, input.preview_tokens);
+    let truncated = previews.truncated || preview.truncated || streams_truncated;
 
     // Complete job
+    job_manager.record_termination_stage(&job_id, termination_stage);
+    release_job_cgroup(job_manager, &job_id, termination_stage.is_some());
+    let termination = record_termination(job_manager, &job_id, exit);
     let status = if timed_out {
         JobStatus::TimedOut
     } else if success {
@@ -1023,38 +1441,18 @@ async fn execute_command_inner(
         duration_secs
     );
 
-    if sudo_looks_used(command) {
-        if let Some(report) = sudo_prime_report {
-            let mut diag = String::new();
-            diag.push_str("\n\n[SUDO_PRIME]\n");
-            diag.push_str(&format!("success: {}\n", report.success));
-            if let Some(code) = report.exit_code {
-                diag.push_str(&format!("exit_code: {}\n", code));
-            }
-            if let Some(askpass) = report.askpass {
-                diag.push_str(&format!("askpass: {}\n", askpass));
-            }
-            if let Some(v) = report.display {
-                diag.push_str(&format!("DISPLAY: {}\n", v));
-            }
-            if let Some(v) = report.wayland_display {
-                diag.push_str(&format!("WAYLAND_DISPLAY: {}\n", v));
-            }
-            if let Some(v) = report.xdg_runtime_dir {
-                diag.push_str(&format!("XDG_RUNTIME_DIR: {}\n", v));
-            }
-            if let Some(v) = report.dbus_session_bus_address {
-                diag.push_str(&format!("DBUS_SESSION_BUS_ADDRESS: {}\n", v));
-            }
-            if !report.stderr.trim().is_empty() {
-                diag.push_str("stderr:\n");
-                diag.push_str(report.stderr.trim());
-                diag.push('\n');
-            }
-            output_str.push_str(&diag);
-        }
+    // If this command used sudo, surface any priming attempt diagnostics in the output.
+    // This makes failures visible even when MCP server logs aren't easily accessible.
+    //
+    // Note: we only surface the report produced by the keepalive/priming attempt for *this*
+    // command invocation to avoid introducing extra async plumbing or dependencies.
+    if sudo_looks_used(command)
+        && let Some(report) = sudo_prime_report
+    {
+        append_sudo_prime_diagnostics(&mut output_str, report);
     }
 
+    let cgroup = job_manager.cgroup_placement(&job_id);
     Ok(ExecutionResult {
         job_id,
         command: command.to_string(),
@@ -1067,16 +1465,31 @@ async fn execute_command_inner(
         switched_to_async: false,
         denied: false,
         denial_reason: None,
+        approval,
+        indirect_execution,
+        redactions,
+        encoding: input.encoding,
+        invalid_utf8,
         duration_secs: Some(duration_secs),
         sudo_wrapper_applied: if sudo_looks_used(command) {
             Some("-n".to_string())
         } else {
             None
         },
+        rewrites,
+        capture_mode: input.capture_mode,
+        stdout,
+        stderr,
+        termination_stage,
+        resource_limits: launch.limits,
+        limit_exceeded,
+        cgroup,
+        termination,
+        sandbox,
+        write_restriction,
     })
 }
 
-static SUDO_KEEPALIVE_TASK: OnceLock<TokioMutex<bool>> = OnceLock::new();
 static SUDO_PRIME_LAST: OnceLock<TokioMutex<Option<SudoPrimeReport>>> = OnceLock::new();
 
 #[derive(Debug, Clone)]
@@ -1224,7 +1637,7 @@ async fn sudo_prime_with_askpass(
     }
 }
 
-fn env_bool(name: &str) -> bool {
+pub(super) fn env_bool(name: &str) -> bool {
     // Default these features ON unless explicitly disabled.
     // Supported values for true: 1/true/yes/on
     // Supported values for false: 0/false/no/off
@@ -1306,40 +1719,6 @@ async fn ensure_sudo_primed_for_wrap(
     take_sudo_prime_report().await
 }
 
-fn wrap_sudo_command_for_server(command: &str) -> String {
-    // Replace any sudo invocation with a non-interactive sudo so the command never prompts.
-    // This is a best-effort string rewrite; it intentionally stays conservative.
-    //
-    // Examples:
-    // - "sudo apt update" -> "sudo -n apt update"
-    // - "cmd && sudo id"  -> "cmd && sudo -n id"
-    //
-    // This does not attempt to perfectly parse shell syntax.
-    // It is opt-in and intended for workflows where you control the command content.
-    let mut out = command.to_string();
-
-    // Start-of-command "sudo "
-    if out.starts_with("sudo ") {
-        out = out.replacen("sudo ", "sudo -n ", 1);
-    }
-
-    // Common separators that can precede sudo. Keep spaces to reduce false positives.
-    for pat in [
-        "&& sudo ",
-        "|| sudo ",
-        "; sudo ",
-        "\n sudo ",
-        "\n\tsudo ",
-        "\tsudo ",
-    ] {
-        if out.contains(pat) {
-            out = out.replace(pat, &pat.replace("sudo ", "sudo -n "));
-        }
-    }
-
-    out
-}
-
 fn env_u64(name: &str, default_value: u64) -> u64 {
     std::env::var(name)
         .ok()
@@ -1422,48 +1801,7 @@ async fn maybe_start_sudo_keepalive(
         }
     }
 
-    // Start keepalive loop at most once
-    let gate = SUDO_KEEPALIVE_TASK.get_or_init(|| TokioMutex::new(false));
-    let mut started = gate.lock().await;
-    if *started {
-        return None;
-    }
-    *started = true;
-    drop(started);
-
     let refresh_secs = env_u64("ENHANCED_TERMINAL_SUDO_KEEPALIVE_REFRESH_SECS", 300).max(30);
-    tracing::info!(
-        "Starting sudo keepalive task (refresh_secs={})",
-        refresh_secs
-    );
-
-    tokio::spawn(async move {
-        let interval = Duration::from_secs(refresh_secs);
-        loop {
-            tokio::time::sleep(interval).await;
-
-            // `sudo -n -v` refreshes timestamp if already authenticated; fails if it would prompt.
-            let status = tokio::process::Command::new("sudo")
-                .arg("-n")
-                .arg("-v")
-                .stdin(std::process::Stdio::null())
-                .stdout(std::process::Stdio::null())
-                .stderr(std::process::Stdio::null())
-                .status()
-                .await;
-
-            match status {
-                Ok(s) if s.success() => {
-                    tracing::debug!("sudo keepalive refreshed timestamp");
-                }
-                Ok(_) => {
-                    tracing::debug!("sudo keepalive: no cached credentials (sudo -n -v failed)");
-                }
-                Err(e) => {
-                    tracing::warn!("sudo keepalive: failed to run sudo -n -v: {}", e);
-                }
-            }
-        }
-    });
+    sudo::start_keepalive(Duration::from_secs(refresh_secs));
     None
 }
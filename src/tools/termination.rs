@@ -0,0 +1,228 @@
+//! How a job ended, beyond its bare exit code.
+//!
+//! A process killed by a signal has no exit code of its own; we report the signal,
+//! whether it dumped core and what it usually means. Commands run through `sh -c`,
+//! so a child killed by a signal often shows up as the shell exiting with
+//! 128 + signal instead; those codes, and the shell's 126/127, are explained too.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::process_group::TerminationStage;
+
+/// Signal, core dump and explanation for a job that did not simply exit 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Termination {
+    /// Signal number that killed the process, or that the shell reported via 128 + n
+    pub signal: Option<i32>,
+    /// Signal name such as "SIGSEGV"
+    pub signal_name: Option<String>,
+    /// True when the kernel reported a core dump
+    pub core_dumped: bool,
+    /// Exit code in shell convention (the real code, or 128 + signal)
+    pub exit_code: Option<i32>,
+    /// Human-readable explanation
+    pub explanation: String,
+}
+
+/// What else is known about the job when explaining its end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerminationContext {
+    /// Escalation stage used after a timeout or cancel request
+    pub stage: Option<TerminationStage>,
+    /// The job's cgroup recorded at least one OOM kill
+    pub oom_killed: bool,
+}
+
+impl Termination {
+    /// Describe an exit status. Returns `None` for a clean exit and for ordinary
+    /// failure codes that carry no extra meaning.
+    pub fn describe(
+        code: Option<i32>,
+        signal: Option<i32>,
+        core_dumped: bool,
+        context: TerminationContext,
+    ) -> Option<Self> {
+        if let Some(signal) = signal {
+            return Some(Self::from_signal(
+                signal,
+                core_dumped,
+                Some(128 + signal),
+                false,
+                context,
+            ));
+        }
+
+        let code = code?;
+        match code {
+            126 => Some(Self::from_code(
+                code,
+                "command found but could not be executed (permission denied, not an executable, \
+                 or the job's launcher could not set up its sandbox or limits)",
+            )),
+            127 => Some(Self::from_code(
+                code,
+                "command not found (check the spelling and PATH)",
+            )),
+            129..=192 => Some(Self::from_signal(
+                code - 128,
+                core_dumped,
+                Some(code),
+                true,
+                context,
+            )),
+            _ => None,
+        }
+    }
+
+    fn from_code(code: i32, explanation: &str) -> Self {
+        Self {
+            signal: None,
+            signal_name: None,
+            core_dumped: false,
+            exit_code: Some(code),
+            explanation: explanation.to_string(),
+        }
+    }
+
+    fn from_signal(
+        signal: i32,
+        core_dumped: bool,
+        exit_code: Option<i32>,
+        via_shell: bool,
+        context: TerminationContext,
+    ) -> Self {
+        let name = signal_name(signal);
+        let mut explanation = match name.as_deref() {
+            Some("SIGKILL") if context.oom_killed => {
+                "killed by the kernel OOM killer (the job's cgroup ran out of memory)".to_string()
+            }
+            Some(name) if context.stage.is_some_and(|s| s.signal_name() == name) => {
+                format!("stopped with {} after a timeout or cancel request", name)
+            }
+            Some(name) => meaning(name).to_string(),
+            None => format!("killed by signal {}", signal),
+        };
+        if via_shell {
+            explanation.push_str(&format!(
+                " (the shell exited with {} = 128 + {})",
+                128 + signal,
+                signal
+            ));
+        }
+        if core_dumped {
+            explanation.push_str("; core dumped");
+        }
+
+        Self {
+            signal: Some(signal),
+            signal_name: name,
+            core_dumped,
+            exit_code,
+            explanation,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<String> {
+    nix::sys::signal::Signal::try_from(signal)
+        .ok()
+        .map(|s| s.as_str().to_string())
+}
+
+#[cfg(not(unix))]
+fn signal_name(_signal: i32) -> Option<String> {
+    None
+}
+
+/// What a signal usually means when it ends a command.
+fn meaning(name: &str) -> &'static str {
+    match name {
+        "SIGSEGV" => "segmentation fault: invalid memory access",
+        "SIGBUS" => {
+            "bus error: misaligned or unmapped memory access (e.g. a truncated mmap'd file)"
+        }
+        "SIGABRT" => "aborted: failed assertion, abort() or a panic with panic=abort",
+        "SIGFPE" => "arithmetic error such as integer division by zero",
+        "SIGILL" => "illegal instruction: corrupted binary or unsupported CPU instruction",
+        "SIGTRAP" => "trace/breakpoint trap",
+        "SIGSYS" => "bad system call, often a seccomp filter",
+        "SIGKILL" => "killed by SIGKILL: an explicit kill -9 or the kernel OOM killer",
+        "SIGTERM" => "terminated by SIGTERM",
+        "SIGINT" => "interrupted by SIGINT (Ctrl-C)",
+        "SIGQUIT" => "quit by SIGQUIT (Ctrl-\\)",
+        "SIGHUP" => "hangup: the controlling terminal went away",
+        "SIGPIPE" => "broken pipe: wrote to a pipe whose reader had exited",
+        "SIGXCPU" => "CPU time limit (RLIMIT_CPU) exceeded",
+        "SIGXFSZ" => "file size limit (RLIMIT_FSIZE) exceeded",
+        "SIGALRM" => "timer alarm (SIGALRM) not handled",
+        "SIGUSR1" | "SIGUSR2" => "user-defined signal not handled",
+        _ => "killed by a signal",
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.signal_name, self.signal, self.exit_code) {
+            (Some(name), Some(signal), _) => write!(f, "{} (signal {})", name, signal)?,
+            (None, Some(signal), _) => write!(f, "signal {}", signal)?,
+            (_, None, Some(code)) => write!(f, "exit code {}", code)?,
+            (_, None, None) => f.write_str("unknown")?,
+        }
+        write!(f, " - {}", self.explanation)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_signals_and_well_known_exit_codes() {
+        let context = TerminationContext::default();
+
+        let segv = Termination::describe(None, Some(11), true, context).unwrap();
+        assert_eq!(segv.signal_name.as_deref(), Some("SIGSEGV"));
+        assert_eq!(segv.exit_code, Some(139));
+        assert!(segv.core_dumped);
+        assert!(segv.explanation.contains("segmentation fault"));
+        assert!(segv.to_string().starts_with("SIGSEGV (signal 11) - "));
+
+        let not_found = Termination::describe(Some(127), None, false, context).unwrap();
+        assert_eq!(
+            not_found.to_string(),
+            "exit code 127 - command not found (check the spelling and PATH)"
+        );
+        assert!(Termination::describe(Some(126), None, false, context).is_some());
+
+        let interrupted = Termination::describe(Some(130), None, false, context).unwrap();
+        assert_eq!(interrupted.signal_name.as_deref(), Some("SIGINT"));
+        assert!(interrupted.explanation.contains("Ctrl-C"));
+        assert!(interrupted.explanation.contains("128 + 2"));
+
+        assert!(Termination::describe(Some(0), None, false, context).is_none());
+        assert!(Termination::describe(Some(1), None, false, context).is_none());
+    }
+
+    #[test]
+    fn uses_job_context_for_sigkill() {
+        let oom = TerminationContext {
+            oom_killed: true,
+            ..Default::default()
+        };
+        let killed = Termination::describe(None, Some(9), false, oom).unwrap();
+        assert!(killed.explanation.contains("OOM killer"));
+
+        let canceled = TerminationContext {
+            stage: Some(TerminationStage::Kill),
+            ..Default::default()
+        };
+        let killed = Termination::describe(Some(137), None, false, canceled).unwrap();
+        assert!(
+            killed
+                .explanation
+                .starts_with("stopped with SIGKILL after a timeout")
+        );
+    }
+}